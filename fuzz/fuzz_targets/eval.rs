@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rs_lisp::engine::{Engine, EngineConfig, SandboxPolicy};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        // Bounded so that loops and deep recursion end in errors, not hangs
+        let mut engine = Engine::with_config(EngineConfig {
+            fuel_limit: Some(10_000),
+            recursion_limit: 64,
+            // Random programs must not write files
+            sandbox: SandboxPolicy::Strict,
+            ..EngineConfig::default()
+        });
+        for line in input.lines() {
+            let _ = engine.eval_to_string(line);
+        }
+    }
+});
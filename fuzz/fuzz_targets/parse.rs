@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rs_lisp::parser::{expr_to_string, parse};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        if let Ok(expr) = parse(input) {
+            let _ = expr_to_string(&expr);
+        }
+    }
+});
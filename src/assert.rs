@@ -0,0 +1,271 @@
+//! `assert`, which on failure shows the expression that failed and the
+//! values of its parts, so that a failing check explains itself, and
+//! `assert-approx` and `assert-matches` for results that exact equality
+//! would make brittle: floats, and data with parts that do not matter.
+
+use crate::builtins::{expect_text, type_error};
+use crate::interpreter::{call, call_site, eval, BuiltinKind, Difference, Expr, Scope};
+use crate::messages::message;
+use crate::parser::expr_to_string;
+use crate::pattern::{bindings_to_expr, match_pattern};
+
+/// How far apart numbers may be for `assert-approx` when no epsilon is given.
+const DEFAULT_EPSILON: f64 = 1e-9;
+
+/// The arguments of a call, each with its value.
+type Parts<'a> = Vec<(&'a Expr, Expr)>;
+
+/// Evaluates `form`, along with the value of each argument when it is a
+/// call to a function or builtin. Each argument is evaluated once, as in
+/// any other call.
+fn eval_parts<'a>(form: &'a Expr, scope: &mut Scope) -> Result<(Expr, Parts<'a>), String> {
+    if let Expr::List(list) = form {
+        if let Some(Expr::Symbol(name)) = list.first() {
+            let callee = scope.get_variable(name);
+            let eager = match &callee {
+                Some(Expr::BuiltinFunction(builtin)) => builtin.kind == BuiltinKind::Eager,
+                Some(Expr::Function(_)) => true,
+                _ => false,
+            };
+            if let (true, Some(callee)) = (eager, callee) {
+                let args = list[1..]
+                    .iter()
+                    .map(|arg| eval(arg, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let value = call(&callee, &args, scope)?;
+                return Ok((value, list[1..].iter().zip(args).collect()));
+            }
+        }
+    }
+    Ok((eval(form, scope)?, Vec::new()))
+}
+
+/// Describes where two values differ, or nothing when they differ only as
+/// a whole, which the values themselves already show.
+fn differing(differences: &[Difference]) -> String {
+    if differences
+        .iter()
+        .all(|difference| difference.path.is_empty())
+    {
+        return String::new();
+    }
+    let differences: Vec<String> = differences.iter().map(Difference::to_string).collect();
+    message!(
+        "assertion-differences",
+        differences = differences.join("; ")
+    )
+}
+
+fn is_comparison(callee: &Expr) -> bool {
+    matches!(callee, Expr::Symbol(name) if name == "=" || name == "eq?")
+}
+
+/// `(assert expr)` is the value of the expression if it is truthy, and
+/// otherwise fails with the expression and the values of its arguments,
+/// and where the two sides differ when it compares two values with `=` or
+/// `eq?`. `(assert expr reason)` gives the reason it should have held.
+pub fn builtin_assert(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let form = &args[0];
+    let (value, parts) = eval_parts(form, scope)?;
+    if value.is_truthy() {
+        return Ok(value);
+    }
+
+    let comparison = match (form, parts.as_slice()) {
+        (Expr::List(list), [(_, left), (_, right)]) if is_comparison(&list[0]) => {
+            differing(&Expr::diff(left, right))
+        }
+        _ => String::new(),
+    };
+    // Literal arguments show their values already
+    let parts: Parts = parts
+        .into_iter()
+        .filter(|(arg, _)| matches!(arg, Expr::List(_) | Expr::Symbol(_)))
+        .collect();
+
+    let site = match form {
+        Expr::List(list) => call_site(list, scope),
+        _ => message!("call-site-unknown", form = expr_to_string(form)),
+    };
+    let mut parts = if parts.is_empty() {
+        String::new()
+    } else {
+        let parts: Vec<String> = parts
+            .iter()
+            .map(|(part, value)| {
+                message!(
+                    "assertion-part",
+                    form = expr_to_string(part),
+                    value = expr_to_string(value)
+                )
+            })
+            .collect();
+        message!("assertion-parts", parts = parts.join(", "))
+    };
+    parts.push_str(&comparison);
+    Err(match args.get(1) {
+        Some(reason) => message!(
+            "assertion-failed-because",
+            reason = expect_text("assert", 2, reason)?,
+            site = site,
+            parts = parts
+        ),
+        None => message!("assertion-failed", site = site, parts = parts),
+    })
+}
+
+/// `(assert-approx expected actual)` is `actual` if it is within 1e-9 of
+/// `expected`, and otherwise fails. Lists, vectors and maps are compared
+/// element by element, and values other than numbers must be equal.
+/// `(assert-approx expected actual epsilon)` sets how far apart numbers may
+/// be.
+pub fn builtin_assert_approx(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let (expected, actual) = (&args[0], &args[1]);
+    let epsilon = match args.get(2) {
+        None => DEFAULT_EPSILON,
+        Some(epsilon) => match epsilon.as_number() {
+            Some(e) if e >= 0.0 => e,
+            _ => {
+                return Err(type_error(
+                    "assert-approx",
+                    "a non-negative number",
+                    3,
+                    epsilon,
+                ))
+            }
+        },
+    };
+    let close = |expected: &Expr, actual: &Expr| match (expected.as_number(), actual.as_number()) {
+        (Some(expected), Some(actual)) => (expected - actual).abs() <= epsilon,
+        _ => expected == actual,
+    };
+    let differences = Expr::diff_by(expected, actual, &close);
+    if differences.is_empty() {
+        return Ok(actual.clone());
+    }
+    Err(message!(
+        "assertion-not-approx",
+        actual = expr_to_string(actual),
+        epsilon = epsilon,
+        expected = expr_to_string(expected),
+        differences = differing(&differences)
+    ))
+}
+
+/// `(assert-matches pattern value)` is the bindings of the pattern's `?`
+/// variables, as `find-all` gives them, if the value matches the pattern,
+/// and otherwise fails.
+pub fn builtin_assert_matches(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let (pattern, value) = (&args[0], &args[1]);
+    let mut bindings = Vec::new();
+    if match_pattern(pattern, value, &mut bindings) {
+        Ok(bindings_to_expr(bindings))
+    } else {
+        Err(message!(
+            "assertion-no-match",
+            value = expr_to_string(value),
+            pattern = expr_to_string(pattern)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{CapturedOutput, Engine};
+
+    #[test]
+    fn assertions() {
+        let mut engine = Engine::new();
+        engine.eval("(def x 2)").unwrap();
+        engine.eval("(defn double (n) (* n 2))").unwrap();
+        for (input, expected) in [
+            ("(assert (= (double x) 4))", "true"),
+            ("(assert (double x))", "4"),
+            (
+                "(assert (= (double x) 5))",
+                "Error: Assertion failed in (= (double x) 5) at line 1, column 9, where (double x) is 4",
+            ),
+            (
+                "(assert (< x (- x 1) 3))",
+                "Error: Assertion failed in (< x (- x 1) 3) at line 1, column 9, where x is 2, (- x 1) is 1",
+            ),
+            (
+                r#"(assert (and (= x 1) true) "x starts at one")"#,
+                "Error: Assertion failed, x starts at one, in (and (= x 1) true) at line 1, column 9",
+            ),
+            ("(assert nil)", "Error: Assertion failed in nil"),
+            (
+                "(assert (eq? [x {:a 1}] [2 {:a 2}]))",
+                "Error: Assertion failed in (eq? [x {:a 1}] [2 {:a 2}]) at line 1, column 9, \
+                 differing at (1 :a): 1 vs 2",
+            ),
+            (
+                "(assert (= (first 1) 1))",
+                "Error: `first` expected a sequence as argument 1, got the integer 1",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+
+        // Arguments are evaluated once, even when the assertion fails
+        let output = CapturedOutput::default();
+        engine.set_output(output.clone());
+        engine
+            .eval(r#"(defn noisy (n) (if (print "!") n n))"#)
+            .unwrap();
+        assert!(engine.eval("(assert (= (noisy 1) 2))").is_err());
+        assert_eq!(output.take(), "!");
+    }
+
+    #[test]
+    fn approximate_and_pattern_assertions() {
+        let mut engine = Engine::new();
+        for (input, expected) in [
+            ("(assert-approx 0.3 (+ 0.1 0.2))", "0.30000000000000004"),
+            ("(assert-approx 1/3 0.333 0.001)", "0.333"),
+            (
+                "(assert-approx (list 1 (list 2.0 :a)) (list 1.0 (list 2.0000000001 :a)))",
+                "(1.0 (2.0000000001 :a))",
+            ),
+            (
+                "(assert-approx 3.14 (sqrt 10) 0.01)",
+                "Error: Assertion failed, 3.1622776601683795 is not within 0.01 of 3.14",
+            ),
+            (
+                "(assert-approx (list 1.0 2.0) (list 1.0 2.5))",
+                "Error: Assertion failed, (1.0 2.5) is not within 0.000000001 of (1.0 2.0), \
+                 differing at (1): 2.0 vs 2.5",
+            ),
+            (
+                "(assert-approx (list 1 :a) (list 1 :b))",
+                "Error: Assertion failed, (1 :b) is not within 0.000000001 of (1 :a), \
+                 differing at (1): :a vs :b",
+            ),
+            (
+                "(assert-approx {:x [1.0 2.0]} {:x [1.0 2.5 3.0] :y 1})",
+                "Error: Assertion failed, {:x [1.0 2.5 3.0] :y 1} is not within 0.000000001 of \
+                 {:x [1.0 2.0]}, differing at (:x 1): 2.0 vs 2.5; at (:x 2): nothing vs 3.0; \
+                 at (:y): nothing vs 1",
+            ),
+            (
+                "(assert-approx 1 1 -0.1)",
+                "Error: `assert-approx` expected a non-negative number as argument 3, \
+                 got the number -0.1",
+            ),
+            (
+                "(assert-matches (quote (point ?x _)) (list (quote point) 1 2))",
+                "((?x 1))",
+            ),
+            (
+                "(assert-matches (quote (?x ?x & _)) (list 1 1 2 3))",
+                "((?x 1))",
+            ),
+            (
+                "(assert-matches (quote (?x ?x)) (list 1 2))",
+                "Error: Assertion failed, (1 2) does not match (?x ?x)",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
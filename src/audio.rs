@@ -0,0 +1,108 @@
+//! Tones for livecoding music in the browser: `tone` queues a note and `at`
+//! delays the notes queued while evaluating its body. The host collects the
+//! queue with `Engine::take_tones` and plays it, on the page with WebAudio.
+
+use crate::builtins::expect_number;
+use crate::interpreter::{eval, Expr, Scope};
+use crate::messages::message;
+
+/// A note to play, `start_ms` after the evaluation that queued it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub start_ms: f64,
+    pub frequency: f64,
+    pub duration_ms: f64,
+}
+
+/// The tones queued so far, and how far `at` forms being evaluated have
+/// pushed back the tones queued now.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    offset_ms: f64,
+    tones: Vec<Tone>,
+}
+
+impl Schedule {
+    pub fn take_tones(&mut self) -> Vec<Tone> {
+        std::mem::take(&mut self.tones)
+    }
+}
+
+fn expect_non_negative(name: &str, position: usize, expr: &Expr) -> Result<f64, String> {
+    let n = expect_number(name, position, expr)?;
+    if n < 0.0 || !n.is_finite() {
+        return Err(message!(
+            "wrong-type",
+            name = name,
+            expected = "a non-negative number",
+            position = position,
+            actual = expr.describe()
+        ));
+    }
+    Ok(n)
+}
+
+/// `(tone frequency duration)` queues a note of `frequency` Hz lasting
+/// `duration` milliseconds.
+pub fn builtin_tone(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let frequency = expect_non_negative("tone", 1, &args[0])?;
+    let duration_ms = expect_non_negative("tone", 2, &args[1])?;
+    let mut schedule = scope.runtime().schedule();
+    let start_ms = schedule.offset_ms;
+    schedule.tones.push(Tone {
+        start_ms,
+        frequency,
+        duration_ms,
+    });
+    Ok(Expr::Nil)
+}
+
+/// `(at ms expr)` evaluates `expr` with the tones it queues starting `ms`
+/// milliseconds later, returning its value.
+pub fn builtin_at(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let delay = eval(&args[0], scope)?;
+    let delay_ms = expect_non_negative("at", 1, &delay)?;
+    scope.runtime().schedule().offset_ms += delay_ms;
+    let result = eval(&args[1], scope);
+    scope.runtime().schedule().offset_ms -= delay_ms;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn schedule_tones() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(list (tone 440 100) (at 250 (list (tone 660 50) (at 50 (tone 880 50)))))")
+            .unwrap();
+        let tone = |start_ms, frequency, duration_ms| Tone {
+            start_ms,
+            frequency,
+            duration_ms,
+        };
+        assert_eq!(
+            engine.take_tones(),
+            vec![
+                tone(0.0, 440.0, 100.0),
+                tone(250.0, 660.0, 50.0),
+                tone(300.0, 880.0, 50.0),
+            ]
+        );
+        assert_eq!(engine.take_tones(), vec![]);
+
+        // A failing body leaves later tones undelayed
+        assert!(engine.eval("(at 100 (tone 440 x))").is_err());
+        engine.eval("(tone 440 10)").unwrap();
+        assert_eq!(engine.take_tones(), vec![tone(0.0, 440.0, 10.0)]);
+        assert_eq!(
+            engine.eval("(at -1 (tone 440 10))"),
+            Err(
+                "`at` expected a non-negative number as argument 1, got the integer -1".to_string()
+            )
+        );
+    }
+}
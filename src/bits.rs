@@ -0,0 +1,106 @@
+//! Bitwise operations on integers, as if written in two's complement with
+//! as many sign bits to the left as needed, so that they work the same on
+//! integers of any size.
+
+use num_bigint::BigInt;
+
+use crate::builtins::type_error;
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+
+/// The furthest `bit-shift-left` shifts, so that a typo cannot ask for an
+/// integer too big to hold in memory.
+const MAX_SHIFT: usize = 1 << 16;
+
+fn expect_integer(name: &str, position: usize, expr: &Expr) -> Result<BigInt, String> {
+    match expr {
+        Expr::Int(n) => Ok(BigInt::from(*n)),
+        Expr::BigInt(n) => Ok(n.clone()),
+        _ => Err(type_error(name, "an integer", position, expr)),
+    }
+}
+
+/// Folds the integer arguments with `op`.
+fn fold(name: &str, args: &[Expr], op: fn(BigInt, BigInt) -> BigInt) -> Result<Expr, String> {
+    let mut result = expect_integer(name, 1, &args[0])?;
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        result = op(result, expect_integer(name, i + 1, arg)?);
+    }
+    Ok(Expr::integer(result))
+}
+
+fn builtin_bit_and(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    fold("bit-and", args, |a, b| a & b)
+}
+
+fn builtin_bit_or(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    fold("bit-or", args, |a, b| a | b)
+}
+
+fn builtin_bit_xor(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    fold("bit-xor", args, |a, b| a ^ b)
+}
+
+/// The shift amount, from 0 to `max`.
+fn expect_shift(name: &str, expr: &Expr, max: usize) -> Result<usize, String> {
+    match expr {
+        Expr::Int(n) => usize::try_from(*n).ok().filter(|n| *n <= max),
+        _ => None,
+    }
+    .ok_or_else(|| type_error(name, &format!("a shift from 0 to {}", max), 2, expr))
+}
+
+/// `(bit-shift-left n k)` is `n` times 2 to the `k`.
+fn builtin_bit_shift_left(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_integer("bit-shift-left", 1, &args[0])?;
+    let shift = expect_shift("bit-shift-left", &args[1], MAX_SHIFT)?;
+    Ok(Expr::integer(n << shift))
+}
+
+/// `(bit-shift-right n k)` is `n` divided by 2 to the `k`, rounded down, so
+/// negative numbers stay negative.
+fn builtin_bit_shift_right(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_integer("bit-shift-right", 1, &args[0])?;
+    let shift = expect_shift("bit-shift-right", &args[1], usize::MAX)?;
+    Ok(Expr::integer(n >> shift))
+}
+
+#[rustfmt::skip]
+pub(crate) const BIT_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("bit-and",         builtin_bit_and,         BuiltinKind::Eager, Arity::AtLeast(2)),
+    ("bit-or",          builtin_bit_or,          BuiltinKind::Eager, Arity::AtLeast(2)),
+    ("bit-xor",         builtin_bit_xor,         BuiltinKind::Eager, Arity::AtLeast(2)),
+    ("bit-shift-left",  builtin_bit_shift_left,  BuiltinKind::Eager, Arity::Exact(2)),
+    ("bit-shift-right", builtin_bit_shift_right, BuiltinKind::Eager, Arity::Exact(2)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn bits() {
+        let mut engine = Engine::new();
+        for (input, expected) in [
+            ("(bit-and 0xFF 0b1010)", "10"),
+            ("(bit-or 0b1000 0b0011 0x10)", "27"),
+            ("(bit-xor 0xFF 0x0F)", "240"),
+            ("(bit-and -1 0xFF)", "255"),
+            ("(bit-xor -1 5)", "-6"),
+            ("(bit-shift-left 1 10)", "1024"),
+            ("(bit-shift-left 1 64)", "18446744073709551616"),
+            ("(bit-shift-right (bit-shift-left 3 100) 99)", "6"),
+            ("(bit-shift-right -7 1)", "-4"),
+            ("(bit-shift-right 5 1000)", "0"),
+            (
+                "(bit-and 1 1.0)",
+                "Error: `bit-and` expected an integer as argument 2, got the number 1.0",
+            ),
+            (
+                "(bit-shift-left 1 -1)",
+                "Error: `bit-shift-left` expected a shift from 0 to 65536 as argument 2, got the integer -1",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
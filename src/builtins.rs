@@ -1,267 +1,709 @@
+use std::cmp::Ordering;
 use std::rc::Rc;
 
-use crate::interpreter::{eval, BuiltinKind, Expr, Scope};
+use num_rational::BigRational;
+use num_traits::{Signed, Zero};
 
-fn builtin_add(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    println!("builtin_add: {:?}", args);
-    let mut result = 0.0;
-    for expr in &args[0..] {
-        if let Expr::Number(n) = expr {
-            result += n;
-        } else {
-            return Err("Non-numeric argument to +".to_string());
-        }
+use crate::assert::{builtin_assert, builtin_assert_approx, builtin_assert_matches};
+use crate::audio::{builtin_at, builtin_tone};
+use crate::edn::{builtin_edn_read, builtin_edn_write};
+use crate::engine::{Redefinition, VERSION};
+use crate::interpreter::{
+    arity_error, call, eval, Arity, BuiltinFn, BuiltinKind, Expr, Function, Map, MapKey, Scope,
+};
+use crate::messages::message;
+use crate::module::{builtin_ns, builtin_require};
+use crate::number_format::{builtin_format_number, builtin_parse_number};
+use crate::parser::{expr_to_string, parse_with_reader};
+use crate::pattern::{builtin_find_all, builtin_select};
+use crate::plot::builtin_plot;
+use crate::resource::{
+    builtin_close, builtin_deref_weak, builtin_open_file, builtin_read, builtin_read_line,
+    builtin_weak_ref, builtin_with_open,
+};
+use crate::rewrite::{builtin_postwalk, builtin_prewalk, builtin_rewrite};
+use crate::spec::{builtin_defspec, builtin_validate};
+use crate::store::{builtin_store_get, builtin_store_set};
+use crate::tail::{builtin_is_tail_recursive, check_loop};
+use crate::testing::{builtin_defbench, builtin_deftest, builtin_use_fixture};
+use crate::turtle::{builtin_forward, builtin_pen_down, builtin_pen_up, builtin_turn};
+use crate::url::{
+    builtin_query_string_to_map, builtin_url_decode, builtin_url_encode, builtin_url_parse,
+};
+
+pub(crate) fn type_error(name: &str, expected: &str, position: usize, actual: &Expr) -> String {
+    message!(
+        "wrong-type",
+        name = name,
+        expected = expected,
+        position = position,
+        actual = actual.describe()
+    )
+}
+
+/// Checks that the argument at the 1-based `position` of builtin `name` is a
+/// number, integer or float, returning it as a float.
+pub fn expect_number(name: &str, position: usize, expr: &Expr) -> Result<f64, String> {
+    expr.as_number()
+        .ok_or_else(|| type_error(name, "a number", position, expr))
+}
+
+/// Checks that the argument at the 1-based `position` of builtin `name` is a list.
+pub fn expect_list<'a>(name: &str, position: usize, expr: &'a Expr) -> Result<&'a [Expr], String> {
+    match expr {
+        Expr::List(list) => Ok(list),
+        _ => Err(type_error(name, "a list", position, expr)),
     }
-    Ok(Expr::Number(result))
 }
 
-fn builtin_apply(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 2 {
-        return Err("apply expects exactly 2 arguments".to_string());
+/// Checks that the argument at the 1-based `position` of builtin `name` is a symbol.
+pub fn expect_symbol<'a>(name: &str, position: usize, expr: &'a Expr) -> Result<&'a str, String> {
+    match expr {
+        Expr::Symbol(symbol) => Ok(symbol),
+        _ => Err(type_error(name, "a symbol", position, expr)),
     }
+}
 
-    println!("builtin_apply: {:?}", args);
-    let func = eval(&args[0], scope)?;
-    let arg_list = match eval(&args[1], scope)? {
-        Expr::List(list) => list,
-        _ => return Err("Second argument to apply must be a list".to_string()),
-    };
+/// Checks that the argument at the 1-based `position` of builtin `name` is a string.
+pub fn expect_string<'a>(name: &str, position: usize, expr: &'a Expr) -> Result<&'a str, String> {
+    match expr {
+        Expr::String(string) => Ok(string),
+        _ => Err(type_error(name, "a string", position, expr)),
+    }
+}
 
-    println!("func: {:?}", func);
-    println!("arg_list: {:?}", arg_list);
-
-    // Apply the function to the evaluated arguments
-    match func {
-        Expr::Lambda(args, func) => {
-            if args.len() != arg_list.len() {
-                return Err(format!(
-                    "Expected {} arguments, got {}",
-                    args.len(),
-                    arg_list.len()
-                ));
-            }
-            // Create a new scope for the function application
-            let mut new_scope = Scope::with_parent(Rc::new(scope.clone()));
-            for (param, arg) in args.iter().zip(arg_list.iter()) {
-                new_scope.set_variable(param.clone(), eval(arg, scope)?);
-            }
-            eval(&func, &mut new_scope)
-        }
-        Expr::Function(func) => {
-            let evaluated_args: Result<Vec<_>, _> =
-                arg_list.iter().map(|arg| eval(arg, scope)).collect();
-            match evaluated_args {
-                Ok(evaluated_args) => {
-                    // Create a new scope for the function application
-                    let mut new_scope = Scope::with_parent(Rc::new(scope.clone()));
-                    for (param, arg) in func.parameters.iter().zip(evaluated_args) {
-                        new_scope.set_variable(param.clone(), arg);
-                    }
-                    eval(&func.body, &mut new_scope)
-                }
-                Err(e) => Err(e),
-            }
-        }
+/// Like `expect_string`, also taking a symbol's name, as text was passed
+/// before there were strings.
+pub fn expect_text<'a>(name: &str, position: usize, expr: &'a Expr) -> Result<&'a str, String> {
+    match expr {
+        Expr::String(text) | Expr::Symbol(text) => Ok(text),
+        _ => Err(type_error(name, "a string", position, expr)),
+    }
+}
 
-        Expr::BuiltinFunction(builtin_func) => {
-            match builtin_func.kind {
-                BuiltinKind::Eager => {
-                    // For eagerly evaluated built-ins
-                    let evaluated_args: Result<Vec<_>, _> =
-                        arg_list.iter().map(|arg| eval(arg, scope)).collect();
-                    println!("evaluated_args: {:?}", evaluated_args);
-                    (builtin_func.func)(&evaluated_args?, scope)
-                }
-                BuiltinKind::SpecialForm => {
-                    // For special forms, pass the raw arguments
-                    (builtin_func.func)(&arg_list, scope)
-                }
-            }
-        }
-        _ => {
-            println!("func: {:?}", func);
-            Err("First argument to apply is not a function".to_string())
-        }
+/// Checks that the argument at the 1-based `position` of builtin `name` is a
+/// character.
+fn expect_char(name: &str, position: usize, expr: &Expr) -> Result<char, String> {
+    match expr {
+        Expr::Char(c) => Ok(*c),
+        _ => Err(type_error(name, "a character", position, expr)),
     }
 }
 
-fn builtin_list(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
-    let mut result = Vec::new();
-    for expr in &args[0..] {
-        match eval(&expr.clone(), scope) {
-            Ok(evaluated) => result.push(evaluated),
-            Err(e) => return Err(e),
+/// Checks that every argument of builtin `name` is a number.
+fn expect_numbers(name: &str, args: &[Expr]) -> Result<(), String> {
+    for (i, expr) in args.iter().enumerate() {
+        expect_number(name, i + 1, expr)?;
+    }
+    Ok(())
+}
+
+/// Applies `int` to two integers, or `exact` when either is a big integer
+/// or ratio or `int` has no integer result, such as on overflow, so that
+/// arithmetic on exact numbers stays exact. Applies `float` when either is a
+/// float.
+fn combine(
+    a: &Expr,
+    b: &Expr,
+    int: fn(i64, i64) -> Option<i64>,
+    exact: fn(&BigRational, &BigRational) -> BigRational,
+    float: fn(f64, f64) -> f64,
+) -> Expr {
+    if let (Expr::Int(x), Expr::Int(y)) = (a, b) {
+        if let Some(n) = int(*x, *y) {
+            return Expr::Int(n);
         }
     }
-    Ok(Expr::List(result))
+    if let (Some(x), Some(y)) = (a.as_rational(), b.as_rational()) {
+        return Expr::rational(exact(&x, &y));
+    }
+    let number = |expr: &Expr| expr.as_number().expect("arguments are checked");
+    Expr::Number(float(number(a), number(b)))
 }
 
-fn builtin_fn(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 2 {
-        return Err("fn expects exactly 2 arguments".to_string());
+/// Whether a number is zero; exact numbers too small for a float are not.
+fn is_zero(n: &Expr) -> bool {
+    match n.as_rational() {
+        Some(n) => n.is_zero(),
+        None => n.as_number() == Some(0.0),
     }
+}
 
-    let parameters = if let Expr::List(parameters) = &args[0] {
-        parameters
-            .iter()
-            .map(|expr| {
-                if let Expr::Symbol(name) = expr {
-                    Ok(name.clone())
+fn builtin_add(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    expect_numbers("+", args)?;
+    Ok(args.iter().fold(Expr::Int(0), |result, n| {
+        combine(&result, n, i64::checked_add, |a, b| a + b, |a, b| a + b)
+    }))
+}
+
+/// `(- x)` is `x` negated, and `(- x y ...)` is `x` minus the rest.
+fn builtin_subtract(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    expect_numbers("-", args)?;
+    Ok(match args {
+        [Expr::Number(x)] => Expr::Number(-x),
+        [x] => combine(
+            &Expr::Int(0),
+            x,
+            i64::checked_sub,
+            |a, b| a - b,
+            |a, b| a - b,
+        ),
+        [x, rest @ ..] => rest.iter().fold(x.clone(), |result, n| {
+            combine(&result, n, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+        }),
+        [] => unreachable!("- arity is checked by the caller"),
+    })
+}
+
+fn builtin_multiply(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    expect_numbers("*", args)?;
+    Ok(args.iter().fold(Expr::Int(1), |result, n| {
+        combine(&result, n, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+    }))
+}
+
+/// `(/ x)` is the reciprocal of `x`, and `(/ x y ...)` is `x` divided by
+/// the rest. Exact numbers divide exactly, so `(/ 1 3)` is the ratio `1/3`.
+/// Dividing by zero is an error rather than infinity.
+fn builtin_divide(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    expect_numbers("/", args)?;
+    let (dividend, divisors) = match args {
+        [x] => (Expr::Int(1), std::slice::from_ref(x)),
+        [x, rest @ ..] => (x.clone(), rest),
+        [] => unreachable!("/ arity is checked by the caller"),
+    };
+    let mut result = dividend;
+    for divisor in divisors {
+        if is_zero(divisor) {
+            return Err(message!("division-by-zero", name = "/"));
+        }
+        result = combine(
+            &result,
+            divisor,
+            |a, b| a.checked_rem(b).filter(|r| *r == 0).and(a.checked_div(b)),
+            |a, b| a / b,
+            |a, b| a / b,
+        );
+    }
+    Ok(result)
+}
+
+/// `(mod x y)` is the remainder of dividing `x` by `y`, with the sign of `y`.
+fn builtin_mod(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    expect_numbers("mod", args)?;
+    if is_zero(&args[1]) {
+        return Err(message!("division-by-zero", name = "mod"));
+    }
+    Ok(combine(
+        &args[0],
+        &args[1],
+        |x, y| {
+            x.checked_rem(y).map(|r| {
+                if r != 0 && (r < 0) != (y < 0) {
+                    r + y
                 } else {
-                    Err("Function parameters must be symbols".to_string())
+                    r
                 }
             })
-            .collect::<Result<Vec<String>, String>>()?
-    } else {
-        return Err("Function parameters must be a list".to_string());
-    };
+        },
+        |x, y| x - y * (x / y).floor(),
+        |x, y| x - y * (x / y).floor(),
+    ))
+}
 
-    Ok(Expr::lambda(parameters, args[1].clone()))
+fn builtin_abs(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    expect_numbers("abs", args)?;
+    Ok(combine(
+        &args[0],
+        &Expr::Int(0),
+        |n, _| n.checked_abs(),
+        |n, _| n.abs(),
+        |n, _| n.abs(),
+    ))
 }
 
-fn builtin_quote(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 1 {
-        return Err("quote expects exactly 1 argument".to_string());
-    }
+/// Checks that the argument at the 1-based `position` of builtin `name` is an
+/// exact number, an integer or a ratio.
+fn expect_rational(name: &str, position: usize, expr: &Expr) -> Result<BigRational, String> {
+    expr.as_rational()
+        .ok_or_else(|| type_error(name, "an integer or ratio", position, expr))
+}
 
-    Ok(args[0].clone())
+/// `(numerator x)` is the numerator of the ratio in lowest terms, or the
+/// integer itself.
+fn builtin_numerator(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_rational("numerator", 1, &args[0])?;
+    Ok(Expr::integer(n.numer().clone()))
 }
 
-fn builtin_def(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 2 {
-        return Err("def expects exactly 2 arguments".to_string());
+/// `(denominator x)` is the denominator of the ratio in lowest terms, or 1
+/// for an integer.
+fn builtin_denominator(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_rational("denominator", 1, &args[0])?;
+    Ok(Expr::integer(n.denom().clone()))
+}
+
+/// `(exact->inexact x)` is the number as a float.
+fn builtin_exact_to_inexact(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Number(expect_number("exact->inexact", 1, &args[0])?))
+}
+
+/// The argument that is `ordering` to all the others, as it was given, so
+/// `(min 1 2.5)` is the integer 1. NaN is passed over.
+fn extreme(name: &str, args: &[Expr], ordering: Ordering) -> Result<Expr, String> {
+    expect_numbers(name, args)?;
+    let mut result = &args[0];
+    for n in &args[1..] {
+        if n.compare(result) == Ok(ordering) || result.as_number().is_some_and(f64::is_nan) {
+            result = n;
+        }
     }
+    Ok(result.clone())
+}
 
-    let name = if let Expr::Symbol(name) = &args[0] {
-        name
-    } else {
-        return Err("First argument to def must be a symbol".to_string());
-    };
+fn builtin_min(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    extreme("min", args, Ordering::Less)
+}
 
-    let value = eval(&args[1], scope)?;
+fn builtin_max(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    extreme("max", args, Ordering::Greater)
+}
 
-    scope.set_variable(name.clone(), value);
+fn builtin_apply(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let arg_list = expect_list("apply", 2, &args[1])?;
 
-    Ok(Expr::Symbol(name.clone()))
+    match &args[0] {
+        Expr::Function(_) | Expr::BuiltinFunction(_) => call(&args[0], arg_list, scope),
+        func => Err(type_error("apply", "a function", 1, func)),
+    }
 }
 
-fn builtin_if(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 3 {
-        return Err("if expects exactly 3 arguments".to_string());
+/// `(eval expr)` evaluates an expression, such as a quoted one, in the
+/// scope of the call, and `(eval expr :global)` in the global scope, where
+/// the locals around the call are not seen and definitions are global.
+fn builtin_eval(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    match args.get(1) {
+        None => eval(&args[0], scope),
+        Some(Expr::Keyword(where_)) if where_ == "global" => eval(&args[0], &mut scope.global()),
+        Some(arg) => Err(type_error("eval", ":global", 2, arg)),
     }
+}
 
-    let condition = eval(&args[0], scope)?;
+fn builtin_list(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::List(args.to_vec()))
+}
 
-    match condition {
-        Expr::Number(n) => {
-            if n == 0.0 {
-                eval(&args[2], scope)
+fn parameter_names(name: &str, position: usize, expr: &Expr) -> Result<Vec<String>, String> {
+    expect_list(name, position, expr)?
+        .iter()
+        .map(|expr| {
+            if let Expr::Symbol(parameter) = expr {
+                Ok(parameter.clone())
             } else {
-                eval(&args[1], scope)
+                Err(message!(
+                    "parameter-not-symbol",
+                    name = name,
+                    actual = expr.describe()
+                ))
             }
-        }
-        _ => Err("Condition must be a number".to_string()),
-    }
+        })
+        .collect()
+}
+
+fn builtin_fn(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let parameters = parameter_names("fn", 1, &args[0])?;
+
+    Ok(Expr::function(parameters, args[1].clone(), scope.clone()))
+}
+
+fn builtin_quote(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(args[0].clone())
 }
 
-fn builtin_first(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 1 {
-        return Err("first expects exactly 1 argument".to_string());
+/// Binds an already evaluated value, subject to the builtin redefinition policy.
+pub(crate) fn define(
+    name: &str,
+    doc: Option<&str>,
+    value: Expr,
+    scope: &mut Scope,
+) -> Result<Expr, String> {
+    // Builtins live in their own layer, so a global definition only shadows
+    // them; local scopes may do so freely.
+    if scope.is_global() && scope.is_builtin(name) {
+        let runtime = scope.runtime();
+        match runtime.config.builtin_redefinition {
+            Redefinition::Allow => {}
+            Redefinition::Warn => runtime.warn(message!("shadows-builtin", name = name)),
+            Redefinition::Error => return Err(message!("would-shadow-builtin", name = name)),
+        }
     }
 
-    let list = match &args[0] {
-        Expr::List(list) => list,
-        _ => return Err("Argument to first must be a list".to_string()),
+    scope.set_variable(name.to_string(), value.clone());
+    scope.set_doc(name.to_string(), doc.map(str::to_string));
+
+    Ok(value)
+}
+
+/// `(def name value)` or `(def name doc value)`, for `def` and `defonce`.
+fn def_form(form: &str, args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let name = expect_symbol(form, 1, &args[0])?;
+    let (doc, value) = match args {
+        [_, value] => (None, value),
+        [_, doc, value] => (Some(expect_text(form, 2, doc)?), value),
+        _ => unreachable!("{} arity is checked by the caller", form),
     };
 
-    if list.is_empty() {
-        return Err("Cannot get first element of empty list".to_string());
-    }
+    let value = eval(value, scope)?.with_name(name);
+    check_loop(name, &value, scope);
+    define(name, doc, value, scope)
+}
 
-    Ok(list[0].clone())
+fn builtin_def(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    def_form("def", args, scope)
 }
 
-fn builtin_rest(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 1 {
-        return Err("rest expects exactly 1 argument".to_string());
+/// `(defonce name value)` is `def` unless `name` is already bound, when the
+/// value is not even evaluated, so that loading a file again keeps the state
+/// it set up the first time. Returns the value `name` is bound to.
+fn builtin_defonce(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let name = expect_symbol("defonce", 1, &args[0])?;
+    if scope.is_bound(name) {
+        return Ok(scope.get_variable(name).expect("bound names have a value"));
     }
+    def_form("defonce", args, scope)
+}
 
-    let list = match &args[0] {
-        Expr::List(list) => list,
-        _ => return Err("Argument to first must be a list".to_string()),
+/// `(defn name (params) body)`, optionally flagged before the name with
+/// `^:pure` to promise the function has no side effects, so that its results
+/// may be memoized, and `^:private` to keep it out of the module's exports.
+fn builtin_defn(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let mut pure = false;
+    let mut private = false;
+    let mut rest = args;
+    while let [Expr::Symbol(flag), tail @ ..] = rest {
+        if !flag.starts_with("^:") {
+            break;
+        }
+        match flag.as_str() {
+            "^:pure" => pure = true,
+            "^:private" => private = true,
+            _ => return Err(message!("unknown-defn-flag", flag = flag)),
+        }
+        rest = tail;
+    }
+    let offset = args.len() - rest.len();
+    let [name, parameters, body] = rest else {
+        return Err(arity_error("defn", args.len(), offset + 3));
     };
 
-    if list.is_empty() {
-        return Err("Cannot get first element of empty list".to_string());
+    let name = expect_symbol("defn", offset + 1, name)?;
+
+    // Parameters may be given as `(name spec)` to check arguments when
+    // instrumentation is enabled
+    let mut names = Vec::new();
+    let mut specs = Vec::new();
+    for parameter in expect_list("defn", offset + 2, parameters)? {
+        match parameter {
+            Expr::List(pair) if pair.len() == 2 => {
+                names.push(expect_symbol("defn", offset + 2, &pair[0])?.to_string());
+                specs.push(Some(pair[1].clone()));
+            }
+            Expr::Symbol(parameter) => {
+                names.push(parameter.clone());
+                specs.push(None);
+            }
+            _ => return Err(message!("defn-parameter", actual = parameter.describe())),
+        }
+    }
+
+    let mut function = Function::new(names, Box::new(body.clone()), scope.clone());
+    function.name = Some(name.to_string());
+    function.pure = pure;
+    function.private = private;
+    if specs.iter().any(Option::is_some) {
+        function.parameter_specs = specs;
     }
 
-    Ok(Expr::List(list[1..].to_vec()))
+    let value = Expr::Function(Rc::new(function));
+    check_loop(name, &value, scope);
+    define(name, None, value, scope)
 }
 
-fn builtin_is_number(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 1 {
-        return Err("number? expects exactly 1 argument".to_string());
+fn builtin_doc(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let name = expect_symbol("doc", 1, &args[0])?;
+
+    Ok(scope.get_doc(name).map(Expr::String).unwrap_or(Expr::Nil))
+}
+
+fn builtin_if(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let condition = eval(&args[0], scope)?;
+
+    if condition.is_truthy() {
+        eval(&args[1], scope)
+    } else {
+        eval(&args[2], scope)
     }
+}
 
-    match &args[0] {
-        Expr::Number(_) => Ok(Expr::Number(1.0)),
-        _ => Ok(Expr::Number(0.0)),
+/// Like `expect_list`, taking nil as the empty list.
+fn expect_list_or_nil<'a>(
+    name: &str,
+    position: usize,
+    expr: &'a Expr,
+) -> Result<&'a [Expr], String> {
+    match expr {
+        Expr::Nil => Ok(&[]),
+        _ => expect_list(name, position, expr),
     }
 }
 
-fn builtin_is_symbol(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 1 {
-        return Err("symbol? expects exactly 1 argument".to_string());
+/// `(and form...)` evaluates the forms in order until one is false, returning
+/// its value, or the last value if none is. `(and)` is `true`.
+fn builtin_and(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let mut value = Expr::Bool(true);
+    for arg in args {
+        value = eval(arg, scope)?;
+        if !value.is_truthy() {
+            break;
+        }
     }
+    Ok(value)
+}
 
-    match &args[0] {
-        Expr::Symbol(_) => Ok(Expr::Number(1.0)),
-        _ => Ok(Expr::Number(0.0)),
+/// `(or form...)` evaluates the forms in order until one is true, returning
+/// its value, or the last value if none is. `(or)` is `nil`.
+fn builtin_or(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let mut value = Expr::Nil;
+    for arg in args {
+        value = eval(arg, scope)?;
+        if value.is_truthy() {
+            break;
+        }
     }
+    Ok(value)
+}
+
+fn builtin_not(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(!args[0].is_truthy()))
+}
+
+fn builtin_is_number(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(args[0].as_number().is_some()))
+}
+
+fn builtin_is_int(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(
+        &args[0],
+        Expr::Int(_) | Expr::BigInt(_)
+    )))
+}
+
+fn builtin_is_float(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Number(_))))
+}
+
+fn builtin_is_char(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Char(_))))
+}
+
+fn builtin_is_symbol(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Symbol(_))))
+}
+
+fn builtin_is_string(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::String(_))))
+}
+
+fn builtin_is_keyword(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Keyword(_))))
+}
+
+fn builtin_is_nil(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Nil)))
 }
 
 fn builtin_is_list(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 1 {
-        return Err("list? expects exactly 1 argument".to_string());
+    Ok(Expr::Bool(matches!(&args[0], Expr::List(_))))
+}
+
+fn builtin_is_fn(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Function(_))))
+}
+
+fn builtin_is_builtin(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::BuiltinFunction(_))))
+}
+
+fn builtin_is_bool(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Bool(_))))
+}
+
+/// `(type-of x)` is a keyword naming the type of the value, the one its
+/// predicate checks for, such as `:integer` for `int?` or `:function` for
+/// `fn?`.
+fn builtin_type_of(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let name = match &args[0] {
+        Expr::Symbol(_) => "symbol",
+        Expr::Keyword(_) => "keyword",
+        Expr::String(_) => "string",
+        Expr::Char(_) => "char",
+        Expr::Int(_) | Expr::BigInt(_) => "integer",
+        Expr::Ratio(_) => "ratio",
+        Expr::Number(_) => "float",
+        Expr::Bool(_) => "bool",
+        Expr::Nil => "nil",
+        Expr::List(_) => "list",
+        Expr::Vector(_) => "vector",
+        Expr::Map(_) => "map",
+        Expr::Set(_) => "set",
+        Expr::Function(_) => "function",
+        Expr::BuiltinFunction(_) => "builtin",
+        Expr::External(_) => "handle",
+        Expr::Weak(_) => "weak-ref",
+        #[cfg(feature = "ndarray")]
+        Expr::Matrix(_) => "matrix",
+    };
+    Ok(Expr::Keyword(name.to_string()))
+}
+
+fn builtin_symbols(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::List(
+        scope.user_symbols().into_iter().map(Expr::Symbol).collect(),
+    ))
+}
+
+/// `(stack-depth)` is the number of function calls yet to return.
+fn builtin_stack_depth(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::int(scope.runtime().backtrace().len() as i64))
+}
+
+/// `(backtrace)` is the list of the names of the functions yet to return,
+/// innermost first, with `fn` for functions without a name.
+fn builtin_backtrace(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::List(
+        scope
+            .runtime()
+            .backtrace()
+            .iter()
+            .map(|function| Expr::symbol(function.name.as_deref().unwrap_or("fn")))
+            .collect(),
+    ))
+}
+
+fn builtin_compare(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Int(match args[0].compare(&args[1])? {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    }))
+}
+
+/// Whether every neighbouring pair of numbers is in the relation, as in
+/// `(< 1 2 3)`.
+fn chain(name: &str, args: &[Expr], related: fn(Ordering) -> bool) -> Result<Expr, String> {
+    expect_numbers(name, args)?;
+    // NaN is not in any relation, not even with itself
+    Ok(Expr::Bool(
+        args.windows(2)
+            .all(|pair| pair[0].compare(&pair[1]).is_ok_and(related)),
+    ))
+}
+
+fn builtin_numbers_equal(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    chain("=", args, Ordering::is_eq)
+}
+
+fn builtin_less(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    chain("<", args, Ordering::is_lt)
+}
+
+fn builtin_greater(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    chain(">", args, Ordering::is_gt)
+}
+
+fn builtin_less_or_equal(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    chain("<=", args, Ordering::is_le)
+}
+
+fn builtin_greater_or_equal(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    chain(">=", args, Ordering::is_ge)
+}
+
+/// `(eq? a b)` is whether the two values are the same, comparing lists
+/// element by element.
+fn builtin_is_eq(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(args[0] == args[1]))
+}
+
+/// A stable merge sort that stops at the first comparison error. The standard
+/// library sorts may panic when a comparator is not a total order, which a
+/// user-supplied comparator need not be.
+fn merge_sort(
+    list: Vec<Expr>,
+    compare: &mut dyn FnMut(&Expr, &Expr) -> Result<Ordering, String>,
+) -> Result<Vec<Expr>, String> {
+    if list.len() <= 1 {
+        return Ok(list);
     }
 
-    match &args[0] {
-        Expr::List(_) => Ok(Expr::Number(1.0)),
-        _ => Ok(Expr::Number(0.0)),
+    let mut left = list;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if compare(a, b)? == Ordering::Greater {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
     }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Sorts a list with `compare`, or with a comparator function returning a
+/// negative, zero, or positive number.
+fn builtin_sort(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let list = expect_list("sort", 1, &args[0])?.to_vec();
+
+    let sorted = merge_sort(list, &mut |a, b| match args.get(1) {
+        None => a.compare(b),
+        Some(comparator) => call(comparator, &[a.clone(), b.clone()], scope)
+            .and_then(|result| expect_number("sort comparator", 1, &result))
+            .map(|n| n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+    })?;
+
+    Ok(Expr::List(sorted))
 }
 
 fn builtin_let(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
-    if args.len() != 2 {
-        return Err("let expects exactly 2 arguments".to_string());
-    }
+    let bindings = expect_list("let", 1, &args[0])?
+        .iter()
+        .map(|expr| {
+            if let Expr::List(binding) = expr {
+                if binding.len() != 2 {
+                    return Err(message!("let-binding-length"));
+                }
 
-    let bindings = if let Expr::List(bindings) = &args[0] {
-        bindings
-            .iter()
-            .map(|expr| {
-                if let Expr::List(binding) = expr {
-                    if binding.len() != 2 {
-                        return Err("Binding must be a list of length 2".to_string());
-                    }
-
-                    let name = if let Expr::Symbol(name) = &binding[0] {
-                        name
-                    } else {
-                        return Err("Binding name must be a symbol".to_string());
-                    };
-
-                    Ok((name.clone(), binding[1].clone()))
+                let name = if let Expr::Symbol(name) = &binding[0] {
+                    name
                 } else {
-                    Err("Binding must be a list".to_string())
-                }
-            })
-            .collect::<Result<Vec<(String, Expr)>, String>>()?
-    } else {
-        return Err("Bindings must be a list".to_string());
-    };
+                    return Err(message!("let-binding-name", actual = binding[0].describe()));
+                };
 
-    let mut new_scope = Scope::with_parent(Rc::new(scope.clone()));
+                Ok((name.clone(), binding[1].clone()))
+            } else {
+                Err(message!("let-binding", actual = expr.describe()))
+            }
+        })
+        .collect::<Result<Vec<(String, Expr)>, String>>()?;
+
+    let mut new_scope = Scope::with_parent(scope);
 
     for (name, value) in bindings {
         new_scope.set_variable(name, eval(&value, scope)?);
@@ -271,102 +713,587 @@ fn builtin_let(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
 }
 
 fn builtin_cond(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
-    if args.is_empty() {
-        return Err("cond expects at least 1 argument".to_string());
-    }
-
-    for arg in args {
+    for (i, arg) in args.iter().enumerate() {
         if let Expr::List(list) = arg {
             if list.len() != 2 {
-                return Err("cond clause must be a list of length 2".to_string());
+                return Err(message!("cond-clause-length"));
             }
 
-            let condition = eval(&list[0], scope)?;
-
-            match condition {
-                Expr::Number(n) => {
-                    if n != 0.0 {
-                        return eval(&list[1], scope);
-                    }
-                }
-                _ => return Err("Condition must be a number".to_string()),
+            if eval(&list[0], scope)?.is_truthy() {
+                return eval(&list[1], scope);
             }
         } else {
-            return Err("cond clause must be a list".to_string());
+            return Err(type_error("cond", "a clause list", i + 1, arg));
+        }
+    }
+
+    Err(message!("no-cond-match"))
+}
+
+fn builtin_now(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Number(scope.runtime().now()))
+}
+
+/// Evaluates its argument, returning the elapsed milliseconds and the value.
+fn builtin_time(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let start = scope.runtime().now();
+    let value = eval(&args[0], scope)?;
+    let elapsed = scope.runtime().now() - start;
+
+    Ok(Expr::List(vec![Expr::Number(elapsed), value]))
+}
+
+/// A float in `[0, 1)`, or with an argument `n` an integer in `[0, n)`.
+fn builtin_rand(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let random = scope.runtime().random();
+    match args.first() {
+        None => Ok(Expr::Number(random)),
+        Some(arg) => {
+            let n = expect_number("rand", 1, arg)?;
+            if n.is_nan() || n < 1.0 {
+                return Err(type_error("rand", "a number of at least 1", 1, arg));
+            }
+            Ok(Expr::Int((random * n.floor()).floor() as i64))
+        }
+    }
+}
+
+/// `(explain form)` evaluates the form and returns the reductions along the
+/// way as `(before after)` pairs, innermost first.
+fn builtin_explain(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let runtime = scope.runtime().clone();
+    let previous = runtime.start_explaining();
+    let result = eval(&args[0], scope);
+    let steps = runtime.finish_explaining(previous);
+    result?;
+
+    Ok(Expr::List(
+        steps
+            .into_iter()
+            .map(|step| Expr::List(vec![step.before, step.after]))
+            .collect(),
+    ))
+}
+
+/// `(deftag name reader)` reads `#name form` as the result of calling the
+/// reader on the form. With a printer as well, values for which it returns
+/// something other than nil or the empty list print as `#name` followed by
+/// that, so that they read back the same.
+fn builtin_deftag(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let name = expect_symbol("deftag", 1, &args[0])?;
+    let reader = eval(&args[1], scope)?;
+    let printer = match args.get(2) {
+        Some(printer) => Some(eval(printer, scope)?),
+        None => None,
+    };
+
+    let runtime = scope.runtime().clone();
+    let caller = scope.clone();
+    runtime.reader().add_tag(name, move |form| {
+        call(&reader, &[form], &mut caller.clone())
+    })?;
+    if let Some(printer) = printer {
+        let caller = scope.clone();
+        runtime.reader().add_printer(name, move |value| {
+            // A failing printer leaves the value printed as usual
+            match call(&printer, std::slice::from_ref(value), &mut caller.clone()) {
+                Ok(Expr::Nil) => None,
+                Ok(Expr::List(list)) if list.is_empty() => None,
+                Ok(form) => Some(form),
+                Err(_) => None,
+            }
+        })?;
+    }
+
+    Ok(Expr::Symbol(name.to_string()))
+}
+
+/// `(read-string s)` is the expression written in the string, unevaluated,
+/// read with the same literal syntax as source code.
+fn builtin_read_string(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("read-string", 1, &args[0])?;
+    let runtime = scope.runtime();
+    let reader = runtime.reader().clone();
+    parse_with_reader(s, &reader, runtime.config.max_nesting_depth).map(|(expr, _)| expr)
+}
+
+/// `(version)` is the interpreter's version.
+fn builtin_version(_: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::string(VERSION))
+}
+
+/// Writes a value for people to read: strings and characters as they are
+/// and anything else as it prints.
+fn display(arg: &Expr, out: &mut String) {
+    match arg {
+        Expr::String(s) => out.push_str(s),
+        Expr::Char(c) => out.push(*c),
+        _ => out.push_str(&expr_to_string(arg)),
+    }
+}
+
+/// `(str x ...)` joins its arguments into one string, as `display` writes
+/// them.
+fn builtin_str(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut out = String::new();
+    for arg in args {
+        display(arg, &mut out);
+    }
+    Ok(Expr::String(out))
+}
+
+/// The arguments as `display` writes them, separated by spaces.
+fn print_text(args: &[Expr]) -> String {
+    let mut out = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        display(arg, &mut out);
+    }
+    out
+}
+
+/// `(display x)` writes the value to the output, strings without quotes.
+fn builtin_display(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let mut out = String::new();
+    display(&args[0], &mut out);
+    scope.runtime().write(&out);
+    Ok(Expr::Nil)
+}
+
+/// `(print x ...)` writes its arguments to the output as `display` does,
+/// separated by spaces.
+fn builtin_print(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    scope.runtime().write(&print_text(args));
+    Ok(Expr::Nil)
+}
+
+/// `(println x ...)` is `print` followed by a newline.
+fn builtin_println(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    scope.runtime().write(&(print_text(args) + "\n"));
+    Ok(Expr::Nil)
+}
+
+/// `(str-len s)` is the number of characters in the string.
+fn builtin_str_len(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("str-len", 1, &args[0])?;
+    Ok(Expr::Int(s.chars().count() as i64))
+}
+
+/// `(char->int c)` is the character's Unicode code point.
+fn builtin_char_to_int(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let c = expect_char("char->int", 1, &args[0])?;
+    Ok(Expr::Int(c as i64))
+}
+
+/// `(int->char n)` is the character with the Unicode code point.
+fn builtin_int_to_char(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    match &args[0] {
+        Expr::Int(n) => u32::try_from(*n).ok().and_then(char::from_u32),
+        _ => None,
+    }
+    .map(Expr::Char)
+    .ok_or_else(|| type_error("int->char", "a Unicode code point", 1, &args[0]))
+}
+
+/// `(string->list s)` is the list of the string's characters.
+fn builtin_string_to_list(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("string->list", 1, &args[0])?;
+    Ok(Expr::List(s.chars().map(Expr::Char).collect()))
+}
+
+/// `(list->string chars)` joins a list of characters into a string.
+fn builtin_list_to_string(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let list = expect_list_or_nil("list->string", 1, &args[0])?;
+    list.iter()
+        .map(|item| match item {
+            Expr::Char(c) => Ok(*c),
+            _ => Err(type_error(
+                "list->string",
+                "a list of characters",
+                1,
+                &args[0],
+            )),
+        })
+        .collect::<Result<String, _>>()
+        .map(Expr::String)
+}
+
+/// `(str-concat s ...)` joins strings, refusing anything else.
+fn builtin_str_concat(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut out = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        out.push_str(expect_string("str-concat", i + 1, arg)?);
+    }
+    Ok(Expr::String(out))
+}
+
+/// `(builtins)` lists every builtin as a `{:name n :kind k :min-args n
+/// :max-args n}` map, with `nil` as the maximum of those taking any number of
+/// arguments.
+fn builtin_builtins(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let field = |key: &str, value: Expr| (MapKey::keyword(key), value);
+    Ok(Expr::List(
+        scope
+            .builtins()
+            .into_iter()
+            .map(|builtin| {
+                let kind = match builtin.kind {
+                    BuiltinKind::Eager => "eager",
+                    BuiltinKind::SpecialForm => "special-form",
+                };
+                let max = match builtin.arity.max() {
+                    Some(max) => Expr::Int(max as i64),
+                    None => Expr::Nil,
+                };
+                Expr::map(Map::from([
+                    field("name", Expr::Symbol(builtin.name)),
+                    field("kind", Expr::keyword(kind)),
+                    field("min-args", Expr::Int(builtin.arity.min() as i64)),
+                    field("max-args", max),
+                ]))
+            })
+            .collect(),
+    ))
+}
+
+#[rustfmt::skip]
+const BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("+",                 builtin_add,                 BuiltinKind::Eager,       Arity::AtLeast(0)),
+    ("apply",             builtin_apply,               BuiltinKind::Eager,       Arity::Exact(2)),
+    ("list",              builtin_list,                BuiltinKind::Eager,       Arity::AtLeast(0)),
+    ("eval",              builtin_eval,                BuiltinKind::Eager,       Arity::Range(1, 2)),
+    ("fn",                builtin_fn,                  BuiltinKind::SpecialForm, Arity::Exact(2)),
+    ("quote",             builtin_quote,               BuiltinKind::SpecialForm, Arity::Exact(1)),
+    ("def",               builtin_def,                 BuiltinKind::SpecialForm, Arity::Range(2, 3)),
+    ("defn",              builtin_defn,                BuiltinKind::SpecialForm, Arity::Range(3, 5)),
+    ("doc",               builtin_doc,                 BuiltinKind::SpecialForm, Arity::Exact(1)),
+    ("if",                builtin_if,                  BuiltinKind::SpecialForm, Arity::Exact(3)),
+    ("not",               builtin_not,                 BuiltinKind::Eager,       Arity::Exact(1)),
+    ("number?",           builtin_is_number,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("symbol?",           builtin_is_symbol,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("string?",           builtin_is_string,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("list?",             builtin_is_list,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("fn?",               builtin_is_fn,               BuiltinKind::Eager,       Arity::Exact(1)),
+    ("builtin?",          builtin_is_builtin,          BuiltinKind::Eager,       Arity::Exact(1)),
+    ("bool?",             builtin_is_bool,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("type-of",           builtin_type_of,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("symbols",           builtin_symbols,             BuiltinKind::Eager,       Arity::Exact(0)),
+    ("stack-depth",       builtin_stack_depth,         BuiltinKind::Eager,       Arity::Exact(0)),
+    ("backtrace",         builtin_backtrace,           BuiltinKind::Eager,       Arity::Exact(0)),
+    ("compare",           builtin_compare,             BuiltinKind::Eager,       Arity::Exact(2)),
+    ("sort",              builtin_sort,                BuiltinKind::Eager,       Arity::Range(1, 2)),
+    ("let",               builtin_let,                 BuiltinKind::SpecialForm, Arity::Exact(2)),
+    ("cond",              builtin_cond,                BuiltinKind::SpecialForm, Arity::AtLeast(1)),
+    ("now",               builtin_now,                 BuiltinKind::Eager,       Arity::Exact(0)),
+    ("time",              builtin_time,                BuiltinKind::SpecialForm, Arity::Exact(1)),
+    ("rand",              builtin_rand,                BuiltinKind::Eager,       Arity::Range(0, 1)),
+    ("defspec",           builtin_defspec,             BuiltinKind::SpecialForm, Arity::Exact(2)),
+    ("validate",          builtin_validate,            BuiltinKind::Eager,       Arity::Exact(2)),
+    ("select",            builtin_select,              BuiltinKind::Eager,       Arity::Exact(2)),
+    ("find-all",          builtin_find_all,            BuiltinKind::Eager,       Arity::Exact(2)),
+    ("postwalk",          builtin_postwalk,            BuiltinKind::Eager,       Arity::Exact(2)),
+    ("prewalk",           builtin_prewalk,             BuiltinKind::Eager,       Arity::Exact(2)),
+    ("rewrite",           builtin_rewrite,             BuiltinKind::Eager,       Arity::Exact(2)),
+    ("explain",           builtin_explain,             BuiltinKind::SpecialForm, Arity::Exact(1)),
+    ("deftag",            builtin_deftag,              BuiltinKind::SpecialForm, Arity::Range(2, 3)),
+    ("edn-read",          builtin_edn_read,            BuiltinKind::Eager,       Arity::Exact(1)),
+    ("edn-write",         builtin_edn_write,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("read-string",       builtin_read_string,         BuiltinKind::Eager,       Arity::Exact(1)),
+    ("version",           builtin_version,             BuiltinKind::Eager,       Arity::Exact(0)),
+    ("builtins",          builtin_builtins,            BuiltinKind::Eager,       Arity::Exact(0)),
+    ("url-parse",         builtin_url_parse,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("url-encode",        builtin_url_encode,          BuiltinKind::Eager,       Arity::Exact(1)),
+    ("url-decode",        builtin_url_decode,          BuiltinKind::Eager,       Arity::Exact(1)),
+    ("query-string->map", builtin_query_string_to_map, BuiltinKind::Eager,       Arity::Exact(1)),
+    ("format-number",     builtin_format_number,       BuiltinKind::Eager,       Arity::Range(1, 2)),
+    ("parse-number",      builtin_parse_number,        BuiltinKind::Eager,       Arity::Range(1, 2)),
+    ("str",               builtin_str,                 BuiltinKind::Eager,       Arity::AtLeast(0)),
+    ("str-len",           builtin_str_len,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("str-concat",        builtin_str_concat,          BuiltinKind::Eager,       Arity::AtLeast(0)),
+    ("display",           builtin_display,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("print",             builtin_print,               BuiltinKind::Eager,       Arity::AtLeast(0)),
+    ("println",           builtin_println,             BuiltinKind::Eager,       Arity::AtLeast(0)),
+    ("plot",              builtin_plot,                BuiltinKind::Eager,       Arity::Exact(1)),
+    ("forward",           builtin_forward,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("turn",              builtin_turn,                BuiltinKind::Eager,       Arity::Exact(1)),
+    ("pen-up",            builtin_pen_up,              BuiltinKind::Eager,       Arity::Exact(0)),
+    ("pen-down",          builtin_pen_down,            BuiltinKind::Eager,       Arity::Exact(0)),
+    ("tone",              builtin_tone,                BuiltinKind::Eager,       Arity::Exact(2)),
+    ("at",                builtin_at,                  BuiltinKind::SpecialForm, Arity::Exact(2)),
+    ("nil?",              builtin_is_nil,              BuiltinKind::Eager,       Arity::Exact(1)),
+    ("-",                 builtin_subtract,            BuiltinKind::Eager,       Arity::AtLeast(1)),
+    ("*",                 builtin_multiply,            BuiltinKind::Eager,       Arity::AtLeast(0)),
+    ("/",                 builtin_divide,              BuiltinKind::Eager,       Arity::AtLeast(1)),
+    ("mod",               builtin_mod,                 BuiltinKind::Eager,       Arity::Exact(2)),
+    ("abs",               builtin_abs,                 BuiltinKind::Eager,       Arity::Exact(1)),
+    ("min",               builtin_min,                 BuiltinKind::Eager,       Arity::AtLeast(1)),
+    ("max",               builtin_max,                 BuiltinKind::Eager,       Arity::AtLeast(1)),
+    ("store-get",         builtin_store_get,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("store-set!",        builtin_store_set,           BuiltinKind::Eager,       Arity::Exact(2)),
+    ("=",                 builtin_numbers_equal,       BuiltinKind::Eager,       Arity::AtLeast(1)),
+    ("<",                 builtin_less,                BuiltinKind::Eager,       Arity::AtLeast(1)),
+    (">",                 builtin_greater,             BuiltinKind::Eager,       Arity::AtLeast(1)),
+    ("<=",                builtin_less_or_equal,       BuiltinKind::Eager,       Arity::AtLeast(1)),
+    (">=",                builtin_greater_or_equal,    BuiltinKind::Eager,       Arity::AtLeast(1)),
+    ("eq?",               builtin_is_eq,               BuiltinKind::Eager,       Arity::Exact(2)),
+    ("with-rollback",     builtin_with_rollback,       BuiltinKind::SpecialForm, Arity::AtLeast(1)),
+    ("with-redefs",       builtin_with_redefs,         BuiltinKind::SpecialForm, Arity::AtLeast(2)),
+    ("keyword?",          builtin_is_keyword,          BuiltinKind::Eager,       Arity::Exact(1)),
+    ("ns",                builtin_ns,                  BuiltinKind::SpecialForm, Arity::AtLeast(1)),
+    ("require",           builtin_require,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("and",               builtin_and,                 BuiltinKind::SpecialForm, Arity::AtLeast(0)),
+    ("or",                builtin_or,                  BuiltinKind::SpecialForm, Arity::AtLeast(0)),
+    ("int?",              builtin_is_int,              BuiltinKind::Eager,       Arity::Exact(1)),
+    ("float?",            builtin_is_float,            BuiltinKind::Eager,       Arity::Exact(1)),
+    ("defonce",           builtin_defonce,             BuiltinKind::SpecialForm, Arity::Range(2, 3)),
+    ("numerator",         builtin_numerator,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("denominator",       builtin_denominator,         BuiltinKind::Eager,       Arity::Exact(1)),
+    ("exact->inexact",    builtin_exact_to_inexact,    BuiltinKind::Eager,       Arity::Exact(1)),
+    ("char?",             builtin_is_char,             BuiltinKind::Eager,       Arity::Exact(1)),
+    ("char->int",         builtin_char_to_int,         BuiltinKind::Eager,       Arity::Exact(1)),
+    ("int->char",         builtin_int_to_char,         BuiltinKind::Eager,       Arity::Exact(1)),
+    ("string->list",      builtin_string_to_list,      BuiltinKind::Eager,       Arity::Exact(1)),
+    ("list->string",      builtin_list_to_string,      BuiltinKind::Eager,       Arity::Exact(1)),
+    ("open-file",         builtin_open_file,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("read-line",         builtin_read_line,           BuiltinKind::Eager,       Arity::Exact(1)),
+    ("read",              builtin_read,                BuiltinKind::Eager,       Arity::Exact(1)),
+    ("close",             builtin_close,               BuiltinKind::Eager,       Arity::Exact(1)),
+    ("with-open",         builtin_with_open,           BuiltinKind::SpecialForm, Arity::AtLeast(2)),
+    ("weak-ref",          builtin_weak_ref,            BuiltinKind::Eager,       Arity::Exact(1)),
+    ("deref-weak",        builtin_deref_weak,          BuiltinKind::Eager,       Arity::Exact(1)),
+    ("assert",            builtin_assert,              BuiltinKind::SpecialForm, Arity::Range(1, 2)),
+    ("assert-approx",     builtin_assert_approx,       BuiltinKind::Eager,       Arity::Range(2, 3)),
+    ("assert-matches",    builtin_assert_matches,      BuiltinKind::Eager,       Arity::Exact(2)),
+    ("deftest",           builtin_deftest,             BuiltinKind::SpecialForm, Arity::AtLeast(2)),
+    ("defbench",          builtin_defbench,            BuiltinKind::SpecialForm, Arity::AtLeast(2)),
+    ("use-fixture",       builtin_use_fixture,         BuiltinKind::Eager,       Arity::Range(2, 3)),
+    ("tail-recursive?",   builtin_is_tail_recursive,   BuiltinKind::Eager,       Arity::Exact(1)),
+];
+
+/// Builtins that reach outside the interpreter, writing files or persistent
+/// storage or loading code, whose calls the `audit` feature records.
+const SIDE_EFFECTING: &[&str] = &["plot", "require", "store-set!"];
+
+pub fn is_side_effecting(name: &str) -> bool {
+    SIDE_EFFECTING.contains(&name)
+}
+
+/// `(progn form...)` evaluates the forms in order, returning the last value.
+pub(crate) fn builtin_progn(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let mut value = Expr::Nil;
+    for arg in args {
+        value = eval(arg, scope)?;
+    }
+    Ok(value)
+}
+
+/// `(with-rollback form...)` evaluates the forms in order like `progn`, but
+/// keeps what they define only if none of them fails.
+fn builtin_with_rollback(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let mut overlay = Scope::overlay(scope);
+    let value = builtin_progn(args, &mut overlay)?;
+    overlay.commit();
+    Ok(value)
+}
+
+/// `(with-redefs ((name value)...) body...)` binds the global names to the
+/// values while the body runs, builtins included, and restores them after,
+/// even when the body fails. Unlike `let`, the new values are seen by every
+/// function looking the names up, such as to fake a builtin doing I/O in a
+/// test.
+fn builtin_with_redefs(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let bindings = expect_list("with-redefs", 1, &args[0])?;
+    let mut redefs = Vec::new();
+    for binding in bindings {
+        match binding {
+            Expr::List(pair) => match pair.as_slice() {
+                [Expr::Symbol(name), value] => redefs.push((name.clone(), eval(value, scope)?)),
+                _ => return Err(message!("with-redefs-binding", actual = binding.describe())),
+            },
+            _ => return Err(message!("with-redefs-binding", actual = binding.describe())),
         }
     }
 
-    Err("No cond clause matched".to_string())
+    let mut global = scope.global();
+    let saved: Vec<(String, Option<Expr>)> = redefs
+        .iter()
+        .map(|(name, _)| {
+            let value = global.is_bound(name).then(|| global.get_variable(name));
+            (name.clone(), value.flatten())
+        })
+        .collect();
+    for (name, value) in redefs {
+        global.set_variable(name, value);
+    }
+    let result = builtin_progn(&args[1..], scope);
+    for (name, value) in saved.into_iter().rev() {
+        match value {
+            Some(value) => global.set_variable(name, value),
+            None => {
+                global.remove_variable(&name);
+            }
+        }
+    }
+    result
+}
+
+/// Scheme's `define`: `(define name value)` or
+/// `(define (name params...) body...)`.
+fn builtin_define(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    match &args[0] {
+        Expr::List(signature) if !signature.is_empty() => {
+            let body = match &args[1..] {
+                [body] => body.clone(),
+                body => {
+                    let mut progn = vec![Expr::symbol("progn")];
+                    progn.extend_from_slice(body);
+                    Expr::List(progn)
+                }
+            };
+            let parameters = Expr::List(signature[1..].to_vec());
+            builtin_defn(&[signature[0].clone(), parameters, body], scope)
+        }
+        _ if args.len() > 3 => Err(arity_error("define", args.len(), Arity::Range(2, 3))),
+        _ => builtin_def(args, scope),
+    }
+}
+
+/// Names familiar from other lisps, registered when the `compat` feature is
+/// enabled, each standing for the builtin it is paired with.
+#[rustfmt::skip]
+const COMPAT_ALIASES: &[(&str, &str)] = &[
+    ("car",    "first"),
+    ("cdr",    "rest"),
+    ("lambda", "fn"),
+    ("setq",   "def"),
+];
+
+#[rustfmt::skip]
+const COMPAT_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("progn",  builtin_progn,  BuiltinKind::SpecialForm, Arity::AtLeast(0)),
+    ("define", builtin_define, BuiltinKind::SpecialForm, Arity::AtLeast(2)),
+];
+
+/// The names of all builtins, in registration order.
+/// The core builtins, including the sequence ones that used to live among
+/// them.
+fn core_builtins() -> impl Iterator<Item = &'static (&'static str, BuiltinFn, BuiltinKind, Arity)> {
+    BUILTINS.iter().chain(crate::seq::SEQ_BUILTINS)
+}
+
+pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+    core_builtins().map(|(name, ..)| *name)
 }
 
 pub fn initialize_global_scope(scope: &mut Scope) {
-    scope.set_variable(
-        "+".to_string(),
-        Expr::builtin_function("+", builtin_add, BuiltinKind::Eager),
-    );
-
-    scope.set_variable(
-        "apply".to_string(),
-        Expr::builtin_function("apply", builtin_apply, BuiltinKind::SpecialForm),
-    );
-
-    scope.set_variable(
-        "list".to_string(),
-        Expr::builtin_function("list", builtin_list, BuiltinKind::Eager),
-    );
-
-    scope.set_variable(
-        "fn".to_string(),
-        Expr::builtin_function("fn", builtin_fn, BuiltinKind::SpecialForm),
-    );
-
-    scope.set_variable(
-        "quote".to_string(),
-        Expr::builtin_function("quote".to_string(), builtin_quote, BuiltinKind::SpecialForm),
-    );
-
-    scope.set_variable(
-        "def".to_string(),
-        Expr::builtin_function("def", builtin_def, BuiltinKind::SpecialForm),
-    );
-
-    scope.set_variable(
-        "if".to_string(),
-        Expr::builtin_function("if", builtin_if, BuiltinKind::SpecialForm),
-    );
-
-    scope.set_variable(
-        "first".to_string(),
-        Expr::builtin_function("first", builtin_first, BuiltinKind::Eager),
-    );
-
-    scope.set_variable(
-        "rest".to_string(),
-        Expr::builtin_function("rest", builtin_rest, BuiltinKind::Eager),
-    );
-
-    scope.set_variable(
-        "number?".to_string(),
-        Expr::builtin_function("number?", builtin_is_number, BuiltinKind::Eager),
-    );
-
-    scope.set_variable(
-        "symbol?".to_string(),
-        Expr::builtin_function("symbol?", builtin_is_symbol, BuiltinKind::Eager),
-    );
-
-    scope.set_variable(
-        "list?".to_string(),
-        Expr::builtin_function("let", builtin_is_list, BuiltinKind::Eager),
-    );
-
-    scope.set_variable(
-        "let".to_string(),
-        Expr::builtin_function("let", builtin_let, BuiltinKind::SpecialForm),
-    );
-
-    scope.set_variable(
-        "cond".to_string(),
-        Expr::builtin_function("let", builtin_cond, BuiltinKind::SpecialForm),
-    );
+    for (name, func, kind, arity) in BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    for (name, func, kind, arity) in crate::math::MATH_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+    for (name, value) in crate::math::MATH_CONSTANTS {
+        scope.set_variable(name.to_string(), Expr::Number(*value));
+    }
+
+    for (name, func, kind, arity) in crate::bits::BIT_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    for (name, func, kind, arity) in crate::map::MAP_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    for (name, func, kind, arity) in crate::seq::SEQ_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    for (name, func, kind, arity) in crate::set::SET_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    for (name, func, kind, arity) in crate::vector::VECTOR_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    for (name, func, kind, arity) in crate::strings::STRING_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    #[cfg(feature = "hashing")]
+    for (name, func, kind, arity) in crate::hash::HASH_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    #[cfg(feature = "ids")]
+    for (name, func, kind, arity) in crate::ids::ID_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    for (name, func, kind, arity) in crate::regexp::REGEX_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    #[cfg(feature = "ndarray")]
+    for (name, func, kind, arity) in crate::matrix::MATRIX_BUILTINS {
+        scope.set_variable(
+            name.to_string(),
+            Expr::builtin_function(*name, *func, kind.clone(), *arity),
+        );
+    }
+
+    if scope.runtime().config.features.compat {
+        for (alias, name) in COMPAT_ALIASES {
+            let (_, func, kind, arity) = core_builtins()
+                .find(|(builtin, ..)| builtin == name)
+                .expect("compat aliases name existing builtins");
+            scope.set_variable(
+                alias.to_string(),
+                Expr::builtin_function(*alias, *func, kind.clone(), *arity),
+            );
+        }
+        for (name, func, kind, arity) in COMPAT_BUILTINS {
+            scope.set_variable(
+                name.to_string(),
+                Expr::builtin_function(*name, *func, kind.clone(), *arity),
+            );
+        }
+    }
 }
@@ -0,0 +1,145 @@
+//! Graphviz DOT output for expression trees and scope chains.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::interpreter::{Expr, Scope};
+use crate::parser::expr_to_string;
+
+/// Values in scope diagrams are cut short after this many characters.
+const VALUE_WIDTH: usize = 30;
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn truncate(text: String) -> String {
+    if text.chars().count() > VALUE_WIDTH {
+        let truncated: String = text.chars().take(VALUE_WIDTH).collect();
+        format!("{}...", truncated)
+    } else {
+        text
+    }
+}
+
+/// Draws an expression as a tree, with one node per list and atom.
+pub fn expr_to_dot(expr: &Expr) -> String {
+    fn node(expr: &Expr, next_id: &mut usize, out: &mut String) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        match expr {
+            Expr::List(items) if !items.is_empty() => {
+                writeln!(out, "  n{} [label=\"list\", shape=point];", id).unwrap();
+                for item in items {
+                    let child = node(item, next_id, out);
+                    writeln!(out, "  n{} -> n{};", id, child).unwrap();
+                }
+            }
+            _ => {
+                let label = escape(&expr_to_string(expr));
+                writeln!(out, "  n{} [label=\"{}\"];", id, label).unwrap();
+            }
+        }
+        id
+    }
+
+    let mut out = String::from("digraph expr {\n  node [shape=box];\n");
+    node(expr, &mut 0, &mut out);
+    out.push_str("}\n");
+    out
+}
+
+/// Draws the chain of scopes from `scope` up to the builtins, along with
+/// the scopes captured by any functions bound along the way.
+pub fn scope_to_dot(scope: &Scope) -> String {
+    let mut out = String::from("digraph scopes {\n  node [shape=box];\n");
+    let mut seen = HashSet::new();
+    let mut pending = vec![scope.clone()];
+
+    while let Some(scope) = pending.pop() {
+        if !seen.insert(scope.id()) {
+            continue;
+        }
+
+        let bindings = scope.bindings();
+        let label = match scope.parent() {
+            None => format!("builtins ({})", bindings.len()),
+            Some(_) => {
+                let title = if scope.is_global() { "global" } else { "scope" };
+                let mut label = format!("{}\\l", title);
+                for (name, value) in &bindings {
+                    let line = format!("{} = {}", name, truncate(expr_to_string(value)));
+                    label.push_str(&escape(&line));
+                    label.push_str("\\l");
+                }
+                label
+            }
+        };
+        writeln!(out, "  s{} [label=\"{}\"];", scope.id(), label).unwrap();
+
+        if let Some(parent) = scope.parent() {
+            writeln!(out, "  s{} -> s{};", scope.id(), parent.id()).unwrap();
+            pending.push(parent);
+        }
+        for (name, value) in &bindings {
+            if let Expr::Function(func) = value {
+                writeln!(
+                    out,
+                    "  s{} -> s{} [label=\"{}\", style=dashed];",
+                    scope.id(),
+                    func.closure.id(),
+                    escape(name)
+                )
+                .unwrap();
+                pending.push(func.closure.clone());
+            }
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::parser::parse;
+
+    #[test]
+    fn expr_tree() {
+        assert_eq!(
+            expr_to_dot(&parse("(+ 1 (f \"a\"))").unwrap()),
+            "digraph expr {
+  node [shape=box];
+  n0 [label=\"list\", shape=point];
+  n1 [label=\"+\"];
+  n0 -> n1;
+  n2 [label=\"1\"];
+  n0 -> n2;
+  n3 [label=\"list\", shape=point];
+  n4 [label=\"f\"];
+  n3 -> n4;
+  n5 [label=\"\\\"a\\\"\"];
+  n3 -> n5;
+  n0 -> n3;
+}
+"
+        );
+    }
+
+    #[test]
+    fn scope_chain() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(def make (fn (x) (let ((y x)) (fn () y))))")
+            .unwrap();
+        engine.eval("(def get (make 1))").unwrap();
+        let dot = engine.scope_to_dot();
+
+        assert!(dot.contains("global\\lget = <function get>\\lmake = <function make>\\l"));
+        assert!(dot.contains("scope\\ly = 1\\l"));
+        assert!(dot.contains("[label=\"get\", style=dashed]"));
+        assert!(dot.contains("builtins ("));
+    }
+}
@@ -0,0 +1,472 @@
+//! Reading and writing [EDN](https://github.com/edn-format/edn), for
+//! exchanging data with Clojure tooling.
+//!
+//! Only the part of EDN this interpreter has values for is supported: map
+//! keys must be keywords, strings or numbers, and sets are rejected.
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+use crate::builtins::expect_text;
+use crate::interpreter::{sorted_entries, sorted_members, Expr, Map, MapKey, Scope, Set};
+use crate::messages::message;
+use crate::parser::{
+    char_name, expr_to_string, format_float, read_char, ReaderConfig, DEFAULT_MAX_DEPTH,
+};
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}\",;".contains(c)
+}
+
+/// Characters EDN allows in symbols besides letters and digits.
+const EDN_SYMBOL_PUNCTUATION: &str = ".*+!-_?$%&=<>/:#'";
+
+fn is_edn_symbol(text: &str) -> bool {
+    let mut chars = text.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    !first.is_ascii_digit()
+        && first != '#'
+        && first != ':'
+        && first != '\''
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || EDN_SYMBOL_PUNCTUATION.contains(c))
+        && !matches!(text, "nil" | "true" | "false")
+        && !(text.len() > 1
+            && "+-.".contains(first)
+            && text[1..].starts_with(|c: char| c.is_ascii_digit()))
+}
+
+fn write(expr: &Expr, out: &mut String) -> Result<(), String> {
+    match expr {
+        Expr::Number(n) if n.is_nan() => out.push_str("##NaN"),
+        Expr::Number(n) if n.is_infinite() => {
+            out.push_str(if *n > 0.0 { "##Inf" } else { "##-Inf" })
+        }
+        Expr::Int(n) => out.push_str(&n.to_string()),
+        Expr::BigInt(n) => out.push_str(&n.to_string()),
+        Expr::Ratio(n) => out.push_str(&n.to_string()),
+        Expr::Number(n) => out.push_str(&format_float(*n)),
+        Expr::Bool(b) => out.push_str(&b.to_string()),
+        Expr::Nil => out.push_str("nil"),
+        Expr::Char(c) => {
+            out.push('\\');
+            out.push_str(&char_name(*c));
+        }
+        Expr::Keyword(name) => {
+            out.push(':');
+            out.push_str(name);
+        }
+        Expr::Symbol(s) if is_edn_symbol(s) => out.push_str(s),
+        Expr::Symbol(s) | Expr::String(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    '\r' => out.push_str("\\r"),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        Expr::List(list) => write_items('(', list, ')', out)?,
+        Expr::Vector(items) => write_items('[', items, ']', out)?,
+        Expr::Map(map) => {
+            let items: Vec<Expr> = sorted_entries(map)
+                .into_iter()
+                .flat_map(|(key, value)| [key.expr().clone(), value.clone()])
+                .collect();
+            write_items('{', &items, '}', out)?
+        }
+        Expr::Set(set) => {
+            out.push('#');
+            let items: Vec<Expr> = sorted_members(set)
+                .into_iter()
+                .map(|member| member.expr().clone())
+                .collect();
+            write_items('{', &items, '}', out)?
+        }
+        #[cfg(feature = "ndarray")]
+        Expr::Matrix(matrix) => {
+            out.push_str("#mat ");
+            write(&crate::matrix::to_rows(matrix), out)?;
+        }
+        Expr::Function(_) | Expr::BuiltinFunction(_) | Expr::External(_) | Expr::Weak(_) => {
+            return Err(message!("edn-unwritable", value = expr.describe()))
+        }
+    }
+    Ok(())
+}
+
+fn write_items(open: char, items: &[Expr], close: char, out: &mut String) -> Result<(), String> {
+    out.push(open);
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        write(item, out)?;
+    }
+    out.push(close);
+    Ok(())
+}
+
+/// Writes the value as EDN. Symbols EDN would not read back as the same
+/// symbol are written as strings, and so read back as strings.
+pub fn to_edn(expr: &Expr) -> Result<String, String> {
+    let mut out = String::new();
+    write(expr, &mut out)?;
+    Ok(out)
+}
+
+fn read_map(items: Vec<Expr>) -> Result<Expr, String> {
+    if !items.len().is_multiple_of(2) {
+        return Err(message!("edn-map-odd"));
+    }
+    let mut map = Map::with_capacity(items.len() / 2);
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        let printed = expr_to_string(&key);
+        let key = MapKey::new(key).ok_or_else(|| message!("edn-map-key", key = printed))?;
+        if map.insert(key, value).is_some() {
+            return Err(message!("edn-duplicate-key", key = printed));
+        }
+    }
+    Ok(Expr::map(map))
+}
+
+fn read_set(items: Vec<Expr>) -> Result<Expr, String> {
+    let mut set = Set::with_capacity(items.len());
+    for item in items {
+        let printed = expr_to_string(&item);
+        let member =
+            MapKey::new(item).ok_or_else(|| message!("edn-set-member", member = printed))?;
+        if !set.insert(member) {
+            return Err(message!("edn-duplicate-member", member = printed));
+        }
+    }
+    Ok(Expr::set(set))
+}
+
+struct EdnReader<'a> {
+    input: &'a str,
+    offset: usize,
+    tags: &'a ReaderConfig,
+}
+
+impl EdnReader<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.bump();
+                }
+            } else if c.is_whitespace() || c == ',' {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// The text up to the next delimiter.
+    fn token(&mut self) -> &str {
+        let start = self.offset;
+        while self.peek().is_some_and(|c| !is_delimiter(c)) {
+            self.bump();
+        }
+        &self.input[start..self.offset]
+    }
+
+    fn string(&mut self) -> Result<Expr, String> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(Expr::String(text)),
+                Some('\\') => text.push(match self.bump() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some(c @ ('"' | '\\')) => c,
+                    Some(c) => return Err(message!("edn-invalid-escape", character = c)),
+                    None => break,
+                }),
+                Some(c) => text.push(c),
+                None => break,
+            }
+        }
+        Err(message!("edn-unclosed-string"))
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let token = self.token();
+        let expr = match token {
+            "nil" => Expr::Nil,
+            "true" => Expr::Bool(true),
+            "false" => Expr::Bool(false),
+            _ if !is_edn_symbol(token) && !token.starts_with(':') => {
+                let digits = token.strip_suffix(['N', 'M']).unwrap_or(token);
+                let invalid = |_| message!("edn-invalid", text = token);
+                if digits.contains('/') {
+                    Expr::rational(digits.parse::<BigRational>().map_err(invalid)?)
+                } else {
+                    match (digits.parse::<BigInt>(), digits.parse::<f64>()) {
+                        (Ok(n), _) if !token.ends_with('M') => Expr::integer(n),
+                        (_, Ok(n)) => Expr::Number(n),
+                        _ => return Err(message!("edn-invalid", text = token)),
+                    }
+                }
+            }
+            _ if token.len() > 1 && token.starts_with(':') => Expr::keyword(&token[1..]),
+            _ => Expr::symbol(token),
+        };
+        Ok(expr)
+    }
+
+    fn character(&mut self) -> Result<Expr, String> {
+        // The first character is taken even if it is a delimiter, as in `\(`
+        let first = self
+            .bump()
+            .ok_or_else(|| message!("edn-missing-character"))?;
+        let text = format!("{}{}", first, self.token());
+        match read_char(&text) {
+            Some(c) => Ok(Expr::Char(c)),
+            None => Err(message!("edn-invalid-character", text = text)),
+        }
+    }
+
+    /// Reads the value after a `#`.
+    fn dispatch(&mut self, depth: usize) -> Result<Option<Expr>, String> {
+        match self.peek() {
+            Some('_') => {
+                self.bump();
+                self.value(depth)?;
+                Ok(None)
+            }
+            Some('{') => {
+                if depth >= DEFAULT_MAX_DEPTH {
+                    return Err(message!("edn-nested-too-deep", depth = DEFAULT_MAX_DEPTH));
+                }
+                self.bump();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some('}') {
+                        self.bump();
+                        return read_set(items).map(Some);
+                    }
+                    items.extend(self.element(depth + 1)?);
+                }
+            }
+            Some('#') => {
+                self.bump();
+                match self.token() {
+                    "Inf" => Ok(Some(Expr::Number(f64::INFINITY))),
+                    "-Inf" => Ok(Some(Expr::Number(f64::NEG_INFINITY))),
+                    "NaN" => Ok(Some(Expr::Number(f64::NAN))),
+                    other => Err(message!("edn-invalid", text = format!("##{}", other))),
+                }
+            }
+            _ => {
+                let tag = self.token().to_string();
+                let handler = self
+                    .tags
+                    .tag(&tag)
+                    .ok_or_else(|| message!("edn-unknown-tag", tag = tag))?;
+                let value = self.value(depth)?;
+                handler(value).map(Some)
+            }
+        }
+    }
+
+    /// Reads the next value, or `None` for a discarded one.
+    fn element(&mut self, depth: usize) -> Result<Option<Expr>, String> {
+        self.skip_whitespace();
+        let c = self.peek().ok_or_else(|| message!("edn-unexpected-end"))?;
+        match c {
+            '(' | '[' | '{' => {
+                if depth >= DEFAULT_MAX_DEPTH {
+                    return Err(message!("edn-nested-too-deep", depth = DEFAULT_MAX_DEPTH));
+                }
+                self.bump();
+                let close = match c {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                };
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(close) {
+                        self.bump();
+                        return match c {
+                            '(' => Ok(Some(Expr::List(items))),
+                            '[' => Ok(Some(Expr::vector(items))),
+                            _ => read_map(items).map(Some),
+                        };
+                    }
+                    items.extend(self.element(depth + 1)?);
+                }
+            }
+            ')' | ']' | '}' => Err(message!("edn-unexpected", character = c)),
+            '"' => {
+                self.bump();
+                self.string().map(Some)
+            }
+            '\\' => {
+                self.bump();
+                self.character().map(Some)
+            }
+            '#' => {
+                self.bump();
+                self.dispatch(depth)
+            }
+            _ => self.atom().map(Some),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Expr, String> {
+        loop {
+            if let Some(value) = self.element(depth)? {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// Reads a single EDN value, using the reader's tags for tagged literals.
+pub fn from_edn_with(input: &str, tags: &ReaderConfig) -> Result<Expr, String> {
+    let mut reader = EdnReader {
+        input,
+        offset: 0,
+        tags,
+    };
+    let value = reader.value(0)?;
+    reader.skip_whitespace();
+    if reader.offset < input.len() {
+        return Err(message!(
+            "edn-trailing",
+            character = reader.token().chars().next().unwrap_or_default()
+        ));
+    }
+    Ok(value)
+}
+
+/// Reads a single EDN value.
+pub fn from_edn(input: &str) -> Result<Expr, String> {
+    from_edn_with(input, &ReaderConfig::default())
+}
+
+/// `(edn-read text)` reads the EDN in the text, with the tags defined by
+/// `deftag`.
+pub fn builtin_edn_read(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let text = expect_text("edn-read", 1, &args[0])?;
+    let tags = scope.runtime().reader().clone();
+    from_edn_with(text, &tags)
+}
+
+/// `(edn-write value)` returns the value written as EDN.
+pub fn builtin_edn_write(args: &[Expr], _scope: &mut Scope) -> Result<Expr, String> {
+    to_edn(&args[0]).map(Expr::String)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::parser::parse;
+
+    #[test]
+    fn read_edn() {
+        assert_eq!(
+            from_edn("[1 -2.5 42N 1e3, :kw sym/ns \"two words\" nil true #_ skipped \\a]"),
+            parse("[1 -2.5 42 1000.0 :kw sym/ns \"two words\" nil true #\\a]")
+        );
+        assert_eq!(from_edn("[\\( \\space]"), parse("[#\\( #\\space]"));
+        assert_eq!(from_edn("(a ; comment\n (b))"), parse("(a (b))"));
+        assert_eq!(from_edn("##-Inf"), Ok(Expr::number(f64::NEG_INFINITY)));
+        assert_eq!(
+            from_edn("{:a [1], \"b\" nil 2 {}}"),
+            parse("{:a [1] \"b\" nil 2 {}}")
+        );
+        assert_eq!(
+            from_edn("{[1] 2}"),
+            Err("EDN map keys must be keywords, strings or numbers, got [1]".to_string())
+        );
+        assert_eq!(
+            from_edn("{:a 1 :a 2}"),
+            Err("Duplicate key :a in EDN map".to_string())
+        );
+        assert_eq!(from_edn("#{1 :a \"b\"}"), parse("#{:a 1 \"b\"}"));
+        assert_eq!(
+            from_edn("#{1 1}"),
+            Err("Duplicate member 1 in EDN set".to_string())
+        );
+        assert_eq!(
+            from_edn("#inst \"2024-01-01\""),
+            Err("Unknown reader tag '#inst'".to_string())
+        );
+        assert_eq!(
+            from_edn("1 2"),
+            Err("Unexpected '2' after the EDN value".to_string())
+        );
+
+        let mut tags = ReaderConfig::default();
+        tags.add_tag("inst", |value| {
+            Ok(Expr::list(vec![Expr::symbol("inst"), value]))
+        })
+        .unwrap();
+        assert_eq!(
+            from_edn_with("#inst \"2024-01-01\"", &tags),
+            parse("(inst \"2024-01-01\")")
+        );
+    }
+
+    #[test]
+    fn write_edn() {
+        let value =
+            parse(r#"(1 2.5 :kw "two words" "\"q\"\n" #\a #\newline false nil ())"#).unwrap();
+        let edn = to_edn(&value).unwrap();
+        assert_eq!(
+            edn,
+            r#"(1 2.5 :kw "two words" "\"q\"\n" \a \newline false nil ())"#
+        );
+        assert_eq!(from_edn(&edn), Ok(value));
+        assert_eq!(
+            to_edn(&parse("(|two words| |nil| |1x|)").unwrap()),
+            Ok(r#"("two words" "nil" "1x")"#.to_string())
+        );
+
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval_to_string(r#"(edn-write (edn-read "[1 \"a b\" [c]]"))"#),
+            r#""[1 \"a b\" [c]]""#
+        );
+        assert_eq!(
+            engine.eval_to_string(r#"(edn-write {:b (list 1) :a "x"})"#),
+            r#""{:a \"x\" :b (1)}""#
+        );
+        assert_eq!(
+            engine.eval_to_string("(edn-write #{3 :c 1})"),
+            r##""#{1 3 :c}""##
+        );
+        assert_eq!(
+            engine.eval_to_string("(edn-write (list first))"),
+            "Error: Cannot write a builtin function as EDN"
+        );
+    }
+}
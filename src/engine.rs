@@ -0,0 +1,1979 @@
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::audio::{Schedule, Tone};
+use crate::builtins::is_side_effecting;
+use crate::dot;
+use crate::interpreter::{
+    call, collect_cycles, eval, Arity, BuiltinFunction, BuiltinKind, CapturedFrames, Expr,
+    Function, Scope,
+};
+use crate::machine::State;
+use crate::messages::message;
+use crate::module::ModuleCache;
+use crate::notebook::{symbols_in, CellOutput, Notebook};
+use crate::parser::{
+    expr_to_string, expr_to_string_with, parse_with_reader, ReaderConfig, Span, SpanTable,
+    DEFAULT_MAX_DEPTH,
+};
+use crate::plot::Chart;
+use crate::refactor::{check_rename, definitions, rename_symbol};
+use crate::store::{JsonFileStorage, MemoryStorage, Storage};
+use crate::syntax::Edit;
+use crate::testing::{run_benches, run_tests, BenchResult, TestFilter, TestResult, TestSuite};
+use crate::turtle::Turtle;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SandboxPolicy {
+    /// Builtins may reach outside the interpreter (files, environment, network).
+    Unrestricted,
+    /// Only builtins without host side effects are available.
+    Strict,
+}
+
+/// What `def` does when it would shadow a builtin.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redefinition {
+    Allow,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Features {
+    /// Print every builtin call and its arguments to stdout.
+    pub trace: bool,
+    /// Check the arguments of functions defined with parameter specs.
+    pub instrument: bool,
+    /// Register aliases familiar from other lisps, such as `car`, `lambda`
+    /// and `define`.
+    pub compat: bool,
+    /// Record every call to a side-effecting builtin in the audit log.
+    pub audit: bool,
+}
+
+impl Features {
+    fn enable(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "trace" => self.trace = true,
+            "instrument" => self.instrument = true,
+            "compat" => self.compat = true,
+            "audit" => self.audit = true,
+            _ => return Err(format!("Unknown feature '{}'", name)),
+        }
+        Ok(())
+    }
+
+    /// The names of the enabled features, as `RS_LISP_FEATURES` takes them.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        [
+            ("trace", self.trace),
+            ("instrument", self.instrument),
+            ("compat", self.compat),
+            ("audit", self.audit),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    /// Maximum number of evaluation steps per top-level form, `None` for no limit.
+    pub fuel_limit: Option<u64>,
+    /// Maximum nesting depth of `eval`.
+    pub recursion_limit: usize,
+    /// Printed results longer than this many characters are truncated.
+    pub print_length: Option<usize>,
+    /// How deeply lists in the source may nest.
+    pub max_nesting_depth: usize,
+    pub features: Features,
+    pub sandbox: SandboxPolicy,
+    pub builtin_redefinition: Redefinition,
+    /// Directories `require` looks for modules in, in order.
+    pub module_path: Vec<PathBuf>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            fuel_limit: None,
+            recursion_limit: 256,
+            print_length: None,
+            max_nesting_depth: DEFAULT_MAX_DEPTH,
+            features: Features::default(),
+            sandbox: SandboxPolicy::Unrestricted,
+            builtin_redefinition: Redefinition::Warn,
+            module_path: vec![PathBuf::from(".")],
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Reads the configuration from `RS_LISP_*` environment variables, falling
+    /// back to the defaults for any that are unset.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = EngineConfig::default();
+
+        if let Some(value) = lookup("RS_LISP_FUEL") {
+            config.fuel_limit = parse_limit("RS_LISP_FUEL", &value)?;
+        }
+        if let Some(value) = lookup("RS_LISP_RECURSION_LIMIT") {
+            config.recursion_limit = parse_number("RS_LISP_RECURSION_LIMIT", &value)?;
+        }
+        if let Some(value) = lookup("RS_LISP_PRINT_LENGTH") {
+            config.print_length = parse_limit("RS_LISP_PRINT_LENGTH", &value)?;
+        }
+        if let Some(value) = lookup("RS_LISP_MAX_NESTING_DEPTH") {
+            config.max_nesting_depth = parse_number("RS_LISP_MAX_NESTING_DEPTH", &value)?;
+        }
+        if let Some(value) = lookup("RS_LISP_FEATURES") {
+            for name in value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                config.features.enable(name)?;
+            }
+        }
+        if let Some(value) = lookup("RS_LISP_SANDBOX") {
+            config.sandbox = match value.as_str() {
+                "strict" => SandboxPolicy::Strict,
+                "unrestricted" => SandboxPolicy::Unrestricted,
+                _ => return Err(format!("Invalid value '{}' for RS_LISP_SANDBOX", value)),
+            };
+        }
+        if let Some(value) = lookup("RS_LISP_BUILTIN_REDEFINITION") {
+            config.builtin_redefinition = match value.as_str() {
+                "allow" => Redefinition::Allow,
+                "warn" => Redefinition::Warn,
+                "error" => Redefinition::Error,
+                _ => {
+                    return Err(format!(
+                        "Invalid value '{}' for RS_LISP_BUILTIN_REDEFINITION",
+                        value
+                    ))
+                }
+            };
+        }
+
+        if let Some(value) = lookup("RS_LISP_PATH") {
+            config.module_path = env::split_paths(&value).collect();
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for {}", value, name))
+}
+
+fn parse_limit<T: std::str::FromStr>(name: &str, value: &str) -> Result<Option<T>, String> {
+    if value.trim() == "none" {
+        Ok(None)
+    } else {
+        parse_number(name, value).map(Some)
+    }
+}
+
+/// Where `now` and `time` read the time from.
+pub trait Clock {
+    /// Milliseconds since the Unix epoch.
+    fn now(&self) -> f64;
+}
+
+/// The operating system clock. Not available on `wasm32-unknown-unknown`,
+/// where the host has to supply a clock instead.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs_f64() * 1000.0)
+            .unwrap_or(0.0)
+    }
+}
+
+/// A clock that always reads the same time, for deterministic tests.
+pub struct FixedClock(pub f64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> f64 {
+        self.0
+    }
+}
+
+/// Where `rand` draws its numbers from.
+pub trait Rng {
+    /// A number in the range `[0, 1)`.
+    fn next_f64(&mut self) -> f64;
+}
+
+/// A small xorshift generator. Seeded explicitly it gives the same sequence
+/// every run; the default seed is random.
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero
+        SeededRng { state: seed.max(1) }
+    }
+}
+
+impl Default for SeededRng {
+    fn default() -> Self {
+        SeededRng::new(RandomState::new().build_hasher().finish())
+    }
+}
+
+impl Rng for SeededRng {
+    fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        // The top 53 bits fill an f64 mantissa exactly
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Where `print`, `println` and `display` write to.
+pub trait Output {
+    fn write(&mut self, text: &str);
+}
+
+/// Standard output, flushed after each write so that text without a newline
+/// shows up at once.
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn write(&mut self, text: &str) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout
+            .write_all(text.as_bytes())
+            .and_then(|_| stdout.flush());
+    }
+}
+
+/// Standard error, for hosts that use standard output themselves.
+pub struct Stderr;
+
+impl Output for Stderr {
+    fn write(&mut self, text: &str) {
+        eprint!("{}", text);
+    }
+}
+
+/// Throws away what is written, for evaluations that must not be seen.
+struct Discard;
+
+impl Output for Discard {
+    fn write(&mut self, _: &str) {}
+}
+
+/// Keeps what is written for the host to take, through any of its clones.
+#[derive(Clone, Default)]
+pub struct CapturedOutput(Rc<RefCell<String>>);
+
+impl CapturedOutput {
+    pub fn take(&self) -> String {
+        self.0.take()
+    }
+
+    /// Takes the output written so far and puts it before `report`, ending
+    /// it with a newline if it did not end with one.
+    pub fn before(&self, report: &str) -> String {
+        let mut output = self.take();
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output + report
+    }
+}
+
+impl Output for CapturedOutput {
+    fn write(&mut self, text: &str) {
+        self.0.borrow_mut().push_str(text);
+    }
+}
+
+/// A JSON file in the working directory, except in the browser, which has
+/// no files and where the host has to supply storage instead.
+fn default_storage() -> Box<dyn Storage> {
+    if cfg!(target_arch = "wasm32") {
+        Box::new(MemoryStorage::default())
+    } else {
+        Box::new(JsonFileStorage::default())
+    }
+}
+
+/// What the last top-level evaluation cost.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    /// Expressions evaluated, the same unit fuel is measured in.
+    pub steps: u64,
+    /// Deepest nesting of `eval` reached.
+    pub max_depth: usize,
+    /// Scope frames and argument lists created.
+    pub allocations: u64,
+    /// Calls to functions and builtins, including special forms.
+    pub function_calls: u64,
+    /// Wall time according to the engine's clock.
+    pub elapsed_ms: f64,
+}
+
+/// What an engine offers, for generating documentation and for scripts
+/// that adapt to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Description {
+    pub version: &'static str,
+    /// Every builtin, compatibility aliases included, sorted by name.
+    pub builtins: Vec<BuiltinDescription>,
+    pub features: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuiltinDescription {
+    pub name: String,
+    pub kind: BuiltinKind,
+    pub arity: Arity,
+}
+
+/// The interpreter's version.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// One reduction recorded while explaining an evaluation: a form and the
+/// value it evaluated to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub before: Expr,
+    pub after: Expr,
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} => {}",
+            expr_to_string(&self.before),
+            expr_to_string(&self.after)
+        )
+    }
+}
+
+/// A call to a side-effecting builtin, as recorded in the audit log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub builtin: String,
+    /// The arguments as the builtin received them.
+    pub args: Vec<Expr>,
+    /// When the call was made, in milliseconds since the Unix epoch according
+    /// to the engine's clock.
+    pub time_ms: f64,
+}
+
+/// Stops an engine's evaluation in progress from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes the evaluation in progress, if any, fail at its next step.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Rewrites a form before the engine evaluates it; see
+/// `Engine::set_before_eval`.
+pub type BeforeEval = Box<dyn Fn(&Expr) -> Option<Expr>>;
+
+/// Sees each form the engine evaluated, with its result; see
+/// `Engine::set_after_eval`.
+pub type AfterEval = Box<dyn Fn(&Expr, &Result<Expr, String>)>;
+
+/// Per-engine state shared by every scope created from it.
+pub struct Runtime {
+    pub config: EngineConfig,
+    fuel: Cell<Option<u64>>,
+    depth: Cell<usize>,
+    calls: RefCell<Vec<Rc<Function>>>,
+    spans: RefCell<SpanTable>,
+    warnings: RefCell<Vec<String>>,
+    stats: Cell<Stats>,
+    steps: RefCell<Option<Vec<Step>>>,
+    clock: RefCell<Box<dyn Clock>>,
+    rng: RefCell<Box<dyn Rng>>,
+    output: RefCell<Box<dyn Output>>,
+    reader: RefCell<ReaderConfig>,
+    charts: RefCell<Vec<Chart>>,
+    turtle: RefCell<Turtle>,
+    schedule: RefCell<Schedule>,
+    storage: RefCell<Box<dyn Storage>>,
+    modules: RefCell<ModuleCache>,
+    tests: RefCell<TestSuite>,
+    before_eval: RefCell<Option<BeforeEval>>,
+    after_eval: RefCell<Option<AfterEval>>,
+    audit_log: RefCell<Vec<AuditEntry>>,
+    interrupt: InterruptHandle,
+    /// The last token handed to capabilities, and the one that may be
+    /// called now, if any.
+    capability_tokens: Cell<u64>,
+    active_capabilities: Cell<Option<u64>>,
+    /// Whether `eval_speculative` is running, which side-effecting builtins
+    /// may not be called during.
+    speculating: Cell<bool>,
+    captured_frames: RefCell<CapturedFrames>,
+}
+
+/// What builtins change in the runtime besides bindings and output, saved
+/// by `eval_speculative` to put back afterwards.
+struct SavedEffects {
+    reader: ReaderConfig,
+    turtle: Turtle,
+    schedule: Schedule,
+    tests: TestSuite,
+}
+
+impl std::fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Runtime")
+            .field("config", &self.config)
+            .field("fuel", &self.fuel)
+            .field("depth", &self.depth)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Runtime {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+    }
+}
+
+impl Runtime {
+    pub fn new(config: EngineConfig) -> Self {
+        #[allow(unused_mut)]
+        let mut reader = ReaderConfig::default();
+        #[cfg(feature = "ndarray")]
+        crate::matrix::add_reader_tag(&mut reader);
+
+        Runtime {
+            fuel: Cell::new(config.fuel_limit),
+            depth: Cell::new(0),
+            calls: RefCell::new(Vec::new()),
+            spans: RefCell::new(SpanTable::default()),
+            warnings: RefCell::new(Vec::new()),
+            charts: RefCell::new(Vec::new()),
+            turtle: RefCell::new(Turtle::default()),
+            schedule: RefCell::new(Schedule::default()),
+            storage: RefCell::new(default_storage()),
+            modules: RefCell::new(ModuleCache::default()),
+            tests: RefCell::new(TestSuite::default()),
+            before_eval: RefCell::new(None),
+            after_eval: RefCell::new(None),
+            audit_log: RefCell::new(Vec::new()),
+            stats: Cell::new(Stats::default()),
+            steps: RefCell::new(None),
+            clock: RefCell::new(Box::new(SystemClock)),
+            rng: RefCell::new(Box::new(SeededRng::default())),
+            output: RefCell::new(Box::new(Stdout)),
+            reader: RefCell::new(reader),
+            interrupt: InterruptHandle::default(),
+            capability_tokens: Cell::new(0),
+            active_capabilities: Cell::new(None),
+            speculating: Cell::new(false),
+            captured_frames: RefCell::new(CapturedFrames::default()),
+            config,
+        }
+    }
+
+    /// Refills the fuel tank and resets the stats, called before each
+    /// top-level evaluation.
+    pub fn refuel(&self) {
+        self.refuel_with(self.config.fuel_limit);
+    }
+
+    /// Like `refuel`, but with a different amount of fuel than configured.
+    pub fn refuel_with(&self, fuel: Option<u64>) {
+        // Interrupts only apply to the evaluation they were sent during
+        self.interrupt.0.store(false, Ordering::Relaxed);
+        self.fuel.set(fuel);
+        self.depth.set(0);
+        self.stats.set(Stats::default());
+    }
+
+    /// Whether the current evaluation has used up all its fuel.
+    pub fn out_of_fuel(&self) -> bool {
+        self.fuel.get() == Some(0)
+    }
+
+    fn update_stats(&self, update: impl FnOnce(&mut Stats)) {
+        let mut stats = self.stats.get();
+        update(&mut stats);
+        self.stats.set(stats);
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.get()
+    }
+
+    /// The literal syntax read on top of the usual grammar.
+    pub fn reader(&self) -> RefMut<'_, ReaderConfig> {
+        self.reader.borrow_mut()
+    }
+
+    /// A new token for the capabilities of an evaluation, which may be
+    /// called until `revoke_capabilities`.
+    pub(crate) fn lend_capabilities(&self) -> u64 {
+        let token = self.capability_tokens.get() + 1;
+        self.capability_tokens.set(token);
+        self.active_capabilities.set(Some(token));
+        token
+    }
+
+    pub(crate) fn revoke_capabilities(&self) {
+        self.active_capabilities.set(None);
+    }
+
+    pub(crate) fn capabilities_lent(&self, token: u64) -> bool {
+        self.active_capabilities.get() == Some(token)
+    }
+
+    /// The frames closures were made in, which `collect_cycles` frees once
+    /// only their own closures keep them alive.
+    pub(crate) fn captured_frames(&self) -> RefMut<'_, CapturedFrames> {
+        self.captured_frames.borrow_mut()
+    }
+
+    pub(crate) fn speculating(&self) -> bool {
+        self.speculating.get()
+    }
+
+    fn save_effects(&self) -> SavedEffects {
+        SavedEffects {
+            reader: self.reader.borrow().clone(),
+            turtle: self.turtle.borrow().clone(),
+            schedule: self.schedule.borrow().clone(),
+            tests: self.tests.borrow().clone(),
+        }
+    }
+
+    fn restore_effects(&self, saved: SavedEffects) {
+        *self.reader.borrow_mut() = saved.reader;
+        *self.turtle.borrow_mut() = saved.turtle;
+        *self.schedule.borrow_mut() = saved.schedule;
+        *self.tests.borrow_mut() = saved.tests;
+    }
+
+    pub fn record_call(&self) {
+        self.update_stats(|stats| stats.function_calls += 1);
+    }
+
+    pub fn record_allocation(&self) {
+        self.update_stats(|stats| stats.allocations += 1);
+    }
+
+    fn record_elapsed(&self, elapsed_ms: f64) {
+        self.update_stats(|stats| stats.elapsed_ms = elapsed_ms);
+    }
+
+    /// Accounts for one evaluation step, failing when the fuel or the
+    /// recursion limit is exhausted.
+    pub fn enter(&self) -> Result<(), String> {
+        if self.interrupt.0.swap(false, Ordering::Relaxed) {
+            return Err(message!("interrupted"));
+        }
+        if let Some(fuel) = self.fuel.get() {
+            if fuel == 0 {
+                return Err(message!("out-of-fuel"));
+            }
+            self.fuel.set(Some(fuel - 1));
+        }
+
+        let depth = self.depth.get();
+        if depth >= self.config.recursion_limit {
+            return Err(message!("recursion-limit"));
+        }
+        self.depth.set(depth + 1);
+        self.update_stats(|stats| {
+            stats.steps += 1;
+            stats.max_depth = stats.max_depth.max(depth + 1);
+        });
+
+        Ok(())
+    }
+
+    pub fn leave(&self) {
+        self.depth.set(self.depth.get().saturating_sub(1));
+    }
+
+    /// Notes that `function` was called, until the matching `leave_call`.
+    pub fn enter_call(&self, function: &Rc<Function>) {
+        self.calls.borrow_mut().push(function.clone());
+    }
+
+    pub fn leave_call(&self) {
+        self.calls.borrow_mut().pop();
+    }
+
+    /// The functions called and not yet returned from, innermost first.
+    pub fn backtrace(&self) -> Vec<Rc<Function>> {
+        self.calls.borrow().iter().rev().cloned().collect()
+    }
+
+    /// Sets the source locations of the expression about to be evaluated.
+    pub fn set_spans(&self, spans: SpanTable) {
+        *self.spans.borrow_mut() = spans;
+    }
+
+    /// Looks up where a list being evaluated appeared in the source, if it
+    /// came straight from the parser.
+    pub fn span_of(&self, list: &[Expr]) -> Option<Span> {
+        self.spans.borrow().get(list)
+    }
+
+    /// Records a warning for the host to show after the current evaluation.
+    pub fn warn(&self, message: String) {
+        self.warnings.borrow_mut().push(message);
+    }
+
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.warnings.take()
+    }
+
+    /// Records a chart drawn by `plot` for the host to show.
+    pub fn add_chart(&self, chart: Chart) {
+        self.charts.borrow_mut().push(chart);
+    }
+
+    pub fn take_charts(&self) -> Vec<Chart> {
+        self.charts.take()
+    }
+
+    /// The turtle the turtle graphics builtins move.
+    pub fn turtle(&self) -> RefMut<'_, Turtle> {
+        self.turtle.borrow_mut()
+    }
+
+    /// The tones queued by `tone` for the host to play.
+    pub fn schedule(&self) -> RefMut<'_, Schedule> {
+        self.schedule.borrow_mut()
+    }
+
+    /// Starts recording reduction steps, returning any recording already in
+    /// progress so that it can be resumed with `finish_explaining`.
+    pub fn start_explaining(&self) -> Option<Vec<Step>> {
+        self.steps.replace(Some(Vec::new()))
+    }
+
+    /// Stops recording, returning the steps recorded since the matching
+    /// `start_explaining`.
+    pub fn finish_explaining(&self, previous: Option<Vec<Step>>) -> Vec<Step> {
+        self.steps.replace(previous).unwrap_or_default()
+    }
+
+    /// Records that `before` evaluated to `after`, if explaining. Only calls
+    /// and variable lookups are recorded, as other values evaluate to
+    /// themselves; lookups of builtins are left out as noise.
+    pub fn record_step(&self, before: &Expr, after: &Expr) {
+        if let Some(steps) = self.steps.borrow_mut().as_mut() {
+            let interesting = match (before, after) {
+                (Expr::List(list), _) => !list.is_empty(),
+                (Expr::Symbol(_), Expr::BuiltinFunction(_)) => false,
+                (Expr::Symbol(_), _) => true,
+                _ => false,
+            };
+            if interesting {
+                steps.push(Step {
+                    before: before.clone(),
+                    after: after.clone(),
+                });
+            }
+        }
+    }
+
+    pub fn set_clock(&self, clock: Box<dyn Clock>) {
+        *self.clock.borrow_mut() = clock;
+    }
+
+    pub fn set_rng(&self, rng: Box<dyn Rng>) {
+        *self.rng.borrow_mut() = rng;
+    }
+
+    pub fn set_output(&self, output: Box<dyn Output>) {
+        *self.output.borrow_mut() = output;
+    }
+
+    /// Sets the output, returning the one it replaces.
+    pub fn replace_output(&self, output: Box<dyn Output>) -> Box<dyn Output> {
+        mem::replace(&mut *self.output.borrow_mut(), output)
+    }
+
+    /// Writes program output, such as from `print`.
+    pub fn write(&self, text: &str) {
+        self.output.borrow_mut().write(text);
+    }
+
+    pub fn set_storage(&self, storage: Box<dyn Storage>) {
+        *self.storage.borrow_mut() = storage;
+    }
+
+    pub fn storage(&self) -> RefMut<'_, Box<dyn Storage>> {
+        self.storage.borrow_mut()
+    }
+
+    pub fn modules(&self) -> RefMut<'_, ModuleCache> {
+        self.modules.borrow_mut()
+    }
+
+    /// The tests defined with `deftest`, and their fixtures.
+    pub fn tests(&self) -> RefMut<'_, TestSuite> {
+        self.tests.borrow_mut()
+    }
+
+    pub fn set_before_eval(&self, hook: BeforeEval) {
+        *self.before_eval.borrow_mut() = Some(hook);
+    }
+
+    pub fn set_after_eval(&self, hook: AfterEval) {
+        *self.after_eval.borrow_mut() = Some(hook);
+    }
+
+    /// Milliseconds since the Unix epoch, according to the engine's clock.
+    pub fn now(&self) -> f64 {
+        self.clock.borrow().now()
+    }
+
+    /// The next number in `[0, 1)` from the engine's random number generator.
+    pub fn random(&self) -> f64 {
+        self.rng.borrow_mut().next_f64()
+    }
+
+    /// Records a call to a builtin in the audit log, if auditing is enabled
+    /// and the builtin has side effects.
+    pub fn audit(&self, builtin: &str, args: &[Expr]) {
+        if self.config.features.audit && is_side_effecting(builtin) {
+            let time_ms = self.now();
+            self.audit_log.borrow_mut().push(AuditEntry {
+                builtin: builtin.to_string(),
+                args: args.to_vec(),
+                time_ms,
+            });
+        }
+    }
+
+    pub fn trace(&self, message: impl FnOnce() -> String) {
+        if self.config.features.trace {
+            println!("{}", message());
+        }
+    }
+}
+
+/// Evaluates a freshly parsed expression with a full tank of fuel, passing
+/// it through the engine's hooks.
+fn eval_top_level(
+    expr: &Expr,
+    spans: SpanTable,
+    scope: &mut Scope,
+    fuel: Option<u64>,
+) -> Result<Expr, String> {
+    let runtime = scope.runtime().clone();
+    let rewritten = runtime
+        .before_eval
+        .borrow()
+        .as_ref()
+        .and_then(|hook| hook(expr));
+    let expr = rewritten.as_ref().unwrap_or(expr);
+
+    runtime.refuel_with(fuel);
+    runtime.set_spans(spans);
+    let start = runtime.now();
+    let result = eval(expr, scope);
+    runtime.record_elapsed(runtime.now() - start);
+    runtime.set_spans(SpanTable::default());
+    collect_cycles(scope);
+
+    if let Some(hook) = &*runtime.after_eval.borrow() {
+        hook(expr, &result);
+    }
+    result
+}
+
+/// The outcome of `Engine::eval_speculative`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Speculation {
+    Value(Expr),
+    Error(String),
+    /// The evaluation did not finish within its budget.
+    TooExpensive,
+}
+
+pub struct Engine {
+    scope: Scope,
+    notebook: Notebook,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+// Closures refer back to the frames they were defined in, and tests to the
+// scope they were defined in, which refers to the runtime holding them. The
+// cycles are broken here so that the frames, and finalized handles in them,
+// are freed with the engine.
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let runtime = self.scope.runtime().clone();
+        drop(mem::take(&mut *runtime.tests()));
+        let modules = runtime.modules().unload();
+        for module in modules {
+            module.clear();
+        }
+        self.scope.clear();
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        Engine {
+            scope: Scope::with_config(config),
+            notebook: Notebook::default(),
+        }
+    }
+
+    fn parse(&self, input: &str) -> Result<(Expr, SpanTable), String> {
+        let runtime = self.scope.runtime();
+        // Tag handlers defined in lisp run while reading, and may define more
+        let reader = runtime.reader().clone();
+        runtime.refuel();
+        parse_with_reader(input, &reader, self.config().max_nesting_depth)
+    }
+
+    /// The literal syntax the engine reads on top of the usual grammar, for
+    /// hosts to add their own.
+    pub fn reader(&self) -> RefMut<'_, ReaderConfig> {
+        self.scope.runtime().reader()
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.scope.runtime().config
+    }
+
+    pub fn scope(&mut self) -> &mut Scope {
+        &mut self.scope
+    }
+
+    pub fn eval(&mut self, input: &str) -> Result<Expr, String> {
+        let (expr, spans) = self.parse(input)?;
+        let fuel = self.config().fuel_limit;
+        eval_top_level(&expr, spans, &mut self.scope, fuel)
+    }
+
+    /// Evaluates the input, also returning each reduction in the order it
+    /// happened, innermost first.
+    pub fn eval_traced(&mut self, input: &str) -> (Result<Expr, String>, Vec<Step>) {
+        let runtime = self.scope.runtime().clone();
+        let previous = runtime.start_explaining();
+        let result = self.eval(input);
+        (result, runtime.finish_explaining(previous))
+    }
+
+    /// Parses the input into the starting state of a step-by-step
+    /// evaluation; see `step`.
+    pub fn start(&mut self, input: &str) -> State {
+        let runtime = self.scope.runtime().clone();
+        runtime.refuel();
+        match self.parse(input) {
+            Ok((expr, spans)) => {
+                runtime.set_spans(spans);
+                State::new(expr, self.scope.clone())
+            }
+            Err(e) => State::failed(e),
+        }
+    }
+
+    /// Advances a state from `start` by one step. Fuel is shared across all
+    /// states since the last `start`.
+    pub fn step(&mut self, state: State) -> State {
+        state.step()
+    }
+
+    /// Runs a notebook cell. Re-running a cell first removes what it defined
+    /// last time, and the output lists the cells that depend on anything the
+    /// run changed and so need to be run again.
+    pub fn eval_cell(&mut self, id: &str, source: &str) -> CellOutput {
+        let (expr, spans) = match self.parse(source) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                return CellOutput {
+                    result: Err(e),
+                    stale: Vec::new(),
+                }
+            }
+        };
+
+        let previous = self.notebook.definitions(id);
+        for name in &previous {
+            self.scope.remove_variable(name);
+        }
+
+        let before = self.scope.bindings();
+        let fuel = self.config().fuel_limit;
+        let result = eval_top_level(&expr, spans, &mut self.scope, fuel);
+        let defines: BTreeSet<String> = self
+            .scope
+            .bindings()
+            .into_iter()
+            .filter(|binding| !before.contains(binding))
+            .map(|(name, _)| name)
+            .collect();
+
+        let mut reads = BTreeSet::new();
+        symbols_in(&expr, &mut reads);
+        let changed = previous.union(&defines).cloned().collect();
+        self.notebook.record(id, defines, reads);
+
+        CellOutput {
+            result,
+            stale: self.notebook.downstream(id, changed),
+        }
+    }
+
+    /// Evaluates the input with extra bindings layered over the global
+    /// scope, without changing the global scope itself: the usual way to
+    /// check a rule or condition against a piece of data. The bindings live
+    /// in an overlay that is never committed, so even a definition made in
+    /// the global scope with `eval` is gone when it returns.
+    pub fn eval_with_context(
+        &mut self,
+        input: &str,
+        context: HashMap<String, Expr>,
+    ) -> Result<Expr, String> {
+        let (expr, spans) = self.parse(input)?;
+        let mut overlay = Scope::overlay(&self.scope);
+        for (name, value) in context {
+            overlay.set_variable(name, value);
+        }
+        let fuel = self.config().fuel_limit;
+        let result = eval_top_level(&expr, spans, &mut overlay, fuel);
+        overlay.clear();
+        result
+    }
+
+    /// Evaluates the input with extra builtins, such as one querying the
+    /// database of the request being served, that exist for this evaluation
+    /// only. They are builtins like any other while it runs, so functions
+    /// defined earlier may call them too, and are removed when it returns.
+    /// A capability the program kept hold of, say with `def`, can no longer
+    /// be called once it has.
+    pub fn eval_with_capabilities(
+        &mut self,
+        input: &str,
+        capabilities: impl IntoIterator<Item = BuiltinFunction>,
+    ) -> Result<Expr, String> {
+        let mut builtins = self.scope.clone();
+        while let Some(parent) = builtins.parent() {
+            builtins = parent;
+        }
+        let token = self.scope.runtime().lend_capabilities();
+        let mut replaced = Vec::new();
+        for mut capability in capabilities {
+            capability.capability = Some(token);
+            let name = capability.name.clone();
+            replaced.push((name.clone(), builtins.get_variable(&name)));
+            builtins.set_variable(name, Expr::BuiltinFunction(capability));
+        }
+
+        let result = self.eval(input);
+
+        self.scope.runtime().revoke_capabilities();
+        for (name, previous) in replaced.into_iter().rev() {
+            match previous {
+                Some(builtin) => builtins.set_variable(name, builtin),
+                None => {
+                    builtins.remove_variable(&name);
+                }
+            }
+        }
+        result
+    }
+
+    /// Evaluates the input with at most `fuel` steps, in an overlay of the
+    /// global scope that is never committed, so that definitions, even global
+    /// ones, are thrown away afterwards, and with output discarded. Turtle
+    /// drawing, queued tones, tests and reader tags are put back as they
+    /// were, and builtins reaching outside the interpreter, such as
+    /// `store-set!`, fail. Meant for editor tooling showing the values of
+    /// expressions as they are typed.
+    pub fn eval_speculative(&mut self, input: &str, fuel: u64) -> Speculation {
+        let (expr, spans) = match self.parse(input) {
+            Ok(parsed) => parsed,
+            Err(e) => return Speculation::Error(e),
+        };
+        let runtime = self.scope.runtime().clone();
+        let warnings = runtime.take_warnings();
+
+        let output = runtime.replace_output(Box::new(Discard));
+        let effects = runtime.save_effects();
+        runtime.speculating.set(true);
+        let mut overlay = Scope::overlay(&self.scope);
+        let result = eval_top_level(&expr, spans, &mut overlay, Some(fuel));
+        overlay.clear();
+        runtime.speculating.set(false);
+        runtime.restore_effects(effects);
+        runtime.set_output(output);
+
+        runtime.take_warnings();
+        for warning in warnings {
+            runtime.warn(warning);
+        }
+
+        match result {
+            Ok(value) => Speculation::Value(value),
+            Err(_) if runtime.out_of_fuel() => Speculation::TooExpensive,
+            Err(e) => Speculation::Error(e),
+        }
+    }
+
+    /// Runs `f` against an overlay of the global scope, lending it this
+    /// engine to evaluate with. What it defines reaches the global scope
+    /// only if it returns `Ok`; on `Err` every definition is discarded.
+    pub fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Engine) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let overlay = Scope::overlay(&self.scope);
+        let global = mem::replace(&mut self.scope, overlay.clone());
+        let result = f(self);
+        self.scope = global;
+        if result.is_ok() {
+            overlay.commit();
+        } else {
+            overlay.clear();
+        }
+        result
+    }
+
+    /// Calls a function value, such as one looked up from the scope, with
+    /// already evaluated arguments.
+    pub fn call(&mut self, callee: &Expr, args: &[Expr]) -> Result<Expr, String> {
+        let runtime = self.scope.runtime().clone();
+        runtime.refuel();
+        let start = runtime.now();
+        let result = call(callee, args, &mut self.scope);
+        runtime.record_elapsed(runtime.now() - start);
+        result
+    }
+
+    /// Runs the tests defined with `deftest` that the filter selects.
+    pub fn run_tests(&mut self, filter: &TestFilter) -> Vec<TestResult> {
+        run_tests(&mut self.scope, filter)
+    }
+
+    /// Times the benchmarks defined with `defbench` that the filter selects,
+    /// running each for about `budget_ms` milliseconds by the engine's clock.
+    pub fn run_benches(&mut self, filter: &TestFilter, budget_ms: f64) -> Vec<BenchResult> {
+        run_benches(&mut self.scope, filter, budget_ms)
+    }
+
+    /// The version, and the builtins and features this engine has enabled.
+    pub fn describe(&self) -> Description {
+        Description {
+            version: VERSION,
+            builtins: self
+                .scope
+                .builtins()
+                .into_iter()
+                .map(|builtin| BuiltinDescription {
+                    name: builtin.name,
+                    kind: builtin.kind,
+                    arity: builtin.arity,
+                })
+                .collect(),
+            features: self.config().features.enabled(),
+        }
+    }
+
+    /// What the last call to `eval` or `call` cost, whether or not it
+    /// succeeded.
+    pub fn last_stats(&self) -> Stats {
+        self.scope.runtime().stats()
+    }
+
+    /// Replaces the clock read by `now` and `time`.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.scope.runtime().set_clock(Box::new(clock));
+    }
+
+    /// Replaces the random number generator used by `rand`.
+    pub fn set_rng(&mut self, rng: impl Rng + 'static) {
+        self.scope.runtime().set_rng(Box::new(rng));
+    }
+
+    /// Replaces where `print`, `println` and `display` write to, standard
+    /// output by default.
+    pub fn set_output(&mut self, output: impl Output + 'static) {
+        self.scope.runtime().set_output(Box::new(output));
+    }
+
+    /// Replaces where `store-get` and `store-set!` keep their values.
+    pub fn set_storage(&mut self, storage: impl Storage + 'static) {
+        self.scope.runtime().set_storage(Box::new(storage));
+    }
+
+    /// Lets `hook` see each form before `eval`, `eval_cell`,
+    /// `eval_with_context` or `eval_speculative` evaluates it, and return a
+    /// form to evaluate instead. Errors in a rewritten form have no line and
+    /// column, as it was never read from the source.
+    pub fn set_before_eval(&mut self, hook: impl Fn(&Expr) -> Option<Expr> + 'static) {
+        self.scope.runtime().set_before_eval(Box::new(hook));
+    }
+
+    /// Calls `hook` with each form evaluated as for `set_before_eval`, as
+    /// rewritten, and its result.
+    pub fn set_after_eval(&mut self, hook: impl Fn(&Expr, &Result<Expr, String>) + 'static) {
+        self.scope.runtime().set_after_eval(Box::new(hook));
+    }
+
+    /// The calls to side-effecting builtins made since the engine was
+    /// created, oldest first. Empty unless the `audit` feature is enabled.
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.scope.runtime().audit_log.borrow().clone()
+    }
+
+    /// A handle for interrupting this engine's evaluations from another
+    /// thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.scope.runtime().interrupt.clone()
+    }
+
+    /// The defined names, builtins included, that start with the prefix.
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        self.scope
+            .visible_symbols()
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .collect()
+    }
+
+    /// The documentation given when the name was defined.
+    pub fn doc(&self, name: &str) -> Option<String> {
+        self.scope.get_doc(name)
+    }
+
+    /// The edits renaming the global `old` to `new` in the source, which
+    /// may define it or use what the engine has defined. Refuses to rename
+    /// builtins, or to a name that is already defined or would be shadowed.
+    pub fn rename(&self, source: &str, old: &str, new: &str) -> Result<Vec<Edit>, String> {
+        let defined = definitions(source)?;
+        check_rename(old, new, |name| {
+            self.scope.get_variable(name).is_some() || defined.iter().any(|d| d == name)
+        })?;
+        rename_symbol(source, old, new)
+    }
+
+    /// Draws an expression tree in Graphviz DOT format.
+    pub fn expr_to_dot(&self, expr: &Expr) -> String {
+        dot::expr_to_dot(expr)
+    }
+
+    /// Draws the global scope, the builtins above it, and the scopes captured
+    /// by functions, in Graphviz DOT format.
+    pub fn scope_to_dot(&self) -> String {
+        dot::scope_to_dot(&self.scope)
+    }
+
+    /// Returns the warnings raised since the last call.
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.scope.runtime().take_warnings()
+    }
+
+    /// Returns the charts drawn by `plot` since the last call.
+    pub fn take_charts(&self) -> Vec<Chart> {
+        self.scope.runtime().take_charts()
+    }
+
+    /// Returns what the turtle has drawn as SVG, if anything, and starts a
+    /// new drawing with the turtle back at the origin.
+    pub fn take_drawing(&self) -> Option<String> {
+        self.scope.runtime().turtle.take().to_svg()
+    }
+
+    /// Returns the tones queued since the last call, with their start times
+    /// counted from the evaluation that queued them.
+    pub fn take_tones(&self) -> Vec<Tone> {
+        self.scope.runtime().schedule().take_tones()
+    }
+
+    /// Prints a value, truncated to the configured print length.
+    pub fn print(&self, expr: &Expr) -> String {
+        let reader = self.scope.runtime().reader().clone();
+        let printed = expr_to_string_with(expr, &reader);
+        match self.config().print_length {
+            Some(limit) if printed.chars().count() > limit => {
+                let truncated: String = printed.chars().take(limit).collect();
+                format!("{}...", truncated)
+            }
+            _ => printed,
+        }
+    }
+
+    /// Evaluates the input and prints any warnings followed by either the
+    /// result or the error.
+    pub fn eval_to_string(&mut self, input: &str) -> String {
+        let result = self.eval(input);
+        self.report(result)
+    }
+
+    /// Prints the warnings raised since the last call followed by either
+    /// the result or the error.
+    pub fn report(&self, result: Result<Expr, String>) -> String {
+        let output = match result {
+            Ok(result) => self.print(&result),
+            Err(e) => message!("error", message = e),
+        };
+        let mut lines: Vec<String> = self
+            .take_warnings()
+            .into_iter()
+            .map(|warning| message!("warning", message = warning))
+            .collect();
+        lines.push(output);
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::parser::parse;
+    use crate::syntax::apply_edits;
+
+    fn config_from(vars: &[(&str, &str)]) -> Result<EngineConfig, String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        EngineConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn config_from_vars() {
+        let config = config_from(&[
+            ("RS_LISP_FUEL", "500"),
+            ("RS_LISP_RECURSION_LIMIT", "64"),
+            ("RS_LISP_PRINT_LENGTH", "none"),
+            ("RS_LISP_MAX_NESTING_DEPTH", "50"),
+            ("RS_LISP_FEATURES", "trace"),
+            ("RS_LISP_SANDBOX", "strict"),
+            ("RS_LISP_PATH", "lib:vendor"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.fuel_limit, Some(500));
+        assert_eq!(config.recursion_limit, 64);
+        assert_eq!(config.print_length, None);
+        assert_eq!(config.max_nesting_depth, 50);
+        assert!(config.features.trace);
+        assert_eq!(config.sandbox, SandboxPolicy::Strict);
+        assert_eq!(
+            config.module_path,
+            vec![PathBuf::from("lib"), PathBuf::from("vendor")]
+        );
+    }
+
+    #[test]
+    fn config_rejects_bad_values() {
+        assert_eq!(
+            config_from(&[("RS_LISP_FUEL", "lots")]),
+            Err("Invalid value 'lots' for RS_LISP_FUEL".to_string())
+        );
+        assert_eq!(
+            config_from(&[("RS_LISP_FEATURES", "jit")]),
+            Err("Unknown feature 'jit'".to_string())
+        );
+    }
+
+    #[test]
+    fn fuel_limit() {
+        let mut engine = Engine::with_config(EngineConfig {
+            fuel_limit: Some(10),
+            ..EngineConfig::default()
+        });
+        assert_eq!(engine.eval("(+ 1 2)"), Ok(Expr::int(3)));
+        assert_eq!(
+            engine.eval("(+ 1 2 3 4 5 6 7 8 9 10)"),
+            Err("Evaluation ran out of fuel".to_string())
+        );
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let mut engine = Engine::with_config(EngineConfig {
+            recursion_limit: 50,
+            ..EngineConfig::default()
+        });
+        engine.eval("(def loop (fn (x) (loop x)))").unwrap();
+        assert_eq!(
+            engine.eval("(loop 1)"),
+            Err("Maximum recursion depth exceeded".to_string())
+        );
+
+        // The default limit must trip before the native stack overflows.
+        let mut engine = Engine::new();
+        engine.eval("(def loop (fn (x) (loop x)))").unwrap();
+        assert_eq!(
+            engine.eval("(loop 1)"),
+            Err("Maximum recursion depth exceeded".to_string())
+        );
+    }
+
+    #[test]
+    fn output() {
+        let mut engine = Engine::new();
+        let output = CapturedOutput::default();
+        engine.set_output(output.clone());
+        assert_eq!(
+            engine.eval_to_string(r#"(println "a" #\b (list "c") 1/2)"#),
+            "nil"
+        );
+        engine.eval(r#"(display "z")"#).unwrap();
+        engine.eval(r#"(print)"#).unwrap();
+        assert_eq!(output.take(), "a b (\"c\") 1/2\nz");
+
+        engine.eval(r#"(print "no newline")"#).unwrap();
+        assert_eq!(output.before("nil"), "no newline\nnil");
+        assert_eq!(output.before("nil"), "nil");
+    }
+
+    #[test]
+    fn backtrace() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(defn inner (x) (list (stack-depth) (backtrace)))")
+            .unwrap();
+        engine
+            .eval("(defn outer (x) ((fn (y) (inner y)) x))")
+            .unwrap();
+        for (input, expected) in [
+            ("(outer 1)", "(3 (inner fn outer))"),
+            ("(list (stack-depth) (backtrace))", "(0 ())"),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+
+        // Calls that fail have returned as well
+        engine.eval("(defn fail (x) (+ x :a))").unwrap();
+        assert!(engine.eval("(fail 1)").is_err());
+        assert_eq!(engine.eval_to_string("(stack-depth)"), "0");
+    }
+
+    #[test]
+    fn not_a_function_errors() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval("(list 1\n  (5 1 2))"),
+            Err("Cannot call the integer 5 in (5 1 2) at line 2, column 3".to_string())
+        );
+        assert_eq!(
+            engine.eval("(lsit 1 2)"),
+            Err(
+                "Cannot call undefined symbol 'lsit' in (lsit 1 2) at line 1, column 1".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn builtin_redefinition() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval_to_string("(def first 1)"),
+            "Warning: def shadows builtin 'first'\n1"
+        );
+
+        let mut engine = Engine::with_config(EngineConfig {
+            builtin_redefinition: Redefinition::Error,
+            ..EngineConfig::default()
+        });
+        assert_eq!(
+            engine.eval("(def first 1)"),
+            Err("def would shadow builtin 'first'".to_string())
+        );
+
+        let mut engine = Engine::with_config(EngineConfig {
+            builtin_redefinition: Redefinition::Allow,
+            ..EngineConfig::default()
+        });
+        assert_eq!(engine.eval_to_string("(def first 1)"), "1");
+    }
+
+    #[test]
+    fn call_from_host() {
+        let mut engine = Engine::new();
+        engine.eval("(def pair (fn (a b) (list a b)))").unwrap();
+        let pair = engine.scope().get_variable("pair").unwrap();
+        assert_eq!(
+            engine.call(&pair, &[Expr::symbol("x"), Expr::int(1)]),
+            Ok(Expr::list(vec![Expr::symbol("x"), Expr::int(1)]))
+        );
+
+        let add = engine.scope().get_variable("+").unwrap();
+        assert_eq!(
+            engine.call(&add, &[Expr::int(1), Expr::int(2)]),
+            Ok(Expr::int(3))
+        );
+    }
+
+    #[test]
+    fn print_length() {
+        let mut engine = Engine::with_config(EngineConfig {
+            print_length: Some(5),
+            ..EngineConfig::default()
+        });
+        assert_eq!(engine.eval_to_string("(list 1 2 3 4)"), "(1 2 ...");
+    }
+
+    #[test]
+    fn deterministic_clock_and_rng() {
+        let mut engine = Engine::new();
+        engine.set_clock(FixedClock(1000.0));
+        assert_eq!(engine.eval("(now)"), Ok(Expr::number(1000.0)));
+        assert_eq!(
+            engine.eval("(time (+ 1 2))"),
+            Ok(Expr::list(vec![Expr::number(0.0), Expr::int(3)]))
+        );
+
+        let draws = |seed| {
+            let mut engine = Engine::new();
+            engine.set_rng(SeededRng::new(seed));
+            engine.eval("(list (rand) (rand 10) (rand 10))").unwrap()
+        };
+        assert_eq!(draws(42), draws(42));
+        assert_ne!(draws(42), draws(43));
+
+        let mut engine = Engine::new();
+        engine.set_rng(SeededRng::new(7));
+        for _ in 0..100 {
+            let Ok(Expr::Int(n)) = engine.eval("(rand 6)") else {
+                panic!("expected an integer");
+            };
+            assert!((0..6).contains(&n));
+        }
+        assert_eq!(
+            engine.eval("(rand 0)"),
+            Err(
+                "`rand` expected a number of at least 1 as argument 1, got the integer 0"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn last_stats() {
+        let mut engine = Engine::new();
+        engine.set_clock(FixedClock(0.0));
+        engine.eval("(def inc (fn (x) (+ x 1)))").unwrap();
+        engine.eval("(inc (inc 1))").unwrap();
+
+        // Two calls to inc, each calling +, and an argument list and a
+        // frame per call to inc, plus an argument list per call to +
+        assert_eq!(
+            engine.last_stats(),
+            Stats {
+                steps: 13,
+                max_depth: 4,
+                allocations: 6,
+                function_calls: 4,
+                elapsed_ms: 0.0,
+            }
+        );
+
+        // Failed evaluations are measured too
+        assert!(engine.eval("(inc (quote a))").is_err());
+        assert_eq!(engine.last_stats().function_calls, 3);
+    }
+
+    #[test]
+    fn eval_speculative() {
+        let mut engine = Engine::new();
+        engine.eval("(def loop (fn (x) (loop x)))").unwrap();
+
+        assert_eq!(
+            engine.eval_speculative("(+ 1 2)", 10),
+            Speculation::Value(Expr::int(3))
+        );
+        assert_eq!(
+            engine.eval_speculative("(loop 1)", 100),
+            Speculation::TooExpensive
+        );
+        assert_eq!(
+            engine.eval_speculative("(first 1)", 10),
+            Speculation::Error(
+                "`first` expected a sequence as argument 1, got the integer 1".to_string()
+            )
+        );
+
+        // Definitions stay in the overlay
+        assert_eq!(
+            engine.eval_speculative("(def x 1)", 10),
+            Speculation::Value(Expr::int(1))
+        );
+        assert_eq!(engine.scope().get_variable("x"), None);
+        assert_eq!(
+            engine.eval_speculative("(def + 1)", 10),
+            Speculation::Value(Expr::int(1))
+        );
+        assert!(engine.take_warnings().is_empty());
+        assert_eq!(engine.eval("(+ 1 2)"), Ok(Expr::int(3)));
+
+        // Nor do global definitions, redefinitions or output escape
+        engine.eval("(def y 1)").unwrap();
+        assert_eq!(
+            engine.eval_speculative("(eval (quote (def leaked 1)) :global)", 50),
+            Speculation::Value(Expr::int(1))
+        );
+        assert_eq!(engine.scope().get_variable("leaked"), None);
+        assert_eq!(
+            engine.eval_speculative("(with-redefs ((y 2)) (def z y))", 50),
+            Speculation::Value(Expr::int(2))
+        );
+        assert_eq!(engine.eval("y"), Ok(Expr::int(1)));
+        assert_eq!(engine.scope().get_variable("z"), None);
+        let output = CapturedOutput::default();
+        engine.set_output(output.clone());
+        assert_eq!(
+            engine.eval_speculative("(print 42)", 10),
+            Speculation::Value(Expr::Nil)
+        );
+        engine.eval("(print 1)").unwrap();
+        assert_eq!(output.take(), "1");
+
+        // Nor other effects, which are either undone or refused
+        engine.set_storage(MemoryStorage::default());
+        assert_eq!(
+            engine.eval_speculative("(store-set! \"k\" 1)", 10),
+            Speculation::Error(
+                "`store-set!` is not available while evaluating speculatively".to_string()
+            )
+        );
+        assert_eq!(engine.eval("(store-get \"k\")"), Ok(Expr::Nil));
+        assert_eq!(
+            engine.eval_speculative("(list (turn 90) (forward 10))", 10),
+            Speculation::Value(Expr::List(vec![Expr::Nil, Expr::Nil]))
+        );
+        assert_eq!(engine.take_drawing(), None);
+        assert_eq!(
+            engine.eval_speculative("(tone 440 100)", 10),
+            Speculation::Value(Expr::Nil)
+        );
+        assert_eq!(engine.take_tones(), Vec::new());
+        assert_eq!(
+            engine.eval_speculative("(deftest speculated (assert false))", 10),
+            Speculation::Value(Expr::Symbol("speculated".to_string()))
+        );
+        assert!(engine.run_tests(&TestFilter::default()).is_empty());
+        assert_eq!(
+            engine.eval_speculative("(deftag twice (fn (x) (* 2 x)))", 10),
+            Speculation::Value(Expr::Symbol("twice".to_string()))
+        );
+        assert!(engine.eval("#twice 2").is_err());
+    }
+
+    #[test]
+    fn transaction() {
+        let mut engine = Engine::new();
+        engine.eval("(def x 1)").unwrap();
+
+        let result = engine.transaction(|tx| {
+            tx.eval("(def x 2)")?;
+            tx.eval("(def y (+ x 1))")?;
+            tx.eval("(first 1)")
+        });
+        assert!(result.is_err());
+        assert_eq!(engine.eval("x"), Ok(Expr::int(1)));
+        assert_eq!(engine.scope().get_variable("y"), None);
+
+        engine
+            .transaction(|tx| {
+                tx.eval("(def get-z (fn () z))")?;
+                tx.eval("(def z \"new\" 2)")
+            })
+            .unwrap();
+        assert_eq!(engine.eval("(get-z)"), Ok(Expr::int(2)));
+        assert_eq!(engine.doc("z"), Some("new".to_string()));
+        // Closures defined in the transaction see later global definitions
+        engine.eval("(def z 3)").unwrap();
+        assert_eq!(engine.eval("(get-z)"), Ok(Expr::int(3)));
+
+        // Definitions in a transaction still count as global ones
+        let mut strict = Engine::with_config(EngineConfig {
+            builtin_redefinition: Redefinition::Error,
+            ..EngineConfig::default()
+        });
+        assert_eq!(
+            strict.transaction(|tx| tx.eval("(def + 1)")),
+            Err("def would shadow builtin '+'".to_string())
+        );
+    }
+
+    #[test]
+    fn eval_with_capabilities() {
+        fn db_query(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+            Ok(Expr::list(vec![Expr::string("row"), args[0].clone()]))
+        }
+        let capability =
+            || BuiltinFunction::new("db-query", db_query, BuiltinKind::Eager, Arity::Exact(1));
+
+        let mut engine = Engine::new();
+        engine.eval("(defn fetch (id) (db-query id))").unwrap();
+        assert_eq!(
+            engine.eval_with_capabilities("(fetch 7)", [capability()]),
+            parse("(\"row\" 7)")
+        );
+        assert!(engine.eval("(fetch 7)").is_err());
+        assert_eq!(engine.scope().get_variable("db-query"), None);
+
+        // Capabilities may stand in for builtins, which come back afterwards
+        let list = BuiltinFunction::new("list", db_query, BuiltinKind::Eager, Arity::Exact(1));
+        assert_eq!(
+            engine.eval_with_capabilities("(list 1)", [list]),
+            parse("(\"row\" 1)")
+        );
+        assert_eq!(engine.eval("(list 1 2)"), parse("(1 2)"));
+
+        // Nor can a capability be kept for later
+        assert_eq!(
+            engine
+                .eval_with_capabilities("(def stolen db-query)", [capability()])
+                .map(|_| ()),
+            Ok(())
+        );
+        assert_eq!(
+            engine.eval("(stolen 1)"),
+            Err("`db-query` was only lent to the evaluation it was given to".to_string())
+        );
+        assert_eq!(
+            engine.eval_with_capabilities("(stolen 1)", [capability()]),
+            Err("`db-query` was only lent to the evaluation it was given to".to_string())
+        );
+    }
+
+    #[test]
+    fn eval_hooks() {
+        let mut engine = Engine::new();
+        // Calls to `launch` are refused without evaluating their arguments
+        engine.set_before_eval(|expr| match expr {
+            Expr::List(list) if list.first() == Some(&Expr::symbol("launch")) => {
+                Some(parse("(quote refused)").unwrap())
+            }
+            _ => None,
+        });
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let audit = log.clone();
+        engine.set_after_eval(move |expr, result| {
+            audit
+                .borrow_mut()
+                .push(format!("{} => {}", expr_to_string(expr), result.is_ok()));
+        });
+
+        assert_eq!(
+            engine.eval("(launch (def x 1))"),
+            Ok(Expr::symbol("refused"))
+        );
+        assert_eq!(engine.scope().get_variable("x"), None);
+        assert!(engine.eval("(first 1)").is_err());
+        assert_eq!(
+            *log.borrow(),
+            ["(quote refused) => true", "(first 1) => false"]
+        );
+    }
+
+    #[test]
+    fn audit_log() {
+        let mut engine = Engine::with_config(EngineConfig {
+            features: Features {
+                audit: true,
+                ..Features::default()
+            },
+            ..EngineConfig::default()
+        });
+        engine.set_clock(FixedClock(1000.0));
+        engine.set_storage(MemoryStorage::default());
+        engine
+            .eval("(list (+ 1 2) (store-set! \"count\" (+ 1 2)))")
+            .unwrap();
+        assert!(engine.eval("(require \"missing\")").is_err());
+
+        assert_eq!(
+            engine.audit_log(),
+            vec![
+                AuditEntry {
+                    builtin: "store-set!".to_string(),
+                    args: vec![Expr::string("count"), Expr::int(3)],
+                    time_ms: 1000.0,
+                },
+                AuditEntry {
+                    builtin: "require".to_string(),
+                    args: vec![Expr::string("missing")],
+                    time_ms: 1000.0,
+                },
+            ]
+        );
+
+        let mut engine = Engine::new();
+        engine.set_storage(MemoryStorage::default());
+        engine.eval("(store-set! \"count\" 1)").unwrap();
+        assert!(engine.audit_log().is_empty());
+    }
+
+    #[test]
+    fn eval_with_context() {
+        let mut engine = Engine::new();
+        engine.eval("(def discount 5)").unwrap();
+
+        let context = HashMap::from([
+            ("price".to_string(), Expr::int(100)),
+            ("discount".to_string(), Expr::int(20)),
+        ]);
+        assert_eq!(
+            engine.eval_with_context("(+ price discount)", context),
+            Ok(Expr::int(120))
+        );
+
+        // Neither the context nor definitions made under it persist
+        assert_eq!(
+            engine.eval_with_context("(def price 1)", HashMap::new()),
+            Ok(Expr::int(1))
+        );
+        assert_eq!(engine.scope().get_variable("price"), None);
+        assert_eq!(engine.eval("discount"), Ok(Expr::int(5)));
+
+        engine.eval("(def g 1)").unwrap();
+        assert_eq!(
+            engine.eval_with_context("(eval (quote (def g 99)) :global)", HashMap::new()),
+            Ok(Expr::int(99))
+        );
+        assert_eq!(engine.eval("g"), Ok(Expr::int(1)));
+    }
+
+    #[test]
+    fn eval_cell() {
+        let mut engine = Engine::new();
+        engine.eval_cell("a", "(def x 1)");
+        engine.eval_cell("b", "(def y (+ x 1))");
+        engine.eval_cell("c", "(list y)");
+        engine.eval_cell("d", "(def z 5)");
+
+        let output = engine.eval_cell("a", "(def x 10)");
+        assert_eq!(output.result, Ok(Expr::int(10)));
+        assert_eq!(output.stale, vec!["b", "c"]);
+
+        // A cell that no longer defines a name takes the old definition away
+        let output = engine.eval_cell("d", "(+ 1 1)");
+        assert_eq!(output.stale, Vec::<String>::new());
+        assert_eq!(engine.scope().get_variable("z"), None);
+
+        let output = engine.eval_cell("a", "(+ 1 1)");
+        assert_eq!(engine.scope().get_variable("x"), None);
+        assert_eq!(output.stale, vec!["b", "c"]);
+        assert_eq!(
+            engine.eval_cell("b", "(def y (+ x 1))").result,
+            Err("Undefined symbol 'x'".to_string())
+        );
+    }
+
+    #[test]
+    fn eval_traced() {
+        let mut engine = Engine::new();
+        engine.eval("(def x 2)").unwrap();
+        let (result, steps) = engine.eval_traced("(+ 1 (+ x 3))");
+
+        assert_eq!(result, Ok(Expr::int(6)));
+        let steps: Vec<String> = steps.iter().map(Step::to_string).collect();
+        assert_eq!(steps, vec!["x => 2", "(+ x 3) => 5", "(+ 1 (+ x 3)) => 6"]);
+
+        assert_eq!(
+            engine.eval_to_string("(explain (+ 1 (+ 2 3)))"),
+            "(((+ 2 3) 5) ((+ 1 (+ 2 3)) 6))"
+        );
+    }
+
+    #[test]
+    fn reader_extensions() {
+        let mut engine = Engine::new();
+        engine
+            .reader()
+            .add_tag("twice", |form| {
+                Ok(Expr::list(vec![Expr::symbol("+"), form.clone(), form]))
+            })
+            .unwrap();
+        engine
+            .reader()
+            .add_dispatch('@', |form| {
+                Ok(Expr::list(vec![Expr::symbol("quote"), form]))
+            })
+            .unwrap();
+
+        assert_eq!(engine.eval_to_string("(list #twice 2 @x)"), "(4 x)");
+        assert_eq!(engine.eval("#twice #twice 1"), Ok(Expr::int(4)));
+        assert_eq!(
+            engine.eval("(list #uuid 1)"),
+            Err("Unknown reader tag '#uuid' at line 1, column 7".to_string())
+        );
+        assert_eq!(
+            engine.eval("(list @)"),
+            Err("Unexpected ')' at line 1, column 8".to_string())
+        );
+        assert!(engine.reader().add_dispatch('(', Ok).is_err());
+        // Other engines read only the usual grammar
+        assert!(Engine::new().eval("@x").is_err());
+    }
+
+    #[test]
+    fn tagged_literals() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(deftag point (fn (form) (list (quote quote) (list (quote point) (first form) (first (rest form))))) (fn (v) (if (list? v) (if (empty? v) () (if (eq? (first v) (quote point)) (rest v) ())) ())))")
+            .unwrap();
+
+        assert_eq!(engine.eval_to_string("#point (1 2)"), "#point (1 2)");
+        assert_eq!(engine.eval_to_string("(first #point (1 2))"), "point");
+        assert_eq!(
+            engine.eval_to_string("(list #point (1 2) 3)"),
+            "(#point (1 2) 3)"
+        );
+
+        engine
+            .reader()
+            .add_tag("n", |form| Ok(Expr::list(vec![Expr::symbol("n"), form])))
+            .unwrap();
+        engine
+            .reader()
+            .add_printer("n", |value| match value {
+                Expr::List(list) if list.first() == Some(&Expr::symbol("n")) => {
+                    list.get(1).cloned()
+                }
+                _ => None,
+            })
+            .unwrap();
+        let printed = engine.eval_to_string("(quote (#n 5 #n #n 6))");
+        assert_eq!(printed, "(#n 5 #n #n 6)");
+        assert_eq!(
+            engine.eval_to_string(&format!("(quote {})", printed)),
+            printed
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let mut engine = Engine::new();
+        engine.eval("(def base 10)").unwrap();
+        let source = "(defn add (x) (+ x base))\n(add 1)";
+
+        let edits = engine.rename(source, "add", "plus").unwrap();
+        assert_eq!(
+            apply_edits(source, &edits),
+            "(defn plus (x) (+ x base))\n(plus 1)"
+        );
+        assert_eq!(
+            apply_edits(source, &engine.rename(source, "base", "offset").unwrap()),
+            "(defn add (x) (+ x offset))\n(add 1)"
+        );
+        assert_eq!(
+            engine.rename(source, "add", "base"),
+            Err("'base' is already defined".to_string())
+        );
+        assert_eq!(
+            engine.rename(source, "sub", "minus"),
+            Err("'sub' is not defined".to_string())
+        );
+        assert_eq!(
+            engine.rename(source, "first", "head"),
+            Err("Cannot rename the builtin 'first'".to_string())
+        );
+    }
+
+    #[test]
+    fn compat_aliases() {
+        let mut engine = Engine::with_config(EngineConfig {
+            features: Features {
+                compat: true,
+                ..Features::default()
+            },
+            ..EngineConfig::default()
+        });
+        engine
+            .eval("(define (sum-pair p) (car p) (+ (car p) (car (cdr p))))")
+            .unwrap();
+        engine.eval("(define twice (lambda (x) (+ x x)))").unwrap();
+        assert_eq!(engine.eval("(sum-pair (quote (1 2)))"), Ok(Expr::int(3)));
+        assert_eq!(
+            engine.eval("(progn (setq y 4) (twice y))"),
+            Ok(Expr::int(8))
+        );
+        assert_eq!(
+            engine.eval("(car)"),
+            Err("wrong number of args (0) passed to: car (expects 1)".to_string())
+        );
+
+        assert!(Engine::new().eval("(car (quote (1)))").is_err());
+    }
+
+    #[test]
+    fn describe() {
+        let engine = Engine::with_config(EngineConfig {
+            features: Features {
+                compat: true,
+                ..Features::default()
+            },
+            ..EngineConfig::default()
+        });
+        let description = engine.describe();
+        assert_eq!(description.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(description.features, vec!["compat"]);
+        assert!(description.builtins.contains(&BuiltinDescription {
+            name: "car".to_string(),
+            kind: BuiltinKind::Eager,
+            arity: Arity::Exact(1),
+        }));
+
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval("(version)"),
+            Ok(Expr::string(env!("CARGO_PKG_VERSION")))
+        );
+        assert_eq!(
+            engine.eval("(first (builtins))"),
+            parse("{:name * :kind :eager :min-args 0 :max-args nil}")
+        );
+    }
+}
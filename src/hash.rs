@@ -0,0 +1,89 @@
+//! Digests for cache keys and integrity checks, behind the `hashing`
+//! feature.
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+
+/// The bytes to hash: the UTF-8 of a string or symbol, or a list of numbers
+/// from 0 to 255.
+fn input_bytes(name: &str, expr: &Expr) -> Result<Vec<u8>, String> {
+    let invalid = || {
+        message!(
+            "wrong-type",
+            name = name,
+            expected = "a string or a list of bytes",
+            position = 1,
+            actual = expr.describe()
+        )
+    };
+    match expr {
+        Expr::String(text) | Expr::Symbol(text) => Ok(text.as_bytes().to_vec()),
+        Expr::List(items) => items
+            .iter()
+            .map(|item| match item {
+                Expr::Int(n) => u8::try_from(*n).map_err(|_| invalid()),
+                Expr::Number(n) if n.fract() == 0.0 && (0.0..=255.0).contains(n) => Ok(*n as u8),
+                _ => Err(invalid()),
+            })
+            .collect(),
+        _ => Err(invalid()),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> Expr {
+    Expr::String(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// `(sha256 data)` is the SHA-256 digest of the data in hex.
+fn builtin_sha256(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let bytes = input_bytes("sha256", &args[0])?;
+    Ok(to_hex(&Sha256::digest(bytes)))
+}
+
+/// `(md5 data)` is the MD5 digest of the data in hex.
+fn builtin_md5(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let bytes = input_bytes("md5", &args[0])?;
+    Ok(to_hex(&Md5::digest(bytes)))
+}
+
+/// `(crc32 data)` is the CRC-32 checksum of the data as 8 hex digits.
+fn builtin_crc32(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let bytes = input_bytes("crc32", &args[0])?;
+    Ok(to_hex(&crc32fast::hash(&bytes).to_be_bytes()))
+}
+
+#[rustfmt::skip]
+pub(crate) const HASH_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("sha256", builtin_sha256, BuiltinKind::Eager, Arity::Exact(1)),
+    ("md5",    builtin_md5,    BuiltinKind::Eager, Arity::Exact(1)),
+    ("crc32",  builtin_crc32,  BuiltinKind::Eager, Arity::Exact(1)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn digests() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval_to_string(r#"(sha256 "abc")"#),
+            r#""ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad""#
+        );
+        assert_eq!(
+            engine.eval_to_string("(md5 (quote abc))"),
+            r#""900150983cd24fb0d6963f7d28e17f72""#
+        );
+        assert_eq!(
+            engine.eval_to_string("(crc32 (list 97 98 99))"),
+            r#""352441c2""#
+        );
+        assert_eq!(
+            engine.eval_to_string("(crc32 (list 256))"),
+            "Error: `crc32` expected a string or a list of bytes as argument 1, got the list (256)"
+        );
+    }
+}
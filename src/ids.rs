@@ -0,0 +1,106 @@
+//! Random identifiers, behind the `ids` feature. Both draw from the
+//! engine's random number generator, so a seeded engine generates the same
+//! identifiers every run.
+
+use crate::builtins::expect_number;
+use crate::engine::Runtime;
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+
+/// The alphabet of nanoid, safe in URLs and file names.
+const ID_ALPHABET: &[u8] = b"useandom-26T198340PX75pxJACKVERYMINDBUSHWOLF_GQZbfghjklqvwyzrict";
+
+const DEFAULT_ID_LENGTH: usize = 21;
+
+fn random_below(runtime: &Runtime, n: usize) -> usize {
+    ((runtime.random() * n as f64) as usize).min(n - 1)
+}
+
+/// `(uuid)` is a random (version 4) UUID in its usual hyphenated form.
+fn builtin_uuid(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let runtime = scope.runtime();
+    let mut bytes = [0u8; 16];
+    for byte in &mut bytes {
+        *byte = random_below(runtime, 256) as u8;
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut uuid = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            uuid.push('-');
+        }
+        uuid.push_str(&format!("{:02x}", byte));
+    }
+    Ok(Expr::String(uuid))
+}
+
+/// `(random-id)` is a random 21 character identifier like a nanoid, or with
+/// an argument one of that many characters.
+fn builtin_random_id(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let length = match args.first() {
+        None => DEFAULT_ID_LENGTH,
+        Some(arg) => {
+            let n = expect_number("random-id", 1, arg)?;
+            if n.fract() != 0.0 || n < 0.0 {
+                return Err(message!(
+                    "wrong-type",
+                    name = "random-id",
+                    expected = "a whole number",
+                    position = 1,
+                    actual = arg.describe()
+                ));
+            }
+            n as usize
+        }
+    };
+    let runtime = scope.runtime();
+    Ok(Expr::String(
+        (0..length)
+            .map(|_| ID_ALPHABET[random_below(runtime, ID_ALPHABET.len())] as char)
+            .collect(),
+    ))
+}
+
+#[rustfmt::skip]
+pub(crate) const ID_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("uuid",      builtin_uuid,      BuiltinKind::Eager, Arity::Exact(0)),
+    ("random-id", builtin_random_id, BuiltinKind::Eager, Arity::Range(0, 1)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{Engine, SeededRng};
+    use crate::interpreter::Expr;
+
+    fn seeded() -> Engine {
+        let mut engine = Engine::new();
+        engine.set_rng(SeededRng::new(7));
+        engine
+    }
+
+    #[test]
+    fn seeded_ids_repeat() {
+        let Ok(Expr::String(uuid)) = seeded().eval("(uuid)") else {
+            panic!("uuid is not a string");
+        };
+        assert_eq!(seeded().eval("(uuid)"), Ok(Expr::String(uuid.clone())));
+        let groups: Vec<usize> = uuid.split('-').map(str::len).collect();
+        assert_eq!(groups, vec![8, 4, 4, 4, 12]);
+        assert_eq!(&uuid[14..15], "4");
+
+        let mut engine = seeded();
+        let Ok(Expr::String(id)) = engine.eval("(random-id)") else {
+            panic!("random-id is not a string");
+        };
+        assert_eq!(id.len(), 21);
+        assert_ne!(engine.eval("(random-id)"), Ok(Expr::String(id)));
+        assert_eq!(
+            engine.eval("(random-id 1.5)"),
+            Err(
+                "`random-id` expected a whole number as argument 1, got the number 1.5".to_string()
+            )
+        );
+    }
+}
@@ -4,23 +4,86 @@ pub enum BuiltinKind {
     SpecialForm,
 }
 
+/// The number of arguments a builtin accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+            Arity::Range(min, max) => (min..=max).contains(&count),
+        }
+    }
+
+    pub fn min(&self) -> usize {
+        match *self {
+            Arity::Exact(n) | Arity::AtLeast(n) | Arity::Range(n, _) => n,
+        }
+    }
+
+    /// The most arguments accepted, `None` for any number.
+    pub fn max(&self) -> Option<usize> {
+        match *self {
+            Arity::Exact(n) | Arity::Range(_, n) => Some(n),
+            Arity::AtLeast(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+            Arity::Range(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+pub fn arity_error(name: &str, given: usize, expected: impl fmt::Display) -> String {
+    message!(
+        "wrong-arity",
+        given = given,
+        name = name,
+        expected = expected
+    )
+}
+
+pub type BuiltinFn = fn(&[Expr], &mut Scope) -> Result<Expr, String>;
+
 #[derive(Clone)]
 pub struct BuiltinFunction {
     pub name: String,
-    pub func: fn(&[Expr], &mut Scope) -> Result<Expr, String>,
+    pub func: BuiltinFn,
     pub kind: BuiltinKind,
+    pub arity: Arity,
+    /// For a capability, the token of the evaluation it was lent to, the only
+    /// one that may call it.
+    pub capability: Option<u64>,
 }
 
 impl BuiltinFunction {
-    pub fn new(
-        name: impl Into<String>,
-        func: fn(&[Expr], &mut Scope) -> Result<Expr, String>,
-        kind: BuiltinKind,
-    ) -> Self {
+    pub fn new(name: impl Into<String>, func: BuiltinFn, kind: BuiltinKind, arity: Arity) -> Self {
         BuiltinFunction {
             name: name.into(),
             func,
             kind,
+            arity,
+            capability: None,
+        }
+    }
+
+    pub fn check_arity(&self, given: usize) -> Result<(), String> {
+        if self.arity.accepts(given) {
+            Ok(())
+        } else {
+            Err(arity_error(&self.name, given, self.arity))
         }
     }
 }
@@ -37,31 +100,189 @@ impl PartialEq for BuiltinFunction {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Function {
+    /// The name the function was defined under, if any.
+    pub name: Option<String>,
     pub parameters: Vec<String>,
     pub body: Box<Expr>,
-    pub closure: Rc<Scope>,
+    pub closure: Scope,
+    /// Declared free of side effects with `^:pure`, so results are memoized.
+    pub pure: bool,
+    /// Declared with `^:private`, so left out of the module's exports.
+    pub private: bool,
+    /// Specs for the parameters, checked when instrumentation is enabled;
+    /// empty when none were given.
+    pub parameter_specs: Vec<Option<Expr>>,
+    cache: RefCell<HashMap<Vec<Expr>, Expr>>,
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.parameters == other.parameters
+            && self.body == other.body
+            && self.closure == other.closure
+            && self.pure == other.pure
+            && self.private == other.private
+            && self.parameter_specs == other.parameter_specs
+    }
 }
 
 impl Function {
-    fn new(parameters: Vec<String>, body: Box<Expr>, closure: Rc<Scope>) -> Self {
+    pub fn new(parameters: Vec<String>, body: Box<Expr>, closure: Scope) -> Self {
+        closure.runtime.captured_frames().capture(&closure.frame);
         Function {
+            name: None,
             parameters,
             body,
             closure,
+            pure: false,
+            private: false,
+            parameter_specs: Vec::new(),
+            cache: RefCell::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("fn")
+    }
+
+    /// Checks the arguments of a call to this function, including against
+    /// their specs when instrumentation is enabled, and counts the call.
+    pub(crate) fn check_call(&self, args: &[Expr], runtime: &Runtime) -> Result<(), String> {
+        self.check_arity(args.len())?;
+        runtime.record_call();
+
+        if !self.parameter_specs.is_empty() && runtime.config.features.instrument {
+            check_arguments(self, args)?;
+        }
+        Ok(())
+    }
+
+    /// A new frame over the closure with the parameters bound to `args`.
+    pub(crate) fn bind(&self, args: &[Expr]) -> Scope {
+        let mut local_scope = Scope::with_parent(&self.closure);
+        for (param, arg) in self.parameters.iter().zip(args) {
+            local_scope.set_variable(param.clone(), arg.clone());
+        }
+        local_scope
+    }
+
+    pub fn check_arity(&self, given: usize) -> Result<(), String> {
+        if given == self.parameters.len() {
+            Ok(())
+        } else {
+            Err(arity_error(self.name(), given, self.parameters.len()))
         }
     }
 }
 
+/// A value that can key a map or be in a set: a keyword, a string, or a
+/// number other than NaN. Keys are the same when `eq?` says they are, so `1`
+/// and `1.0` are different keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MapKey(Expr);
+
+impl MapKey {
+    pub fn new(expr: Expr) -> Option<MapKey> {
+        match &expr {
+            Expr::Keyword(_)
+            | Expr::String(_)
+            | Expr::Int(_)
+            | Expr::BigInt(_)
+            | Expr::Ratio(_) => Some(MapKey(expr)),
+            Expr::Number(n) if !n.is_nan() => Some(MapKey(expr)),
+            _ => None,
+        }
+    }
+
+    /// The key `:name`, which builtins returning maps key them with.
+    pub fn keyword(name: impl Into<String>) -> MapKey {
+        MapKey(Expr::keyword(name))
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.0
+    }
+}
+
+/// Keys are ordered as `compare` orders them, with an exact number before a
+/// float of the same value, so that `1` always comes before `1.0`.
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let is_float = |key: &MapKey| matches!(key.0, Expr::Number(_));
+        // Keys are never NaN, so they always compare
+        self.0
+            .compare(&other.0)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| is_float(self).cmp(&is_float(other)))
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub type Map = HashMap<MapKey, Expr>;
+
+/// The entries of the map in the order of their keys, for anything that
+/// shows, lists or hashes them, so that equal maps always agree.
+pub fn sorted_entries(map: &Map) -> Vec<(&MapKey, &Expr)> {
+    let mut entries: Vec<(&MapKey, &Expr)> = map.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+pub type Set = HashSet<MapKey>;
+
+/// The members of the set in order, like the keys of `sorted_entries`.
+pub fn sorted_members(set: &Set) -> Vec<&MapKey> {
+    let mut members: Vec<&MapKey> = set.iter().collect();
+    members.sort();
+    members
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Symbol(String),
+    /// Written `:name`, and evaluates to itself. Holds the name without the
+    /// colon.
+    Keyword(String),
+    String(String),
+    /// Written `#\a`, or `#\space` and the like for characters without a
+    /// visible form.
+    Char(char),
+    Int(i64),
+    /// An integer too large for an `Int`; smaller ones are always `Int`.
+    BigInt(BigInt),
+    /// An exact fraction such as `1/3`; whole numbers are always integers.
+    Ratio(BigRational),
+    /// A float.
     Number(f64),
+    Bool(bool),
+    Nil,
     List(Vec<Expr>),
-    Lambda(Vec<String>, Box<Expr>),
+    /// Written `[1 2 3]`, for data that is indexed rather than walked. Its
+    /// elements are shared between copies of the vector.
+    Vector(Rc<Vec<Expr>>),
+    /// Written `{:a 1 :b 2}`, from keys to values. Shared between copies
+    /// like a vector.
+    Map(Rc<Map>),
+    /// Written `#{1 2 3}`, holding each key at most once. Shared between
+    /// copies like a vector.
+    Set(Rc<Set>),
     Function(Rc<Function>),
     BuiltinFunction(BuiltinFunction),
+    /// A handle to something outside the interpreter, such as an open file.
+    External(Rc<dyn Resource>),
+    /// Made by `weak-ref`, refers to a handle or function without keeping
+    /// it alive.
+    Weak(WeakRef),
+    #[cfg(feature = "ndarray")]
+    Matrix(Rc<crate::matrix::Matrix>),
 }
 
 impl Expr {
@@ -69,41 +290,432 @@ impl Expr {
         Expr::Symbol(s.into())
     }
 
+    pub fn keyword(name: impl Into<String>) -> Self {
+        Expr::Keyword(name.into())
+    }
+
+    pub fn string(s: impl Into<String>) -> Self {
+        Expr::String(s.into())
+    }
+
+    pub fn int(n: i64) -> Self {
+        Expr::Int(n)
+    }
+
+    /// An integer of any size, as an `Int` if it fits in one.
+    pub fn integer(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(n) => Expr::Int(n),
+            None => Expr::BigInt(n),
+        }
+    }
+
+    /// An exact number, as an integer if it is a whole number.
+    pub fn rational(n: BigRational) -> Self {
+        if n.is_integer() {
+            Expr::integer(n.to_integer())
+        } else {
+            Expr::Ratio(n)
+        }
+    }
+
     pub fn number(n: f64) -> Self {
         Expr::Number(n)
     }
 
+    pub fn bool(b: bool) -> Self {
+        Expr::Bool(b)
+    }
+
     pub fn list(expressions: Vec<Expr>) -> Self {
         Expr::List(expressions)
     }
 
-    pub fn lambda(parameters: Vec<String>, body: Expr) -> Self {
-        Expr::Lambda(parameters, Box::new(body))
+    pub fn vector(items: Vec<Expr>) -> Self {
+        Expr::Vector(Rc::new(items))
     }
 
-    pub fn function(parameters: Vec<String>, body: Expr, closure: Rc<Scope>) -> Self {
+    pub fn map(map: Map) -> Self {
+        Expr::Map(Rc::new(map))
+    }
+
+    pub fn set(set: Set) -> Self {
+        Expr::Set(Rc::new(set))
+    }
+
+    pub fn function(parameters: Vec<String>, body: Expr, closure: Scope) -> Self {
         Expr::Function(Rc::new(Function::new(parameters, Box::new(body), closure)))
     }
 
+    pub fn external(resource: impl Resource) -> Self {
+        Expr::External(Rc::new(resource))
+    }
+
     pub fn builtin_function(
         name: impl Into<String>,
-        func: fn(&[Expr], &mut Scope) -> Result<Expr, String>,
+        func: BuiltinFn,
         kind: BuiltinKind,
+        arity: Arity,
     ) -> Self {
-        Expr::BuiltinFunction(BuiltinFunction::new(name, func, kind))
+        Expr::BuiltinFunction(BuiltinFunction::new(name, func, kind, arity))
     }
-}
 
-use core::fmt;
-use std::collections::HashMap;
-use std::rc::Rc;
+    /// Describes the kind of value, for use in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Expr::Symbol(_) => "symbol",
+            Expr::Keyword(_) => "keyword",
+            Expr::String(_) => "string",
+            Expr::Char(_) => "character",
+            Expr::Int(_) | Expr::BigInt(_) => "integer",
+            Expr::Ratio(_) => "ratio",
+            Expr::Number(_) => "number",
+            Expr::Bool(_) => "boolean",
+            Expr::Nil => "nil",
+            Expr::List(_) => "list",
+            Expr::Vector(_) => "vector",
+            Expr::Map(_) => "map",
+            Expr::Set(_) => "set",
+            Expr::Function(_) => "function",
+            Expr::BuiltinFunction(_) => "builtin function",
+            Expr::External(_) => "handle",
+            Expr::Weak(_) => "weak reference",
+            #[cfg(feature = "ndarray")]
+            Expr::Matrix(_) => "matrix",
+        }
+    }
+
+    /// Describes the value itself, e.g. "the number 42", for use in error messages.
+    pub fn describe(&self) -> String {
+        match self {
+            Expr::Nil => "nil".to_string(),
+            Expr::Symbol(_)
+            | Expr::Keyword(_)
+            | Expr::String(_)
+            | Expr::Char(_)
+            | Expr::Int(_)
+            | Expr::BigInt(_)
+            | Expr::Ratio(_)
+            | Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::List(_)
+            | Expr::Vector(_)
+            | Expr::Map(_)
+            | Expr::Set(_) => {
+                format!("the {} {}", self.type_name(), expr_to_string(self))
+            }
+            _ => format!("a {}", self.type_name()),
+        }
+    }
+
+    /// Orders comparable values: nil before booleans before numbers before
+    /// keywords before symbols before characters before strings before lists
+    /// before vectors, with `false` before `true`, integers and floats by
+    /// value, keywords by name, characters by code point and lists and vectors
+    /// compared element by element. Maps, sets and functions cannot be
+    /// ordered.
+    pub fn compare(&self, other: &Expr) -> Result<Ordering, String> {
+        fn rank(expr: &Expr) -> Option<u8> {
+            match expr {
+                Expr::Nil => Some(0),
+                Expr::Bool(_) => Some(1),
+                Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio(_) | Expr::Number(_) => Some(2),
+                Expr::Keyword(_) => Some(3),
+                Expr::Symbol(_) => Some(4),
+                Expr::Char(_) => Some(5),
+                Expr::String(_) => Some(6),
+                Expr::List(_) => Some(7),
+                Expr::Vector(_) => Some(8),
+                Expr::Map(_)
+                | Expr::Set(_)
+                | Expr::Function(_)
+                | Expr::BuiltinFunction(_)
+                | Expr::External(_)
+                | Expr::Weak(_) => None,
+                #[cfg(feature = "ndarray")]
+                Expr::Matrix(_) => None,
+            }
+        }
+
+        fn compare_items(a: &[Expr], b: &[Expr]) -> Result<Ordering, String> {
+            for (x, y) in a.iter().zip(b) {
+                match x.compare(y)? {
+                    Ordering::Equal => {}
+                    ordering => return Ok(ordering),
+                }
+            }
+            Ok(a.len().cmp(&b.len()))
+        }
+
+        match (self, other) {
+            (Expr::Int(a), Expr::Int(b)) => Ok(a.cmp(b)),
+            (
+                Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio(_),
+                Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio(_),
+            ) => Ok(self.as_rational().cmp(&other.as_rational())),
+            (
+                Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio(_) | Expr::Number(_),
+                Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio(_) | Expr::Number(_),
+            ) => {
+                let (a, b) = (self.as_number(), other.as_number());
+                a.partial_cmp(&b).ok_or_else(|| message!("compare-nan"))
+            }
+            (Expr::Nil, Expr::Nil) => Ok(Ordering::Equal),
+            (Expr::Bool(a), Expr::Bool(b)) => Ok(a.cmp(b)),
+            (Expr::Symbol(a), Expr::Symbol(b))
+            | (Expr::Keyword(a), Expr::Keyword(b))
+            | (Expr::String(a), Expr::String(b)) => Ok(a.cmp(b)),
+            (Expr::Char(a), Expr::Char(b)) => Ok(a.cmp(b)),
+            (Expr::List(a), Expr::List(b)) => compare_items(a, b),
+            (Expr::Vector(a), Expr::Vector(b)) => compare_items(a, b),
+            _ => match (rank(self), rank(other)) {
+                (Some(a), Some(b)) => Ok(a.cmp(&b)),
+                _ => Err(message!(
+                    "cannot-compare",
+                    left = self.describe(),
+                    right = other.describe()
+                )),
+            },
+        }
+    }
+
+    /// Whether the value counts as true in a conditional: everything but
+    /// `false` and `nil`, so 0 and the empty list are true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Expr::Bool(false) | Expr::Nil)
+    }
+
+    /// The value of an integer or float as a float, or `None` for anything
+    /// else.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Expr::Int(n) => Some(*n as f64),
+            Expr::BigInt(n) => n.to_f64(),
+            Expr::Ratio(n) => n.to_f64(),
+            Expr::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value of an integer or ratio, or `None` for floats and anything
+    /// else.
+    pub fn as_rational(&self) -> Option<BigRational> {
+        match self {
+            Expr::Int(n) => Some(BigRational::from_integer(BigInt::from(*n))),
+            Expr::BigInt(n) => Some(BigRational::from_integer(n.clone())),
+            Expr::Ratio(n) => Some(n.clone()),
+            _ => None,
+        }
+    }
 
-use crate::builtins::initialize_global_scope;
+    /// Names an anonymous function value; other values are returned unchanged.
+    pub fn with_name(self, name: &str) -> Self {
+        match self {
+            Expr::Function(func) if func.name.is_none() => Expr::Function(Rc::new(Function {
+                name: Some(name.to_string()),
+                ..(*func).clone()
+            })),
+            expr => expr,
+        }
+    }
+
+    /// Lists the places where two values differ, descending into lists and
+    /// vectors element by element and into maps key by key; equal values
+    /// have no differences.
+    pub fn diff(a: &Expr, b: &Expr) -> Vec<Difference> {
+        Expr::diff_by(a, b, &|a, b| a == b)
+    }
+
+    /// Like `diff`, with `same` telling whether two values that are not
+    /// lists, vectors or maps are alike, such as numbers close enough.
+    pub fn diff_by(a: &Expr, b: &Expr, same: &dyn Fn(&Expr, &Expr) -> bool) -> Vec<Difference> {
+        type Step<'a> = (Expr, Option<&'a Expr>, Option<&'a Expr>);
+
+        fn indexed<'a>(left: &'a [Expr], right: &'a [Expr]) -> Vec<Step<'a>> {
+            (0..left.len().max(right.len()))
+                .map(|i| (Expr::int(i as i64), left.get(i), right.get(i)))
+                .collect()
+        }
+
+        fn walk(
+            a: &Expr,
+            b: &Expr,
+            same: &dyn Fn(&Expr, &Expr) -> bool,
+            path: &mut Vec<Expr>,
+            differences: &mut Vec<Difference>,
+        ) {
+            let steps = match (a, b) {
+                (Expr::List(left), Expr::List(right)) => indexed(left, right),
+                (Expr::Vector(left), Expr::Vector(right)) => indexed(left, right),
+                (Expr::Map(left), Expr::Map(right)) => {
+                    let keys: BTreeSet<&MapKey> = left.keys().chain(right.keys()).collect();
+                    keys.into_iter()
+                        .map(|key| (key.expr().clone(), left.get(key), right.get(key)))
+                        .collect()
+                }
+                _ if same(a, b) => return,
+                _ => {
+                    differences.push(Difference {
+                        path: path.clone(),
+                        left: Some(a.clone()),
+                        right: Some(b.clone()),
+                    });
+                    return;
+                }
+            };
+            for (key, x, y) in steps {
+                path.push(key);
+                match (x, y) {
+                    (Some(x), Some(y)) => walk(x, y, same, path, differences),
+                    (x, y) => differences.push(Difference {
+                        path: path.clone(),
+                        left: x.cloned(),
+                        right: y.cloned(),
+                    }),
+                }
+                path.pop();
+            }
+        }
 
+        let mut differences = Vec::new();
+        walk(a, b, same, &mut Vec::new(), &mut differences);
+        differences
+    }
+}
+
+/// One place where two values differ, as found by `Expr::diff`.
 #[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// The indices into nested lists and vectors, and keys into nested
+    /// maps, leading to the differing element.
+    pub path: Vec<Expr>,
+    /// The element on either side, `None` where a list or vector is shorter
+    /// or a map lacks the key.
+    pub left: Option<Expr>,
+    pub right: Option<Expr>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |expr: &Option<Expr>| match expr {
+            Some(expr) => expr_to_string(expr),
+            None => "nothing".to_string(),
+        };
+        if self.path.is_empty() {
+            write!(f, "{} vs {}", side(&self.left), side(&self.right))
+        } else {
+            let path: Vec<String> = self.path.iter().map(expr_to_string).collect();
+            write!(
+                f,
+                "at ({}): {} vs {}",
+                path.join(" "),
+                side(&self.left),
+                side(&self.right)
+            )
+        }
+    }
+}
+
+/// `Expr` is used as a key in hash maps and sets. Equality on numbers is
+/// floating-point equality, so a NaN is never equal to anything, itself
+/// included, and will not be found again once inserted.
+impl Eq for Expr {}
+
+impl PartialOrd for Expr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare(other).ok()
+    }
+}
+
+impl Hash for Expr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Expr::Symbol(s) | Expr::Keyword(s) | Expr::String(s) => s.hash(state),
+            Expr::Char(c) => c.hash(state),
+            Expr::Int(n) => n.hash(state),
+            Expr::BigInt(n) => n.hash(state),
+            Expr::Ratio(n) => n.hash(state),
+            // 0.0 and -0.0 are equal, so they have to hash the same
+            Expr::Number(n) => (if *n == 0.0 { 0.0 } else { *n }).to_bits().hash(state),
+            Expr::Bool(b) => b.hash(state),
+            Expr::Nil => {}
+            Expr::List(list) => list.hash(state),
+            Expr::Vector(items) => items.hash(state),
+            Expr::Map(map) => sorted_entries(map).hash(state),
+            Expr::Set(set) => sorted_members(set).hash(state),
+            // Equal functions share parameters and body; hashing a subset of
+            // what equality compares keeps the two consistent.
+            Expr::Function(func) => {
+                func.parameters.hash(state);
+                func.body.hash(state);
+            }
+            Expr::BuiltinFunction(builtin) => builtin.name.hash(state),
+            Expr::External(resource) => resource.hash(state),
+            Expr::Weak(weak) => weak.hash(state),
+            #[cfg(feature = "ndarray")]
+            Expr::Matrix(matrix) => crate::matrix::hash_matrix(matrix, state),
+        }
+    }
+}
+
+use core::fmt;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+
+use crate::builtins::{initialize_global_scope, is_side_effecting};
+use crate::engine::{EngineConfig, Runtime};
+use crate::messages::message;
+use crate::module::Namespace;
+use crate::parser::expr_to_string;
+use crate::resource::{Resource, WeakRef};
+use crate::spec::check_arguments;
+
+// Bindings are kept sorted so anything listing them is deterministic.
+#[derive(Default)]
+struct Frame {
+    variables: BTreeMap<String, Expr>,
+    docs: BTreeMap<String, String>,
+    parent: Option<Scope>,
+    /// Stands in for the parent until its bindings are committed to it.
+    overlay: bool,
+    /// Declared with `ns`, making the frame a module's.
+    namespace: Option<Namespace>,
+}
+
+/// A handle to a frame of variable bindings. Cloning a scope is cheap and
+/// yields another handle to the same frame, which is how closures capture
+/// their defining environment by reference.
+#[derive(Clone)]
 pub struct Scope {
-    variables: HashMap<String, Expr>,
-    parent: Option<Rc<Scope>>,
+    frame: Rc<RefCell<Frame>>,
+    runtime: Rc<Runtime>,
+}
+
+impl fmt::Debug for Scope {
+    // Closures usually live in the scope they capture, so printing the
+    // bindings themselves would never terminate.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame = self.frame.borrow();
+        f.debug_struct("Scope")
+            .field("variables", &frame.variables.keys().collect::<Vec<_>>())
+            .field("parent", &frame.parent)
+            .finish()
+    }
+}
+
+impl PartialEq for Scope {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.frame, &other.frame)
+    }
 }
 
 impl Default for Scope {
@@ -114,30 +726,230 @@ impl Default for Scope {
 
 impl Scope {
     pub fn new() -> Self {
-        let mut scope = Scope {
-            variables: HashMap::new(),
-            parent: None,
+        Scope::with_config(EngineConfig::default())
+    }
+
+    /// Creates a global scope whose parent is a separate layer holding the
+    /// builtins, so definitions can shadow a builtin but never overwrite it.
+    pub fn with_config(config: EngineConfig) -> Self {
+        let mut builtins = Scope {
+            frame: Rc::default(),
+            runtime: Rc::new(Runtime::new(config)),
         };
-        initialize_global_scope(&mut scope);
+        initialize_global_scope(&mut builtins);
 
-        scope
+        Scope::with_parent(&builtins)
     }
 
-    pub fn with_parent(parent: Rc<Scope>) -> Self {
+    pub fn with_parent(parent: &Scope) -> Self {
+        parent.runtime.record_allocation();
         Scope {
-            variables: HashMap::new(),
-            parent: Some(parent),
+            frame: Rc::new(RefCell::new(Frame {
+                parent: Some(parent.clone()),
+                ..Frame::default()
+            })),
+            runtime: parent.runtime.clone(),
+        }
+    }
+
+    /// Creates a scope whose definitions stay out of `parent` until
+    /// `commit` moves them there, and are lost if the scope is dropped
+    /// instead.
+    pub fn overlay(parent: &Scope) -> Self {
+        let scope = Scope::with_parent(parent);
+        scope.frame.borrow_mut().overlay = true;
+        scope
+    }
+
+    /// Moves the bindings made directly in this overlay into its parent.
+    /// The overlay is left empty, so closures that captured it see the
+    /// parent's bindings from now on.
+    pub fn commit(&self) {
+        let (variables, mut docs, namespace, parent) = {
+            let mut frame = self.frame.borrow_mut();
+            let Some(parent) = frame.parent.clone() else {
+                return;
+            };
+            (
+                mem::take(&mut frame.variables),
+                mem::take(&mut frame.docs),
+                frame.namespace.take(),
+                parent,
+            )
+        };
+        let mut frame = parent.frame.borrow_mut();
+        if namespace.is_some() {
+            frame.namespace = namespace;
+        }
+        for (name, value) in variables {
+            match docs.remove(&name) {
+                Some(doc) => frame.docs.insert(name.clone(), doc),
+                None => frame.docs.remove(&name),
+            };
+            frame.variables.insert(name, value);
         }
     }
 
+    pub fn runtime(&self) -> &Rc<Runtime> {
+        &self.runtime
+    }
+
+    pub fn parent(&self) -> Option<Scope> {
+        self.frame.borrow().parent.clone()
+    }
+
+    /// Identifies the frame this handle points to, for as long as it lives.
+    pub fn id(&self) -> usize {
+        Rc::as_ptr(&self.frame) as usize
+    }
+
+    /// Whether this is the global scope, directly above the builtins, or an
+    /// overlay standing in for it.
+    pub fn is_global(&self) -> bool {
+        match self.parent() {
+            Some(parent) if self.frame.borrow().overlay => parent.is_global(),
+            Some(parent) => parent.parent().is_none(),
+            None => false,
+        }
+    }
+
+    /// The global scope this scope is in, or the overlay standing in for it.
+    pub fn global(&self) -> Scope {
+        let mut scope = self.clone();
+        while !scope.is_global() {
+            match scope.parent() {
+                Some(parent) => scope = parent,
+                None => break,
+            }
+        }
+        scope
+    }
+
+    /// Whether `name` is one of the builtins.
+    pub fn is_builtin(&self, name: &str) -> bool {
+        match self.parent() {
+            Some(parent) => parent.is_builtin(name),
+            None => self.frame.borrow().variables.contains_key(name),
+        }
+    }
+
+    /// Whether `name` is bound in this scope or one enclosing it, not counting
+    /// the builtins.
+    pub fn is_bound(&self, name: &str) -> bool {
+        let frame = self.frame.borrow();
+        match &frame.parent {
+            Some(parent) => frame.variables.contains_key(name) || parent.is_bound(name),
+            None => false,
+        }
+    }
+
+    /// The builtins registered in the layer below the global scope, sorted
+    /// by name.
+    pub fn builtins(&self) -> Vec<BuiltinFunction> {
+        match self.parent() {
+            Some(parent) => parent.builtins(),
+            None => self
+                .frame
+                .borrow()
+                .variables
+                .values()
+                .filter_map(|value| match value {
+                    Expr::BuiltinFunction(builtin) => Some(builtin.clone()),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// The bindings made directly in this scope, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Expr)> {
+        self.frame
+            .borrow()
+            .variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// The names visible from this scope, excluding builtins, sorted.
+    pub fn user_symbols(&self) -> Vec<String> {
+        self.symbols(false)
+    }
+
+    /// The names visible from this scope, builtins included, sorted.
+    pub fn visible_symbols(&self) -> Vec<String> {
+        self.symbols(true)
+    }
+
+    fn symbols(&self, builtins: bool) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut scope = Some(self.clone());
+        while let Some(current) = scope {
+            let parent = current.parent();
+            if builtins || parent.is_some() {
+                names.extend(current.frame.borrow().variables.keys().cloned());
+            }
+            scope = parent;
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
     pub fn set_variable(&mut self, name: String, value: Expr) {
-        self.variables.insert(name, value);
+        self.frame.borrow_mut().variables.insert(name, value);
+    }
+
+    /// Drops the bindings made directly in this scope. A closure keeps the
+    /// frame it was defined in alive, so a frame binding one is only freed,
+    /// along with everything else it binds, once it is cleared.
+    pub fn clear(&self) {
+        let variables = mem::take(&mut self.frame.borrow_mut().variables);
+        self.frame.borrow_mut().docs.clear();
+        drop(variables);
+    }
+
+    /// Removes a binding made directly in this scope, with its docstring.
+    pub fn remove_variable(&mut self, name: &str) -> Option<Expr> {
+        let mut frame = self.frame.borrow_mut();
+        frame.docs.remove(name);
+        frame.variables.remove(name)
+    }
+
+    pub fn set_doc(&mut self, name: String, doc: Option<String>) {
+        let mut frame = self.frame.borrow_mut();
+        match doc {
+            Some(doc) => frame.docs.insert(name, doc),
+            None => frame.docs.remove(&name),
+        };
+    }
+
+    /// Looks up the docstring of the binding that `name` currently resolves to.
+    pub fn get_doc(&self, name: &str) -> Option<String> {
+        let frame = self.frame.borrow();
+        if frame.variables.contains_key(name) {
+            return frame.docs.get(name).cloned();
+        }
+        frame
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.get_doc(name))
     }
 
-    pub fn get_variable(&self, name: &str) -> Option<&Expr> {
-        match self.variables.get(name) {
-            Some(value) => Some(value),
-            None => match &self.parent {
+    /// The namespace declared directly in this scope, if any.
+    pub fn namespace(&self) -> Option<Namespace> {
+        self.frame.borrow().namespace.clone()
+    }
+
+    pub fn set_namespace(&mut self, namespace: Namespace) {
+        self.frame.borrow_mut().namespace = Some(namespace);
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<Expr> {
+        let frame = self.frame.borrow();
+        match frame.variables.get(name) {
+            Some(value) => Some(value.clone()),
+            None => match &frame.parent {
                 Some(parent) => parent.get_variable(name),
                 None => None,
             },
@@ -145,100 +957,388 @@ impl Scope {
     }
 }
 
-fn apply_function(func: Expr, args: Vec<Expr>, scope: &mut Scope) -> Result<Expr, String> {
-    match func {
-        Expr::Function(func) => {
-            if args.len() != func.parameters.len() {
-                return Err("Argument count does not match parameter count".to_string());
+/// The frames closures were made in. A call frame binding a closure made in
+/// it stays alive through the closure long after the call returns, so
+/// `collect_cycles` looks among these for frames nothing else reaches.
+#[derive(Default)]
+pub(crate) struct CapturedFrames {
+    frames: Vec<Weak<RefCell<Frame>>>,
+    /// How many frames to hold before forgetting the freed ones.
+    limit: usize,
+}
+
+impl CapturedFrames {
+    fn capture(&mut self, frame: &Rc<RefCell<Frame>>) {
+        if self
+            .frames
+            .last()
+            .is_some_and(|last| last.as_ptr() == Rc::as_ptr(frame))
+        {
+            return;
+        }
+        if self.frames.len() >= self.limit {
+            self.frames.retain(|frame| frame.strong_count() > 0);
+            self.limit = (self.frames.len() * 2).max(1024);
+        }
+        self.frames.push(Rc::downgrade(frame));
+    }
+}
+
+/// A frame or function in the graph `collect_cycles` searches.
+enum Node {
+    Frame(Rc<RefCell<Frame>>),
+    Function(Rc<Function>),
+}
+
+impl Node {
+    fn address(&self) -> *const () {
+        match self {
+            Node::Frame(frame) => Rc::as_ptr(frame).cast(),
+            Node::Function(function) => Rc::as_ptr(function).cast(),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Frame(frame) => Rc::strong_count(frame),
+            Node::Function(function) => Rc::strong_count(function),
+        }
+    }
+}
+
+/// The captured frames, the frames they are in and the functions they
+/// bind, with the references among them.
+#[derive(Default)]
+struct CycleGraph {
+    nodes: Vec<Node>,
+    index: HashMap<*const (), usize>,
+    edges: Vec<Vec<usize>>,
+    /// How many of each node's references come from other nodes.
+    internal: Vec<usize>,
+    /// Nodes whose references could not be followed, which are kept.
+    pinned: Vec<bool>,
+}
+
+impl CycleGraph {
+    fn add(&mut self, node: Node, pending: &mut Vec<usize>) -> usize {
+        if let Some(&index) = self.index.get(&node.address()) {
+            return index;
+        }
+        let index = self.nodes.len();
+        self.index.insert(node.address(), index);
+        self.nodes.push(node);
+        self.edges.push(Vec::new());
+        self.internal.push(0);
+        self.pinned.push(false);
+        pending.push(index);
+        index
+    }
+
+    fn link(&mut self, from: usize, to: usize) {
+        self.edges[from].push(to);
+        self.internal[to] += 1;
+    }
+
+    /// Adds the frame unless it is global or the builtins, which the engine
+    /// keeps and which are never collected.
+    fn add_frame(&mut self, scope: &Scope, pending: &mut Vec<usize>) -> Option<usize> {
+        if scope.parent().is_none() || scope.is_global() {
+            return None;
+        }
+        Some(self.add(Node::Frame(scope.frame.clone()), pending))
+    }
+
+    /// Links `from` to each function in the value, looking inside vectors
+    /// and maps only when nothing else shares them.
+    fn add_functions(&mut self, from: usize, value: &Expr, pending: &mut Vec<usize>) {
+        match value {
+            Expr::Function(function) => {
+                let to = self.add(Node::Function(function.clone()), pending);
+                self.link(from, to);
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.add_functions(from, item, pending);
+                }
             }
+            Expr::Vector(items) if Rc::strong_count(items) == 1 => {
+                for item in items.iter() {
+                    self.add_functions(from, item, pending);
+                }
+            }
+            Expr::Map(map) if Rc::strong_count(map) == 1 => {
+                for value in map.values() {
+                    self.add_functions(from, value, pending);
+                }
+            }
+            _ => {}
+        }
+    }
 
-            let mut local_scope = Scope::with_parent(scope.clone().into());
-            for (param, arg) in func.parameters.iter().zip(args) {
-                local_scope.set_variable(param.clone(), arg);
+    fn follow(&mut self, index: usize, runtime: &Rc<Runtime>, pending: &mut Vec<usize>) {
+        match &self.nodes[index] {
+            Node::Frame(frame) => {
+                let frame = frame.clone();
+                let Ok(frame) = frame.try_borrow() else {
+                    self.pinned[index] = true;
+                    return;
+                };
+                if let Some(parent) = &frame.parent {
+                    if let Some(to) = self.add_frame(parent, pending) {
+                        self.link(index, to);
+                    }
+                }
+                for value in frame.variables.values() {
+                    self.add_functions(index, value, pending);
+                }
             }
+            Node::Function(function) => {
+                let function = function.clone();
+                let closure = Scope {
+                    frame: function.closure.frame.clone(),
+                    runtime: runtime.clone(),
+                };
+                if let Some(to) = self.add_frame(&closure, pending) {
+                    self.link(index, to);
+                }
+                let Ok(cache) = function.cache.try_borrow() else {
+                    self.pinned[index] = true;
+                    return;
+                };
+                for (args, result) in cache.iter() {
+                    for arg in args {
+                        self.add_functions(index, arg, pending);
+                    }
+                    self.add_functions(index, result, pending);
+                }
+            }
+        }
+    }
+}
+
+/// Frees the frames closures captured that are kept alive only by cycles
+/// through the closures they bind, such as a call frame binding a closure
+/// made in it once the call has returned.
+///
+/// A frame or function referenced from outside the graph, by the global
+/// scope, a host or a value being evaluated, is live, and so is everything
+/// it reaches. The other frames are garbage, and clearing them breaks the
+/// cycles so that they, and everything they bind, are dropped.
+pub(crate) fn collect_cycles(scope: &Scope) {
+    let captured: Vec<Rc<RefCell<Frame>>> = {
+        let captured = scope.runtime.captured_frames();
+        captured.frames.iter().filter_map(Weak::upgrade).collect()
+    };
+    if captured.is_empty() {
+        return;
+    }
+
+    let mut graph = CycleGraph::default();
+    let mut pending = Vec::new();
+    for frame in captured {
+        let frame = Scope {
+            frame,
+            runtime: scope.runtime.clone(),
+        };
+        graph.add_frame(&frame, &mut pending);
+    }
+    while let Some(index) = pending.pop() {
+        graph.follow(index, &scope.runtime, &mut pending);
+    }
 
-            eval(&func.body, &mut local_scope)
+    // Every reference to a node not from another node, less the graph's own
+    let mut live: Vec<bool> = (0..graph.nodes.len())
+        .map(|i| graph.pinned[i] || graph.nodes[i].strong_count() - 1 > graph.internal[i])
+        .collect();
+    let mut reached: Vec<usize> = (0..live.len()).filter(|&i| live[i]).collect();
+    while let Some(index) = reached.pop() {
+        for &to in &graph.edges[index] {
+            if !live[to] {
+                live[to] = true;
+                reached.push(to);
+            }
         }
-        Expr::BuiltinFunction(builtin) => {
-            let evaluated_args: Result<Vec<_>, _> =
-                args.into_iter().map(|arg| eval(&arg, scope)).collect();
+    }
 
-            // Execute builtin function
-            (builtin.func)(&evaluated_args?, scope)
+    let mut garbage = Vec::new();
+    let mut kept = Vec::new();
+    for (node, live) in graph.nodes.iter().zip(live) {
+        match node {
+            Node::Frame(frame) if live => kept.push(Rc::downgrade(frame)),
+            Node::Frame(frame) => {
+                let mut frame = frame.borrow_mut();
+                frame.docs.clear();
+                garbage.extend(mem::take(&mut frame.variables).into_values());
+            }
+            Node::Function(function) if !live => {
+                for (args, result) in function.cache.take() {
+                    garbage.extend(args);
+                    garbage.push(result);
+                }
+            }
+            Node::Function(_) => {}
         }
-        _ => Err("First argument to apply is not a function".to_string()),
+    }
+    scope.runtime.captured_frames().frames = kept;
+    drop(graph);
+    drop(garbage);
+}
+
+/// Calls a function value with arguments. Functions and eager builtins
+/// receive `args` as already evaluated values; special forms receive them
+/// as they are. This is the single path every call goes through, and the
+/// entry point for hosts calling into lisp.
+pub fn call(callee: &Expr, args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    match callee {
+        Expr::Function(func) => {
+            func.check_call(args, scope.runtime())?;
+
+            if func.pure {
+                if let Some(result) = func.cache.borrow().get(args) {
+                    return Ok(result.clone());
+                }
+            }
+
+            let runtime = scope.runtime().clone();
+            runtime.enter_call(func);
+            let result = eval(&func.body, &mut func.bind(args));
+            runtime.leave_call();
+            let result = result?;
+            if func.pure {
+                func.cache
+                    .borrow_mut()
+                    .insert(args.to_vec(), result.clone());
+            }
+            Ok(result)
+        }
+        Expr::BuiltinFunction(builtin) => {
+            builtin.check_arity(args.len())?;
+            let runtime = scope.runtime();
+            if let Some(token) = builtin.capability {
+                if !runtime.capabilities_lent(token) {
+                    return Err(message!("capability-revoked", name = builtin.name));
+                }
+            }
+            if runtime.speculating() && is_side_effecting(&builtin.name) {
+                return Err(message!("speculative", name = builtin.name));
+            }
+            runtime.record_call();
+            runtime.trace(|| format!("{}: {:?}", builtin.name, args));
+            runtime.audit(&builtin.name, args);
+            (builtin.func)(args, scope)
+        }
+        _ => Err(message!(
+            "cannot-call",
+            value = callee.describe(),
+            site = ""
+        )),
+    }
+}
+
+/// Describes where a call form is, for error messages.
+pub(crate) fn call_site(list: &[Expr], scope: &Scope) -> String {
+    let form = expr_to_string(&Expr::List(list.to_vec()));
+    match scope.runtime().span_of(list) {
+        Some(span) => message!("call-site", form = form, span = span),
+        None => message!("call-site-unknown", form = form),
     }
 }
 
 pub fn eval(expr: &Expr, scope: &mut Scope) -> Result<Expr, String> {
+    let runtime = scope.runtime().clone();
+    runtime.enter()?;
+    let result = eval_expr(expr, scope);
+    runtime.leave();
+    if let Ok(value) = &result {
+        runtime.record_step(expr, value);
+    }
+    result
+}
+
+fn eval_expr(expr: &Expr, scope: &mut Scope) -> Result<Expr, String> {
     match expr {
         Expr::List(list) => {
+            // The empty list evaluates to itself
             if list.is_empty() {
-                return Err("Cannot evaluate an empty list".to_string());
+                return Ok(expr.clone());
             }
 
             let first = &list[0];
-            let evaluated_first = eval(first, scope)?;
-
-            match evaluated_first {
-                Expr::Lambda(parameters, body) => {
-                    if list.len() != parameters.len() + 1 {
-                        println!("list: {:?}, params: {:?}", list, parameters);
-                        return Err("Argument count does not match parameter count".to_string());
-                    }
-
-                    let mut local_scope = Scope::with_parent(scope.clone().into());
-                    for (param, arg) in parameters.iter().zip(&list[1..]) {
-                        local_scope.set_variable(param.clone(), eval(arg, scope)?);
-                    }
-
-                    eval(&body, &mut local_scope)
-                }
-                Expr::Function(func) => {
-                    let args: Result<Vec<_>, _> =
-                        list[1..].iter().map(|arg| eval(arg, scope)).collect();
-                    apply_function(Expr::Function(func), args?, scope)
+            if let Expr::Symbol(name) = first {
+                if scope.get_variable(name).is_none() {
+                    return Err(message!(
+                        "call-undefined",
+                        name = name,
+                        site = call_site(list, scope)
+                    ));
                 }
-                Expr::BuiltinFunction(builtin_func) => {
-                    match builtin_func.kind {
-                        BuiltinKind::Eager => {
-                            let args: Result<Vec<_>, _> =
-                                list[1..].iter().map(|arg| eval(arg, scope)).collect();
-                            (builtin_func.func)(&args?, scope)
-                        }
-                        BuiltinKind::SpecialForm => {
-                            // For special forms, pass the raw arguments
-                            (builtin_func.func)(&list[1..], scope)
-                        }
-                    }
+            }
+            let callee = eval(first, scope)?;
+
+            match &callee {
+                Expr::BuiltinFunction(builtin) if builtin.kind == BuiltinKind::SpecialForm => {
+                    call(&callee, &list[1..], scope)
                 }
-                _ => {
-                    println!("evaluated_first: {:?}", evaluated_first);
-                    Err("First element in the list is not a function or special form".to_string())
+                Expr::Function(_) | Expr::BuiltinFunction(_) => {
+                    scope.runtime().record_allocation();
+                    let args = list[1..]
+                        .iter()
+                        .map(|arg| eval(arg, scope))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    call(&callee, &args, scope)
                 }
+                _ => Err(message!(
+                    "cannot-call",
+                    value = callee.describe(),
+                    site = call_site(list, scope)
+                )),
             }
         }
-        Expr::Number(_) => Ok(expr.clone()), // Numbers evaluate to themselves
+        // Numbers, strings, characters, booleans and nil evaluate to themselves
+        Expr::Char(_)
+        | Expr::Int(_)
+        | Expr::BigInt(_)
+        | Expr::Ratio(_)
+        | Expr::Number(_)
+        | Expr::Keyword(_)
+        | Expr::String(_)
+        | Expr::Bool(_)
+        | Expr::Nil
+        // Sets hold only keys, which evaluate to themselves
+        | Expr::Set(_) => Ok(expr.clone()),
+        // Vectors evaluate their elements, in order
+        Expr::Vector(items) => Ok(Expr::vector(
+            items
+                .iter()
+                .map(|item| eval(item, scope))
+                .collect::<Result<_, _>>()?,
+        )),
+        // Maps evaluate their values, in the order of their keys
+        Expr::Map(map) => {
+            let mut evaluated = Map::with_capacity(map.len());
+            for (key, value) in sorted_entries(map) {
+                evaluated.insert(key.clone(), eval(value, scope)?);
+            }
+            Ok(Expr::map(evaluated))
+        }
         Expr::Symbol(name) => {
             // Look up symbols in the scope
             match scope.get_variable(name) {
-                Some(value) => Ok(value.clone()),
-                None => Err(format!("Undefined symbol '{}'", name)),
+                Some(value) => Ok(value),
+                None => Err(message!("undefined-symbol", name = name)),
             }
         }
         Expr::Function(function) => Ok(Expr::Function(function.clone())),
-        Expr::BuiltinFunction(_) => Ok(expr.clone()),
-        Expr::Lambda(parameters, body) => Ok(Expr::Function(Rc::new(Function::new(
-            parameters.clone(),
-            body.clone(),
-            Rc::new(scope.clone()),
-        )))),
+        Expr::BuiltinFunction(_) | Expr::External(_) | Expr::Weak(_) => Ok(expr.clone()),
+        #[cfg(feature = "ndarray")]
+        Expr::Matrix(_) => Ok(expr.clone()),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::Redefinition;
     use crate::interpreter::Expr;
     use crate::parser::parse;
 
@@ -257,92 +1357,397 @@ mod tests {
     #[test]
     fn scope() {
         let mut global_scope = Scope::new();
-        global_scope.set_variable("x".to_string(), Expr::number(42.0));
+        global_scope.set_variable("x".to_string(), Expr::int(42));
         assert_eq!(
             eval(&Expr::symbol("x"), &mut global_scope),
-            Ok(Expr::number(42.0))
+            Ok(Expr::int(42))
+        );
+    }
+
+    #[test]
+    fn scope_parent() {
+        let mut global_scope = Scope::new();
+        global_scope.set_variable("x".to_string(), Expr::int(42));
+        let mut scope = Scope::with_parent(&global_scope);
+        assert_eq!(eval(&Expr::symbol("x"), &mut scope), Ok(Expr::int(42)));
+    }
+
+    #[test]
+    fn lambda() {
+        let mut global_scope = Scope::new();
+        global_scope.set_variable("x".to_string(), Expr::int(42));
+        assert_eq!(
+            eval(&parse("(fn (x) x)").unwrap(), &mut global_scope),
+            Ok(Expr::Function(Rc::new(Function::new(
+                vec!["x".to_string()],
+                Box::new(Expr::symbol("x")),
+                global_scope.clone()
+            ))))
+        );
+    }
+
+    #[test]
+    fn closures_capture_by_reference() {
+        let mut global_scope = Scope::new();
+
+        // Helpers defined after the closure are still visible to it
+        eval(
+            &parse("(def answer (fn () (helper)))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        eval(
+            &parse("(def helper (fn () 42))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(answer)").unwrap(), &mut global_scope),
+            Ok(Expr::int(42))
+        );
+
+        // Closures keep their defining environment alive
+        eval(
+            &parse("(def make-adder (fn (n) (fn (x) (+ x n))))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        eval(
+            &parse("(def add2 (make-adder 2))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(add2 3)").unwrap(), &mut global_scope),
+            Ok(Expr::int(5))
+        );
+
+        // Function bodies cannot see their caller's locals
+        eval(
+            &parse("(def leak (fn () secret))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(
+                &parse("(let ((secret 1)) (leak))").unwrap(),
+                &mut global_scope
+            ),
+            Err("Undefined symbol 'secret'".to_string())
+        );
+    }
+
+    #[test]
+    fn lambda_call() {
+        let mut global_scope = Scope::new();
+        let lambda = parse("(fn (x) x)").unwrap();
+
+        // Evaluating fn creates a function object
+        let function = eval(&lambda, &mut global_scope).unwrap();
+
+        // Apply the function (e.g., (func 42))
+        let application = Expr::list(vec![function, Expr::int(42)]);
+        let result = eval(&application, &mut global_scope);
+
+        assert_eq!(result, Ok(Expr::int(42)));
+    }
+
+    #[test]
+    fn if_call() {
+        let mut global_scope = Scope::new();
+        let if_expr = Expr::list(vec![
+            Expr::symbol("if"),
+            Expr::int(1),
+            Expr::int(42),
+            Expr::int(0),
+        ]);
+
+        let result = eval(&if_expr, &mut global_scope);
+
+        assert_eq!(result, Ok(Expr::int(42)));
+    }
+
+    #[test]
+    fn truthiness() {
+        let mut global_scope = Scope::new();
+        assert_eq!(
+            eval(&parse("(if () 1 2)").unwrap(), &mut global_scope),
+            Ok(Expr::int(1))
+        );
+        assert_eq!(
+            eval(&parse("(if (quote x) 1 2)").unwrap(), &mut global_scope),
+            Ok(Expr::int(1))
+        );
+        assert_eq!(
+            eval(
+                &parse("(cond (false 1) (0 2) ((list) 3))").unwrap(),
+                &mut global_scope
+            ),
+            Ok(Expr::int(2))
+        );
+        assert_eq!(
+            eval(&parse("(if false 1 2)").unwrap(), &mut global_scope),
+            Ok(Expr::int(2))
+        );
+        assert_eq!(
+            eval(&parse("(not 0.0)").unwrap(), &mut global_scope),
+            Ok(Expr::bool(false))
+        );
+        assert_eq!(
+            eval(&parse("(not (list))").unwrap(), &mut global_scope),
+            Ok(Expr::bool(false))
+        );
+    }
+
+    #[test]
+    fn define() {
+        let mut global_scope = Scope::new();
+        let define_expr = Expr::list(vec![Expr::symbol("def"), Expr::symbol("x"), Expr::int(42)]);
+
+        let result = eval(&define_expr, &mut global_scope);
+
+        assert_eq!(result, Ok(Expr::int(42)));
+
+        assert_eq!(global_scope.get_variable("x"), Some(Expr::int(42)));
+    }
+
+    #[test]
+    fn define_with_doc() {
+        let mut global_scope = Scope::new();
+        assert_eq!(
+            eval(
+                &parse(r#"(def answer "The answer" 42)"#).unwrap(),
+                &mut global_scope
+            ),
+            Ok(Expr::int(42))
+        );
+        assert_eq!(
+            eval(&parse("(doc answer)").unwrap(), &mut global_scope),
+            Ok(Expr::string("The answer"))
+        );
+
+        // Redefining without a docstring drops the old one
+        eval(&parse("(def answer 43)").unwrap(), &mut global_scope).unwrap();
+        assert_eq!(
+            eval(&parse("(doc answer)").unwrap(), &mut global_scope),
+            Ok(Expr::Nil)
+        );
+    }
+
+    #[test]
+    fn defonce() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("(defonce counter \"Loads so far\" 1)", "1"),
+            // Already bound, so the value is not evaluated
+            ("(defonce counter (first 1))", "1"),
+            ("(doc counter)", "\"Loads so far\""),
+            ("(let ((x 2)) (defonce x 3))", "2"),
+            // Builtins do not count as bound
+            ("(defonce list 4)", "4"),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn defn_pure_memoizes() {
+        let fib = "(fib (n) (if (< n 2) n (+ (fib (+ n -1)) (fib (+ n -2)))))";
+        let with_fuel = || {
+            Scope::with_config(EngineConfig {
+                fuel_limit: Some(10_000),
+                ..EngineConfig::default()
+            })
+        };
+
+        let mut global_scope = with_fuel();
+        eval(
+            &parse(&format!("(defn ^:pure {}", &fib[1..])).unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(fib 30)").unwrap(), &mut global_scope),
+            Ok(Expr::int(832040))
+        );
+
+        let mut global_scope = with_fuel();
+        eval(
+            &parse(&format!("(defn {}", &fib[1..])).unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(fib 30)").unwrap(), &mut global_scope),
+            Err("Evaluation ran out of fuel".to_string())
+        );
+
+        let mut global_scope = Scope::new();
+        assert_eq!(
+            eval(&parse("(defn ^:fast f (x) x)").unwrap(), &mut global_scope),
+            Err("Unknown `defn` flag '^:fast'".to_string())
+        );
+        assert_eq!(
+            eval(&parse("(defn ^:pure f (x))").unwrap(), &mut global_scope),
+            Err("wrong number of args (3) passed to: defn (expects 4)".to_string())
+        );
+    }
+
+    #[test]
+    fn shadow_builtins() {
+        let mut global_scope = Scope::with_config(EngineConfig {
+            builtin_redefinition: Redefinition::Error,
+            ..EngineConfig::default()
+        });
+        assert_eq!(
+            eval(&parse("(def + 5)").unwrap(), &mut global_scope),
+            Err("def would shadow builtin '+'".to_string())
+        );
+
+        // Local scopes may shadow builtins intentionally
+        assert_eq!(
+            eval(&parse("(let ((+ 5)) +)").unwrap(), &mut global_scope),
+            Ok(Expr::int(5))
+        );
+        assert_eq!(
+            eval(
+                &parse("((fn (first) (def rest first)) 1)").unwrap(),
+                &mut global_scope
+            ),
+            Ok(Expr::int(1))
+        );
+        assert_eq!(
+            eval(&parse("(+ 1 2)").unwrap(), &mut global_scope),
+            Ok(Expr::int(3))
         );
     }
 
     #[test]
-    fn scope_parent() {
-        let mut global_scope = Scope::new();
-        global_scope.set_variable("x".to_string(), Expr::number(42.0));
-        let mut scope = Scope::with_parent(Rc::new(global_scope));
-        assert_eq!(eval(&Expr::symbol("x"), &mut scope), Ok(Expr::number(42.0)));
-    }
-
-    #[test]
-    fn lambda() {
+    fn symbols_are_sorted() {
         let mut global_scope = Scope::new();
-        global_scope.set_variable("x".to_string(), Expr::number(42.0));
+        eval(&parse("(def zeta 1)").unwrap(), &mut global_scope).unwrap();
+        eval(&parse("(def alpha 2)").unwrap(), &mut global_scope).unwrap();
         assert_eq!(
             eval(
-                &Expr::lambda(vec!["x".to_string()], Expr::symbol("x")),
+                &parse("(let ((mid 3)) (symbols))").unwrap(),
                 &mut global_scope
             ),
-            Ok(Expr::Function(Rc::new(Function::new(
-                vec!["x".to_string()],
-                Box::new(Expr::symbol("x")),
-                Rc::new(global_scope.clone())
-            ))))
+            Ok(Expr::list(vec![
+                Expr::symbol("alpha"),
+                Expr::symbol("mid"),
+                Expr::symbol("zeta")
+            ]))
+        );
+        assert_eq!(
+            global_scope.bindings(),
+            vec![
+                ("alpha".to_string(), Expr::int(2)),
+                ("zeta".to_string(), Expr::int(1))
+            ]
         );
     }
 
     #[test]
-    fn lambda_call() {
-        let mut global_scope = Scope::new();
-        let lambda = Expr::lambda(vec!["x".to_string()], Expr::symbol("x"));
-
-        // Convert the lambda expression into a function object
-        let function = eval(&lambda, &mut global_scope).unwrap();
-
-        // Apply the function (e.g., (func 42))
-        let application = Expr::list(vec![function, Expr::number(42.0)]);
-        let result = eval(&application, &mut global_scope);
+    fn hash_consistent_with_eq() {
+        use std::collections::HashSet;
 
-        assert_eq!(result, Ok(Expr::number(42.0)));
+        let mut global_scope = Scope::new();
+        let mut set = HashSet::new();
+        set.insert(Expr::int(0));
+        set.insert(Expr::int(-0));
+        set.insert(parse("(1 (a b))").unwrap());
+        set.insert(parse("(1 (a b))").unwrap());
+        set.insert(eval(&Expr::symbol("+"), &mut global_scope).unwrap());
+        set.insert(eval(&Expr::symbol("+"), &mut global_scope).unwrap());
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&parse("(1 (a b))").unwrap()));
     }
 
     #[test]
-    fn if_call() {
-        let mut global_scope = Scope::new();
-        let if_expr = Expr::list(vec![
-            Expr::symbol("if"),
-            Expr::number(1.0),
-            Expr::number(42.0),
-            Expr::number(0.0),
-        ]);
+    fn diff() {
+        let a = parse("(1 (2 3) 4)").unwrap();
+        let b = parse("(1 (2 5))").unwrap();
+        let differences: Vec<String> = Expr::diff(&a, &b).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            differences,
+            vec!["at (1 1): 3 vs 5", "at (2): 4 vs nothing"]
+        );
 
-        let result = eval(&if_expr, &mut global_scope);
+        assert_eq!(Expr::diff(&a, &a), vec![]);
+        assert_eq!(
+            Expr::diff(&Expr::int(1), &Expr::symbol("a"))[0].to_string(),
+            "1 vs a"
+        );
 
-        assert_eq!(result, Ok(Expr::number(42.0)));
+        let a = parse("{:a [1 2] :b 1 :c (1)}").unwrap();
+        let b = parse("{:a [1 3 4] :c [1] :d 2}").unwrap();
+        let differences: Vec<String> = Expr::diff(&a, &b).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            differences,
+            vec![
+                "at (:a 1): 2 vs 3",
+                "at (:a 2): nothing vs 4",
+                "at (:b): 1 vs nothing",
+                "at (:c): (1) vs [1]",
+                "at (:d): nothing vs 2",
+            ]
+        );
     }
 
     #[test]
-    fn define() {
+    fn compare_and_sort() {
         let mut global_scope = Scope::new();
-        let define_expr = Expr::list(vec![
-            Expr::symbol("def"),
-            Expr::symbol("x"),
-            Expr::number(42.0),
-        ]);
-
-        let result = eval(&define_expr, &mut global_scope);
-
-        assert_eq!(result, Ok(Expr::symbol("x")));
-
-        assert_eq!(global_scope.get_variable("x"), Some(&Expr::number(42.0)));
+        assert_eq!(
+            eval(&parse("(compare 1 2)").unwrap(), &mut global_scope),
+            Ok(Expr::int(-1))
+        );
+        assert_eq!(
+            eval(
+                &parse("(compare (quote (1 b)) (quote (1 a)))").unwrap(),
+                &mut global_scope
+            ),
+            Ok(Expr::int(1))
+        );
+        assert_eq!(
+            eval(
+                &parse("(sort (quote (c 3 (1) a 1)))").unwrap(),
+                &mut global_scope
+            ),
+            Ok(parse("(1 3 a c (1))").unwrap())
+        );
+        assert_eq!(
+            eval(
+                &parse("(sort (list 1 3 2) (fn (a b) (compare b a)))").unwrap(),
+                &mut global_scope
+            ),
+            Ok(parse("(3 2 1)").unwrap())
+        );
+        assert_eq!(
+            eval(&parse("(sort (list 1 +))").unwrap(), &mut global_scope),
+            Err("Cannot compare the integer 1 with a builtin function".to_string())
+        );
+        // A comparator that is not a total order must not panic
+        assert!(eval(
+            &parse("(sort (list 5 3 8 1 9 2 7 4 6 0) (fn (a b) 1))").unwrap(),
+            &mut global_scope
+        )
+        .is_ok());
     }
 
     #[test]
     fn quote() {
         let mut global_scope = Scope::new();
-        let quote_expr = Expr::list(vec![Expr::symbol("quote"), Expr::Number(42.0)]);
+        let quote_expr = Expr::list(vec![Expr::symbol("quote"), Expr::Int(42)]);
 
         let result = eval(&quote_expr, &mut global_scope);
 
-        assert_eq!(result, Ok(Expr::number(42.0)));
+        assert_eq!(result, Ok(Expr::int(42)));
     }
 
     #[test]
@@ -350,11 +1755,7 @@ mod tests {
         let mut global_scope = Scope::new();
         let quote_expr = Expr::list(vec![
             Expr::symbol("quote"),
-            Expr::list(vec![
-                Expr::symbol("def"),
-                Expr::symbol("x"),
-                Expr::number(42.0),
-            ]),
+            Expr::list(vec![Expr::symbol("def"), Expr::symbol("x"), Expr::int(42)]),
         ]);
 
         let result = eval(&quote_expr, &mut global_scope);
@@ -364,11 +1765,43 @@ mod tests {
             Ok(Expr::List(vec![
                 Expr::symbol("def"),
                 Expr::symbol("x"),
-                Expr::number(42.0),
+                Expr::int(42),
             ]))
         );
     }
 
+    #[test]
+    fn read_string() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            (r#"(read-string "(+ 1 2)")"#, "(+ 1 2)"),
+            (r#"(first (read-string "  (def x 42) ; x"))"#, "def"),
+            (r##"(read-string "#\\a")"##, r"#\a"),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+        for (input, expected) in [
+            (r#"(read-string "")"#, "Unexpected end of input"),
+            (
+                r#"(read-string "1 2")"#,
+                "Unexpected '2' after the expression at line 1, column 3",
+            ),
+        ] {
+            assert_eq!(
+                eval(&parse(input).unwrap(), &mut global_scope),
+                Err(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn first() {
         let mut global_scope = Scope::new();
@@ -376,13 +1809,13 @@ mod tests {
             Expr::symbol("first"),
             Expr::List(vec![
                 Expr::symbol("quote"),
-                Expr::List(vec![Expr::number(1.0), Expr::number(2.0)]),
+                Expr::List(vec![Expr::int(1), Expr::int(2)]),
             ]),
         ]);
 
         let result = eval(&first_expr, &mut global_scope);
 
-        assert_eq!(result, Ok(Expr::number(1.0)));
+        assert_eq!(result, Ok(Expr::int(1)));
     }
 
     #[test]
@@ -392,46 +1825,235 @@ mod tests {
             Expr::symbol("rest"),
             Expr::list(vec![
                 Expr::symbol("quote"),
-                Expr::list(vec![Expr::number(1.0), Expr::number(2.0)]),
+                Expr::list(vec![Expr::int(1), Expr::int(2)]),
             ]),
         ]);
 
         let result = eval(&first_expr, &mut global_scope);
 
-        assert_eq!(result, Ok(Expr::list(vec![Expr::number(2.0)])));
+        assert_eq!(result, Ok(Expr::list(vec![Expr::int(2)])));
     }
 
     #[test]
-    fn list() {
+    fn empty_lists() {
         let mut global_scope = Scope::new();
-        let list_expr = Expr::list(vec![
-            Expr::symbol("list"),
-            Expr::number(1.0),
-            Expr::number(2.0),
-        ]);
+        assert_eq!(
+            eval(&parse("(rest (list))").unwrap(), &mut global_scope),
+            Ok(Expr::list(vec![]))
+        );
+        assert_eq!(
+            eval(&parse("(first (list))").unwrap(), &mut global_scope),
+            Ok(Expr::Nil)
+        );
+        assert_eq!(
+            eval(&parse("(empty? (list))").unwrap(), &mut global_scope),
+            Ok(Expr::bool(true))
+        );
+        assert_eq!(
+            eval(&parse("(empty? (list 1))").unwrap(), &mut global_scope),
+            Ok(Expr::bool(false))
+        );
 
-        let result = eval(&list_expr, &mut global_scope);
+        eval(
+            &parse("(def sum (fn (xs) (if (empty? xs) 0 (+ (first xs) (sum (rest xs))))))")
+                .unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(sum (list 1 2 3))").unwrap(), &mut global_scope),
+            Ok(Expr::int(6))
+        );
+    }
+
+    #[test]
+    fn logical_operators() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("(and)", "true"),
+            ("(or)", "nil"),
+            ("(and 1 2 3)", "3"),
+            ("(and 1 false (undefined))", "false"),
+            ("(or nil 0 (undefined))", "0"),
+            ("(or nil false)", "false"),
+            ("(not (and true nil))", "true"),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+        assert!(eval(&parse("(and 1 (undefined))").unwrap(), &mut global_scope).is_err());
+    }
 
+    #[test]
+    fn keywords() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            (":port", ":port"),
+            ("(list :a (quote :b))", "(:a :b)"),
+            ("(keyword? :a)", "true"),
+            ("(keyword? (quote a))", "false"),
+            ("(eq? :a :a)", "true"),
+            ("(eq? :a (quote a))", "false"),
+            ("(sort (list :b (quote a) :a 1))", "(1 :a :b a)"),
+            ("(compare :a :b)", "-1"),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
         assert_eq!(
-            result,
-            Ok(Expr::list(vec![Expr::number(1.0), Expr::number(2.0)]))
+            eval(&parse("(+ 1 :a)").unwrap(), &mut global_scope),
+            Err("`+` expected a number as argument 2, got the keyword :a".to_string())
+        );
+    }
+
+    #[test]
+    fn types() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            (
+                r#"(list (type-of 1) (type-of 1/2) (type-of 1.5) (type-of #\a) (type-of "a"))"#,
+                "(:integer :ratio :float :char :string)",
+            ),
+            (
+                "(list (type-of (quote a)) (type-of :a) (type-of true) (type-of nil) (type-of ()))",
+                "(:symbol :keyword :bool :nil :list)",
+            ),
+            (
+                "(list (type-of (fn (x) x)) (type-of first))",
+                "(:function :builtin)",
+            ),
+            // Predicates see evaluated arguments
+            (
+                "(list (number? (+ 1 2)) (symbol? (quote a)) (symbol? 1))",
+                "(true true false)",
+            ),
+            (
+                "(list (list? (list 1)) (list? nil) (nil? nil))",
+                "(true false true)",
+            ),
+            (r#"(list (string? "a") (string? #\a))"#, "(true false)"),
+            ("(list (bool? false) (bool? nil))", "(true false)"),
+            ("(list (fn? (fn (x) x)) (fn? first))", "(true false)"),
+            (
+                "(list (builtin? first) (builtin? (fn (x) x)))",
+                "(true false)",
+            ),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn nil() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("nil", "nil"),
+            ("(nil? nil)", "true"),
+            ("(nil? (list))", "false"),
+            ("(first nil)", "nil"),
+            ("(rest nil)", "()"),
+            ("(empty? nil)", "true"),
+            ("(if nil 1 2)", "2"),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+
+        // Recursion can stop at the nil past the end of the list
+        eval(
+            &parse("(defn size (xs) (if (nil? (first xs)) 0 (+ 1 (size (rest xs)))))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(size (list 1 2 3))").unwrap(), &mut global_scope),
+            Ok(Expr::int(3))
+        );
+    }
+
+    #[test]
+    fn empty_list_evaluates_to_itself() {
+        let mut global_scope = Scope::new();
+        assert_eq!(
+            eval(&parse("()").unwrap(), &mut global_scope),
+            Ok(Expr::list(vec![]))
+        );
+
+        eval(
+            &parse("(def tail (fn (xs) (if (empty? xs) () (rest xs))))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(tail ())").unwrap(), &mut global_scope),
+            Ok(Expr::list(vec![]))
         );
     }
 
+    #[test]
+    fn list() {
+        let mut global_scope = Scope::new();
+        let list_expr = Expr::list(vec![Expr::symbol("list"), Expr::int(1), Expr::int(2)]);
+
+        let result = eval(&list_expr, &mut global_scope);
+
+        assert_eq!(result, Ok(Expr::list(vec![Expr::int(1), Expr::int(2)])));
+    }
+
     #[test]
     fn apply() {
         let mut global_scope = Scope::new();
         let apply_expr = Expr::List(vec![
             Expr::symbol("apply"),
             Expr::symbol("+"),
-            Expr::list(vec![
-                Expr::symbol("list"),
-                Expr::number(1.0),
-                Expr::number(2.0),
-            ]),
+            Expr::list(vec![Expr::symbol("list"), Expr::int(1), Expr::int(2)]),
         ]);
 
-        assert_eq!(eval(&apply_expr, &mut global_scope), Ok(Expr::number(3.0)));
+        assert_eq!(eval(&apply_expr, &mut global_scope), Ok(Expr::int(3)));
+    }
+
+    #[test]
+    fn arguments_are_evaluated_once() {
+        let mut global_scope = Scope::new();
+        assert_eq!(
+            eval(&parse("(list (quote x))").unwrap(), &mut global_scope),
+            Ok(Expr::list(vec![Expr::symbol("x")]))
+        );
+        assert_eq!(
+            eval(
+                &parse("(apply list (list (quote x) (quote y)))").unwrap(),
+                &mut global_scope
+            ),
+            Ok(Expr::list(vec![Expr::symbol("x"), Expr::symbol("y")]))
+        );
+        assert_eq!(
+            eval(
+                &parse("(apply (fn (x) x) (list (quote y)))").unwrap(),
+                &mut global_scope
+            ),
+            Ok(Expr::symbol("y"))
+        );
     }
 
     #[test]
@@ -439,13 +2061,13 @@ mod tests {
         let mut global_scope = Scope::new();
         let apply_expr = Expr::List(vec![
             Expr::symbol("apply"),
-            Expr::lambda(vec!["x".to_string()], Expr::symbol("x")),
-            Expr::list(vec![Expr::symbol("list"), Expr::number(3.0)]),
+            parse("(fn (x) x)").unwrap(),
+            Expr::list(vec![Expr::symbol("list"), Expr::int(3)]),
         ]);
 
         let result = eval(&apply_expr, &mut global_scope);
 
-        assert_eq!(result, Ok(Expr::number(3.0)));
+        assert_eq!(result, Ok(Expr::int(3)));
     }
 
     #[test]
@@ -454,14 +2076,15 @@ mod tests {
         let lambda = eval(&parse("(fn (x) (list + x 1))").unwrap(), &mut global_scope);
         assert_eq!(
             lambda,
-            Ok(Expr::lambda(
+            Ok(Expr::function(
                 vec!["x".to_string()],
                 Expr::list(vec![
                     Expr::symbol("list"),
                     Expr::symbol("+"),
                     Expr::symbol("x"),
-                    Expr::number(1.0)
-                ])
+                    Expr::int(1)
+                ]),
+                global_scope.clone()
             ))
         );
         assert_eq!(
@@ -469,23 +2092,376 @@ mod tests {
                 &parse("(apply (fn (x) (+ x 2)) (list 3))").unwrap(),
                 &mut global_scope
             ),
-            Ok(Expr::number(5.0))
+            Ok(Expr::int(5))
         );
         assert_eq!(
             eval(
                 &parse("(def inc (fn (x) (+ x 1)))").unwrap(),
                 &mut global_scope
-            ),
-            Ok(Expr::symbol("inc"))
+            )
+            .map(|inc| expr_to_string(&inc)),
+            Ok("<function inc>".to_string())
         );
         assert_eq!(
             eval(&parse("(apply inc (list 2))").unwrap(), &mut global_scope),
-            Ok(Expr::number(3.0))
+            Ok(Expr::int(3))
         );
         assert_eq!(
             eval(&parse("(apply inc (list 1 3))").unwrap(), &mut global_scope),
-            Err("Expected 1 arguments, got 2".to_string())
+            Err("wrong number of args (2) passed to: inc (expects 1)".to_string())
+        )
+    }
+
+    #[test]
+    fn arity_errors() {
+        let mut global_scope = Scope::new();
+        assert_eq!(
+            eval(
+                &parse("(first (list 1) (list 2))").unwrap(),
+                &mut global_scope
+            ),
+            Err("wrong number of args (2) passed to: first (expects 1)".to_string())
+        );
+        assert_eq!(
+            eval(&parse("(cond)").unwrap(), &mut global_scope),
+            Err("wrong number of args (0) passed to: cond (expects at least 1)".to_string())
+        );
+        assert_eq!(
+            eval(&parse("((fn (x y) x) 1)").unwrap(), &mut global_scope),
+            Err("wrong number of args (1) passed to: fn (expects 2)".to_string())
+        );
+
+        eval(
+            &parse("(def pair (fn (x y) (list x y)))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(pair 1 2 3)").unwrap(), &mut global_scope),
+            Err("wrong number of args (3) passed to: pair (expects 2)".to_string())
+        );
+    }
+
+    #[test]
+    fn type_errors() {
+        let mut global_scope = Scope::new();
+        assert_eq!(
+            eval(&parse("(first 42)").unwrap(), &mut global_scope),
+            Err("`first` expected a sequence as argument 1, got the integer 42".to_string())
+        );
+        assert_eq!(
+            eval(&parse("(rest (quote x))").unwrap(), &mut global_scope),
+            Err("`rest` expected a sequence as argument 1, got the symbol x".to_string())
+        );
+        assert_eq!(
+            eval(&parse("(+ 1 (list 2))").unwrap(), &mut global_scope),
+            Err("`+` expected a number as argument 2, got the list (2)".to_string())
+        );
+        assert_eq!(
+            eval(&parse("(apply 1 (list))").unwrap(), &mut global_scope),
+            Err("`apply` expected a function as argument 1, got the integer 1".to_string())
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("(- 5)", Ok(Expr::int(-5))),
+            ("(- 10 1 2)", Ok(Expr::int(7))),
+            ("(*)", Ok(Expr::int(1))),
+            ("(* 2 3 4)", Ok(Expr::int(24))),
+            ("(/ 4.0)", Ok(Expr::number(0.25))),
+            ("(/ 12 2 3)", Ok(Expr::int(2))),
+            ("(/ 7 2.0)", Ok(Expr::number(3.5))),
+            ("(mod 7 3)", Ok(Expr::int(1))),
+            ("(mod -7 3)", Ok(Expr::int(2))),
+            ("(mod 7 -3)", Ok(Expr::int(-2))),
+            ("(mod 7.5 2)", Ok(Expr::number(1.5))),
+            ("(abs -2.5)", Ok(Expr::number(2.5))),
+            ("(abs -2)", Ok(Expr::int(2))),
+            ("(min 3 1 2)", Ok(Expr::int(1))),
+            ("(max 3 1.5 2)", Ok(Expr::int(3))),
+            ("(min 3 1.5 2)", Ok(Expr::number(1.5))),
+            ("(/ 1 0)", Err("`/` cannot divide by zero")),
+            ("(/ 0)", Err("`/` cannot divide by zero")),
+            ("(mod 1 0)", Err("`mod` cannot divide by zero")),
+        ] {
+            assert_eq!(
+                eval(&parse(input).unwrap(), &mut global_scope),
+                expected.map_err(str::to_string),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            eval(&parse("(-)").unwrap(), &mut global_scope),
+            Err("wrong number of args (0) passed to: - (expects at least 1)".to_string())
+        );
+    }
+
+    #[test]
+    fn integers() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("(+ 1 2.5)", Expr::number(3.5)),
+            ("(+ 0.5 0.5)", Expr::number(1.0)),
+            ("(+ 9007199254740993 0)", Expr::int(9007199254740993)),
+            ("(floor 2.5)", Expr::int(2)),
+            (
+                "(list (int? 1) (int? 1.0) (float? 1.0) (number? 1))",
+                parse("(true false true true)").unwrap(),
+            ),
+        ] {
+            assert_eq!(
+                eval(&parse(input).unwrap(), &mut global_scope),
+                Ok(expected),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(expr_to_string(&Expr::number(3.0)), "3.0");
+        assert_eq!(expr_to_string(&Expr::int(3)), "3");
+    }
+
+    #[test]
+    fn ratios() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("(/ 1 3)", "1/3"),
+            ("(/ 4)", "1/4"),
+            ("(+ 1/3 2/3)", "1"),
+            ("(int? (+ 1/3 2/3))", "true"),
+            ("(* 2/3 -3/4)", "-1/2"),
+            ("(- 1/2 1)", "-1/2"),
+            ("(mod 7/2 2)", "3/2"),
+            ("(abs -1/2)", "1/2"),
+            ("(+ 1/2 0.25)", "0.75"),
+            ("(< 1/3 0.34 1/2)", "true"),
+            ("(= 1/2 2/4 0.5)", "true"),
+            ("(list (floor -1/2) (ceil -1/2) (round 5/2))", "(-1 0 3)"),
+            (
+                "(list (numerator 6/4) (denominator 6/4) (denominator 5))",
+                "(3 2 1)",
+            ),
+            ("(exact->inexact 1/4)", "0.25"),
+            (
+                "(list (number? 1/2) (int? 1/2) (float? 1/2))",
+                "(true false false)",
+            ),
+            (
+                "(numerator 0.5)",
+                "Error: `numerator` expected an integer or ratio as argument 1, got the number 0.5",
+            ),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            let printed = match result {
+                Ok(value) => expr_to_string(&value),
+                Err(e) => format!("Error: {}", e),
+            };
+            assert_eq!(printed, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn characters() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            (r"#\a", r"#\a"),
+            (r"(char->int #\A)", "65"),
+            ("(int->char 955)", r"#\λ"),
+            (r#"(string->list "ab c")"#, r"(#\a #\b #\space #\c)"),
+            (r#"(list->string (string->list "héllo"))"#, r#""héllo""#),
+            ("(list->string nil)", r#""""#),
+            (r#"(str #\a "b" #\c)"#, r#""abc""#),
+            (r"(list (char? #\a) (char? 97) (compare #\a #\b))", "(true false -1)"),
+            (r#"(eq? #\a (first (string->list "a")))"#, "true"),
+            (
+                "(int->char -1)",
+                "Error: `int->char` expected a Unicode code point as argument 1, got the integer -1",
+            ),
+            (
+                r#"(char->int "a")"#,
+                r#"Error: `char->int` expected a character as argument 1, got the string "a""#,
+            ),
+            (
+                r"(list->string (list #\a 1))",
+                r"Error: `list->string` expected a list of characters as argument 1, got the list (#\a 1)",
+            ),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            let printed = match result {
+                Ok(value) => expr_to_string(&value),
+                Err(e) => format!("Error: {}", e),
+            };
+            assert_eq!(printed, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn big_integers() {
+        let mut global_scope = Scope::new();
+        eval(
+            &parse("(defn fact (n) (if (<= n 1) 1 (* n (fact (- n 1)))))").unwrap(),
+            &mut global_scope,
+        )
+        .unwrap();
+        for (input, expected) in [
+            ("(fact 30)", "265252859812191058636308480000000"),
+            ("(/ (fact 30) (fact 28))", "870"),
+            ("(int? (/ (fact 30) (fact 28)))", "true"),
+            ("(- -9223372036854775808)", "9223372036854775808"),
+            ("(mod (- (fact 25)) 7)", "0"),
+            ("(mod (- (fact 25) 1) -7)", "-1"),
+            ("(abs (- (fact 25)))", "15511210043330985984000000"),
+            ("(/ (fact 25) 7 (fact 5))", "18465726242060697600000"),
+            ("(+ (fact 25) 0.5)", "15511210043330986000000000.0"),
+            ("(< 1 (fact 25) 1e30)", "true"),
+            ("(floor 1e20)", "100000000000000000000"),
+            ("(int? 9223372036854775808)", "true"),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("(= 1 1 1)", true),
+            ("(= 1 1 2)", false),
+            ("(< 1 2 3)", true),
+            ("(< 1 3 2)", false),
+            ("(> 3 2 1)", true),
+            ("(<= 1 1 2)", true),
+            ("(>= 2 3)", false),
+            ("(< 1)", true),
+            ("(= 1 1.0)", true),
+            ("(< 1 1.5 2)", true),
+            ("(eq? 1 1.0)", false),
+            (
+                "(eq? (quote (a (1 \"b\"))) (list (quote a) (list 1 \"b\")))",
+                true,
+            ),
+            ("(eq? (quote a) (quote b))", false),
+            ("(eq? 1 (quote (1)))", false),
+            ("(eq? nil nil)", true),
+        ] {
+            assert_eq!(
+                eval(&parse(input).unwrap(), &mut global_scope),
+                Ok(Expr::bool(expected)),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            eval(&parse("(< 1 (quote a))").unwrap(), &mut global_scope),
+            Err("`<` expected a number as argument 2, got the symbol a".to_string())
+        );
+        // Conditions can now come from data
+        eval(
+            &parse("(defn fact (n) (if (<= n 1) 1 (* n (fact (- n 1)))))").unwrap(),
+            &mut global_scope,
         )
+        .unwrap();
+        assert_eq!(
+            eval(&parse("(fact 5)").unwrap(), &mut global_scope),
+            Ok(Expr::int(120))
+        );
+    }
+
+    #[test]
+    fn with_rollback() {
+        let mut global_scope = Scope::new();
+        for input in ["(def x 1)", "(with-rollback (def x 2) (def y 3) (first 1))"] {
+            let _ = eval(&parse(input).unwrap(), &mut global_scope);
+        }
+        assert_eq!(global_scope.get_variable("x"), Some(Expr::int(1)));
+        assert_eq!(global_scope.get_variable("y"), None);
+
+        assert_eq!(
+            eval(
+                &parse("(with-rollback (def x 2) (def y (+ x 1)))").unwrap(),
+                &mut global_scope
+            ),
+            Ok(Expr::int(3))
+        );
+        assert_eq!(global_scope.get_variable("x"), Some(Expr::int(2)));
+        assert_eq!(global_scope.get_variable("y"), Some(Expr::int(3)));
+    }
+
+    #[test]
+    fn eval_builtin() {
+        let mut global_scope = Scope::new();
+        for (input, expected) in [
+            ("(eval (quote (+ 1 2)))", "3"),
+            ("(eval (list (quote *) 2 3))", "6"),
+            (r#"(eval (read-string "(list 1 (quote a))"))"#, "(1 a)"),
+            ("(let ((x 1)) (eval (quote x)))", "1"),
+            ("(def x 10)", "10"),
+            ("(let ((x 1)) (eval (quote x) :global))", "10"),
+            (
+                "((fn (y) (eval (list (quote def) (quote z) y) :global)) 5)",
+                "5",
+            ),
+            ("z", "5"),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                Ok(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            eval(&parse("(eval 1 :local)").unwrap(), &mut global_scope),
+            Err("`eval` expected :global as argument 2, got the keyword :local".to_string())
+        );
+    }
+
+    #[test]
+    fn with_redefs() {
+        let mut global_scope = Scope::new();
+        for input in [
+            "(def limit 3)",
+            "(defn fetch (url) (str-len url))",
+            "(defn report (url) (list (fetch url) limit (first (list 1 2))))",
+        ] {
+            eval(&parse(input).unwrap(), &mut global_scope).unwrap();
+        }
+        for (input, expected) in [
+            (
+                r#"(with-redefs ((fetch (fn (url) 200)) (limit 5) (first rest)) (report "x"))"#,
+                Ok("(200 5 (2))"),
+            ),
+            (r#"(report "abc")"#, Ok("(3 3 1)")),
+            // Restored even when the body fails
+            (
+                "(with-redefs ((limit 0) (first rest)) (+ limit :a))",
+                Err("`+` expected a number as argument 2, got the keyword :a"),
+            ),
+            ("(list limit (first (list 1 2)))", Ok("(3 1)")),
+            (
+                "(with-redefs (limit) 1)",
+                Err("`with-redefs` binding must be a (name value) list, got the symbol limit"),
+            ),
+        ] {
+            let result = eval(&parse(input).unwrap(), &mut global_scope);
+            assert_eq!(
+                result.map(|e| expr_to_string(&e)),
+                expected.map(str::to_string).map_err(str::to_string),
+                "{}",
+                input
+            );
+        }
+        assert!(!global_scope.is_bound("first"));
     }
 
     #[test]
@@ -506,18 +2482,19 @@ mod tests {
                 )
                 .unwrap(),
                 &mut global_scope
-            ),
-            Ok(Expr::symbol("eval"))
+            )
+            .map(|eval| expr_to_string(&eval)),
+            Ok("<function eval>".to_string())
         );
 
         assert_eq!(
             eval(&parse(r#"(eval 5)"#).unwrap(), &mut global_scope),
-            Ok(Expr::number(5.0))
+            Ok(Expr::int(5))
         );
 
         assert_eq!(
             eval(&parse(r#"(eval (list + 1 2))"#).unwrap(), &mut global_scope),
-            Ok(Expr::number(3.0))
+            Ok(Expr::int(3))
         );
     }
 }
@@ -1,13 +1,189 @@
-use interpreter::{eval, Scope};
-use parser::{expr_to_string, parse};
+use audio::Tone;
+use engine::{CapturedOutput, Engine, Stats};
+use messages::Catalog;
 use wasm_bindgen::prelude::*;
+pub mod assert;
+pub mod audio;
+pub mod bits;
 pub mod builtins;
+pub mod dot;
+pub mod edn;
+pub mod engine;
+#[cfg(feature = "hashing")]
+pub mod hash;
+#[cfg(feature = "ids")]
+pub mod ids;
 pub mod interpreter;
+pub mod machine;
+pub mod map;
+pub mod math;
+#[cfg(feature = "ndarray")]
+pub mod matrix;
+pub mod messages;
+pub mod module;
+pub mod notebook;
+pub mod number_format;
 pub mod parser;
+pub mod pattern;
+pub mod plot;
+pub mod program;
+pub mod refactor;
+#[cfg(feature = "regex")]
+pub mod regexp;
+pub mod resource;
+pub mod rewrite;
+pub mod seq;
+pub mod server;
+pub mod set;
+pub mod spec;
+pub mod store;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy;
+pub mod strings;
+pub mod syntax;
+pub mod tail;
+pub mod template;
+pub mod testing;
+pub mod turtle;
+pub mod url;
+pub mod vector;
+
+#[cfg(target_arch = "wasm32")]
+mod js {
+    use crate::engine::{Clock, Rng};
+    use crate::store::Storage;
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = Date)]
+        fn now() -> f64;
+        #[wasm_bindgen(js_namespace = Math)]
+        fn random() -> f64;
+        #[wasm_bindgen(catch, js_namespace = localStorage, js_name = getItem)]
+        fn get_item(key: &str) -> Result<Option<String>, JsValue>;
+        #[wasm_bindgen(catch, js_namespace = localStorage, js_name = setItem)]
+        fn set_item(key: &str, value: &str) -> Result<(), JsValue>;
+    }
+
+    /// `Date.now()`, since there is no system clock in the browser.
+    pub struct JsClock;
+
+    impl Clock for JsClock {
+        fn now(&self) -> f64 {
+            now()
+        }
+    }
+
+    /// `Math.random()`.
+    pub struct JsRng;
+
+    impl Rng for JsRng {
+        fn next_f64(&mut self) -> f64 {
+            random()
+        }
+    }
+
+    /// `localStorage`, with keys prefixed so they stay apart from the page's
+    /// own.
+    pub struct JsStorage;
+
+    const PREFIX: &str = "rs-lisp:";
+
+    fn describe(error: JsValue) -> String {
+        error.as_string().unwrap_or_else(|| format!("{:?}", error))
+    }
+
+    impl Storage for JsStorage {
+        fn get(&self, key: &str) -> Result<Option<String>, String> {
+            get_item(&format!("{}{}", PREFIX, key)).map_err(describe)
+        }
+
+        fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+            set_item(&format!("{}{}", PREFIX, key), value).map_err(describe)
+        }
+    }
+}
+
+/// The cost of the last evaluation, with counts as plain JS numbers.
+#[wasm_bindgen]
+pub struct EvalStats(Stats);
+
+#[wasm_bindgen]
+impl EvalStats {
+    #[wasm_bindgen(getter)]
+    pub fn steps(&self) -> f64 {
+        self.0.steps as f64
+    }
+
+    #[wasm_bindgen(getter, js_name = maxDepth)]
+    pub fn max_depth(&self) -> f64 {
+        self.0.max_depth as f64
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn allocations(&self) -> f64 {
+        self.0.allocations as f64
+    }
+
+    #[wasm_bindgen(getter, js_name = functionCalls)]
+    pub fn function_calls(&self) -> f64 {
+        self.0.function_calls as f64
+    }
+
+    #[wasm_bindgen(getter, js_name = elapsedMs)]
+    pub fn elapsed_ms(&self) -> f64 {
+        self.0.elapsed_ms
+    }
+}
+
+/// A note for the page to play, `startMs` after the evaluation that queued
+/// it.
+#[wasm_bindgen]
+pub struct ToneEvent(Tone);
+
+#[wasm_bindgen]
+impl ToneEvent {
+    #[wasm_bindgen(getter, js_name = startMs)]
+    pub fn start_ms(&self) -> f64 {
+        self.0.start_ms
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn frequency(&self) -> f64 {
+        self.0.frequency
+    }
+
+    #[wasm_bindgen(getter, js_name = durationMs)]
+    pub fn duration_ms(&self) -> f64 {
+        self.0.duration_ms
+    }
+}
+
+/// The printed result of running a notebook cell, and the ids of the cells
+/// that need to be run again because of it.
+#[wasm_bindgen(getter_with_clone)]
+pub struct CellResult {
+    pub output: String,
+    pub stale: Vec<String>,
+}
+
+/// A chart drawn by `plot`, for the page to render: its `kind` is `line`,
+/// `scatter` or `bar`, and `x` and `y` hold the points. `svg` is the chart
+/// already drawn, for pages with no charting of their own.
+#[wasm_bindgen(getter_with_clone)]
+pub struct ChartResult {
+    pub kind: String,
+    pub title: Option<String>,
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub svg: String,
+}
 
 #[wasm_bindgen]
 pub struct Evaluator {
-    scope: Scope,
+    engine: Engine,
+    output: CapturedOutput,
 }
 
 impl Default for Evaluator {
@@ -20,17 +196,91 @@ impl Default for Evaluator {
 impl Evaluator {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Evaluator {
-        let scope = Scope::new();
-        Evaluator { scope }
+        #[allow(unused_mut)]
+        let mut engine = Engine::new();
+        #[cfg(target_arch = "wasm32")]
+        {
+            engine.set_clock(js::JsClock);
+            engine.set_rng(js::JsRng);
+            engine.set_storage(js::JsStorage);
+        }
+        let output = CapturedOutput::default();
+        engine.set_output(output.clone());
+        Evaluator { engine, output }
     }
 
+    /// Evaluates the input, returning what it printed with `print` followed
+    /// by the result or error.
     pub fn eval(&mut self, input: &str) -> String {
-        match parse(input) {
-            Ok(expr) => match eval(&expr, &mut self.scope) {
-                Ok(result) => expr_to_string(&result),
-                Err(e) => format!("Error: {}", e),
-            },
-            Err(e) => format!("Error: {}", e),
+        let report = self.engine.eval_to_string(input);
+        self.output.before(&report)
+    }
+
+    #[wasm_bindgen(js_name = evalCell)]
+    pub fn eval_cell(&mut self, id: &str, source: &str) -> CellResult {
+        let cell = self.engine.eval_cell(id, source);
+        CellResult {
+            output: self.output.before(&self.engine.report(cell.result)),
+            stale: cell.stale,
         }
     }
+
+    /// Evaluates the input, returning each reduction step printed as
+    /// `before => after` followed by the result or error.
+    pub fn explain(&mut self, input: &str) -> Vec<String> {
+        let (result, steps) = self.engine.eval_traced(input);
+        let mut lines: Vec<String> = steps.iter().map(ToString::to_string).collect();
+        lines.push(self.output.before(&self.engine.report(result)));
+        lines
+    }
+
+    pub fn stats(&self) -> EvalStats {
+        EvalStats(self.engine.last_stats())
+    }
+
+    /// What the turtle has drawn since the last call, as SVG, or `undefined`
+    /// if it has drawn nothing.
+    #[wasm_bindgen(js_name = takeDrawing)]
+    pub fn take_drawing(&mut self) -> Option<String> {
+        self.engine.take_drawing()
+    }
+
+    /// The tones `tone` has queued since the last call.
+    #[wasm_bindgen(js_name = takeTones)]
+    pub fn take_tones(&mut self) -> Vec<ToneEvent> {
+        self.engine
+            .take_tones()
+            .into_iter()
+            .map(ToneEvent)
+            .collect()
+    }
+
+    /// The charts `plot` has drawn since the last call.
+    #[wasm_bindgen(js_name = takeCharts)]
+    pub fn take_charts(&mut self) -> Vec<ChartResult> {
+        self.engine
+            .take_charts()
+            .into_iter()
+            .map(|chart| ChartResult {
+                svg: chart.to_svg(),
+                kind: chart.kind.name().to_string(),
+                title: chart.title,
+                x: chart.x,
+                y: chart.y,
+            })
+            .collect()
+    }
+
+    /// Rewords or translates one kind of error message, for every evaluator
+    /// on the page.
+    #[wasm_bindgen(js_name = setMessage)]
+    pub fn set_message(kind: &str, template: &str) -> Result<(), String> {
+        messages::set_message(kind, template)
+    }
+
+    /// The kinds of message `setMessage` accepts.
+    #[wasm_bindgen(js_name = messageKinds)]
+    pub fn message_kinds() -> Vec<String> {
+        Catalog::kinds().map(str::to_string).collect()
+    }
 }
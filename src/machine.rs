@@ -0,0 +1,363 @@
+//! A small-step evaluator. Where `eval` recurses on the Rust stack, the
+//! machine keeps the rest of the computation as an explicit stack of frames,
+//! so a host can advance evaluation one step at a time, keep earlier states
+//! around, or interleave evaluation with other work.
+//!
+//! Calls, `if`, `quote` and `def` are taken apart into steps. Other special
+//! forms and builtins run to completion within a single step, including any
+//! functions they call back into.
+
+use std::fmt;
+
+use crate::builtins::{define, expect_symbol};
+use crate::interpreter::{call, call_site, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+use crate::parser::expr_to_string;
+
+/// What the machine is doing right now.
+#[derive(Debug, Clone)]
+pub enum Control {
+    /// About to evaluate an expression in a scope.
+    Eval(Expr, Scope),
+    /// Returning a value to the innermost frame.
+    Value(Expr),
+    /// Evaluation failed; the machine stops here.
+    Error(String),
+}
+
+/// What to do with a value once the current expression has been evaluated.
+#[derive(Debug, Clone)]
+enum Frame {
+    /// The operator of `form` is being evaluated.
+    Operator { form: Vec<Expr>, scope: Scope },
+    /// The arguments of a call are being evaluated, left to right.
+    Arguments {
+        callee: Expr,
+        form: Vec<Expr>,
+        values: Vec<Expr>,
+        scope: Scope,
+    },
+    /// The condition of an `if` is being evaluated.
+    If {
+        then: Expr,
+        otherwise: Expr,
+        scope: Scope,
+    },
+    /// The value of a `def` is being evaluated.
+    Def {
+        name: String,
+        doc: Option<String>,
+        scope: Scope,
+    },
+}
+
+/// A snapshot of an evaluation in progress. States share scopes with the
+/// engine, so stepping one state on can be observed through definitions in
+/// another.
+#[derive(Debug, Clone)]
+pub struct State {
+    pub control: Control,
+    stack: Vec<Frame>,
+}
+
+impl State {
+    pub fn new(expr: Expr, scope: Scope) -> Self {
+        State {
+            control: Control::Eval(expr, scope),
+            stack: Vec::new(),
+        }
+    }
+
+    /// A state that has already failed, such as for input that did not parse.
+    pub fn failed(error: String) -> Self {
+        State {
+            control: Control::Error(error),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self.control {
+            Control::Value(_) => self.stack.is_empty(),
+            Control::Error(_) => true,
+            Control::Eval(..) => false,
+        }
+    }
+
+    /// The outcome, once the evaluation is done.
+    pub fn result(&self) -> Option<Result<Expr, String>> {
+        if !self.is_done() {
+            return None;
+        }
+        match &self.control {
+            Control::Value(value) => Some(Ok(value.clone())),
+            Control::Error(e) => Some(Err(e.clone())),
+            Control::Eval(..) => None,
+        }
+    }
+
+    /// How many frames are waiting for a value.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Advances the evaluation by one step. Done states are returned as is.
+    pub fn step(self) -> State {
+        let State { control, mut stack } = self;
+        let control = match control {
+            Control::Eval(expr, scope) => {
+                let runtime = scope.runtime().clone();
+                match runtime.enter() {
+                    Ok(()) => {
+                        runtime.leave();
+                        if stack.len() >= runtime.config.recursion_limit {
+                            Control::Error("Maximum recursion depth exceeded".to_string())
+                        } else {
+                            eval_step(expr, scope, &mut stack)
+                        }
+                    }
+                    Err(e) => Control::Error(e),
+                }
+            }
+            Control::Value(value) => match stack.pop() {
+                Some(frame) => return_step(value, frame, &mut stack),
+                None => Control::Value(value),
+            },
+            control @ Control::Error(_) => control,
+        };
+        State { control, stack }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.control {
+            Control::Eval(expr, _) => write!(f, "eval {}", expr_to_string(expr))?,
+            Control::Value(value) => write!(f, "value {}", expr_to_string(value))?,
+            Control::Error(e) => write!(f, "error {}", e)?,
+        }
+        write!(f, " [depth {}]", self.stack.len())
+    }
+}
+
+fn eval_step(expr: Expr, scope: Scope, stack: &mut Vec<Frame>) -> Control {
+    match expr {
+        Expr::Symbol(name) => match scope.get_variable(&name) {
+            Some(value) => Control::Value(value),
+            None => Control::Error(message!("undefined-symbol", name = name)),
+        },
+        Expr::List(form) if !form.is_empty() => {
+            if let Expr::Symbol(name) = &form[0] {
+                if scope.get_variable(name).is_none() {
+                    return Control::Error(message!(
+                        "call-undefined",
+                        name = name,
+                        site = call_site(&form, &scope)
+                    ));
+                }
+            }
+            let operator = form[0].clone();
+            stack.push(Frame::Operator {
+                form,
+                scope: scope.clone(),
+            });
+            Control::Eval(operator, scope)
+        }
+        // Everything else evaluates to itself
+        expr => Control::Value(expr),
+    }
+}
+
+fn return_step(value: Expr, frame: Frame, stack: &mut Vec<Frame>) -> Control {
+    match frame {
+        Frame::Operator { form, mut scope } => match &value {
+            Expr::BuiltinFunction(builtin) if builtin.kind == BuiltinKind::SpecialForm => {
+                let args = &form[1..];
+                if let Err(e) = builtin.check_arity(args.len()) {
+                    return Control::Error(e);
+                }
+                match builtin.name.as_str() {
+                    "quote" => Control::Value(args[0].clone()),
+                    "if" => {
+                        stack.push(Frame::If {
+                            then: args[1].clone(),
+                            otherwise: args[2].clone(),
+                            scope: scope.clone(),
+                        });
+                        Control::Eval(args[0].clone(), scope)
+                    }
+                    "def" => match def_frame(args, &scope) {
+                        Ok((frame, value)) => {
+                            stack.push(frame);
+                            Control::Eval(value, scope)
+                        }
+                        Err(e) => Control::Error(e),
+                    },
+                    _ => finish(call(&value, args, &mut scope)),
+                }
+            }
+            Expr::Function(_) | Expr::BuiltinFunction(_) => match form.get(1) {
+                None => apply(value, Vec::new(), scope),
+                Some(first) => {
+                    let first = first.clone();
+                    stack.push(Frame::Arguments {
+                        callee: value,
+                        form,
+                        values: Vec::new(),
+                        scope: scope.clone(),
+                    });
+                    Control::Eval(first, scope)
+                }
+            },
+            _ => Control::Error(message!(
+                "cannot-call",
+                value = value.describe(),
+                site = call_site(&form, &scope)
+            )),
+        },
+        Frame::Arguments {
+            callee,
+            form,
+            mut values,
+            scope,
+        } => {
+            values.push(value);
+            match form.get(values.len() + 1) {
+                Some(next) => {
+                    let next = next.clone();
+                    stack.push(Frame::Arguments {
+                        callee,
+                        form,
+                        values,
+                        scope: scope.clone(),
+                    });
+                    Control::Eval(next, scope)
+                }
+                None => apply(callee, values, scope),
+            }
+        }
+        Frame::If {
+            then,
+            otherwise,
+            scope,
+        } => {
+            if value.is_truthy() {
+                Control::Eval(then, scope)
+            } else {
+                Control::Eval(otherwise, scope)
+            }
+        }
+        Frame::Def {
+            name,
+            doc,
+            mut scope,
+        } => {
+            let value = value.with_name(&name);
+            finish(define(&name, doc.as_deref(), value, &mut scope))
+        }
+    }
+}
+
+/// Sets up a `def`, returning its frame and the expression for its value.
+fn def_frame(args: &[Expr], scope: &Scope) -> Result<(Frame, Expr), String> {
+    let name = expect_symbol("def", 1, &args[0])?.to_string();
+    let (doc, value) = match args {
+        [_, value] => (None, value),
+        [_, doc, value] => (Some(expect_symbol("def", 2, doc)?.to_string()), value),
+        _ => unreachable!("def arity is checked by the caller"),
+    };
+    let frame = Frame::Def {
+        name,
+        doc,
+        scope: scope.clone(),
+    };
+    Ok((frame, value.clone()))
+}
+
+/// Calls a function with evaluated arguments. A user function's body
+/// replaces the call rather than running under a new frame, so tail calls
+/// run in constant stack space.
+fn apply(callee: Expr, args: Vec<Expr>, mut scope: Scope) -> Control {
+    match &callee {
+        Expr::Function(func) => match func.check_call(&args, scope.runtime()) {
+            Ok(()) => Control::Eval((*func.body).clone(), func.bind(&args)),
+            Err(e) => Control::Error(e),
+        },
+        _ => finish(call(&callee, &args, &mut scope)),
+    }
+}
+
+fn finish(result: Result<Expr, String>) -> Control {
+    match result {
+        Ok(value) => Control::Value(value),
+        Err(e) => Control::Error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::interpreter::Expr;
+
+    #[test]
+    fn step_by_step() {
+        let mut engine = Engine::new();
+        let mut state = engine.start("(+ 1 (if false 2 3))");
+        let mut trace = vec![state.to_string()];
+        while !state.is_done() {
+            state = engine.step(state);
+            trace.push(state.to_string());
+        }
+
+        assert_eq!(state.result(), Some(Ok(Expr::int(4))));
+        assert_eq!(
+            trace,
+            vec![
+                "eval (+ 1 (if false 2 3)) [depth 0]",
+                "eval + [depth 1]",
+                "value <builtin-function> [depth 1]",
+                "eval 1 [depth 1]",
+                "value 1 [depth 1]",
+                "eval (if false 2 3) [depth 1]",
+                "eval if [depth 2]",
+                "value <builtin-function> [depth 2]",
+                "eval false [depth 2]",
+                "value false [depth 2]",
+                "eval 3 [depth 1]",
+                "value 3 [depth 1]",
+                "value 4 [depth 0]",
+            ]
+        );
+    }
+
+    #[test]
+    fn tail_calls_and_errors() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(def count (fn (n) (if (= n 0) (quote done) (count (+ n -1)))))")
+            .unwrap();
+
+        // Far deeper than the recursion limit of the recursive evaluator
+        assert_eq!(
+            engine.eval("(count 1000)"),
+            Err("Maximum recursion depth exceeded".to_string())
+        );
+        let mut state = engine.start("(count 1000)");
+        while !state.is_done() {
+            state = engine.step(state);
+        }
+        assert_eq!(state.result(), Some(Ok(Expr::symbol("done"))));
+
+        let mut state = engine.start("(def x (first 1))");
+        while !state.is_done() {
+            state = engine.step(state);
+        }
+        assert_eq!(
+            state.result(),
+            Some(Err(
+                "`first` expected a sequence as argument 1, got the integer 1".to_string()
+            ))
+        );
+        assert_eq!(engine.scope().get_variable("x"), None);
+    }
+}
@@ -1,9 +1,445 @@
-use crate::repl::repl;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use crate::builtins::builtin_names;
+use crate::engine::{Engine, EngineConfig};
+use crate::refactor::{check_rename, definitions, rename_symbol, unused_definitions};
+use crate::repl::{repl, ReplOptions};
+use crate::syntax::parse_syntax;
+use crate::testing::{Baseline, TestFilter};
+pub mod assert;
+pub mod audio;
+pub mod bits;
 pub mod builtins;
+pub mod dot;
+pub mod edn;
+pub mod engine;
+#[cfg(feature = "hashing")]
+pub mod hash;
+#[cfg(feature = "ids")]
+pub mod ids;
 pub mod interpreter;
+pub mod machine;
+pub mod map;
+pub mod math;
+#[cfg(feature = "ndarray")]
+pub mod matrix;
+pub mod messages;
+pub mod module;
+pub mod notebook;
+pub mod number_format;
 pub mod parser;
+pub mod pattern;
+pub mod plot;
+pub mod program;
+pub mod refactor;
+#[cfg(feature = "regex")]
+pub mod regexp;
 pub mod repl;
+pub mod resource;
+pub mod rewrite;
+pub mod seq;
+pub mod server;
+pub mod set;
+pub mod spec;
+pub mod store;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy;
+pub mod strings;
+pub mod syntax;
+pub mod tail;
+pub mod template;
+pub mod testing;
+pub mod turtle;
+pub mod url;
+pub mod vector;
+
+const USAGE: &str = "Usage: rs_lisp [--path DIR]... [--record FILE] [--replay FILE] | [template FILE [--data DATA.json] | rename OLD NEW FILE... | deadcode DIR [--entry NAME]... | test FILE... [--tag TAG]... [--name PATTERN] | bench FILE... [--tag TAG]... [--name PATTERN] [--baseline FILE] [--threshold PERCENT] [--save FILE] | serve --stdio | serve --websocket ADDRESS]";
+
+/// `template FILE [--data DATA.json]`: renders a template to stdout.
+fn run_template(config: EngineConfig, args: &[String]) -> Result<String, String> {
+    let (path, data_path) = match args {
+        [path] => (path, None),
+        [path, flag, data_path] if flag == "--data" => (path, Some(data_path)),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let read = |path: &str| fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e));
+    let data = match data_path {
+        Some(data_path) => template::data_from_json(&read(data_path)?)?,
+        None => HashMap::new(),
+    };
+
+    template::render(&mut Engine::with_config(config), &read(path)?, &data)
+}
+
+/// `rename OLD NEW FILE...`: renames a global definition and its references
+/// across the files, rewriting them in place.
+fn run_rename(args: &[String]) -> Result<String, String> {
+    let [old, new, paths @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut sources = Vec::new();
+    let mut defined = Vec::new();
+    for path in paths {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        defined.extend(definitions(&source).map_err(|e| format!("{}: {}", path, e))?);
+        sources.push(source);
+    }
+    check_rename(old, new, |name| {
+        defined.iter().any(|d| d == name) || builtin_names().any(|b| b == name)
+    })?;
+
+    // Nothing is written until every file has been checked
+    let mut changes = Vec::new();
+    for (path, source) in paths.iter().zip(&sources) {
+        let edits = rename_symbol(source, old, new).map_err(|e| format!("{}: {}", path, e))?;
+        if !edits.is_empty() {
+            changes.push((path, syntax::apply_edits(source, &edits), edits.len()));
+        }
+    }
+    let mut report = String::new();
+    for (path, source, count) in changes {
+        fs::write(path, source).map_err(|e| format!("{}: {}", path, e))?;
+        report.push_str(&format!("{}: {} changed\n", path, count));
+    }
+    Ok(report)
+}
+
+/// The `.lisp` files under the directory, in a stable order.
+fn lisp_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .path();
+        if path.is_dir() {
+            lisp_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "lisp")
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(())
+}
+
+/// `deadcode DIR [--entry NAME]...`: lists the definitions in the directory's
+/// files that nothing outside a definition uses, other than the entry points.
+fn run_deadcode(args: &[String]) -> Result<String, String> {
+    let Some((dir, flags)) = args.split_first() else {
+        return Err(USAGE.to_string());
+    };
+    let mut entries = Vec::new();
+    for pair in flags.chunks(2) {
+        match pair {
+            [flag, name] if flag == "--entry" => entries.push(name.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let mut paths = Vec::new();
+    lisp_files(Path::new(dir), &mut paths)?;
+    let sources = paths
+        .iter()
+        .map(|path| fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e)))
+        .collect::<Result<Vec<String>, String>>()?;
+    let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+
+    let mut report = String::new();
+    for unused in unused_definitions(&sources, &entries)? {
+        report.push_str(&format!(
+            "{}:{}:{}: '{}' is never used\n",
+            paths[unused.source].display(),
+            unused.span.line,
+            unused.span.column,
+            unused.name
+        ));
+    }
+    Ok(report)
+}
+
+/// Evaluates each file form by form, so that errors point into the file.
+fn load_files(engine: &mut Engine, paths: &[&String]) -> Result<(), String> {
+    for path in paths {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let tree = parse_syntax(&source).map_err(|e| format!("{}: {}", path, e))?;
+        for form in &tree.forms {
+            engine
+                .eval(&source[form.span.start..form.span.end])
+                .map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// `test FILE... [--tag TAG]... [--name PATTERN]`: loads the files and runs
+/// the tests they define with `deftest`, those with any of the tags and
+/// whose names match the pattern. Fails if any test does.
+fn run_test(config: EngineConfig, args: &[String]) -> Result<String, String> {
+    let mut paths = Vec::new();
+    let mut filter = TestFilter::default();
+    let mut args = args;
+    loop {
+        args = match args {
+            [flag, tag, rest @ ..] if flag == "--tag" => {
+                filter.tags.push(tag.clone());
+                rest
+            }
+            [flag, pattern, rest @ ..] if flag == "--name" => {
+                filter.name = Some(pattern.clone());
+                rest
+            }
+            [flag, ..] if flag.starts_with("--") => return Err(USAGE.to_string()),
+            [path, rest @ ..] => {
+                paths.push(path);
+                rest
+            }
+            [] => break,
+        };
+    }
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut engine = Engine::with_config(config);
+    load_files(&mut engine, &paths)?;
+
+    let results = engine.run_tests(&filter);
+    let mut report = String::new();
+    let mut failed = 0;
+    for result in &results {
+        match &result.error {
+            None => report.push_str(&format!("ok   {}\n", result.name)),
+            Some(e) => {
+                failed += 1;
+                report.push_str(&format!("FAIL {}: {}\n", result.name, e));
+            }
+        }
+    }
+    report.push_str(&format!(
+        "{} passed, {} failed\n",
+        results.len() - failed,
+        failed
+    ));
+    if failed > 0 {
+        print!("{}", report);
+        return Err(format!("{} of {} tests failed", failed, results.len()));
+    }
+    Ok(report)
+}
+
+/// How long `bench` runs each benchmark for, in milliseconds.
+const BENCH_BUDGET_MS: f64 = 500.0;
+
+/// How much slower than its baseline, in percent, a benchmark may get
+/// before `bench` fails.
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+/// `bench FILE... [--tag TAG]... [--name PATTERN] [--baseline FILE]
+/// [--threshold PERCENT] [--save FILE]`: loads the files and times the
+/// benchmarks they define with `defbench`, selected as for `test`. Fails if
+/// any benchmark does, or runs slower than in the baseline by more than the
+/// threshold. `--save` writes the timings as a new baseline.
+fn run_bench(config: EngineConfig, args: &[String]) -> Result<String, String> {
+    let mut paths = Vec::new();
+    let mut filter = TestFilter::default();
+    let mut baseline_path = None;
+    let mut save_path = None;
+    let mut threshold = DEFAULT_THRESHOLD;
+    let mut args = args;
+    loop {
+        args = match args {
+            [flag, tag, rest @ ..] if flag == "--tag" => {
+                filter.tags.push(tag.clone());
+                rest
+            }
+            [flag, pattern, rest @ ..] if flag == "--name" => {
+                filter.name = Some(pattern.clone());
+                rest
+            }
+            [flag, path, rest @ ..] if flag == "--baseline" => {
+                baseline_path = Some(path);
+                rest
+            }
+            [flag, path, rest @ ..] if flag == "--save" => {
+                save_path = Some(path);
+                rest
+            }
+            [flag, percent, rest @ ..] if flag == "--threshold" => {
+                threshold = percent
+                    .parse()
+                    .map_err(|_| format!("Invalid threshold '{}'", percent))?;
+                rest
+            }
+            [flag, ..] if flag.starts_with("--") => return Err(USAGE.to_string()),
+            [path, rest @ ..] => {
+                paths.push(path);
+                rest
+            }
+            [] => break,
+        };
+    }
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let baseline = match baseline_path {
+        Some(path) => {
+            let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            Baseline::from_json(&json).map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => Baseline::default(),
+    };
+
+    let mut engine = Engine::with_config(config);
+    load_files(&mut engine, &paths)?;
+
+    let results = engine.run_benches(&filter, BENCH_BUDGET_MS);
+    let mut report = String::new();
+    let (mut failed, mut regressed) = (0, 0);
+    for result in &results {
+        if let Some(e) = &result.error {
+            failed += 1;
+            report.push_str(&format!("FAIL {}: {}\n", result.name, e));
+            continue;
+        }
+        let change = baseline.change(result);
+        let status = match change {
+            Some(change) if change > threshold => {
+                regressed += 1;
+                "SLOW"
+            }
+            _ => "ok  ",
+        };
+        report.push_str(&format!(
+            "{} {}: {:.6} ms over {} runs",
+            status, result.name, result.mean_ms, result.iterations
+        ));
+        if let Some(change) = change {
+            report.push_str(&format!(", {:+.1}% on the baseline", change));
+        }
+        report.push('\n');
+    }
+    report.push_str(&format!(
+        "{} ok, {} slow, {} failed\n",
+        results.len() - failed - regressed,
+        regressed,
+        failed
+    ));
+    if let Some(path) = save_path {
+        fs::write(path, Baseline::from_results(&results).to_json())
+            .map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if failed > 0 {
+        print!("{}", report);
+        return Err(format!("{} of {} benchmarks failed", failed, results.len()));
+    }
+    if regressed > 0 {
+        print!("{}", report);
+        return Err(format!(
+            "{} of {} benchmarks are more than {}% slower than the baseline",
+            regressed,
+            results.len(),
+            threshold
+        ));
+    }
+    Ok(report)
+}
+
+/// `serve --stdio`, or with the `websocket` feature `serve --websocket ADDRESS`.
+fn run_serve(config: EngineConfig, args: &[String]) -> Result<String, String> {
+    match args {
+        [flag] if flag == "--stdio" => {
+            let input = std::io::BufReader::new(std::io::stdin());
+            server::serve(config, input, &mut std::io::stdout())?;
+        }
+        #[cfg(feature = "websocket")]
+        [flag, address] if flag == "--websocket" => {
+            let listener =
+                std::net::TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+            server::serve_websocket(config, listener);
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(String::new())
+}
+
+/// `--path DIR` flags ahead of everything else, putting the directories on
+/// the module path before those from `RS_LISP_PATH`. Returns the other
+/// arguments.
+fn take_path_flags<'a>(mut args: &'a [String], config: &mut EngineConfig) -> &'a [String] {
+    let mut dirs = Vec::new();
+    while let [flag, dir, rest @ ..] = args {
+        if flag != "--path" {
+            break;
+        }
+        dirs.push(PathBuf::from(dir));
+        args = rest;
+    }
+    config.module_path.splice(0..0, dirs);
+    args
+}
+
+/// `[--record FILE] [--replay FILE]`: the options of the interactive REPL.
+fn repl_options(args: &[String]) -> Result<ReplOptions, String> {
+    let mut options = ReplOptions::default();
+    for pair in args.chunks(2) {
+        match pair {
+            [flag, path] if flag == "--record" => options.record = Some(path.clone()),
+            [flag, path] if flag == "--replay" => options.replay = Some(path.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(options)
+}
 
 fn main() {
-    repl();
+    let mut config = match EngineConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = take_path_flags(&args, &mut config);
+    let result = match args.split_first() {
+        None => {
+            repl(config, ReplOptions::default());
+            return;
+        }
+        Some((flag, _)) if flag.starts_with("--") => {
+            match repl_options(args) {
+                Ok(options) => repl(config, options),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some((command, rest)) if command == "template" => run_template(config, rest),
+        Some((command, rest)) if command == "rename" => run_rename(rest),
+        Some((command, rest)) if command == "deadcode" => run_deadcode(rest),
+        Some((command, rest)) if command == "test" => run_test(config, rest),
+        Some((command, rest)) if command == "bench" => run_bench(config, rest),
+        Some((command, rest)) if command == "serve" => run_serve(config, rest),
+        Some(_) => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(output) => print!("{}", output),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,187 @@
+//! Maps, written `{:a 1 :b 2}`, from keywords, strings or numbers to any
+//! value. Like vectors they are values, so `assoc`, `dissoc` and `merge`
+//! give a new map. nil stands for the empty map wherever a map is expected.
+
+use std::rc::Rc;
+
+use crate::builtins::type_error;
+use crate::interpreter::{sorted_entries, Arity, BuiltinFn, BuiltinKind, Expr, Map, MapKey, Scope};
+use crate::messages::message;
+
+pub(crate) fn expect_map(name: &str, position: usize, expr: &Expr) -> Result<Rc<Map>, String> {
+    match expr {
+        Expr::Map(map) => Ok(map.clone()),
+        Expr::Nil => Ok(Rc::new(Map::new())),
+        _ => Err(type_error(name, "a map", position, expr)),
+    }
+}
+
+fn expect_key(name: &str, position: usize, expr: &Expr) -> Result<MapKey, String> {
+    MapKey::new(expr.clone())
+        .ok_or_else(|| type_error(name, "a keyword, string or number", position, expr))
+}
+
+fn builtin_is_map(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Map(_))))
+}
+
+/// `(get m key)` is the value of the key, or nil if the map has none, and
+/// `(get m key default)` gives the default instead of nil.
+fn builtin_get(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let map = expect_map("get", 1, &args[0])?;
+    let key = expect_key("get", 2, &args[1])?;
+    Ok(map.get(&key).or(args.get(2)).cloned().unwrap_or(Expr::Nil))
+}
+
+/// `(assoc m key value...)` is the map with each key set to the value after
+/// it.
+fn builtin_assoc(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut map = expect_map("assoc", 1, &args[0])?;
+    if args.len().is_multiple_of(2) {
+        return Err(message!("assoc-odd"));
+    }
+    let entries = Rc::make_mut(&mut map);
+    for (i, pair) in args[1..].chunks(2).enumerate() {
+        entries.insert(expect_key("assoc", 2 * i + 2, &pair[0])?, pair[1].clone());
+    }
+    Ok(Expr::Map(map))
+}
+
+/// `(dissoc m key...)` is the map without the keys.
+fn builtin_dissoc(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut map = expect_map("dissoc", 1, &args[0])?;
+    let entries = Rc::make_mut(&mut map);
+    for (i, key) in args.iter().enumerate().skip(1) {
+        entries.remove(&expect_key("dissoc", i + 1, key)?);
+    }
+    Ok(Expr::Map(map))
+}
+
+/// `(keys m)` is the list of the keys, in order.
+fn builtin_keys(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let map = expect_map("keys", 1, &args[0])?;
+    Ok(Expr::List(
+        sorted_entries(&map)
+            .into_iter()
+            .map(|(key, _)| key.expr().clone())
+            .collect(),
+    ))
+}
+
+/// `(vals m)` is the list of the values, in the order of their keys.
+fn builtin_vals(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let map = expect_map("vals", 1, &args[0])?;
+    Ok(Expr::List(
+        sorted_entries(&map)
+            .into_iter()
+            .map(|(_, value)| value.clone())
+            .collect(),
+    ))
+}
+
+fn builtin_contains(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let map = expect_map("contains?", 1, &args[0])?;
+    let key = expect_key("contains?", 2, &args[1])?;
+    Ok(Expr::Bool(map.contains_key(&key)))
+}
+
+/// `(merge m...)` is one map with the entries of all of them, those of later
+/// maps replacing those of earlier ones with the same key.
+fn builtin_merge(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut merged = Map::new();
+    for (i, arg) in args.iter().enumerate() {
+        let map = expect_map("merge", i + 1, arg)?;
+        merged.extend(map.iter().map(|(key, value)| (key.clone(), value.clone())));
+    }
+    Ok(Expr::map(merged))
+}
+
+#[rustfmt::skip]
+pub(crate) const MAP_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("map?",      builtin_is_map,   BuiltinKind::Eager, Arity::Exact(1)),
+    ("get",       builtin_get,      BuiltinKind::Eager, Arity::Range(2, 3)),
+    ("assoc",     builtin_assoc,    BuiltinKind::Eager, Arity::AtLeast(3)),
+    ("dissoc",    builtin_dissoc,   BuiltinKind::Eager, Arity::AtLeast(1)),
+    ("keys",      builtin_keys,     BuiltinKind::Eager, Arity::Exact(1)),
+    ("vals",      builtin_vals,     BuiltinKind::Eager, Arity::Exact(1)),
+    ("contains?", builtin_contains, BuiltinKind::Eager, Arity::Exact(2)),
+    ("merge",     builtin_merge,    BuiltinKind::Eager, Arity::AtLeast(0)),
+];
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    use crate::engine::Engine;
+    use crate::parser::parse;
+
+    #[test]
+    fn maps() {
+        let mut engine = Engine::new();
+        engine
+            .eval(r#"(def m {:b (+ 1 1) :a 1 "c" [3] 4 :four})"#)
+            .unwrap();
+        for (input, expected) in [
+            ("m", r#"{4 :four :a 1 :b 2 "c" [3]}"#),
+            ("(map? m)", "true"),
+            ("(map? [])", "false"),
+            ("(type-of {})", ":map"),
+            ("(eq? m {4 :four \"c\" [3] :b 2 :a 1})", "true"),
+            ("(get m :a)", "1"),
+            ("(get m \"c\")", "[3]"),
+            ("(get m 4)", ":four"),
+            ("(get m 4.0)", "nil"),
+            ("(get m :z)", "nil"),
+            ("(get m :z 0)", "0"),
+            ("(get nil :a)", "nil"),
+            ("(assoc m :a 10 :z 26)", r#"{4 :four :a 10 :b 2 :z 26 "c" [3]}"#),
+            ("(get m :a)", "1"),
+            ("(assoc nil :a 1)", "{:a 1}"),
+            ("(dissoc m :a 4 :missing)", r#"{:b 2 "c" [3]}"#),
+            ("(keys m)", r#"(4 :a :b "c")"#),
+            ("(vals m)", "(:four 1 2 [3])"),
+            ("(contains? m :b)", "true"),
+            ("(contains? m :z)", "false"),
+            ("(merge {:a 1 :b 1} nil {:b 2})", "{:a 1 :b 2}"),
+            ("(merge)", "{}"),
+            ("{1.0 :b 1 :a 0.5 :d 1/2 :c}", "{1/2 :c 0.5 :d 1 :a 1.0 :b}"),
+            (
+                "(assoc m :a 1 :b)",
+                "Error: `assoc` needs a value for each key",
+            ),
+            (
+                "(assoc m (list 1) 2)",
+                "Error: `assoc` expected a keyword, string or number as argument 2, \
+                 got the list (1)",
+            ),
+            (
+                "(get [1] 0)",
+                "Error: `get` expected a map as argument 1, got the vector [1]",
+            ),
+            (
+                "{:a}",
+                "Error: Map needs a value for each key at line 1, column 1",
+            ),
+            (
+                "(list {x 1})",
+                "Error: Map keys must be keywords, strings or numbers, got x in the map at line 1, column 7",
+            ),
+            (
+                "{:a 1 :a 2}",
+                "Error: Duplicate key :a in the map at line 1, column 1",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+
+        // However the entries happen to be stored, equal maps hash the same
+        let hash = |source: &str| {
+            let mut hasher = DefaultHasher::new();
+            parse(source).unwrap().hash(&mut hasher);
+            hasher.finish()
+        };
+        for _ in 0..20 {
+            assert_eq!(hash("{1 :a 1.0 :b 2 :c}"), hash("{2 :c 1.0 :b 1 :a}"));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! The common floating point functions, and `pi`.
+//!
+//! Arguments outside a function's domain, such as the square root of a
+//! negative number, are errors rather than NaN.
+
+use std::f64::consts::PI;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::FromPrimitive;
+
+use crate::builtins::expect_number;
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+
+/// The argument as a number, if `valid` accepts it.
+fn expect_in_domain(
+    name: &str,
+    position: usize,
+    expr: &Expr,
+    expected: &str,
+    valid: fn(f64) -> bool,
+) -> Result<f64, String> {
+    let n = expect_number(name, position, expr)?;
+    if !valid(n) {
+        return Err(message!(
+            "wrong-type",
+            name = name,
+            expected = expected,
+            position = position,
+            actual = expr.describe()
+        ));
+    }
+    Ok(n)
+}
+
+fn unary(name: &str, args: &[Expr], f: fn(f64) -> f64) -> Result<Expr, String> {
+    Ok(Expr::Number(f(expect_number(name, 1, &args[0])?)))
+}
+
+fn builtin_sqrt(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_in_domain("sqrt", 1, &args[0], "a non-negative number", |n| n >= 0.0)?;
+    Ok(Expr::Number(n.sqrt()))
+}
+
+/// `(pow x y)` is `x` to the power of `y`.
+fn builtin_pow(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let x = expect_number("pow", 1, &args[0])?;
+    let y = expect_number("pow", 2, &args[1])?;
+    Ok(Expr::Number(x.powf(y)))
+}
+
+fn builtin_exp(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    unary("exp", args, f64::exp)
+}
+
+/// `(log x)` is the natural logarithm of `x`, and `(log x base)` the
+/// logarithm in that base.
+fn builtin_log(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let x = expect_in_domain("log", 1, &args[0], "a positive number", |n| n > 0.0)?;
+    match args.get(1) {
+        Some(base) => {
+            let base = expect_in_domain("log", 2, base, "a positive number other than 1", |n| {
+                n > 0.0 && n != 1.0
+            })?;
+            Ok(Expr::Number(x.log(base)))
+        }
+        None => Ok(Expr::Number(x.ln())),
+    }
+}
+
+/// Rounds the argument to an integer with `exact` or, for a float, `f`.
+/// Infinity and NaN stay as they are.
+fn to_whole(
+    name: &str,
+    args: &[Expr],
+    exact: fn(&BigRational) -> BigRational,
+    f: fn(f64) -> f64,
+) -> Result<Expr, String> {
+    if let Some(n) = args[0].as_rational() {
+        return Ok(Expr::rational(exact(&n)));
+    }
+    let n = f(expect_number(name, 1, &args[0])?);
+    Ok(BigInt::from_f64(n).map_or(Expr::Number(n), Expr::integer))
+}
+
+fn builtin_floor(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    to_whole("floor", args, BigRational::floor, f64::floor)
+}
+
+fn builtin_ceil(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    to_whole("ceil", args, BigRational::ceil, f64::ceil)
+}
+
+/// `(round x)` rounds half away from zero, so `(round 2.5)` is 3.
+fn builtin_round(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    to_whole("round", args, BigRational::round, f64::round)
+}
+
+fn builtin_sin(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    unary("sin", args, f64::sin)
+}
+
+fn builtin_cos(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    unary("cos", args, f64::cos)
+}
+
+fn builtin_tan(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    unary("tan", args, f64::tan)
+}
+
+fn builtin_asin(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_in_domain("asin", 1, &args[0], "a number from -1 to 1", |n| {
+        (-1.0..=1.0).contains(&n)
+    })?;
+    Ok(Expr::Number(n.asin()))
+}
+
+fn builtin_acos(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_in_domain("acos", 1, &args[0], "a number from -1 to 1", |n| {
+        (-1.0..=1.0).contains(&n)
+    })?;
+    Ok(Expr::Number(n.acos()))
+}
+
+/// `(atan x)` is the arc tangent of `x`, and `(atan y x)` the angle of the
+/// point `(x, y)` from the positive x axis.
+fn builtin_atan(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let y = expect_number("atan", 1, &args[0])?;
+    match args.get(1) {
+        Some(x) => Ok(Expr::Number(y.atan2(expect_number("atan", 2, x)?))),
+        None => Ok(Expr::Number(y.atan())),
+    }
+}
+
+#[rustfmt::skip]
+pub(crate) const MATH_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("sqrt",  builtin_sqrt,  BuiltinKind::Eager, Arity::Exact(1)),
+    ("pow",   builtin_pow,   BuiltinKind::Eager, Arity::Exact(2)),
+    ("exp",   builtin_exp,   BuiltinKind::Eager, Arity::Exact(1)),
+    ("log",   builtin_log,   BuiltinKind::Eager, Arity::Range(1, 2)),
+    ("floor", builtin_floor, BuiltinKind::Eager, Arity::Exact(1)),
+    ("ceil",  builtin_ceil,  BuiltinKind::Eager, Arity::Exact(1)),
+    ("round", builtin_round, BuiltinKind::Eager, Arity::Exact(1)),
+    ("sin",   builtin_sin,   BuiltinKind::Eager, Arity::Exact(1)),
+    ("cos",   builtin_cos,   BuiltinKind::Eager, Arity::Exact(1)),
+    ("tan",   builtin_tan,   BuiltinKind::Eager, Arity::Exact(1)),
+    ("asin",  builtin_asin,  BuiltinKind::Eager, Arity::Exact(1)),
+    ("acos",  builtin_acos,  BuiltinKind::Eager, Arity::Exact(1)),
+    ("atan",  builtin_atan,  BuiltinKind::Eager, Arity::Range(1, 2)),
+];
+
+/// Constants defined next to the builtins.
+pub(crate) const MATH_CONSTANTS: &[(&str, f64)] = &[("pi", PI)];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn math() {
+        let mut engine = Engine::new();
+        for (input, expected) in [
+            ("(sqrt 16)", "4.0"),
+            ("(pow 2 10)", "1024.0"),
+            ("(log (exp 2))", "2.0"),
+            ("(log 8 2)", "3.0"),
+            (
+                "(list (floor -1.5) (ceil -1.5) (round 2.5) (round -2.5))",
+                "(-2 -1 3 -3)",
+            ),
+            ("(round (* 1000 (sin (/ pi 6))))", "500"),
+            ("(cos 0)", "1.0"),
+            ("(atan -1 0)", "-1.5707963267948966"),
+            ("(= (* 4 (atan 1)) pi)", "true"),
+            (
+                "(sqrt -1)",
+                "Error: `sqrt` expected a non-negative number as argument 1, got the integer -1",
+            ),
+            (
+                "(log 0)",
+                "Error: `log` expected a positive number as argument 1, got the integer 0",
+            ),
+            (
+                "(asin 2)",
+                "Error: `asin` expected a number from -1 to 1 as argument 1, got the integer 2",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
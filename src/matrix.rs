@@ -0,0 +1,245 @@
+//! Matrices of numbers, behind the `ndarray` feature, for small numeric
+//! experiments without nested lists of floats.
+//!
+//! Matrices print as `#mat` followed by their rows, which the engine reads
+//! back as the same matrix.
+
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use ndarray::{Array2, Zip};
+
+use crate::builtins::expect_number;
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+use crate::parser::ReaderConfig;
+
+pub type Matrix = Array2<f64>;
+
+fn expected_rows(name: &str, position: usize, expr: &Expr) -> String {
+    message!(
+        "wrong-type",
+        name = name,
+        expected = "a list of equally long rows of numbers",
+        position = position,
+        actual = expr.describe()
+    )
+}
+
+/// A matrix from a list of rows, each a list of numbers.
+pub fn from_rows(name: &str, position: usize, expr: &Expr) -> Result<Matrix, String> {
+    let invalid = || expected_rows(name, position, expr);
+    let Expr::List(rows) = expr else {
+        return Err(invalid());
+    };
+    let width = match rows.first() {
+        Some(Expr::List(row)) => row.len(),
+        Some(_) => return Err(invalid()),
+        None => 0,
+    };
+    let mut values = Vec::with_capacity(rows.len() * width);
+    for row in rows {
+        match row {
+            Expr::List(row) if row.len() == width => {
+                for value in row {
+                    values.push(value.as_number().ok_or_else(invalid)?);
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Array2::from_shape_vec((rows.len(), width), values).map_err(|_| invalid())
+}
+
+/// The rows of the matrix as lists of numbers.
+pub fn to_rows(matrix: &Matrix) -> Expr {
+    Expr::List(
+        matrix
+            .rows()
+            .into_iter()
+            .map(|row| Expr::List(row.iter().map(|n| Expr::Number(*n)).collect()))
+            .collect(),
+    )
+}
+
+pub(crate) fn hash_matrix<H: Hasher>(matrix: &Matrix, state: &mut H) {
+    matrix.shape().hash(state);
+    for n in matrix {
+        // 0.0 and -0.0 are equal, so they have to hash the same
+        (if *n == 0.0 { 0.0 } else { *n }).to_bits().hash(state);
+    }
+}
+
+/// Reads `#mat rows` as the matrix with those rows.
+pub(crate) fn add_reader_tag(reader: &mut ReaderConfig) {
+    reader
+        .add_tag("mat", |rows| {
+            from_rows("#mat", 1, &rows).map(|matrix| Expr::Matrix(Rc::new(matrix)))
+        })
+        .expect("mat is a valid tag");
+}
+
+fn shape(matrix: &Matrix) -> String {
+    format!("{}x{}", matrix.nrows(), matrix.ncols())
+}
+
+fn expect_matrix<'a>(name: &str, position: usize, expr: &'a Expr) -> Result<&'a Matrix, String> {
+    match expr {
+        Expr::Matrix(matrix) => Ok(matrix),
+        _ => Err(message!(
+            "wrong-type",
+            name = name,
+            expected = "a matrix",
+            position = position,
+            actual = expr.describe()
+        )),
+    }
+}
+
+fn shape_error(name: &str, left: &Matrix, right: &Matrix) -> String {
+    message!(
+        "matrix-shapes",
+        name = name,
+        left = shape(left),
+        right = shape(right)
+    )
+}
+
+/// `(mat rows)` is the matrix with the given rows.
+fn builtin_mat(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Matrix(Rc::new(from_rows("mat", 1, &args[0])?)))
+}
+
+/// `(mat->list m)` is the rows of the matrix as lists.
+fn builtin_mat_to_list(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(to_rows(expect_matrix("mat->list", 1, &args[0])?))
+}
+
+/// `(mat-shape m)` is the matrix's `(rows columns)`.
+fn builtin_mat_shape(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let matrix = expect_matrix("mat-shape", 1, &args[0])?;
+    Ok(Expr::List(vec![
+        Expr::Int(matrix.nrows() as i64),
+        Expr::Int(matrix.ncols() as i64),
+    ]))
+}
+
+fn builtin_transpose(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let matrix = expect_matrix("transpose", 1, &args[0])?;
+    Ok(Expr::Matrix(Rc::new(matrix.t().to_owned())))
+}
+
+/// `(mat-mul a b)` is the matrix product.
+fn builtin_mat_mul(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let a = expect_matrix("mat-mul", 1, &args[0])?;
+    let b = expect_matrix("mat-mul", 2, &args[1])?;
+    if a.ncols() != b.nrows() {
+        return Err(shape_error("mat-mul", a, b));
+    }
+    Ok(Expr::Matrix(Rc::new(a.dot(b))))
+}
+
+/// `(dot a b)` is the dot product of two single row or column matrices of
+/// the same length.
+fn builtin_dot(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let a = expect_matrix("dot", 1, &args[0])?;
+    let b = expect_matrix("dot", 2, &args[1])?;
+    let is_vector = |m: &Matrix| m.nrows() == 1 || m.ncols() == 1;
+    if !is_vector(a) || !is_vector(b) || a.len() != b.len() {
+        return Err(shape_error("dot", a, b));
+    }
+    Ok(Expr::Number(a.iter().zip(b).map(|(x, y)| x * y).sum()))
+}
+
+/// Applies the operation element by element to two matrices of the same
+/// shape, or to a matrix and a number.
+fn elementwise(name: &str, args: &[Expr], op: fn(f64, f64) -> f64) -> Result<Expr, String> {
+    let result = match (&args[0], &args[1]) {
+        (Expr::Matrix(a), Expr::Matrix(b)) => {
+            if a.shape() != b.shape() {
+                return Err(shape_error(name, a, b));
+            }
+            Zip::from(&**a).and(&**b).map_collect(|x, y| op(*x, *y))
+        }
+        (Expr::Matrix(a), n @ (Expr::Int(_) | Expr::Number(_))) => {
+            let n = expect_number(name, 2, n)?;
+            a.mapv(|x| op(x, n))
+        }
+        (n @ (Expr::Int(_) | Expr::Number(_)), Expr::Matrix(b)) => {
+            let n = expect_number(name, 1, n)?;
+            b.mapv(|y| op(n, y))
+        }
+        (Expr::Matrix(_), other) => return Err(expect_matrix(name, 2, other).unwrap_err()),
+        (other, _) => return Err(expect_matrix(name, 1, other).unwrap_err()),
+    };
+    Ok(Expr::Matrix(Rc::new(result)))
+}
+
+fn builtin_mat_add(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    elementwise("mat+", args, |x, y| x + y)
+}
+
+fn builtin_mat_sub(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    elementwise("mat-", args, |x, y| x - y)
+}
+
+fn builtin_mat_mul_elements(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    elementwise("mat*", args, |x, y| x * y)
+}
+
+fn builtin_mat_div(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    elementwise("mat/", args, |x, y| x / y)
+}
+
+#[rustfmt::skip]
+pub(crate) const MATRIX_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("mat",       builtin_mat,              BuiltinKind::Eager, Arity::Exact(1)),
+    ("mat->list", builtin_mat_to_list,      BuiltinKind::Eager, Arity::Exact(1)),
+    ("mat-shape", builtin_mat_shape,        BuiltinKind::Eager, Arity::Exact(1)),
+    ("transpose", builtin_transpose,        BuiltinKind::Eager, Arity::Exact(1)),
+    ("mat-mul",   builtin_mat_mul,          BuiltinKind::Eager, Arity::Exact(2)),
+    ("dot",       builtin_dot,              BuiltinKind::Eager, Arity::Exact(2)),
+    ("mat+",      builtin_mat_add,          BuiltinKind::Eager, Arity::Exact(2)),
+    ("mat-",      builtin_mat_sub,          BuiltinKind::Eager, Arity::Exact(2)),
+    ("mat*",      builtin_mat_mul_elements, BuiltinKind::Eager, Arity::Exact(2)),
+    ("mat/",      builtin_mat_div,          BuiltinKind::Eager, Arity::Exact(2)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn matrices() {
+        let mut engine = Engine::new();
+        engine.eval("(def a (mat (quote ((1 2) (3 4)))))").unwrap();
+        assert_eq!(
+            engine.eval_to_string("(mat-mul a (transpose a))"),
+            "#mat ((5.0 11.0) (11.0 25.0))"
+        );
+        assert_eq!(
+            engine.eval_to_string("(mat+ (mat* a 2) (mat- a a))"),
+            "#mat ((2.0 4.0) (6.0 8.0))"
+        );
+        assert_eq!(
+            engine.eval_to_string(
+                "(dot (mat (quote ((1 2 3)))) (transpose (mat (quote ((4 5 6))))))"
+            ),
+            "32.0"
+        );
+        assert_eq!(engine.eval_to_string("(mat-shape (mat ()))"), "(0 0)");
+        // Printed matrices read back as the same matrix
+        assert_eq!(
+            engine.eval_to_string("(mat->list #mat ((1 2) (3 4)))"),
+            "((1.0 2.0) (3.0 4.0))"
+        );
+        assert_eq!(
+            engine.eval_to_string("(mat-mul a (mat (quote ((1 2 3)))))"),
+            "Error: `mat-mul` cannot combine a 2x2 matrix with a 1x3 one"
+        );
+        assert_eq!(
+            engine.eval_to_string("(mat (quote ((1 2) (3))))"),
+            "Error: `mat` expected a list of equally long rows of numbers as argument 1, got the list ((1 2) (3))"
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! The text of the errors and warnings users see, keyed by kind so that
+//! embedders can reword or translate them.
+//!
+//! Each message is a template whose `{name}` placeholders are filled in with
+//! the details of the error. The catalog in use belongs to the thread, and
+//! so is shared by every engine on it, since errors are raised in places
+//! such as the reader that have no engine at hand.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Every message kind with its default template.
+#[rustfmt::skip]
+const MESSAGES: &[(&str, &str)] = &[
+    // Reporting
+    ("error",                    "Error: {message}"),
+    ("warning",                  "Warning: {message}"),
+    ("located",                  "{message} at {span}"),
+    ("call-site",                " in {form} at {span}"),
+    ("call-site-unknown",        " in {form}"),
+
+    // Reading
+    ("unexpected-end",           "Unexpected end of input"),
+    ("unexpected-character",     "Unexpected '{character}' at {span}"),
+    ("unexpected-after",         "Unexpected '{token}' after the expression at {span}"),
+    ("missing-closing",          "Missing closing {delimiter} for the one at {span}"),
+    ("invalid-symbol-character", "Invalid character '{character}' in symbol '{symbol}' at {span}"),
+    ("invalid-number",           "Invalid number '{text}'"),
+    ("invalid-character",        "Invalid character '#\\{name}' at {span}"),
+    ("zero-denominator",         "Ratio '{text}' has a zero denominator"),
+    ("nested-too-deep",          "Lists nested deeper than {depth} levels at {span}"),
+    ("map-literal-odd",          "Map needs a value for each key at {span}"),
+    ("invalid-map-key",          "Map keys must be keywords, strings or numbers, got {key} in the map at {span}"),
+    ("duplicate-map-key",        "Duplicate key {key} in the map at {span}"),
+    ("invalid-set-member",       "Set members must be keywords, strings or numbers, got {member} in the set at {span}"),
+    ("duplicate-set-member",     "Duplicate member {member} in the set at {span}"),
+    ("invalid-reader-tag",       "Invalid reader tag '{name}'"),
+    ("unknown-reader-tag",       "Unknown reader tag '{tag}' at {span}"),
+    ("invalid-dispatch",         "'{character}' cannot be a dispatch character"),
+
+    // Evaluation
+    ("undefined-symbol",         "Undefined symbol '{name}'"),
+    ("call-undefined",           "Cannot call undefined symbol '{name}'{site}"),
+    ("cannot-call",              "Cannot call {value}{site}"),
+    ("capability-revoked",       "`{name}` was only lent to the evaluation it was given to"),
+    ("wrong-arity",              "wrong number of args ({given}) passed to: {name} (expects {expected})"),
+    ("wrong-type",               "`{name}` expected {expected} as argument {position}, got {actual}"),
+    ("division-by-zero",         "`{name}` cannot divide by zero"),
+    ("index-out-of-range",       "`{name}` index {index} is out of range for {length} elements"),
+    ("slice-backwards",          "`{name}` cannot slice from {start} back to {end}"),
+    ("reduce-empty",             "`reduce` of an empty sequence needs an initial value"),
+    ("assoc-odd",                "`assoc` needs a value for each key"),
+    ("compare-nan",              "Cannot compare NaN"),
+    ("cannot-compare",           "Cannot compare {left} with {right}"),
+    ("interrupted",              "Evaluation interrupted"),
+    ("out-of-fuel",              "Evaluation ran out of fuel"),
+    ("recursion-limit",          "Maximum recursion depth exceeded"),
+    ("sandboxed",                "`{name}` is not available in the strict sandbox"),
+    ("speculative",              "`{name}` is not available while evaluating speculatively"),
+    ("read-failed",              "Cannot read '{path}': {error}"),
+    ("write-failed",             "Cannot write '{path}': {error}"),
+    ("resource-closed",          "`{name}` cannot use a closed {kind}"),
+    ("assertion-failed",         "Assertion failed{site}{parts}"),
+    ("assertion-failed-because", "Assertion failed, {reason},{site}{parts}"),
+    ("assertion-parts",          ", where {parts}"),
+    ("assertion-part",           "{form} is {value}"),
+    ("assertion-not-approx",     "Assertion failed, {actual} is not within {epsilon} of {expected}{differences}"),
+    ("assertion-differences",    ", differing {differences}"),
+    ("assertion-no-match",       "Assertion failed, {value} does not match {pattern}"),
+
+    // Special forms
+    ("parameter-not-symbol",     "`{name}` parameters must be symbols, got {actual}"),
+    ("defn-parameter",           "`defn` parameters must be symbols or (symbol spec) pairs, got {actual}"),
+    ("unknown-defn-flag",        "Unknown `defn` flag '{flag}'"),
+    ("shadows-builtin",          "def shadows builtin '{name}'"),
+    ("would-shadow-builtin",     "def would shadow builtin '{name}'"),
+    ("non-tail-self-call",       "`{name}` calls itself outside tail position in {form}, so each call keeps a frame on the stack"),
+    ("let-binding",              "`let` binding must be a list, got {actual}"),
+    ("let-binding-length",       "`let` binding must be a list of length 2"),
+    ("let-binding-name",         "`let` binding name must be a symbol, got {actual}"),
+    ("cond-clause-length",       "`cond` clause must be a list of length 2"),
+    ("no-cond-match",            "No cond clause matched"),
+    ("with-open-binding",        "`with-open` binding must be a (name handle) list, got {actual}"),
+    ("with-redefs-binding",      "`with-redefs` binding must be a (name value) list, got {actual}"),
+
+    // Modules
+    ("invalid-module-name",      "Invalid module name '{name}'"),
+    ("module-not-found",         "Cannot find module '{name}' in {path}"),
+    ("require-cycle",            "Module '{name}' requires itself through {chain}"),
+    ("in-module",                "{message} in {path}"),
+    ("undefined-export",         "Module {module} exports '{name}', which it does not define"),
+
+    // Specs and rewriting
+    ("undefined-spec",           "Undefined spec '{name}'"),
+    ("invalid-spec",             "Invalid spec {spec}"),
+    ("nonconforming-argument",   "Argument {position} to `{name}` does not conform to its spec: {violations}"),
+    ("rewrite-rule",             "`rewrite` rules must be (pattern template) lists, got {actual}"),
+    ("rewrite-unsettled",        "`rewrite` did not settle after {passes} passes"),
+
+    // Options
+    ("invalid-option",           "Unknown option {option} for `{name}`"),
+    ("ambiguous-separators",     "`{name}` needs a decimal separator different from the thousands one"),
+
+    // Regular expressions
+    ("invalid-regex",            "Invalid regular expression '{pattern}': {error}"),
+
+    // Matrices
+    ("matrix-shapes",            "`{name}` cannot combine a {left} matrix with a {right} one"),
+
+    // Plotting
+    ("plot-missing-y",           "`{name}` needs the y values to plot"),
+    ("plot-lengths",             "`{name}` needs as many x values as y values, got {x} and {y}"),
+
+    // Storage
+    ("store-corrupt",            "'{path}' is not a JSON object of stored values"),
+
+    // URLs
+    ("invalid-url",              "Invalid URL '{url}'"),
+    ("invalid-percent-encoding", "Invalid percent-encoding in '{text}'"),
+
+    // EDN
+    ("edn-unexpected-end",       "Unexpected end of EDN"),
+    ("edn-unexpected",           "Unexpected '{character}' in EDN"),
+    ("edn-trailing",             "Unexpected '{character}' after the EDN value"),
+    ("edn-invalid",              "Invalid EDN '{text}'"),
+    ("edn-invalid-escape",       "Invalid escape '\\{character}' in EDN string"),
+    ("edn-unclosed-string",      "Missing closing \" in EDN string"),
+    ("edn-missing-character",    "Missing character after '\\' in EDN"),
+    ("edn-invalid-character",    "Invalid EDN character '\\{text}'"),
+    ("edn-unknown-tag",          "Unknown reader tag '#{tag}'"),
+    ("edn-nested-too-deep",      "EDN nested deeper than {depth} levels"),
+    ("edn-map-odd",              "EDN map needs a value for each key"),
+    ("edn-map-key",              "EDN map keys must be keywords, strings or numbers, got {key}"),
+    ("edn-duplicate-key",        "Duplicate key {key} in EDN map"),
+    ("edn-set-member",           "EDN set members must be keywords, strings or numbers, got {member}"),
+    ("edn-duplicate-member",     "Duplicate member {member} in EDN set"),
+    ("edn-unwritable",           "Cannot write {value} as EDN"),
+];
+
+fn default_template(kind: &str) -> Option<&'static str> {
+    MESSAGES
+        .iter()
+        .find(|(name, _)| *name == kind)
+        .map(|(_, template)| *template)
+}
+
+/// The `{name}` placeholders in a template, with the offsets of their braces.
+fn placeholders(template: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let start = offset + template[offset..].find('{')?;
+        let end = start + template[start..].find('}')?;
+        offset = end + 1;
+        Some((start, end + 1, &template[start + 1..end]))
+    })
+}
+
+/// Message templates overriding the defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+    templates: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Every kind of message, for embedders translating them all.
+    pub fn kinds() -> impl Iterator<Item = &'static str> {
+        MESSAGES.iter().map(|(kind, _)| *kind)
+    }
+
+    /// Replaces the template for a kind of message. The template may only
+    /// use the placeholders of the default one.
+    pub fn set(&mut self, kind: &str, template: &str) -> Result<(), String> {
+        let default =
+            default_template(kind).ok_or_else(|| format!("Unknown message kind '{}'", kind))?;
+        for (_, _, name) in placeholders(template) {
+            if !placeholders(default).any(|(_, _, known)| known == name) {
+                return Err(format!("Message '{}' has no parameter '{}'", kind, name));
+            }
+        }
+        self.templates
+            .insert(kind.to_string(), template.to_string());
+        Ok(())
+    }
+
+    pub fn template(&self, kind: &str) -> Option<&str> {
+        self.templates
+            .get(kind)
+            .map(String::as_str)
+            .or_else(|| default_template(kind))
+    }
+
+    /// The message of a kind, with its placeholders filled in from the
+    /// parameters.
+    pub fn format(&self, kind: &str, parameters: &[(&str, &dyn Display)]) -> String {
+        let Some(template) = self.template(kind) else {
+            return kind.to_string();
+        };
+        let mut message = String::with_capacity(template.len());
+        let mut copied = 0;
+        for (start, end, name) in placeholders(template) {
+            if let Some((_, value)) = parameters.iter().find(|(param, _)| *param == name) {
+                message.push_str(&template[copied..start]);
+                message.push_str(&value.to_string());
+                copied = end;
+            }
+        }
+        message.push_str(&template[copied..]);
+        message
+    }
+}
+
+thread_local! {
+    static CATALOG: RefCell<Catalog> = RefCell::new(Catalog::default());
+}
+
+/// Uses the catalog for the messages of every engine on this thread.
+pub fn set_catalog(catalog: Catalog) {
+    CATALOG.with(|current| *current.borrow_mut() = catalog);
+}
+
+/// Replaces one template in this thread's catalog.
+pub fn set_message(kind: &str, template: &str) -> Result<(), String> {
+    CATALOG.with(|current| current.borrow_mut().set(kind, template))
+}
+
+/// Formats a message with this thread's catalog.
+pub fn format_message(kind: &str, parameters: &[(&str, &dyn Display)]) -> String {
+    CATALOG.with(|current| current.borrow().format(kind, parameters))
+}
+
+/// `message!("kind", name = value, ...)` formats the message of that kind
+/// with the current catalog.
+macro_rules! message {
+    ($kind:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::messages::format_message(
+            $kind,
+            &[$((stringify!($name), &$value as &dyn std::fmt::Display)),*],
+        )
+    };
+}
+pub(crate) use message;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn defaults_use_their_parameters() {
+        assert_eq!(
+            message!("wrong-arity", given = 2, name = "inc", expected = 1),
+            "wrong number of args (2) passed to: inc (expects 1)"
+        );
+        // Placeholders without a value are left as they are
+        assert_eq!(message!("undefined-symbol"), "Undefined symbol '{name}'");
+        for kind in Catalog::kinds() {
+            assert!(Catalog::default()
+                .set(kind, default_template(kind).unwrap())
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn override_messages() {
+        let mut engine = Engine::new();
+        set_message("undefined-symbol", "Symbole inconnu : {name}").unwrap();
+        set_message("error", "Erreur : {message}").unwrap();
+        assert_eq!(
+            engine.eval_to_string("zut"),
+            "Erreur : Symbole inconnu : zut"
+        );
+
+        assert_eq!(
+            set_message("undefined", "?"),
+            Err("Unknown message kind 'undefined'".to_string())
+        );
+        assert_eq!(
+            set_message("out-of-fuel", "Out of {fuel}"),
+            Err("Message 'out-of-fuel' has no parameter 'fuel'".to_string())
+        );
+
+        set_catalog(Catalog::default());
+        assert_eq!(
+            engine.eval_to_string("zut"),
+            "Error: Undefined symbol 'zut'"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Modules: a file that starts with `(ns name)` is a module, and what it
+//! defines at the top level is what requiring it gives access to.
+//!
+//! `(require "utils.strings")` loads `utils/strings.lisp` from the first
+//! directory on the engine's module path that has it, evaluating it in a
+//! scope of its own the first time and defining what it exports in the
+//! requiring scope every time.
+//!
+//! `(ns name (:export f g))` limits that to the names listed. Without an
+//! export list every definition is exported except private ones: those
+//! whose name starts with `-`, such as `-helper`, and functions defined
+//! with `(defn ^:private ...)`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::builtins::{define, expect_symbol, expect_text};
+use crate::engine::SandboxPolicy;
+use crate::interpreter::{eval, Expr, Scope};
+use crate::messages::message;
+use crate::parser::{expr_to_string, parse_with_reader};
+use crate::syntax::parse_syntax;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespace {
+    pub name: String,
+    /// The names listed with `:export`, or `None` to export every public
+    /// definition.
+    pub exports: Option<Vec<String>>,
+}
+
+/// Whether the binding stays inside its module when there is no export list.
+pub fn is_private(name: &str, value: &Expr) -> bool {
+    (name.len() > 1 && name.starts_with('-'))
+        || matches!(value, Expr::Function(func) if func.private)
+}
+
+/// The bindings a module scope gives to the modules that require it, sorted
+/// by name.
+pub fn exports(scope: &Scope) -> Result<Vec<(String, Expr)>, String> {
+    let bindings = scope.bindings();
+    let Some(Namespace {
+        name: module,
+        exports: Some(exports),
+    }) = scope.namespace()
+    else {
+        return Ok(bindings
+            .into_iter()
+            .filter(|(name, value)| !is_private(name, value))
+            .collect());
+    };
+
+    for name in &exports {
+        if !bindings.iter().any(|(bound, _)| bound == name) {
+            return Err(message!("undefined-export", name = name, module = module));
+        }
+    }
+    Ok(bindings
+        .into_iter()
+        .filter(|(name, _)| exports.contains(name))
+        .collect())
+}
+
+/// The modules an engine has loaded, by name, and those it is loading.
+#[derive(Debug, Default)]
+pub struct ModuleCache {
+    loaded: HashMap<String, Scope>,
+    /// Innermost last, to report which requires form a cycle.
+    loading: Vec<String>,
+}
+
+impl ModuleCache {
+    /// Forgets every loaded module, returning their scopes.
+    pub(crate) fn unload(&mut self) -> Vec<Scope> {
+        self.loaded.drain().map(|(_, scope)| scope).collect()
+    }
+}
+
+/// The path of the module's file relative to a directory on the module
+/// path: `utils.strings` is `utils/strings.lisp`.
+fn module_file(name: &str) -> Result<PathBuf, String> {
+    let parts: Vec<&str> = name.split('.').collect();
+    let plain = |part: &&str| !part.is_empty() && !part.contains(['/', '\\']);
+    if !parts.iter().all(plain) {
+        return Err(message!("invalid-module-name", name = name));
+    }
+    let mut path: PathBuf = parts.iter().collect();
+    path.set_extension("lisp");
+    Ok(path)
+}
+
+/// Evaluates the module's file in a scope directly above the builtins.
+fn load(name: &str, scope: &Scope) -> Result<Scope, String> {
+    let runtime = scope.runtime();
+    let file = module_file(name)?;
+    let Some(path) = runtime
+        .config
+        .module_path
+        .iter()
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+    else {
+        let dirs: Vec<String> = runtime
+            .config
+            .module_path
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        return Err(message!(
+            "module-not-found",
+            name = name,
+            path = dirs.join(", ")
+        ));
+    };
+    let in_module = |e: String| message!("in-module", message = e, path = path.display());
+    let source = std::fs::read_to_string(&path)
+        .map_err(|e| message!("read-failed", path = path.display(), error = e))?;
+
+    let mut builtins = scope.clone();
+    while let Some(parent) = builtins.parent() {
+        builtins = parent;
+    }
+    let mut module = Scope::with_parent(&builtins);
+    let tree = parse_syntax(&source).map_err(in_module)?;
+    for form in &tree.forms {
+        let reader = runtime.reader().clone();
+        let (expr, _) = parse_with_reader(
+            &source[form.span.start..form.span.end],
+            &reader,
+            runtime.config.max_nesting_depth,
+        )
+        .map_err(in_module)?;
+        eval(&expr, &mut module).map_err(in_module)?;
+    }
+    Ok(module)
+}
+
+/// `(require name)` defines what the module exports in the current scope,
+/// loading the module first unless it has been already.
+pub fn builtin_require(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let runtime = scope.runtime().clone();
+    if runtime.config.sandbox == SandboxPolicy::Strict {
+        return Err(message!("sandboxed", name = "require"));
+    }
+    let name = expect_text("require", 1, &args[0])?;
+
+    let cached = runtime.modules().loaded.get(name).cloned();
+    let module = match cached {
+        Some(module) => module,
+        None => {
+            {
+                let mut modules = runtime.modules();
+                if modules.loading.iter().any(|loading| loading == name) {
+                    let mut chain = modules.loading.clone();
+                    chain.push(name.to_string());
+                    return Err(message!(
+                        "require-cycle",
+                        name = name,
+                        chain = chain.join(" -> ")
+                    ));
+                }
+                modules.loading.push(name.to_string());
+            }
+            let loaded = load(name, scope);
+            let mut modules = runtime.modules();
+            modules.loading.pop();
+            let module = loaded?;
+            modules.loaded.insert(name.to_string(), module.clone());
+            module
+        }
+    };
+
+    for (export, value) in exports(&module)? {
+        let doc = module.get_doc(&export);
+        define(&export, doc.as_deref(), value, scope)?;
+    }
+    Ok(Expr::Nil)
+}
+
+/// `(ns name clause...)` declares the scope a module, with `(:export name...)`
+/// clauses listing what it exports.
+pub fn builtin_ns(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let name = expect_symbol("ns", 1, &args[0])?.to_string();
+    let mut exports: Option<Vec<String>> = None;
+    for clause in &args[1..] {
+        let invalid = || {
+            message!(
+                "invalid-option",
+                name = "ns",
+                option = expr_to_string(clause)
+            )
+        };
+        match clause {
+            Expr::List(items) if items.first() == Some(&Expr::keyword("export")) => {
+                let exports = exports.get_or_insert_with(Vec::new);
+                for item in &items[1..] {
+                    match item {
+                        Expr::Symbol(name) => exports.push(name.clone()),
+                        _ => return Err(invalid()),
+                    }
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    scope.set_namespace(Namespace { name, exports });
+    Ok(Expr::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+    use std::path::Path;
+
+    fn exported_names(engine: &mut Engine) -> Result<Vec<String>, String> {
+        exports(engine.scope()).map(|exports| exports.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// A directory of its own for the test's module files.
+    fn module_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rs-lisp-{}-{}", test, std::process::id()));
+        for (file, source) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn engine_with_path(dir: &Path) -> Engine {
+        Engine::with_config(EngineConfig {
+            module_path: vec![PathBuf::from("missing"), dir.to_path_buf()],
+            ..EngineConfig::default()
+        })
+    }
+
+    #[test]
+    fn require_modules() {
+        let dir = module_dir(
+            "require",
+            &[(
+                "utils/strings.lisp",
+                "; Text helpers\n(ns utils.strings (:export shout))\n(def -mark \"!\")\n(def shout \"Adds a mark\" (fn (s) (str s -mark)))",
+            )],
+        );
+        let mut engine = engine_with_path(&dir);
+        assert_eq!(engine.eval("(require \"utils.strings\")"), Ok(Expr::Nil));
+        assert_eq!(engine.eval("(shout \"hi\")"), Ok(Expr::string("hi!")));
+        assert_eq!(engine.doc("shout"), Some("Adds a mark".to_string()));
+        assert_eq!(engine.scope().get_variable("-mark"), None);
+
+        // Requiring again uses the module already loaded
+        std::fs::write(dir.join("utils/strings.lisp"), "(def shout 1)").unwrap();
+        engine.eval("(def shout 2)").unwrap();
+        engine.eval("(require (quote utils.strings))").unwrap();
+        assert_eq!(engine.eval("(shout \"hi\")"), Ok(Expr::string("hi!")));
+        // A new engine loads it afresh
+        let mut fresh = engine_with_path(&dir);
+        fresh.eval("(require \"utils.strings\")").unwrap();
+        assert_eq!(fresh.eval("shout"), Ok(Expr::int(1)));
+
+        assert_eq!(
+            engine.eval("(require \"nowhere\")"),
+            Err(format!(
+                "Cannot find module 'nowhere' in missing, {}",
+                dir.display()
+            ))
+        );
+        assert_eq!(
+            engine.eval("(require \"utils..strings\")"),
+            Err("Invalid module name 'utils..strings'".to_string())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn require_cycle() {
+        let dir = module_dir(
+            "cycle",
+            &[("a.lisp", "(require \"b\")"), ("b.lisp", "(require \"a\")")],
+        );
+        let mut engine = engine_with_path(&dir);
+        let result = engine.eval("(require \"a\")");
+        std::fs::remove_dir_all(&dir).unwrap();
+        // Each module the error passes through adds where it happened
+        assert_eq!(
+            result,
+            Err(format!(
+                "Module 'a' requires itself through a -> b -> a in {} in {}",
+                dir.join("b.lisp").display(),
+                dir.join("a.lisp").display()
+            ))
+        );
+    }
+
+    #[test]
+    fn export_lists() {
+        let mut engine = Engine::new();
+        engine.eval("(ns utils (:export double))").unwrap();
+        engine.eval("(defn double (x) (* 2 x))").unwrap();
+        engine.eval("(defn triple (x) (* 3 x))").unwrap();
+        assert_eq!(exported_names(&mut engine), Ok(vec!["double".to_string()]));
+        assert_eq!(
+            engine.scope().namespace().map(|ns| ns.name),
+            Some("utils".to_string())
+        );
+
+        engine
+            .eval("(ns utils (:export double) (:export half))")
+            .unwrap();
+        assert_eq!(
+            exported_names(&mut engine),
+            Err("Module utils exports 'half', which it does not define".to_string())
+        );
+        assert_eq!(
+            engine.eval_to_string("(ns utils (export double))"),
+            "Error: Unknown option (export double) for `ns`"
+        );
+    }
+
+    #[test]
+    fn private_definitions() {
+        let mut engine = Engine::new();
+        engine.eval("(ns utils)").unwrap();
+        engine.eval("(def -scale 2)").unwrap();
+        engine
+            .eval("(defn ^:private ^:pure helper (x) (* -scale x))")
+            .unwrap();
+        engine.eval("(defn scale (x) (helper x))").unwrap();
+        engine.eval("(def - 1)").unwrap();
+        assert_eq!(
+            exported_names(&mut engine),
+            Ok(vec!["-".to_string(), "scale".to_string()])
+        );
+        assert_eq!(engine.eval("(scale 4)"), Ok(Expr::int(8)));
+    }
+}
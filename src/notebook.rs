@@ -0,0 +1,111 @@
+//! Bookkeeping for notebook-style evaluation, where source is split into
+//! cells that can be edited and re-run in any order.
+
+use std::collections::BTreeSet;
+
+use crate::interpreter::Expr;
+
+/// What a cell did the last time it ran.
+#[derive(Debug)]
+struct CellRecord {
+    id: String,
+    /// Global names the cell defined or redefined.
+    defines: BTreeSet<String>,
+    /// Every symbol appearing in the cell's source.
+    reads: BTreeSet<String>,
+}
+
+/// The result of running a cell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellOutput {
+    pub result: Result<Expr, String>,
+    /// Cells that used definitions the run changed, directly or through
+    /// other cells, in the order they first ran.
+    pub stale: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Notebook {
+    /// In the order the cells first ran.
+    cells: Vec<CellRecord>,
+}
+
+impl Notebook {
+    /// The names the cell defined the last time it ran.
+    pub fn definitions(&self, id: &str) -> BTreeSet<String> {
+        self.cells
+            .iter()
+            .find(|cell| cell.id == id)
+            .map(|cell| cell.defines.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn record(&mut self, id: &str, defines: BTreeSet<String>, reads: BTreeSet<String>) {
+        match self.cells.iter_mut().find(|cell| cell.id == id) {
+            Some(cell) => {
+                cell.defines = defines;
+                cell.reads = reads;
+            }
+            None => self.cells.push(CellRecord {
+                id: id.to_string(),
+                defines,
+                reads,
+            }),
+        }
+    }
+
+    /// The cells other than `id` that depend on any of the `changed` names.
+    pub fn downstream(&self, id: &str, changed: BTreeSet<String>) -> Vec<String> {
+        let mut changed = changed;
+        let mut stale: Vec<&CellRecord> = Vec::new();
+
+        // Marking a cell stale also invalidates what it defined, so repeat
+        // until nothing new is found.
+        loop {
+            let newly_stale: Vec<&CellRecord> = self
+                .cells
+                .iter()
+                .filter(|cell| cell.id != id && !stale.iter().any(|s| s.id == cell.id))
+                .filter(|cell| !cell.reads.is_disjoint(&changed))
+                .collect();
+            if newly_stale.is_empty() {
+                break;
+            }
+            for cell in newly_stale {
+                changed.extend(cell.defines.iter().cloned());
+                stale.push(cell);
+            }
+        }
+
+        self.cells
+            .iter()
+            .filter(|cell| stale.iter().any(|s| s.id == cell.id))
+            .map(|cell| cell.id.clone())
+            .collect()
+    }
+}
+
+/// Every symbol appearing anywhere in the expression.
+pub fn symbols_in(expr: &Expr, symbols: &mut BTreeSet<String>) {
+    match expr {
+        Expr::Symbol(name) => {
+            symbols.insert(name.clone());
+        }
+        Expr::List(list) => {
+            for item in list {
+                symbols_in(item, symbols);
+            }
+        }
+        Expr::Vector(items) => {
+            for item in items.iter() {
+                symbols_in(item, symbols);
+            }
+        }
+        Expr::Map(map) => {
+            for value in map.values() {
+                symbols_in(value, symbols);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,292 @@
+//! Formatting numbers for reports and reading them back, the same way
+//! whatever the host's locale.
+//!
+//! Options are a map with keyword keys:
+//!
+//! - `:precision n`: digits after the decimal point, rounding half away
+//!   from zero; without it, as many as the number needs
+//! - `:thousands sep`: put between groups of three digits, none by default
+//!   when formatting; when parsing, digits may be grouped by `,` unless
+//!   another separator is given
+//! - `:decimal sep`: the decimal point, `.` by default
+
+use crate::builtins::{expect_number, expect_text};
+use crate::interpreter::{sorted_entries, Expr, Scope};
+use crate::map::expect_map;
+use crate::messages::message;
+use crate::parser::expr_to_string;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    pub precision: Option<usize>,
+    pub thousands: String,
+    pub decimal: String,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            precision: None,
+            thousands: String::new(),
+            decimal: ".".to_string(),
+        }
+    }
+}
+
+/// The most digits after the decimal point `precision` may ask for.
+const MAX_PRECISION: usize = 20;
+
+impl NumberFormat {
+    /// Reads the options over `format`, which they change only where given.
+    fn from_options(
+        name: &str,
+        options: Option<&Expr>,
+        mut format: NumberFormat,
+    ) -> Result<Self, String> {
+        let options = match options {
+            Some(options) => expect_map(name, 2, options)?,
+            None => Default::default(),
+        };
+        for (key, value) in sorted_entries(&options) {
+            match key.expr() {
+                Expr::Keyword(key) if key == "precision" => {
+                    let n = expect_number(name, 2, value)?;
+                    if n.fract() != 0.0 || !(0.0..=MAX_PRECISION as f64).contains(&n) {
+                        return Err(message!(
+                            "wrong-type",
+                            name = name,
+                            expected = format!("a precision from 0 to {}", MAX_PRECISION),
+                            position = 2,
+                            actual = value.describe()
+                        ));
+                    }
+                    format.precision = Some(n as usize);
+                }
+                Expr::Keyword(key) if key == "thousands" => {
+                    format.thousands = expect_text(name, 2, value)?.to_string();
+                }
+                Expr::Keyword(key) if key == "decimal" => {
+                    format.decimal = expect_text(name, 2, value)?.to_string();
+                }
+                key => {
+                    return Err(message!(
+                        "invalid-option",
+                        name = name,
+                        option = format!("{} {}", expr_to_string(key), expr_to_string(value))
+                    ))
+                }
+            }
+        }
+        if format.decimal.is_empty() || format.decimal == format.thousands {
+            return Err(message!("ambiguous-separators", name = name));
+        }
+        Ok(format)
+    }
+
+    pub fn format(&self, n: f64) -> String {
+        if n.is_nan() {
+            return "NaN".to_string();
+        }
+        if n.is_infinite() {
+            return if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+        }
+        let digits = match self.precision {
+            Some(precision) => {
+                // Round half away from zero rather than to even
+                let scale = 10f64.powi(precision as i32);
+                let scaled = n.abs() * scale;
+                let rounded = if scaled.is_finite() {
+                    scaled.round() / scale
+                } else {
+                    n.abs()
+                };
+                format!("{:.*}", precision, rounded)
+            }
+            None => n.abs().to_string(),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (digits.as_str(), None),
+        };
+
+        let mut out = String::new();
+        if n < 0.0 && digits.bytes().any(|b| b.is_ascii_digit() && b != b'0') {
+            out.push('-');
+        }
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                out.push_str(&self.thousands);
+            }
+            out.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            out.push_str(&self.decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+
+    /// Reads a number written as `format` would write it: an optional
+    /// sign, digits in groups of three if separated at all, an optional
+    /// fraction and an optional exponent. Anything else is rejected.
+    pub fn parse(&self, text: &str) -> Result<f64, String> {
+        let invalid = || message!("invalid-number", text = text);
+        let (sign, rest) = match text.strip_prefix(['+', '-']) {
+            Some(rest) => (&text[..1], rest),
+            None => ("", text),
+        };
+        let (mantissa, exponent) = match rest.find(['e', 'E']) {
+            Some(e) => (&rest[..e], Some(&rest[e + 1..])),
+            None => (rest, None),
+        };
+        let (whole, fraction) = match mantissa.split_once(self.decimal.as_str()) {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (mantissa, None),
+        };
+
+        let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        let whole = if self.thousands.is_empty() || !whole.contains(self.thousands.as_str()) {
+            if !all_digits(whole) {
+                return Err(invalid());
+            }
+            whole.to_string()
+        } else {
+            let groups: Vec<&str> = whole.split(self.thousands.as_str()).collect();
+            let first_ok = all_digits(groups[0]) && groups[0].len() <= 3;
+            if !first_ok || !groups[1..].iter().all(|g| all_digits(g) && g.len() == 3) {
+                return Err(invalid());
+            }
+            groups.concat()
+        };
+        if fraction.is_some_and(|fraction| !all_digits(fraction)) {
+            return Err(invalid());
+        }
+        if let Some(exponent) = exponent {
+            let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            if !all_digits(digits) {
+                return Err(invalid());
+            }
+        }
+
+        let mut normalized = format!("{}{}", sign, whole);
+        if let Some(fraction) = fraction {
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        if let Some(exponent) = exponent {
+            normalized.push('e');
+            normalized.push_str(exponent);
+        }
+        normalized.parse().map_err(|_| invalid())
+    }
+}
+
+/// `(format-number n options)` writes the number as the options say.
+pub fn builtin_format_number(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_number("format-number", 1, &args[0])?;
+    let format = NumberFormat::from_options("format-number", args.get(1), NumberFormat::default())?;
+    Ok(Expr::String(format.format(n)))
+}
+
+/// `(parse-number text options)` reads a number written with the same
+/// options, failing on anything that is not exactly such a number. Digits
+/// may be grouped by `,` unless the options give another separator.
+pub fn builtin_parse_number(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let text = expect_text("parse-number", 1, &args[0])?;
+    let grouped = NumberFormat {
+        thousands: ",".to_string(),
+        ..NumberFormat::default()
+    };
+    let format = NumberFormat::from_options("parse-number", args.get(1), grouped)?;
+    format.parse(text).map(Expr::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn format_numbers() {
+        let european = NumberFormat {
+            precision: Some(2),
+            thousands: ".".to_string(),
+            decimal: ",".to_string(),
+        };
+        assert_eq!(european.format(-1234567.891), "-1.234.567,89");
+        assert_eq!(european.format(0.125), "0,13");
+        assert_eq!(european.format(-0.001), "0,00");
+        assert_eq!(NumberFormat::default().format(1234.5), "1234.5");
+
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval_to_string(r#"(format-number 1234.5 {:precision 2 :thousands ","})"#),
+            r#""1,234.50""#
+        );
+        assert_eq!(
+            engine.eval_to_string("(format-number 1234.5)"),
+            r#""1234.5""#
+        );
+        assert_eq!(
+            engine.eval_to_string("(format-number 1 {:width 3})"),
+            "Error: Unknown option :width 3 for `format-number`"
+        );
+        assert_eq!(
+            engine.eval_to_string("(format-number 1 (quote ((precision 2))))"),
+            "Error: `format-number` expected a map as argument 2, got the list ((precision 2))"
+        );
+    }
+
+    #[test]
+    fn parse_numbers() {
+        let format = NumberFormat {
+            thousands: ",".to_string(),
+            ..NumberFormat::default()
+        };
+        assert_eq!(format.parse("-1,234.5e2"), Ok(-123450.0));
+        assert_eq!(format.parse("+12"), Ok(12.0));
+        for text in [
+            "1,23",
+            "12,345,67",
+            "1.",
+            ".5",
+            "1e",
+            "--1",
+            "1 000",
+            "0x10",
+            "",
+        ] {
+            assert_eq!(
+                format.parse(text),
+                Err(format!("Invalid number '{}'", text)),
+                "{}",
+                text
+            );
+        }
+
+        let mut engine = Engine::new();
+        for (input, expected) in [
+            (r#"(parse-number "1,234.5")"#, "1234.5"),
+            (r#"(parse-number "1234.5")"#, "1234.5"),
+            (r#"(parse-number "1_234.5" {:thousands "_"})"#, "1234.5"),
+            (
+                r#"(parse-number "1,234.5" {:thousands "_"})"#,
+                "Error: Invalid number '1,234.5'",
+            ),
+            (
+                r#"(parse-number "1.234,5" {:thousands "." :decimal ","})"#,
+                "1234.5",
+            ),
+            (
+                r#"(parse-number "1,5" {:decimal ","})"#,
+                "Error: `parse-number` needs a decimal separator different from the thousands one",
+            ),
+            (
+                r#"(parse-number (format-number 1234567.25 {:thousands "_"}) {:thousands "_"})"#,
+                "1234567.25",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
@@ -1,95 +1,894 @@
-use crate::interpreter::Expr;
-
-fn tokenize(input: &str) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut current_token = String::new();
-    let mut in_string = false;
-    let mut escaped = false;
-
-    for c in input.chars() {
-        if escaped {
-            current_token.push(c);
-            escaped = false;
-        } else if c == '\\' {
-            escaped = true;
-        } else if c == '"' {
-            in_string = !in_string;
-        } else if c.is_whitespace() && !in_string {
-            if !current_token.is_empty() {
-                tokens.push(current_token);
-                current_token = String::new();
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
+
+use crate::interpreter::{sorted_entries, sorted_members, Expr, Map, MapKey, Set};
+use crate::messages::message;
+use crate::syntax::Trivia;
+
+/// A region of source text, used to point error messages at the offending form.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Maps the lists of a parsed expression back to where they appeared in the
+/// source. Lists are identified by the address of their element buffer, so
+/// entries are only meaningful while the parsed expression is alive and
+/// unmodified.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpanTable {
+    spans: Vec<(usize, Span)>,
+}
+
+impl SpanTable {
+    fn insert(&mut self, list: &[Expr], span: Span) {
+        if !list.is_empty() {
+            self.spans.push((list.as_ptr() as usize, span));
+        }
+    }
+
+    pub fn get(&self, list: &[Expr]) -> Option<Span> {
+        if list.is_empty() {
+            return None;
+        }
+        let address = list.as_ptr() as usize;
+        self.spans
+            .iter()
+            .find(|(key, _)| *key == address)
+            .map(|(_, span)| *span)
+    }
+}
+
+pub(crate) struct Token<'a> {
+    /// Borrowed from the input unless quoting or escapes changed it.
+    pub(crate) text: Cow<'a, str>,
+    pub(crate) span: Span,
+    /// Written between `"` or `|`, so always a string or symbol, whatever
+    /// its text.
+    pub(crate) quoted: bool,
+    /// A string literal, written between `"`.
+    pub(crate) string: bool,
+    /// A character literal, whose text is what was written after `#\`.
+    pub(crate) character: bool,
+}
+
+/// Reads tokens one at a time as the parser asks for them, borrowing their
+/// text from the input where it can.
+pub(crate) struct Lexer<'a, 'r> {
+    input: &'a str,
+    offset: usize,
+    line: usize,
+    column: usize,
+    reader: &'r ReaderConfig,
+}
+
+impl<'a, 'r> Lexer<'a, 'r> {
+    pub(crate) fn new(input: &'a str, reader: &'r ReaderConfig) -> Self {
+        Lexer {
+            input,
+            offset: 0,
+            line: 1,
+            column: 1,
+            reader,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    /// Moves past the next character, returning it along with where it was.
+    fn bump(&mut self) -> Option<(char, Span)> {
+        let c = self.peek()?;
+        let here = Span {
+            start: self.offset,
+            end: self.offset + c.len_utf8(),
+            line: self.line,
+            column: self.column,
+        };
+        self.offset = here.end;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some((c, here))
+    }
+
+    /// Moves past whitespace and `;` comments, keeping them if asked to.
+    pub(crate) fn skip_trivia(&mut self, mut keep: Option<&mut Vec<Trivia>>) {
+        loop {
+            let start = self.offset;
+            let comment = match self.peek() {
+                Some(';') => true,
+                Some(c) if c.is_whitespace() => false,
+                _ => return,
+            };
+            if comment {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.bump();
+                }
+            } else {
+                while self.peek().is_some_and(char::is_whitespace) {
+                    self.bump();
+                }
             }
-        } else if c == '(' || c == ')' {
-            if !current_token.is_empty() {
-                tokens.push(current_token);
-                current_token = String::new();
+            if let Some(keep) = keep.as_deref_mut() {
+                let text = self.input[start..self.offset].to_string();
+                keep.push(if comment {
+                    Trivia::Comment(text)
+                } else {
+                    Trivia::Whitespace(text)
+                });
             }
-            tokens.push(c.to_string());
-        } else {
-            current_token.push(c);
         }
     }
 
-    if !current_token.is_empty() {
-        tokens.push(current_token);
+    /// The input from the next token on.
+    fn rest(&mut self) -> &'a str {
+        self.skip_trivia(None);
+        &self.input[self.offset..]
     }
 
-    tokens
-}
+    pub(crate) fn next_token(&mut self) -> Option<Result<Token<'a>, String>> {
+        self.skip_trivia(None);
+        let (first, start) = self.bump()?;
+        if "()[]{}".contains(first) || self.reader.dispatch.contains_key(&first) {
+            return Some(Ok(Token {
+                text: Cow::Borrowed(&self.input[start.start..start.end]),
+                span: start,
+                quoted: false,
+                string: false,
+                character: false,
+            }));
+        }
+        if first == '"' {
+            return Some(self.string(start));
+        }
+        if first == '#' && self.peek() == Some('\\') {
+            return Some(self.character(start));
+        }
+        if first == '#' && self.peek() == Some('{') {
+            self.bump();
+            return Some(Ok(Token {
+                text: Cow::Borrowed(&self.input[start.start..self.offset]),
+                span: Span {
+                    end: self.offset,
+                    ..start
+                },
+                quoted: false,
+                string: false,
+                character: false,
+            }));
+        }
+
+        // Only built up once quoting or an escape makes the text differ from
+        // the input
+        let mut owned: Option<String> = None;
+        let mut quoted = false;
+        let mut next = Some((first, start));
+        while let Some((c, here)) = next {
+            if c == '\\' || c == '|' {
+                let text =
+                    owned.get_or_insert_with(|| self.input[start.start..here.start].to_string());
+                if c == '\\' {
+                    if let Some((escaped, _)) = self.bump() {
+                        text.push(escaped);
+                    }
+                } else {
+                    quoted = true;
+                    loop {
+                        match self.bump() {
+                            Some((d, _)) if d == c => break,
+                            Some(('\\', _)) => {
+                                if let Some((escaped, _)) = self.bump() {
+                                    text.push(escaped);
+                                }
+                            }
+                            Some((d, _)) => text.push(d),
+                            None => {
+                                return Some(Err(message!(
+                                    "missing-closing",
+                                    delimiter = c,
+                                    span = here
+                                )))
+                            }
+                        }
+                    }
+                }
+            } else if let Some(text) = &mut owned {
+                text.push(c);
+            }
+
+            match self.peek() {
+                Some(c) if c.is_whitespace() || "()[]{}\";".contains(c) => break,
+                _ => next = self.bump(),
+            }
+        }
+
+        let span = Span {
+            end: self.offset,
+            ..start
+        };
+        let text = match owned {
+            // A lone trailing backslash leaves nothing to read
+            Some(text) if text.is_empty() && !quoted => return self.next_token(),
+            Some(text) => Cow::Owned(text),
+            None => Cow::Borrowed(&self.input[span.start..span.end]),
+        };
+        Some(Ok(Token {
+            text,
+            span,
+            quoted,
+            string: false,
+            character: false,
+        }))
+    }
 
-fn parse_expr(tokens: &mut Vec<String>) -> Result<Expr, String> {
-    if tokens.is_empty() {
-        return Err("Unexpected end of input".to_string());
+    /// Reads a character literal after its `#`. The character after the
+    /// `\` is taken even if it would end a token, as in `#\(`, and any
+    /// letters after it make a name such as `space`.
+    fn character(&mut self, start: Span) -> Result<Token<'a>, String> {
+        self.bump();
+        let after = self.offset;
+        self.bump().ok_or_else(|| message!("unexpected-end"))?;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !"()[]{}\";".contains(c))
+        {
+            self.bump();
+        }
+        Ok(Token {
+            text: Cow::Borrowed(&self.input[after..self.offset]),
+            span: Span {
+                end: self.offset,
+                ..start
+            },
+            quoted: false,
+            string: false,
+            character: true,
+        })
     }
 
-    let token = tokens.remove(0);
-    match token.as_str() {
-        "(" => {
-            let mut list = Vec::new();
-            while !tokens.is_empty() && tokens[0] != ")" {
-                list.push(parse_expr(tokens)?);
+    /// Reads a string literal after its opening `"`, turning `\n`, `\t` and
+    /// `\r` into the characters they stand for and taking any other escaped
+    /// character as it is.
+    fn string(&mut self, start: Span) -> Result<Token<'a>, String> {
+        let mut owned: Option<String> = None;
+        loop {
+            let (c, here) = self
+                .bump()
+                .ok_or_else(|| message!("missing-closing", delimiter = '"', span = start))?;
+            match c {
+                '"' => break,
+                '\\' => {
+                    let text =
+                        owned.get_or_insert_with(|| self.input[start.end..here.start].to_string());
+                    let (escaped, _) = self.bump().ok_or_else(|| {
+                        message!("missing-closing", delimiter = '"', span = start)
+                    })?;
+                    text.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        c => c,
+                    });
+                }
+                c => {
+                    if let Some(text) = &mut owned {
+                        text.push(c);
+                    }
+                }
             }
-            if tokens.is_empty() {
-                return Err("Unexpected end of input".to_string());
+        }
+
+        let span = Span {
+            end: self.offset,
+            ..start
+        };
+        let text = match owned {
+            Some(text) => Cow::Owned(text),
+            None => Cow::Borrowed(&self.input[start.end..span.end - 1]),
+        };
+        Ok(Token {
+            text,
+            span,
+            quoted: true,
+            string: true,
+            character: false,
+        })
+    }
+}
+
+/// Turns the form after a reader tag or dispatch character into the value
+/// it stands for.
+pub type ReaderHandler = Rc<dyn Fn(Expr) -> Result<Expr, String>>;
+
+/// Returns the form to print after a tag in place of the value, or `None`
+/// for values the tag does not stand for.
+pub type TagPrinter = Rc<dyn Fn(&Expr) -> Option<Expr>>;
+
+/// Literal syntax added by the host on top of the usual grammar: tags such as
+/// `#uuid "..."`, and dispatch characters such as `@x`, each handing the form
+/// that follows to a handler.
+#[derive(Clone, Default)]
+pub struct ReaderConfig {
+    dispatch: HashMap<char, ReaderHandler>,
+    tags: HashMap<String, ReaderHandler>,
+    printers: Vec<(String, TagPrinter)>,
+}
+
+impl std::fmt::Debug for ReaderConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut tags: Vec<&String> = self.tags.keys().collect();
+        tags.sort();
+        let mut dispatch: Vec<&char> = self.dispatch.keys().collect();
+        dispatch.sort();
+        f.debug_struct("ReaderConfig")
+            .field("dispatch", &dispatch)
+            .field("tags", &tags)
+            .finish()
+    }
+}
+
+impl ReaderConfig {
+    /// Reads `#name form` by passing the form to the handler.
+    pub fn add_tag(
+        &mut self,
+        name: &str,
+        handler: impl Fn(Expr) -> Result<Expr, String> + 'static,
+    ) -> Result<(), String> {
+        if !is_plain_symbol(name) {
+            return Err(message!("invalid-reader-tag", name = name));
+        }
+        self.tags.insert(name.to_string(), Rc::new(handler));
+        Ok(())
+    }
+
+    /// Prints the values the printer recognizes as `#name form`, so that
+    /// they read back through the tag's handler. Printers added later are
+    /// tried first.
+    pub fn add_printer(
+        &mut self,
+        name: &str,
+        printer: impl Fn(&Expr) -> Option<Expr> + 'static,
+    ) -> Result<(), String> {
+        if !is_plain_symbol(name) {
+            return Err(message!("invalid-reader-tag", name = name));
+        }
+        self.printers
+            .insert(0, (name.to_string(), Rc::new(printer)));
+        Ok(())
+    }
+
+    /// Reads the character, wherever a token starts with it, as its own
+    /// token that passes the form after it to the handler.
+    pub fn add_dispatch(
+        &mut self,
+        c: char,
+        handler: impl Fn(Expr) -> Result<Expr, String> + 'static,
+    ) -> Result<(), String> {
+        if c.is_alphanumeric() || c.is_whitespace() || "()[]{}\"|\\#".contains(c) {
+            return Err(message!("invalid-dispatch", character = c));
+        }
+        self.dispatch.insert(c, Rc::new(handler));
+        Ok(())
+    }
+
+    /// The handler for the tag, if one was added.
+    pub(crate) fn tag(&self, name: &str) -> Option<ReaderHandler> {
+        self.tags.get(name).cloned()
+    }
+
+    /// The handler a token stands for, if any.
+    fn handler(&self, token: &Token) -> Result<Option<ReaderHandler>, String> {
+        if token.quoted || token.character || token.text == "#{" {
+            return Ok(None);
+        }
+        let mut chars = token.text.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(self.dispatch.get(&c).cloned());
+        }
+        match token.text.strip_prefix('#').filter(|name| !name.is_empty()) {
+            Some(name) => match self.tags.get(name) {
+                Some(handler) => Ok(Some(handler.clone())),
+                None => Err(message!(
+                    "unknown-reader-tag",
+                    tag = token.text,
+                    span = token.span
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Characters allowed in symbols besides letters and digits. Others need
+/// the symbol to be written between bars, such as `|odd name[]|`.
+const SYMBOL_PUNCTUATION: &str = "+-*/<>=!?_.&%$^:~@";
+
+/// Whether the text reads back as this symbol without quoting.
+pub fn is_plain_symbol(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || SYMBOL_PUNCTUATION.contains(c))
+        && read_number(text).is_none()
+        && !matches!(text, "true" | "false" | "nil")
+        && !is_keyword(text)
+}
+
+/// Whether the token reads as a keyword: a colon followed by a name.
+fn is_keyword(text: &str) -> bool {
+    text.len() > 1 && text.starts_with(':')
+}
+
+/// The characters written by name, as in `#\space`.
+pub(crate) const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+];
+
+/// The character a literal stands for, given the text after its `#\`.
+pub(crate) fn read_char(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => CHAR_NAMES
+            .iter()
+            .find(|(name, _)| *name == text)
+            .map(|(_, c)| *c),
+    }
+}
+
+/// The name of the character, or the character itself if it has none.
+pub(crate) fn char_name(c: char) -> String {
+    match CHAR_NAMES.iter().find(|(_, named)| *named == c) {
+        Some((name, _)) => name.to_string(),
+        None => c.to_string(),
+    }
+}
+
+/// Writes the string as a literal that reads back as the same string.
+fn print_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Rejects unquoted symbols containing characters outside the grammar.
+fn check_symbol(token: &Token) -> Result<(), String> {
+    match token
+        .text
+        .chars()
+        .find(|&c| !c.is_alphanumeric() && !SYMBOL_PUNCTUATION.contains(c))
+    {
+        None => Ok(()),
+        Some(c) if token.text.chars().count() == 1 => Err(message!(
+            "unexpected-character",
+            character = c,
+            span = token.span
+        )),
+        Some(c) => Err(message!(
+            "invalid-symbol-character",
+            character = c,
+            symbol = token.text,
+            span = token.span
+        )),
+    }
+}
+
+/// Reads a number literal: decimal with optional `_` separators between
+/// digits, an integer with a `0x`, `0o` or `0b` prefix, or a ratio such as
+/// `1/3`. Integers read as integers of whatever size they need, ratios as
+/// exact ratios and everything else as a float. Returns
+/// `None` for tokens that are not numbers at all, and an error for tokens
+/// that start like a number but are not one.
+pub(crate) fn read_number(text: &str) -> Option<Result<Expr, String>> {
+    if let Ok(n) = text.parse::<i64>() {
+        return Some(Ok(Expr::Int(n)));
+    }
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    // Integers too large for an i64 would parse as floats
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        if let Ok(number) = text.parse::<f64>() {
+            return Some(Ok(Expr::Number(number)));
+        }
+    }
+
+    let starts_like_number = digits.starts_with(|c: char| c.is_ascii_digit())
+        || (digits.starts_with('.') && digits[1..].starts_with(|c: char| c.is_ascii_digit()));
+    if !starts_like_number {
+        return None;
+    }
+
+    let invalid = || Err(message!("invalid-number", text = text));
+    // Underscores may only separate digits
+    let bytes = digits.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'_' {
+            let between_digits = i > 0
+                && bytes[i - 1].is_ascii_hexdigit()
+                && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit);
+            if !between_digits {
+                return Some(invalid());
             }
-            tokens.remove(0); // Remove closing paren
-            Ok(Expr::List(list))
         }
-        ")" => Err("Unexpected ')'".to_string()),
-        _ => {
-            if let Ok(number) = token.parse::<f64>() {
-                Ok(Expr::Number(number))
-            } else {
-                Ok(Expr::Symbol(token))
+    }
+    let digits = digits.replace('_', "");
+
+    if let Some((numerator, denominator)) = digits.split_once('/') {
+        let decimal = |s: &str| {
+            BigInt::parse_bytes(s.as_bytes(), 10).filter(|_| s.chars().all(|c| c.is_ascii_digit()))
+        };
+        let (Some(numerator), Some(denominator)) = (decimal(numerator), decimal(denominator))
+        else {
+            return Some(invalid());
+        };
+        if denominator.is_zero() {
+            return Some(Err(message!("zero-denominator", text = text)));
+        }
+        let numerator = if negative { -numerator } else { numerator };
+        return Some(Ok(Expr::rational(BigRational::new(numerator, denominator))));
+    }
+
+    let radix = match digits.get(..2) {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => 10,
+    };
+    let digits = if radix == 10 { &digits } else { &digits[2..] };
+    if let Some(n) = BigInt::parse_bytes(digits.as_bytes(), radix)
+        .filter(|_| digits.chars().all(|c| c.is_digit(radix)))
+    {
+        return Some(Ok(Expr::integer(if negative { -n } else { n })));
+    }
+    Some(match digits.parse::<f64>() {
+        Ok(n) if radix == 10 && negative => Ok(Expr::Number(-n)),
+        Ok(n) if radix == 10 => Ok(Expr::Number(n)),
+        _ => invalid(),
+    })
+}
+
+/// Prints a float with a fractional part, `4.0` rather than `4`, so that it
+/// reads back as a float rather than an integer.
+pub(crate) fn format_float(n: f64) -> String {
+    let text = n.to_string();
+    if n.is_finite() && !text.contains('.') {
+        text + ".0"
+    } else {
+        text
+    }
+}
+
+/// How deeply lists may nest unless configured otherwise. Deeper data would
+/// risk overflowing the stack when it is printed, compared or dropped.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// What the parser is in the middle of reading.
+enum Open {
+    List(Span, Vec<Expr>),
+    Vector(Vec<Expr>),
+    /// Keys and values in turn.
+    Map(Span, Vec<Expr>),
+    Set(Span, Vec<Expr>),
+    /// A tag or dispatch character waiting for the form after it.
+    Handler(Span, ReaderHandler),
+}
+
+/// The map written with these keys and values in turn, starting at `span`.
+fn read_map(items: Vec<Expr>, span: Span) -> Result<Expr, String> {
+    if !items.len().is_multiple_of(2) {
+        return Err(message!("map-literal-odd", span = span));
+    }
+    let mut map = Map::with_capacity(items.len() / 2);
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        let printed = expr_to_string(&key);
+        let key = MapKey::new(key)
+            .ok_or_else(|| message!("invalid-map-key", key = printed, span = span))?;
+        if map.insert(key, value).is_some() {
+            return Err(message!("duplicate-map-key", key = printed, span = span));
+        }
+    }
+    Ok(Expr::map(map))
+}
+
+/// The set written with these members, starting at `span`.
+fn read_set(items: Vec<Expr>, span: Span) -> Result<Expr, String> {
+    let mut set = Set::with_capacity(items.len());
+    for item in items {
+        let printed = expr_to_string(&item);
+        let member = MapKey::new(item)
+            .ok_or_else(|| message!("invalid-set-member", member = printed, span = span))?;
+        if !set.insert(member) {
+            return Err(message!(
+                "duplicate-set-member",
+                member = printed,
+                span = span
+            ));
+        }
+    }
+    Ok(Expr::set(set))
+}
+
+/// Reads one expression, keeping the lists still open on an explicit stack
+/// so that deeply nested input cannot overflow the call stack.
+fn parse_expr(lexer: &mut Lexer, spans: &mut SpanTable, max_depth: usize) -> Result<Expr, String> {
+    let mut open: Vec<Open> = Vec::new();
+    let mut depth = 0;
+
+    loop {
+        let token = match lexer.next_token() {
+            Some(token) => token?,
+            None => return Err(message!("unexpected-end")),
+        };
+
+        if let Some(handler) = lexer.reader.handler(&token)? {
+            open.push(Open::Handler(token.span, handler));
+            continue;
+        }
+        let mut expr = if token.string {
+            Expr::String(token.text.into_owned())
+        } else if token.character {
+            match read_char(&token.text) {
+                Some(c) => Expr::Char(c),
+                None => {
+                    return Err(message!(
+                        "invalid-character",
+                        name = token.text,
+                        span = token.span
+                    ))
+                }
+            }
+        } else if token.quoted {
+            Expr::Symbol(token.text.into_owned())
+        } else {
+            match token.text.as_ref() {
+                "(" | "[" | "{" | "#{" => {
+                    if depth >= max_depth {
+                        return Err(message!(
+                            "nested-too-deep",
+                            depth = max_depth,
+                            span = token.span
+                        ));
+                    }
+                    depth += 1;
+                    open.push(match token.text.as_ref() {
+                        "(" => Open::List(token.span, Vec::new()),
+                        "[" => Open::Vector(Vec::new()),
+                        "{" => Open::Map(token.span, Vec::new()),
+                        _ => Open::Set(token.span, Vec::new()),
+                    });
+                    continue;
+                }
+                ")" | "]" | "}" => match (token.text.as_ref(), open.pop()) {
+                    (")", Some(Open::List(start, list))) => {
+                        depth -= 1;
+                        spans.insert(
+                            &list,
+                            Span {
+                                end: token.span.end,
+                                ..start
+                            },
+                        );
+                        Expr::List(list)
+                    }
+                    ("]", Some(Open::Vector(items))) => {
+                        depth -= 1;
+                        Expr::vector(items)
+                    }
+                    ("}", Some(Open::Map(start, items))) => {
+                        depth -= 1;
+                        read_map(items, start)?
+                    }
+                    ("}", Some(Open::Set(start, items))) => {
+                        depth -= 1;
+                        read_set(items, start)?
+                    }
+                    (close, _) => {
+                        return Err(message!(
+                            "unexpected-character",
+                            character = close,
+                            span = token.span
+                        ))
+                    }
+                },
+                _ => match read_number(&token.text) {
+                    Some(Ok(number)) => number,
+                    Some(Err(e)) => {
+                        return Err(message!("located", message = e, span = token.span))
+                    }
+                    None => match token.text.as_ref() {
+                        "true" => Expr::Bool(true),
+                        "false" => Expr::Bool(false),
+                        "nil" => Expr::Nil,
+                        text => {
+                            check_symbol(&token)?;
+                            if is_keyword(text) {
+                                Expr::keyword(&text[1..])
+                            } else {
+                                Expr::Symbol(token.text.into_owned())
+                            }
+                        }
+                    },
+                },
+            }
+        };
+
+        loop {
+            match open.last_mut() {
+                Some(
+                    Open::List(_, items)
+                    | Open::Vector(items)
+                    | Open::Map(_, items)
+                    | Open::Set(_, items),
+                ) => {
+                    items.push(expr);
+                    break;
+                }
+                Some(Open::Handler(span, handler)) => {
+                    expr =
+                        handler(expr).map_err(|e| message!("located", message = e, span = span))?;
+                    open.pop();
+                }
+                None => return Ok(expr),
             }
         }
     }
 }
 
 pub fn parse(input: &str) -> Result<Expr, String> {
-    let mut tokens = tokenize(input);
-    let expr = parse_expr(&mut tokens)?;
-    if !tokens.is_empty() {
-        return Err("Unexpected tokens at end of input".to_string());
+    parse_with_spans(input).map(|(expr, _)| expr)
+}
+
+/// Parses the input, also returning where each list in it came from.
+pub fn parse_with_spans(input: &str) -> Result<(Expr, SpanTable), String> {
+    parse_with_max_depth(input, DEFAULT_MAX_DEPTH)
+}
+
+/// Like `parse_with_spans`, with a limit on how deeply lists may nest.
+pub fn parse_with_max_depth(input: &str, max_depth: usize) -> Result<(Expr, SpanTable), String> {
+    parse_with_reader(input, &ReaderConfig::default(), max_depth)
+}
+
+/// Like `parse_with_max_depth`, also reading the host's literal syntax.
+pub fn parse_with_reader(
+    input: &str,
+    reader: &ReaderConfig,
+    max_depth: usize,
+) -> Result<(Expr, SpanTable), String> {
+    let mut lexer = Lexer::new(input, reader);
+    let mut spans = SpanTable::default();
+    let expr = parse_expr(&mut lexer, &mut spans, max_depth)?;
+    if let Some(token) = lexer.next_token() {
+        let token = token?;
+        return Err(message!(
+            "unexpected-after",
+            token = token.text,
+            span = token.span
+        ));
     }
-    Ok(expr)
+    Ok((expr, spans))
+}
+
+/// Parses the first expression in the input, returning it along with the
+/// rest of the input from the next token on, for reading one expression at
+/// a time. The rest is not looked at, so it may hold anything.
+pub fn parse_partial(input: &str) -> Result<(Expr, &str), String> {
+    let reader = ReaderConfig::default();
+    let mut lexer = Lexer::new(input, &reader);
+    let expr = parse_expr(&mut lexer, &mut SpanTable::default(), DEFAULT_MAX_DEPTH)?;
+    Ok((expr, lexer.rest()))
 }
 
 pub fn expr_to_string(expr: &Expr) -> String {
+    print_expr(expr, None)
+}
+
+/// Like `expr_to_string`, printing values through the tags that have
+/// printers so they read back the same.
+pub fn expr_to_string_with(expr: &Expr, reader: &ReaderConfig) -> String {
+    print_expr(expr, Some(reader))
+}
+
+fn print_expr(expr: &Expr, reader: Option<&ReaderConfig>) -> String {
+    if let Some(reader) = reader {
+        for (name, printer) in &reader.printers {
+            // A printer handing back the value itself would never finish
+            if let Some(form) = printer(expr).filter(|form| form != expr) {
+                return format!("#{} {}", name, print_expr(&form, Some(reader)));
+            }
+        }
+    }
+    print_form(expr, reader)
+}
+
+fn print_form(expr: &Expr, reader: Option<&ReaderConfig>) -> String {
     match expr {
-        Expr::Symbol(s) => s.clone(),
-        Expr::Number(n) => n.to_string(),
+        Expr::Symbol(s) if is_plain_symbol(s) => s.clone(),
+        Expr::Symbol(s) => format!("|{}|", s.replace('\\', "\\\\").replace('|', "\\|")),
+        Expr::Keyword(name) => format!(":{}", name),
+        Expr::String(s) => print_string(s),
+        Expr::Char(c) => format!("#\\{}", char_name(*c)),
+        Expr::Int(n) => n.to_string(),
+        Expr::BigInt(n) => n.to_string(),
+        Expr::Ratio(n) => n.to_string(),
+        Expr::Number(n) => format_float(*n),
+        Expr::Bool(b) => b.to_string(),
+        Expr::Nil => "nil".to_string(),
         Expr::List(list) => {
-            let items: Vec<String> = list.iter().map(expr_to_string).collect();
+            let items: Vec<String> = list.iter().map(|item| print_expr(item, reader)).collect();
             format!("({})", items.join(" "))
         }
-        Expr::Lambda(params, body) => {
-            let params_str = params.join(" ");
-            let body_str = expr_to_string(body);
-            format!("(fn ({}) {})", params_str, body_str)
+        Expr::Vector(items) => {
+            let items: Vec<String> = items.iter().map(|item| print_expr(item, reader)).collect();
+            format!("[{}]", items.join(" "))
         }
-        Expr::Function(_) => "<function>".to_string(),
+        Expr::Map(map) => {
+            let entries: Vec<String> = sorted_entries(map)
+                .into_iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{} {}",
+                        print_expr(key.expr(), reader),
+                        print_expr(value, reader)
+                    )
+                })
+                .collect();
+            format!("{{{}}}", entries.join(" "))
+        }
+        Expr::Set(set) => {
+            let members: Vec<String> = sorted_members(set)
+                .into_iter()
+                .map(|member| print_expr(member.expr(), reader))
+                .collect();
+            format!("#{{{}}}", members.join(" "))
+        }
+        Expr::Function(func) => match &func.name {
+            Some(name) => format!("<function {}>", name),
+            None => "<function>".to_string(),
+        },
         Expr::BuiltinFunction(_) => "<builtin-function>".to_string(),
+        Expr::External(resource) => format!("<{}>", resource.kind()),
+        Expr::Weak(_) => "<weak-ref>".to_string(),
+        #[cfg(feature = "ndarray")]
+        Expr::Matrix(matrix) => format!(
+            "#mat {}",
+            print_expr(&crate::matrix::to_rows(matrix), reader)
+        ),
     }
 }
 
@@ -99,7 +898,213 @@ mod tests {
 
     #[test]
     fn parse_number() {
-        assert_eq!(parse("42"), Ok(Expr::number(42.0)));
+        assert_eq!(parse("42"), Ok(Expr::int(42)));
+    }
+
+    #[test]
+    fn parse_number_syntax() {
+        assert_eq!(parse("1_000_000"), Ok(Expr::int(1_000_000)));
+        assert_eq!(parse("1e3"), Ok(Expr::number(1000.0)));
+        assert_eq!(parse("-9223372036854775808"), Ok(Expr::int(i64::MIN)));
+        assert_eq!(
+            parse("9223372036854775808"),
+            Ok(Expr::BigInt(BigInt::from(1) << 63u32))
+        );
+        assert_eq!(
+            parse("-0x8000_0000_0000_0001"),
+            Ok(Expr::BigInt(-(BigInt::from(1) << 63u32) - 1))
+        );
+        assert_eq!(parse("0x1F"), Ok(Expr::int(31)));
+        assert_eq!(parse("-0o17"), Ok(Expr::int(-15)));
+        assert_eq!(parse("0b101"), Ok(Expr::int(5)));
+        assert_eq!(parse("1_0.5e1_0"), Ok(Expr::number(10.5e10)));
+        assert_eq!(parse("-"), Ok(Expr::symbol("-")));
+        assert_eq!(parse("-x1"), Ok(Expr::symbol("-x1")));
+
+        assert_eq!(
+            parse("(+ 1 1.2.3)"),
+            Err("Invalid number '1.2.3' at line 1, column 6".to_string())
+        );
+        assert_eq!(
+            parse("0b102"),
+            Err("Invalid number '0b102' at line 1, column 1".to_string())
+        );
+        assert_eq!(
+            parse("1__0"),
+            Err("Invalid number '1__0' at line 1, column 1".to_string())
+        );
+        assert_eq!(
+            parse("-6/8"),
+            Ok(Expr::Ratio(BigRational::new((-3).into(), 4.into())))
+        );
+        assert_eq!(parse("4/2"), Ok(Expr::int(2)));
+        assert_eq!(
+            parse("3/0"),
+            Err("Ratio '3/0' has a zero denominator at line 1, column 1".to_string())
+        );
+        assert_eq!(
+            parse("3/x"),
+            Err("Invalid number '3/x' at line 1, column 1".to_string())
+        );
+    }
+
+    #[test]
+    fn symbol_syntax() {
+        assert_eq!(parse("|odd name[]|"), Ok(Expr::symbol("odd name[]")));
+        assert_eq!(parse("|42|"), Ok(Expr::symbol("42")));
+        assert_eq!(
+            parse("(list a,)"),
+            Err("Invalid character ',' in symbol 'a,' at line 1, column 7".to_string())
+        );
+        assert_eq!(
+            parse("(list\n  ])"),
+            Err("Unexpected ']' at line 2, column 3".to_string())
+        );
+        assert_eq!(
+            parse("(list |a b)"),
+            Err("Missing closing | for the one at line 1, column 7".to_string())
+        );
+
+        for symbol in ["odd name[]", "42", "", "a|b", "true"] {
+            let printed = expr_to_string(&Expr::symbol(symbol));
+            assert_eq!(parse(&printed), Ok(Expr::symbol(symbol)), "{}", printed);
+        }
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(
+            parse("(true false |true|)"),
+            Ok(Expr::list(vec![
+                Expr::bool(true),
+                Expr::bool(false),
+                Expr::symbol("true")
+            ]))
+        );
+        assert_eq!(expr_to_string(&Expr::bool(false)), "false");
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(
+            parse("(:name :a-b? : |:x|)"),
+            Ok(Expr::list(vec![
+                Expr::keyword("name"),
+                Expr::keyword("a-b?"),
+                Expr::symbol(":"),
+                Expr::symbol(":x")
+            ]))
+        );
+        assert_eq!(expr_to_string(&Expr::keyword("name")), ":name");
+        assert_eq!(expr_to_string(&Expr::symbol(":x")), "|:x|");
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(
+            parse(r#""(not a list  \"quoted\"\n\q""#),
+            Ok(Expr::string("(not a list  \"quoted\"\nq"))
+        );
+        assert_eq!(
+            parse(r#"(f"a"b)"#),
+            Ok(Expr::list(vec![
+                Expr::symbol("f"),
+                Expr::string("a"),
+                Expr::symbol("b")
+            ]))
+        );
+        assert_eq!(
+            parse(r#"(list "a b)"#),
+            Err("Missing closing \" for the one at line 1, column 7".to_string())
+        );
+
+        for string in ["", "a b", "\"\\\n\t|", "ü"] {
+            let printed = expr_to_string(&Expr::string(string));
+            assert_eq!(parse(&printed), Ok(Expr::string(string)), "{}", printed);
+        }
+        assert_eq!(
+            expr_to_string(&Expr::string("say \"hi\"")),
+            r#""say \"hi\"""#
+        );
+    }
+
+    #[test]
+    fn character_literals() {
+        assert_eq!(
+            parse(r"(#\a #\( #\) #\space #\ü)"),
+            Ok(Expr::list(vec![
+                Expr::Char('a'),
+                Expr::Char('('),
+                Expr::Char(')'),
+                Expr::Char(' '),
+                Expr::Char('ü')
+            ]))
+        );
+        assert_eq!(
+            parse(r"#\spaces"),
+            Err(r"Invalid character '#\spaces' at line 1, column 1".to_string())
+        );
+        assert_eq!(parse(r"#\"), Err("Unexpected end of input".to_string()));
+
+        for c in ['a', ' ', '\n', '\t', '"', '\\', ';', '#'] {
+            let printed = expr_to_string(&Expr::Char(c));
+            assert_eq!(parse(&printed), Ok(Expr::Char(c)), "{}", printed);
+        }
+        assert_eq!(expr_to_string(&Expr::Char('\n')), r"#\newline");
+    }
+
+    #[test]
+    fn trailing_input() {
+        assert_eq!(
+            parse("(+ 1 2)\n  (+ 3 4)"),
+            Err("Unexpected '(' after the expression at line 2, column 3".to_string())
+        );
+
+        let (first, rest) = parse_partial("(+ 1 2)\n  (+ 3 4) x").unwrap();
+        assert_eq!(first, parse("(+ 1 2)").unwrap());
+        assert_eq!(rest, "(+ 3 4) x");
+        let (second, rest) = parse_partial(rest).unwrap();
+        assert_eq!(second, parse("(+ 3 4)").unwrap());
+        assert_eq!(parse_partial(rest), Ok((Expr::symbol("x"), "")));
+        // Only the first expression is read
+        assert_eq!(
+            parse_partial("x |unclosed"),
+            Ok((Expr::symbol("x"), "|unclosed"))
+        );
+    }
+
+    #[test]
+    fn large_input() {
+        // Quadratic reading would take minutes over this many tokens
+        let items = vec!["|a b|"; 200_000].join(" ");
+        let Ok(Expr::List(list)) = parse(&format!("(list {})", items)) else {
+            panic!("expected a list");
+        };
+        assert_eq!(list.len(), 200_001);
+        assert_eq!(list[1], Expr::symbol("a b"));
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let nested = |depth| format!("{}{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(DEFAULT_MAX_DEPTH)).is_ok());
+        assert_eq!(
+            parse(&nested(DEFAULT_MAX_DEPTH + 1)),
+            Err("Lists nested deeper than 1000 levels at line 1, column 1001".to_string())
+        );
+        // Far more than the call stack could take if parsing recursed
+        assert_eq!(
+            parse(&"(".repeat(100_000)),
+            Err("Lists nested deeper than 1000 levels at line 1, column 1001".to_string())
+        );
+        assert_eq!(
+            parse_with_max_depth("((a))", 1).map(|(expr, _)| expr),
+            Err("Lists nested deeper than 1 levels at line 1, column 2".to_string())
+        );
+        assert_eq!(
+            parse(")"),
+            Err("Unexpected ')' at line 1, column 1".to_string())
+        );
     }
 
     #[test]
@@ -107,15 +1112,68 @@ mod tests {
         assert_eq!(parse("x"), Ok(Expr::symbol("x")));
     }
 
+    #[test]
+    fn parse_spans() {
+        let (expr, spans) = parse_with_spans("(list 1\n  (+ 2 3))").unwrap();
+        let Expr::List(outer) = &expr else {
+            panic!("expected a list");
+        };
+        let Expr::List(inner) = &outer[2] else {
+            panic!("expected a list");
+        };
+
+        assert_eq!(
+            spans.get(outer),
+            Some(Span {
+                start: 0,
+                end: 18,
+                line: 1,
+                column: 1
+            })
+        );
+        assert_eq!(
+            spans.get(inner),
+            Some(Span {
+                start: 10,
+                end: 17,
+                line: 2,
+                column: 3
+            })
+        );
+    }
+
     #[test]
     fn parse_list() {
         assert_eq!(
             parse("(+ 1 2)"),
             Ok(Expr::list(vec![
                 Expr::symbol("+"),
-                Expr::number(1.0),
-                Expr::number(2.0)
+                Expr::int(1),
+                Expr::int(2)
+            ]))
+        );
+    }
+
+    #[test]
+    fn parse_vector() {
+        assert_eq!(
+            parse("[1 [a] ()]"),
+            Ok(Expr::vector(vec![
+                Expr::int(1),
+                Expr::vector(vec![Expr::symbol("a")]),
+                Expr::list(vec![])
             ]))
         );
+        assert_eq!(parse("(f[1]x)"), parse("(f [1] x)"));
+        assert_eq!(expr_to_string(&parse("[1 [a] ()]").unwrap()), "[1 [a] ()]");
+        assert_eq!(
+            parse("[1 2)"),
+            Err("Unexpected ')' at line 1, column 5".to_string())
+        );
+        assert_eq!(
+            parse("(1 2]"),
+            Err("Unexpected ']' at line 1, column 5".to_string())
+        );
+        assert_eq!(parse("[1 2"), Err("Unexpected end of input".to_string()));
     }
 }
@@ -0,0 +1,200 @@
+//! Structural pattern matching over quoted data.
+//!
+//! In a pattern, `_` matches anything, a symbol starting with `?` matches
+//! anything and binds it (matching the same value wherever it appears
+//! again), and `& pattern` at the end of a list or vector matches the
+//! remaining elements as a list or vector. Lists and vectors are matched
+//! element by element, a map matches any map with each of its keys whose
+//! value matches, and a set any set holding each of its members. Everything
+//! else matches only an equal value.
+
+use crate::interpreter::{sorted_entries, sorted_members, Expr, Scope};
+
+pub type Bindings = Vec<(String, Expr)>;
+
+/// Matches `data` against `pattern`, adding to `bindings`. On failure the
+/// bindings may be left partially filled in.
+pub fn match_pattern(pattern: &Expr, data: &Expr, bindings: &mut Bindings) -> bool {
+    match pattern {
+        Expr::Symbol(name) if name == "_" => true,
+        Expr::Symbol(name) if name.starts_with('?') => {
+            match bindings.iter().find(|(bound, _)| bound == name) {
+                Some((_, value)) => value == data,
+                None => {
+                    bindings.push((name.clone(), data.clone()));
+                    true
+                }
+            }
+        }
+        Expr::List(patterns) => match data {
+            Expr::List(items) => match_sequence(patterns, items, bindings, Expr::List),
+            _ => false,
+        },
+        Expr::Vector(patterns) => match data {
+            Expr::Vector(items) => match_sequence(patterns, items, bindings, Expr::vector),
+            _ => false,
+        },
+        Expr::Map(patterns) => match data {
+            Expr::Map(entries) => sorted_entries(patterns).into_iter().all(|(key, pattern)| {
+                entries
+                    .get(key)
+                    .is_some_and(|value| match_pattern(pattern, value, bindings))
+            }),
+            _ => false,
+        },
+        Expr::Set(members) => match data {
+            Expr::Set(set) => members.is_subset(set),
+            _ => false,
+        },
+        _ => pattern == data,
+    }
+}
+
+/// Matches the elements of a list or vector, `rebuild` making the same kind
+/// of sequence of the elements a `&` pattern matches.
+fn match_sequence(
+    patterns: &[Expr],
+    items: &[Expr],
+    bindings: &mut Bindings,
+    rebuild: fn(Vec<Expr>) -> Expr,
+) -> bool {
+    match patterns.iter().position(|p| *p == Expr::symbol("&")) {
+        Some(rest) if rest + 2 == patterns.len() => {
+            items.len() >= rest
+                && match_all(&patterns[..rest], &items[..rest], bindings)
+                && match_pattern(
+                    &patterns[rest + 1],
+                    &rebuild(items[rest..].to_vec()),
+                    bindings,
+                )
+        }
+        _ => patterns.len() == items.len() && match_all(patterns, items, bindings),
+    }
+}
+
+fn match_all(patterns: &[Expr], items: &[Expr], bindings: &mut Bindings) -> bool {
+    patterns
+        .iter()
+        .zip(items)
+        .all(|(pattern, item)| match_pattern(pattern, item, bindings))
+}
+
+/// Every subtree of `data` matching `pattern`, outermost first, with the
+/// bindings of each match.
+pub fn find_matches(pattern: &Expr, data: &Expr) -> Vec<(Expr, Bindings)> {
+    fn walk(pattern: &Expr, data: &Expr, matches: &mut Vec<(Expr, Bindings)>) {
+        let mut bindings = Vec::new();
+        if match_pattern(pattern, data, &mut bindings) {
+            matches.push((data.clone(), bindings));
+        }
+        match data {
+            Expr::List(items) => {
+                for item in items {
+                    walk(pattern, item, matches);
+                }
+            }
+            Expr::Vector(items) => {
+                for item in items.iter() {
+                    walk(pattern, item, matches);
+                }
+            }
+            Expr::Map(entries) => {
+                for (key, value) in sorted_entries(entries) {
+                    walk(pattern, key.expr(), matches);
+                    walk(pattern, value, matches);
+                }
+            }
+            Expr::Set(members) => {
+                for member in sorted_members(members) {
+                    walk(pattern, member.expr(), matches);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut matches = Vec::new();
+    walk(pattern, data, &mut matches);
+    matches
+}
+
+pub fn bindings_to_expr(bindings: Bindings) -> Expr {
+    Expr::List(
+        bindings
+            .into_iter()
+            .map(|(name, value)| Expr::List(vec![Expr::Symbol(name), value]))
+            .collect(),
+    )
+}
+
+/// `(select pattern data)`: every subtree of `data` matching `pattern`.
+pub fn builtin_select(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::List(
+        find_matches(&args[0], &args[1])
+            .into_iter()
+            .map(|(subtree, _)| subtree)
+            .collect(),
+    ))
+}
+
+/// `(find-all pattern data)`: the bindings of every match, each as a list
+/// of `(?name value)` pairs.
+pub fn builtin_find_all(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::List(
+        find_matches(&args[0], &args[1])
+            .into_iter()
+            .map(|(_, bindings)| bindings_to_expr(bindings))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn select_and_find_all() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(def data (quote ((user (id 1) (tags a b)) (user (id 2) (tags)))))")
+            .unwrap();
+
+        assert_eq!(
+            engine.eval_to_string("(select (quote (id _)) data)"),
+            "((id 1) (id 2))"
+        );
+        assert_eq!(
+            engine.eval_to_string("(find-all (quote (user (id ?id) (tags & ?tags))) data)"),
+            "(((?id 1) (?tags (a b))) ((?id 2) (?tags ())))"
+        );
+        // A repeated binding has to match the same value
+        assert_eq!(
+            engine.eval_to_string("(select (quote (?x ?x)) (quote ((1 2) (3 3))))"),
+            "((3 3))"
+        );
+
+        engine
+            .eval("(def users [{:id 1 :tags #{:admin :dev} :langs [:rust :lisp]} {:id 2}])")
+            .unwrap();
+        for (input, expected) in [
+            ("(select (quote {:a ?x}) [{:a 1}])", "({:a 1})"),
+            (
+                "(find-all (quote {:id ?id}) users)",
+                "(((?id 1)) ((?id 2)))",
+            ),
+            (
+                "(find-all (quote {:id ?id :tags #{:admin}}) users)",
+                "(((?id 1)))",
+            ),
+            (
+                "(find-all (quote [:rust & ?rest]) users)",
+                "(((?rest [:lisp])))",
+            ),
+            ("(select :dev users)", "(:dev)"),
+            ("(select (quote (?x)) [1])", "()"),
+            ("(select (quote [?x]) (quote (1)))", "()"),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Charts for the playground: `plot` hands the chart to the browser to draw,
+//! and elsewhere writes it to an SVG file.
+//!
+//! Options are a map with keyword keys:
+//!
+//! - `:y numbers`: the values to plot, required
+//! - `:x numbers`: where along the x axis each value goes, `0, 1, 2, ...`
+//!   by default
+//! - `:kind k`: `:line`, `:scatter` or `:bar`, `:line` by default
+//! - `:title text`: shown above the chart
+//! - `:file path`: where to write the SVG outside the browser, `plot.svg`
+//!   by default
+
+use std::fmt::Write;
+
+use crate::builtins::{expect_number, expect_text};
+use crate::engine::SandboxPolicy;
+use crate::interpreter::{sorted_entries, Expr, Scope};
+use crate::map::expect_map;
+use crate::messages::message;
+use crate::parser::expr_to_string;
+use crate::seq::Seq;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChartKind {
+    Line,
+    Scatter,
+    Bar,
+}
+
+impl ChartKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChartKind::Line => "line",
+            ChartKind::Scatter => "scatter",
+            ChartKind::Bar => "bar",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chart {
+    pub kind: ChartKind,
+    pub title: Option<String>,
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+}
+
+const DEFAULT_FILE: &str = "plot.svg";
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 400.0;
+const MARGIN: f64 = 40.0;
+
+fn numbers(name: &str, position: usize, expr: &Expr) -> Result<Vec<f64>, String> {
+    Seq::new(name, position, expr)?
+        .map(|n| expect_number(name, position, &n))
+        .collect()
+}
+
+/// The smallest and largest of the values, spread apart if they are equal
+/// so that scaling by their difference stays finite.
+fn bounds(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 1.0);
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if min == max {
+        (min - 1.0, max + 1.0)
+    } else {
+        (min, max)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Chart {
+    /// Reads the chart from the options, with the file to write it to.
+    fn from_options(name: &str, options: &Expr) -> Result<(Self, String), String> {
+        let mut kind = ChartKind::Line;
+        let mut title = None;
+        let mut x = None;
+        let mut y = None;
+        let mut file = DEFAULT_FILE.to_string();
+        let options = expect_map(name, 1, options)?;
+        for (key, value) in sorted_entries(&options) {
+            let invalid = || {
+                message!(
+                    "invalid-option",
+                    name = name,
+                    option = format!("{} {}", expr_to_string(key.expr()), expr_to_string(value))
+                )
+            };
+            let Expr::Keyword(key) = key.expr() else {
+                return Err(invalid());
+            };
+            match key.as_str() {
+                "x" => x = Some(numbers(name, 1, value)?),
+                "y" => y = Some(numbers(name, 1, value)?),
+                "kind" => {
+                    kind = match value {
+                        Expr::Keyword(kind) if kind == "line" => ChartKind::Line,
+                        Expr::Keyword(kind) if kind == "scatter" => ChartKind::Scatter,
+                        Expr::Keyword(kind) if kind == "bar" => ChartKind::Bar,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "title" => title = Some(expect_text(name, 1, value)?.to_string()),
+                "file" => file = expect_text(name, 1, value)?.to_string(),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let Some(y) = y else {
+            return Err(message!("plot-missing-y", name = name));
+        };
+        let x = x.unwrap_or_else(|| (0..y.len()).map(|i| i as f64).collect());
+        if x.len() != y.len() {
+            return Err(message!(
+                "plot-lengths",
+                name = name,
+                x = x.len(),
+                y = y.len()
+            ));
+        }
+        Ok((Chart { kind, title, x, y }, file))
+    }
+
+    /// Draws the chart as a standalone SVG document, scaled to fit.
+    pub fn to_svg(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = WIDTH,
+            h = HEIGHT
+        )
+        .unwrap();
+        writeln!(out, r#"  <rect width="100%" height="100%" fill="white"/>"#).unwrap();
+        if let Some(title) = &self.title {
+            writeln!(
+                out,
+                r#"  <text x="{}" y="{}" text-anchor="middle" font-family="sans-serif">{}</text>"#,
+                WIDTH / 2.0,
+                MARGIN / 2.0,
+                escape_xml(title)
+            )
+            .unwrap();
+        }
+
+        let (x_min, x_max) = bounds(&self.x);
+        // Bars grow from zero, so zero has to be in view
+        let (y_min, y_max) = match self.kind {
+            ChartKind::Bar => bounds(&[self.y.as_slice(), &[0.0]].concat()),
+            _ => bounds(&self.y),
+        };
+        let left = MARGIN;
+        let right = WIDTH - MARGIN;
+        let top = MARGIN;
+        let bottom = HEIGHT - MARGIN;
+        let scale_x = |x: f64| left + (x - x_min) / (x_max - x_min) * (right - left);
+        let scale_y = |y: f64| bottom - (y - y_min) / (y_max - y_min) * (bottom - top);
+
+        writeln!(
+            out,
+            r#"  <path d="M{left} {top} V{bottom} H{right}" fill="none" stroke="black"/>"#
+        )
+        .unwrap();
+        for (value, x, y, anchor) in [
+            (y_min, left - 4.0, bottom, "end"),
+            (y_max, left - 4.0, top + 4.0, "end"),
+            (x_min, left, bottom + 16.0, "start"),
+            (x_max, right, bottom + 16.0, "end"),
+        ] {
+            writeln!(
+                out,
+                r#"  <text x="{}" y="{}" text-anchor="{}" font-family="sans-serif" font-size="12">{}</text>"#,
+                x, y, anchor, value
+            )
+            .unwrap();
+        }
+
+        let points: Vec<(f64, f64)> = self
+            .x
+            .iter()
+            .zip(&self.y)
+            .map(|(x, y)| (scale_x(*x), scale_y(*y)))
+            .collect();
+        match self.kind {
+            ChartKind::Line => {
+                let points: Vec<String> =
+                    points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+                writeln!(
+                    out,
+                    r#"  <polyline points="{}" fill="none" stroke="steelblue" stroke-width="2"/>"#,
+                    points.join(" ")
+                )
+                .unwrap();
+            }
+            ChartKind::Scatter => {
+                for (x, y) in points {
+                    writeln!(
+                        out,
+                        r#"  <circle cx="{}" cy="{}" r="3" fill="steelblue"/>"#,
+                        x, y
+                    )
+                    .unwrap();
+                }
+            }
+            ChartKind::Bar => {
+                let width = (right - left) / self.y.len().max(1) as f64 * 0.8;
+                let zero = scale_y(0.0);
+                for (x, y) in points {
+                    writeln!(
+                        out,
+                        r#"  <rect x="{}" y="{}" width="{}" height="{}" fill="steelblue"/>"#,
+                        (x - width / 2.0).clamp(left, right - width),
+                        y.min(zero),
+                        width,
+                        (y - zero).abs()
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+/// `(plot options)` draws a chart. In the browser the host is handed the
+/// chart to draw and the result is `nil`; elsewhere the chart is written as
+/// SVG and the result is the file's path.
+pub fn builtin_plot(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let (chart, file) = Chart::from_options("plot", &args[0])?;
+    let runtime = scope.runtime();
+    if cfg!(target_arch = "wasm32") {
+        runtime.add_chart(chart);
+        return Ok(Expr::Nil);
+    }
+
+    if runtime.config.sandbox == SandboxPolicy::Strict {
+        return Err(message!("sandboxed", name = "plot"));
+    }
+    std::fs::write(&file, chart.to_svg())
+        .map_err(|e| message!("write-failed", path = file, error = e))?;
+    runtime.add_chart(chart);
+    Ok(Expr::String(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+
+    #[test]
+    fn svg() {
+        let chart = Chart {
+            kind: ChartKind::Bar,
+            title: Some("a < b".to_string()),
+            x: vec![0.0, 1.0],
+            y: vec![2.0, -1.0],
+        };
+        let svg = chart.to_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.contains(">a &lt; b</text>"));
+        assert_eq!(svg.matches("fill=\"steelblue\"").count(), 2);
+    }
+
+    #[test]
+    fn plot_writes_svg() {
+        let path = std::env::temp_dir().join(format!("rs-lisp-plot-{}.svg", std::process::id()));
+        let mut engine = Engine::new();
+        engine
+            .eval(&format!(
+                "(def file {})",
+                expr_to_string(&Expr::string(path.to_str().unwrap()))
+            ))
+            .unwrap();
+        assert_eq!(
+            engine.eval("(plot {:y [1 4 9] :file file})"),
+            Ok(Expr::string(path.to_str().unwrap()))
+        );
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(svg.contains("<polyline points=\"40,360 320,240 600,40\""));
+        assert_eq!(
+            engine.take_charts(),
+            vec![Chart {
+                kind: ChartKind::Line,
+                title: None,
+                x: vec![0.0, 1.0, 2.0],
+                y: vec![1.0, 4.0, 9.0],
+            }]
+        );
+
+        engine.eval("(def xs (list 1 2 3))").unwrap();
+        engine.eval("(def ys [2 4 8])").unwrap();
+        engine
+            .eval("(plot {:x xs :y ys :kind :line :title \"doubling\" :file file})")
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            engine.take_charts(),
+            vec![Chart {
+                kind: ChartKind::Line,
+                title: Some("doubling".to_string()),
+                x: vec![1.0, 2.0, 3.0],
+                y: vec![2.0, 4.0, 8.0],
+            }]
+        );
+
+        assert_eq!(
+            engine.eval("(plot {:x [1 2] :y [1]})"),
+            Err("`plot` needs as many x values as y values, got 2 and 1".to_string())
+        );
+        assert_eq!(
+            engine.eval("(plot {:kind :pie :y [1]})"),
+            Err("Unknown option :kind :pie for `plot`".to_string())
+        );
+        assert_eq!(
+            engine.eval("(plot (quote ((y (1)))))"),
+            Err("`plot` expected a map as argument 1, got the list ((y (1)))".to_string())
+        );
+
+        let mut strict = Engine::with_config(EngineConfig {
+            sandbox: SandboxPolicy::Strict,
+            ..EngineConfig::default()
+        });
+        assert_eq!(
+            strict.eval("(plot {:y [1]})"),
+            Err("`plot` is not available in the strict sandbox".to_string())
+        );
+    }
+}
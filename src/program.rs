@@ -0,0 +1,40 @@
+//! Running whole programs for end-to-end tests, with a transcript of what
+//! each top-level form printed.
+
+use std::fs;
+
+use crate::engine::{CapturedOutput, Engine, FixedClock, SeededRng};
+use crate::messages::message;
+use crate::syntax::parse_syntax;
+
+/// Evaluates each top-level form of the source in turn, carrying on past
+/// errors, and returns a transcript of the forms and what they printed,
+/// output written with `print` included.
+pub fn run_program(engine: &mut Engine, source: &str) -> String {
+    let output = CapturedOutput::default();
+    engine.set_output(output.clone());
+
+    let tree = match parse_syntax(source) {
+        Ok(tree) => tree,
+        Err(e) => return format!("{}\n", message!("error", message = e)),
+    };
+
+    let mut transcript = String::new();
+    for form in &tree.forms {
+        let form_source = &source[form.span.start..form.span.end];
+        transcript.push_str(&format!("> {}\n", form_source));
+        transcript.push_str(&output.before(&engine.eval_to_string(form_source)));
+        transcript.push('\n');
+    }
+    transcript
+}
+
+/// Runs the program in the file with a fresh engine whose clock and random
+/// numbers are fixed, so that the transcript is the same on every run.
+pub fn run_program_file(path: &str) -> Result<String, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut engine = Engine::new();
+    engine.set_clock(FixedClock(0.0));
+    engine.set_rng(SeededRng::new(0));
+    Ok(run_program(&mut engine, &source))
+}
@@ -0,0 +1,332 @@
+//! Refactorings over source text, built on the lossless syntax tree so that
+//! everything they do not change stays as the author wrote it.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::builtins::builtin_names;
+use crate::interpreter::Expr;
+use crate::parser::{expr_to_string, Span};
+use crate::syntax::{parse_syntax, Brackets, Edit, Node, NodeKind};
+
+/// The symbol an atom reads as, if it is one.
+fn symbol(node: &Node) -> Option<String> {
+    match (&node.kind, node.to_expr()) {
+        (NodeKind::Atom(_), Ok(Expr::Symbol(name))) => Some(name),
+        _ => None,
+    }
+}
+
+/// The symbol a call or special form starts with. Vectors are data, so
+/// they have none.
+fn head(node: &Node) -> Option<String> {
+    match &node.kind {
+        NodeKind::List {
+            brackets: Brackets::Round,
+            items,
+            ..
+        } => items.first().and_then(symbol),
+        _ => None,
+    }
+}
+
+fn items(node: &Node) -> &[Node] {
+    match &node.kind {
+        NodeKind::List { items, .. } => items,
+        NodeKind::Atom(_) => &[],
+    }
+}
+
+/// Where `defn` puts its name, after any `^:` flags.
+fn defn_name_index(items: &[Node]) -> usize {
+    1 + items[1..]
+        .iter()
+        .take_while(|item| symbol(item).is_some_and(|name| name.starts_with("^:")))
+        .count()
+}
+
+/// The global name a top-level form defines, if any.
+pub fn defined_name(node: &Node) -> Option<String> {
+    let items = items(node);
+    let index = match head(node)?.as_str() {
+        "def" | "defspec" | "deftag" => 1,
+        "defn" => defn_name_index(items),
+        _ => return None,
+    };
+    symbol(items.get(index)?)
+}
+
+/// Every global name the source defines at its top level.
+pub fn definitions(source: &str) -> Result<Vec<String>, String> {
+    Ok(parse_syntax(source)?
+        .forms
+        .iter()
+        .filter_map(defined_name)
+        .collect())
+}
+
+/// The names a parameter list binds, allowing `(name spec)` pairs.
+fn parameter_names(node: &Node) -> Vec<String> {
+    items(node)
+        .iter()
+        .filter_map(|parameter| match &parameter.kind {
+            NodeKind::Atom(_) => symbol(parameter),
+            NodeKind::List { items, .. } => items.first().and_then(symbol),
+        })
+        .collect()
+}
+
+struct Renamer<'a> {
+    old: &'a str,
+    new: &'a str,
+    edits: Vec<Edit>,
+}
+
+impl Renamer<'_> {
+    fn walk(&mut self, node: &Node, bound: &[String]) -> Result<(), String> {
+        let items = match &node.kind {
+            NodeKind::Atom(_) => {
+                if symbol(node).as_deref() == Some(self.old) && !bound.iter().any(|b| b == self.old)
+                {
+                    self.edits.push(Edit {
+                        span: node.span,
+                        text: expr_to_string(&Expr::symbol(self.new)),
+                    });
+                }
+                return Ok(());
+            }
+            NodeKind::List { items, .. } => items,
+        };
+
+        match head(node).as_deref() {
+            // Quoted forms are data, so their symbols are left alone
+            Some("quote") => Ok(()),
+            Some("fn") if items.len() > 2 => {
+                self.walk_scope(node, parameter_names(&items[1]), &items[2..], bound)
+            }
+            Some("defn") => {
+                let index = defn_name_index(items);
+                if items.len() <= index + 2 {
+                    return self.walk_all(items, bound);
+                }
+                self.walk(&items[index], bound)?;
+                let parameters = parameter_names(&items[index + 1]);
+                self.walk_scope(node, parameters, &items[index + 2..], bound)
+            }
+            Some("let") if items.len() > 2 => {
+                let bindings = self::items(&items[1]);
+                let names = bindings
+                    .iter()
+                    .filter_map(|binding| self::items(binding).first().and_then(symbol))
+                    .collect();
+                let values: Vec<&Node> = bindings
+                    .iter()
+                    .filter_map(|binding| self::items(binding).get(1))
+                    .chain(&items[2..])
+                    .collect();
+                self.walk_scope(node, names, values, bound)
+            }
+            _ => self.walk_all(items, bound),
+        }
+    }
+
+    fn walk_all<'n>(
+        &mut self,
+        nodes: impl IntoIterator<Item = &'n Node>,
+        bound: &[String],
+    ) -> Result<(), String> {
+        for node in nodes {
+            self.walk(node, bound)?;
+        }
+        Ok(())
+    }
+
+    /// Walks code that sees the names as local variables, refusing when one
+    /// of them would capture the renamed references.
+    fn walk_scope<'n>(
+        &mut self,
+        form: &Node,
+        names: Vec<String>,
+        body: impl IntoIterator<Item = &'n Node>,
+        bound: &[String],
+    ) -> Result<(), String> {
+        let before = self.edits.len();
+        let mut inner = bound.to_vec();
+        inner.extend(names);
+        self.walk_all(body, &inner)?;
+
+        if self.edits.len() > before && inner[bound.len()..].iter().any(|n| n == self.new) {
+            return Err(format!(
+                "Renaming '{}' to '{}' would be shadowed by the local '{}' at {}",
+                self.old, self.new, self.new, form.span
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether renaming `old` to `new` is allowed, given what is defined.
+pub fn check_rename(old: &str, new: &str, is_defined: impl Fn(&str) -> bool) -> Result<(), String> {
+    if builtin_names().any(|name| name == old) {
+        return Err(format!("Cannot rename the builtin '{}'", old));
+    }
+    if !is_defined(old) {
+        return Err(format!("'{}' is not defined", old));
+    }
+    if is_defined(new) {
+        return Err(format!("'{}' is already defined", new));
+    }
+    Ok(())
+}
+
+/// The edits renaming the global `old` to `new` in the source, leaving
+/// local variables and quoted data of the same name alone. Checking that
+/// `new` is not already defined is up to the caller, which knows what else
+/// is loaded.
+pub fn rename_symbol(source: &str, old: &str, new: &str) -> Result<Vec<Edit>, String> {
+    let tree = parse_syntax(source)?;
+    let mut renamer = Renamer {
+        old,
+        new,
+        edits: Vec::new(),
+    };
+    renamer.walk_all(&tree.forms, &[])?;
+    Ok(renamer.edits)
+}
+
+/// The symbols a form refers to, outside quoted data.
+fn references(node: &Node, symbols: &mut BTreeSet<String>) {
+    match &node.kind {
+        NodeKind::Atom(_) => symbols.extend(symbol(node)),
+        NodeKind::List { items, .. } => {
+            if head(node).as_deref() != Some("quote") {
+                for item in items {
+                    references(item, symbols);
+                }
+            }
+        }
+    }
+}
+
+/// A top-level definition nothing reachable refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unused {
+    /// The index of the source it is in.
+    pub source: usize,
+    pub name: String,
+    pub span: Span,
+}
+
+/// The definitions across the sources that neither the entry points nor the
+/// top-level forms that are not definitions use, directly or through other
+/// definitions.
+pub fn unused_definitions(sources: &[&str], entries: &[String]) -> Result<Vec<Unused>, String> {
+    let mut defs: Vec<(Unused, BTreeSet<String>)> = Vec::new();
+    let mut live: BTreeSet<String> = entries.iter().cloned().collect();
+    for (index, source) in sources.iter().enumerate() {
+        for form in parse_syntax(source)?.forms {
+            let mut symbols = BTreeSet::new();
+            references(&form, &mut symbols);
+            match defined_name(&form) {
+                Some(name) => defs.push((
+                    Unused {
+                        source: index,
+                        name,
+                        span: form.span,
+                    },
+                    symbols,
+                )),
+                None => live.extend(symbols),
+            }
+        }
+    }
+
+    let mut uses: HashMap<&str, Vec<&BTreeSet<String>>> = HashMap::new();
+    for (def, symbols) in &defs {
+        uses.entry(def.name.as_str()).or_default().push(symbols);
+    }
+    let mut pending: Vec<String> = live.iter().cloned().collect();
+    while let Some(name) = pending.pop() {
+        for symbols in uses.get(name.as_str()).into_iter().flatten() {
+            for symbol in symbols.iter() {
+                if live.insert(symbol.clone()) {
+                    pending.push(symbol.clone());
+                }
+            }
+        }
+    }
+
+    Ok(defs
+        .into_iter()
+        .map(|(def, _)| def)
+        .filter(|def| !live.contains(&def.name))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::apply_edits;
+
+    fn rename(source: &str, old: &str, new: &str) -> Result<String, String> {
+        rename_symbol(source, old, new).map(|edits| apply_edits(source, &edits))
+    }
+
+    #[test]
+    fn rename_references() {
+        assert_eq!(
+            rename(
+                "(defn ^:pure sq (x) (* x x)) ; squares\n(sq (quote sq))\n(fn (sq) sq)",
+                "sq",
+                "square"
+            ),
+            Ok(
+                "(defn ^:pure square (x) (* x x)) ; squares\n(square (quote sq))\n(fn (sq) sq)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            rename("(let ((y x)) (list x y))", "x", "z"),
+            Ok("(let ((y z)) (list z y))".to_string())
+        );
+        assert_eq!(
+            rename("(def x 1)\n(fn (y) (+ x y))", "x", "y"),
+            Err(
+                "Renaming 'x' to 'y' would be shadowed by the local 'y' at line 2, column 1"
+                    .to_string()
+            )
+        );
+        // Without references inside, a local of the new name is harmless
+        assert_eq!(
+            rename("(def x 1)\n(fn (y) y)", "x", "y"),
+            Ok("(def y 1)\n(fn (y) y)".to_string())
+        );
+        assert_eq!(
+            definitions("(def a 1) (defn ^:pure b (x) x) (list c) (defspec d number?)"),
+            Ok(vec!["a".to_string(), "b".to_string(), "d".to_string()])
+        );
+    }
+
+    #[test]
+    fn unused() {
+        let prelude = "(defn sq (x) (* x x))\n(defn cube (x) (* x (sq x)))\n(def unused (quote main))\n(defn loop (n) (loop n))";
+        let main = "(defn main () (cube 2))\n(def helper 1)";
+        let names = |entries: &[String]| {
+            unused_definitions(&[prelude, main], entries).map(|unused| {
+                unused
+                    .into_iter()
+                    .map(|def| format!("{} {} {}", def.source, def.name, def.span))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            names(&["main".to_string()]),
+            Ok(vec![
+                "0 unused line 3, column 1".to_string(),
+                "0 loop line 4, column 1".to_string(),
+                "1 helper line 2, column 1".to_string()
+            ])
+        );
+        assert_eq!(names(&[]).unwrap().len(), 6);
+    }
+}
@@ -0,0 +1,105 @@
+//! Regular expressions, behind the `regex` feature, in the syntax of the
+//! [regex](https://docs.rs/regex) crate.
+//!
+//! A match is the matched string when the pattern has no groups, and
+//! otherwise a list of the whole match followed by each group, nil for
+//! groups that took no part in it.
+
+use regex::{Captures, Regex};
+
+use crate::builtins::expect_string;
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+
+fn compile(name: &str, expr: &Expr) -> Result<Regex, String> {
+    let pattern = expect_string(name, 1, expr)?;
+    Regex::new(pattern).map_err(|e| message!("invalid-regex", pattern = pattern, error = e))
+}
+
+fn to_match(captures: &Captures) -> Expr {
+    if captures.len() == 1 {
+        return Expr::string(&captures[0]);
+    }
+    Expr::List(
+        captures
+            .iter()
+            .map(|group| match group {
+                Some(group) => Expr::string(group.as_str()),
+                None => Expr::Nil,
+            })
+            .collect(),
+    )
+}
+
+/// `(re-match pattern s)` is the first match of the pattern in the string,
+/// or nil if there is none.
+fn builtin_re_match(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let regex = compile("re-match", &args[0])?;
+    let s = expect_string("re-match", 2, &args[1])?;
+    Ok(regex.captures(s).map_or(Expr::Nil, |c| to_match(&c)))
+}
+
+/// `(re-find-all pattern s)` is the list of every match of the pattern in
+/// the string, in order and without overlapping.
+fn builtin_re_find_all(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let regex = compile("re-find-all", &args[0])?;
+    let s = expect_string("re-find-all", 2, &args[1])?;
+    Ok(Expr::List(
+        regex.captures_iter(s).map(|c| to_match(&c)).collect(),
+    ))
+}
+
+/// `(re-replace pattern s replacement)` replaces every match in the string.
+/// The replacement may refer to groups as `$1`, or `${name}` for named ones.
+fn builtin_re_replace(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let regex = compile("re-replace", &args[0])?;
+    let s = expect_string("re-replace", 2, &args[1])?;
+    let replacement = expect_string("re-replace", 3, &args[2])?;
+    Ok(Expr::string(regex.replace_all(s, replacement)))
+}
+
+#[rustfmt::skip]
+pub(crate) const REGEX_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("re-match",    builtin_re_match,    BuiltinKind::Eager, Arity::Exact(2)),
+    ("re-find-all", builtin_re_find_all, BuiltinKind::Eager, Arity::Exact(2)),
+    ("re-replace",  builtin_re_replace,  BuiltinKind::Eager, Arity::Exact(3)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn regular_expressions() {
+        let mut engine = Engine::new();
+        for (input, expected) in [
+            (r#"(re-match "[0-9]+" "abc 123 456")"#, r#""123""#),
+            (r#"(re-match "[0-9]+" "abc")"#, "nil"),
+            (
+                r#"(re-match "(\\w+)@(\\w+)?" "me@ you@home")"#,
+                r#"("me@" "me" nil)"#,
+            ),
+            (
+                r#"(re-find-all "[0-9]+" "1, 22 and 333")"#,
+                r#"("1" "22" "333")"#,
+            ),
+            (
+                r#"(re-find-all "(\\w)=(\\d)" "a=1 b=2")"#,
+                r#"(("a=1" "a" "1") ("b=2" "b" "2"))"#,
+            ),
+            (
+                r#"(re-replace "(\\w+)@(\\w+)" "me@home" "$2 at ${1}")"#,
+                r#""home at me""#,
+            ),
+            (
+                r#"(re-match 1 "a")"#,
+                "Error: `re-match` expected a string as argument 1, got the integer 1",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+        assert!(engine
+            .eval_to_string(r#"(re-match "(" "a")"#)
+            .starts_with("Error: Invalid regular expression '(': "));
+    }
+}
@@ -1,13 +1,49 @@
-use crate::{
-    interpreter::{eval, Scope},
-    parser::{expr_to_string, parse},
-};
+use crate::engine::{Engine, EngineConfig};
+use crate::parser::parse;
+use crate::syntax::parse_syntax;
+use std::fs::{self, OpenOptions};
 use std::io::{self, Write}; // Import Write for the flush method
 
-pub fn repl() {
-    let mut global_scope = Scope::new();
+#[derive(Debug, Default)]
+pub struct ReplOptions {
+    /// Append every form that evaluates successfully to this file.
+    pub record: Option<String>,
+    /// Evaluate the forms in this file before the first prompt.
+    pub replay: Option<String>,
+}
+
+/// Evaluates each form in the file, stopping at the first error.
+fn replay(engine: &mut Engine, path: &str) -> Result<usize, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let tree = parse_syntax(&source).map_err(|e| format!("{}: {}", path, e))?;
+    for form in &tree.forms {
+        engine
+            .eval(&source[form.span.start..form.span.end])
+            .map_err(|e| format!("{}: {} at {}", path, e, form.span))?;
+    }
+    Ok(tree.forms.len())
+}
+
+fn record(path: &str, input: &str) -> Result<(), String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", input))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn repl(config: EngineConfig, options: ReplOptions) {
+    let mut engine = Engine::with_config(config);
     let mut input = String::new();
 
+    if let Some(path) = &options.replay {
+        match replay(&mut engine, path) {
+            Ok(count) => println!("Replayed {} forms from {}", count, path),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
     loop {
         input.clear();
         print!("> ");
@@ -18,12 +54,36 @@ pub fn repl() {
         if input == "exit" {
             break;
         }
-        match parse(input) {
-            Ok(expr) => match eval(&expr, &mut global_scope) {
-                Ok(result) => println!("{}", expr_to_string(&result)),
-                Err(e) => println!("Error: {}", e),
-            },
-            Err(e) => println!("Error: {}", e),
+        if input == ":env" {
+            for (name, value) in engine.scope().bindings() {
+                println!("{} = {}", name, engine.print(&value));
+            }
+            continue;
+        }
+        if let Some(rest) = input.strip_prefix(":viz") {
+            // `:viz` draws the scopes, `:viz EXPR` the expression's tree
+            let (path, dot) = match rest.trim() {
+                "" => ("scope.dot", engine.scope_to_dot()),
+                source => match parse(source) {
+                    Ok(expr) => ("expr.dot", engine.expr_to_dot(&expr)),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                },
+            };
+            match fs::write(path, dot) {
+                Ok(()) => println!("Wrote {}", path),
+                Err(e) => println!("Error: {}: {}", path, e),
+            }
+            continue;
+        }
+        let result = engine.eval(input);
+        if let (Ok(_), Some(path)) = (&result, &options.record) {
+            if let Err(e) = record(path, input) {
+                println!("Error: {}", e);
+            }
         }
+        println!("{}", engine.report(result));
     }
 }
@@ -0,0 +1,502 @@
+//! Handles to things outside the interpreter, such as open files, which
+//! scripts pass around without seeing inside.
+//!
+//! Hosts add their own kinds of handle by implementing `Resource` and
+//! handing them to scripts with `Expr::external`, from builtins of their own.
+//! `(with-open ((f (open-file "data.txt"))) (read-line f))` closes the
+//! handles it binds when its body is done, even when the body fails.
+//!
+//! Handles that have to be cleaned up when scripts lose track of them are
+//! wrapped in a `Finalized`, whose finalizer runs when the last reference
+//! to the handle goes away. `(weak-ref handle)` refers to a handle without
+//! keeping it alive, and `(deref-weak r)` is the handle while it lives.
+
+use std::any::Any;
+use std::cell::{RefCell, RefMut};
+use std::fmt;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read};
+use std::rc::{Rc, Weak};
+
+use crate::builtins::{builtin_progn, expect_list, expect_text, type_error};
+use crate::engine::SandboxPolicy;
+use crate::interpreter::{collect_cycles, eval, Expr, Function, Scope};
+use crate::messages::message;
+use crate::parser::parse_partial;
+
+/// Something a handle holds on to until it is closed.
+pub trait Resource: Any {
+    /// What kind of handle this is, such as "file", for printing.
+    fn kind(&self) -> &str;
+
+    /// Releases what the handle holds. Closing a handle again does nothing.
+    fn close(&self) -> Result<(), String>;
+
+    /// The handle as `Any`, for builtins to get at their own kind of handle.
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl fmt::Debug for dyn Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Resource {{ kind: {} }}", self.kind())
+    }
+}
+
+/// Handles are only ever equal to themselves.
+impl PartialEq for dyn Resource {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+impl Hash for dyn Resource {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self as *const dyn Resource as *const () as usize).hash(state);
+    }
+}
+
+/// Cleans up after a handle of type `R`; see `Finalized`.
+pub type Finalizer<R> = Box<dyn FnOnce(&R)>;
+
+/// A handle that calls a finalizer when the last reference to it is
+/// dropped, such as to release what the host tied to it. It is a handle of
+/// the wrapped kind to builtins, so `expect_resource` finds the `R` inside.
+pub struct Finalized<R: Resource> {
+    resource: R,
+    finalizer: Option<Finalizer<R>>,
+}
+
+impl<R: Resource> Finalized<R> {
+    pub fn new(resource: R, finalizer: impl FnOnce(&R) + 'static) -> Self {
+        Finalized {
+            resource,
+            finalizer: Some(Box::new(finalizer)),
+        }
+    }
+}
+
+impl<R: Resource> Resource for Finalized<R> {
+    fn kind(&self) -> &str {
+        self.resource.kind()
+    }
+
+    fn close(&self) -> Result<(), String> {
+        self.resource.close()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self.resource.as_any()
+    }
+}
+
+impl<R: Resource> Drop for Finalized<R> {
+    fn drop(&mut self) {
+        if let Some(finalizer) = self.finalizer.take() {
+            finalizer(&self.resource);
+        }
+    }
+}
+
+/// What a weak reference made by `weak-ref` refers to.
+#[derive(Debug, Clone)]
+pub enum WeakRef {
+    External(Weak<dyn Resource>),
+    Function(Weak<Function>),
+}
+
+impl WeakRef {
+    /// The value referred to, if something else still keeps it alive.
+    pub fn upgrade(&self) -> Option<Expr> {
+        match self {
+            WeakRef::External(resource) => resource.upgrade().map(Expr::External),
+            WeakRef::Function(function) => function.upgrade().map(Expr::Function),
+        }
+    }
+
+    fn address(&self) -> *const () {
+        match self {
+            WeakRef::External(resource) => resource.as_ptr() as *const (),
+            WeakRef::Function(function) => function.as_ptr() as *const (),
+        }
+    }
+}
+
+/// Weak references are equal when they refer to the same value.
+impl PartialEq for WeakRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl Hash for WeakRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.address() as usize).hash(state);
+    }
+}
+
+/// Checks that the argument at the 1-based `position` of builtin `name` is a
+/// handle of type `T`, described as `expected` otherwise.
+pub fn expect_resource<'a, T: Resource>(
+    name: &str,
+    position: usize,
+    expected: &str,
+    expr: &'a Expr,
+) -> Result<&'a T, String> {
+    match expr {
+        Expr::External(resource) => resource.as_any().downcast_ref(),
+        _ => None,
+    }
+    .ok_or_else(|| type_error(name, expected, position, expr))
+}
+
+/// A file opened for reading by `open-file`.
+pub struct FileHandle {
+    path: String,
+    reader: RefCell<Option<BufReader<File>>>,
+    /// Text read past the last expression `read` returned.
+    pending: RefCell<String>,
+}
+
+impl FileHandle {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| message!("read-failed", path = path, error = e))?;
+        Ok(FileHandle {
+            path: path.to_string(),
+            reader: RefCell::new(Some(BufReader::new(file))),
+            pending: RefCell::new(String::new()),
+        })
+    }
+
+    /// The reader, or an error for builtin `name` once the file is closed.
+    fn reader(&self, name: &str) -> Result<RefMut<'_, BufReader<File>>, String> {
+        RefMut::filter_map(self.reader.borrow_mut(), Option::as_mut)
+            .map_err(|_| message!("resource-closed", name = name, kind = "file"))
+    }
+
+    /// The next line without its line ending, or `None` at the end of the
+    /// file.
+    fn read_line(&self) -> Result<Option<String>, String> {
+        let mut reader = self.reader("read-line")?;
+        let mut line = self.pending.take();
+        if let Some(end) = line.find('\n') {
+            *self.pending.borrow_mut() = line.split_off(end + 1);
+        } else if reader
+            .read_line(&mut line)
+            .map_err(|e| message!("read-failed", path = self.path, error = e))?
+            == 0
+            && line.is_empty()
+        {
+            return Ok(None);
+        }
+        let end = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(end);
+        Ok(Some(line))
+    }
+
+    /// The next expression, or `None` when only whitespace and comments are
+    /// left.
+    fn read_expr(&self) -> Result<Option<Expr>, String> {
+        let mut reader = self.reader("read")?;
+        let mut text = self.pending.take();
+        reader
+            .read_to_string(&mut text)
+            .map_err(|e| message!("read-failed", path = self.path, error = e))?;
+        match parse_partial(&text) {
+            Ok((expr, rest)) => {
+                *self.pending.borrow_mut() = rest.to_string();
+                Ok(Some(expr))
+            }
+            Err(e) if e == message!("unexpected-end") && is_blank(&text) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Whether the text holds nothing but whitespace and comments.
+fn is_blank(text: &str) -> bool {
+    text.lines()
+        .all(|line| line.trim_start().is_empty() || line.trim_start().starts_with(';'))
+}
+
+impl Resource for FileHandle {
+    fn kind(&self) -> &str {
+        "file"
+    }
+
+    fn close(&self) -> Result<(), String> {
+        self.reader.borrow_mut().take();
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// `(open-file path)` opens a file for reading with `read-line`.
+pub fn builtin_open_file(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    if scope.runtime().config.sandbox == SandboxPolicy::Strict {
+        return Err(message!("sandboxed", name = "open-file"));
+    }
+    let path = expect_text("open-file", 1, &args[0])?;
+    Ok(Expr::external(FileHandle::open(path)?))
+}
+
+/// `(read-line file)` is the next line of the file, or nil at its end.
+pub fn builtin_read_line(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let file: &FileHandle = expect_resource("read-line", 1, "a file", &args[0])?;
+    Ok(file.read_line()?.map(Expr::String).unwrap_or(Expr::Nil))
+}
+
+/// `(read file)` is the next expression in the file, unevaluated, or nil at
+/// its end.
+pub fn builtin_read(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let file: &FileHandle = expect_resource("read", 1, "a file", &args[0])?;
+    Ok(file.read_expr()?.unwrap_or(Expr::Nil))
+}
+
+/// `(close handle)` closes a handle of any kind.
+pub fn builtin_close(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    match &args[0] {
+        Expr::External(resource) => resource.close()?,
+        arg => return Err(type_error("close", "a handle", 1, arg)),
+    }
+    Ok(Expr::Nil)
+}
+
+/// `(weak-ref value)` refers to a handle or function without keeping it
+/// alive.
+pub fn builtin_weak_ref(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    match &args[0] {
+        Expr::External(resource) => Ok(Expr::Weak(WeakRef::External(Rc::downgrade(resource)))),
+        Expr::Function(function) => Ok(Expr::Weak(WeakRef::Function(Rc::downgrade(function)))),
+        arg => Err(type_error("weak-ref", "a handle or function", 1, arg)),
+    }
+}
+
+/// `(deref-weak r)` is the value the weak reference refers to, or nil once
+/// nothing else keeps it alive. Closures kept alive only by the frames they
+/// were made in are freed first, so they do not count.
+pub fn builtin_deref_weak(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    match &args[0] {
+        Expr::Weak(weak) => {
+            collect_cycles(scope);
+            Ok(weak.upgrade().unwrap_or(Expr::Nil))
+        }
+        arg => Err(type_error("deref-weak", "a weak reference", 1, arg)),
+    }
+}
+
+/// Binds each `(name handle)` pair in turn, remembering the handles opened
+/// so that they can be closed whatever happens.
+fn bind_handles(
+    bindings: &[Expr],
+    scope: &mut Scope,
+    opened: &mut Vec<Expr>,
+) -> Result<(), String> {
+    for binding in bindings {
+        let (name, init) = match binding {
+            Expr::List(pair) => match pair.as_slice() {
+                [Expr::Symbol(name), init] => (name, init),
+                _ => return Err(message!("with-open-binding", actual = binding.describe())),
+            },
+            _ => return Err(message!("with-open-binding", actual = binding.describe())),
+        };
+        let handle = eval(init, scope)?;
+        if !matches!(handle, Expr::External(_)) {
+            return Err(type_error("with-open", "a handle", 1, &handle));
+        }
+        opened.push(handle.clone());
+        scope.set_variable(name.clone(), handle);
+    }
+    Ok(())
+}
+
+/// `(with-open ((name handle)...) body...)` binds the handles like `let`,
+/// evaluates the body and closes the handles in reverse order, also when
+/// binding them or the body fails. An error from the body wins over one
+/// from closing.
+pub fn builtin_with_open(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let bindings = expect_list("with-open", 1, &args[0])?;
+    let mut local = Scope::with_parent(scope);
+    let mut opened = Vec::new();
+    let result = bind_handles(bindings, &mut local, &mut opened)
+        .and_then(|()| builtin_progn(&args[1..], &mut local));
+
+    let mut closed = Ok(());
+    for handle in opened.iter().rev() {
+        if let Expr::External(resource) = handle {
+            let status = resource.close();
+            if closed.is_ok() {
+                closed = status;
+            }
+        }
+    }
+
+    let value = result?;
+    closed?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::engine::Engine;
+    use crate::interpreter::{Arity, BuiltinFunction, BuiltinKind};
+
+    #[test]
+    fn with_open_reads_files() {
+        let path = std::env::temp_dir().join("rs-lisp-with-open.txt");
+        std::fs::write(&path, "first\r\nsecond\n").unwrap();
+        let path = path.display().to_string();
+
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval_to_string(&format!(
+                "(def lines (with-open ((f (open-file {:?}))) (list f (read-line f) (read-line f) (read-line f))))",
+                path
+            )),
+            r#"(<file> "first" "second" nil)"#
+        );
+        assert_eq!(
+            engine.eval_to_string("(read-line (first lines))"),
+            "Error: `read-line` cannot use a closed file"
+        );
+        std::fs::write(&path, "(def x\n  1) ; one\n:a\nrest of the line\n; done\n").unwrap();
+        assert_eq!(
+            engine.eval_to_string(&format!(
+                "(with-open ((f (open-file {:?}))) (list (read f) (read f) (read-line f) (read f)))",
+                path
+            )),
+            r#"((def x 1) :a "rest of the line" nil)"#
+        );
+        std::fs::write(&path, "(1 2").unwrap();
+        assert_eq!(
+            engine.eval_to_string(&format!(
+                "(with-open ((f (open-file {:?}))) (read f))",
+                path
+            )),
+            "Error: Unexpected end of input"
+        );
+        assert_eq!(
+            engine.eval_to_string("(with-open ((x 1)) x)"),
+            "Error: `with-open` expected a handle as argument 1, got the integer 1"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    struct Counted(Rc<Cell<u32>>);
+
+    impl Resource for Counted {
+        fn kind(&self) -> &str {
+            "counted"
+        }
+
+        fn close(&self) -> Result<(), String> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn weak_refs_and_finalizers() {
+        thread_local! {
+            static FINALIZED: Rc<Cell<u32>> = Rc::new(Cell::new(0));
+        }
+        fn open(_: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+            let counted = Counted(Rc::new(Cell::new(0)));
+            Ok(Expr::external(Finalized::new(counted, |_| {
+                FINALIZED.with(|finalized| finalized.set(finalized.get() + 1))
+            })))
+        }
+        let finalized = || FINALIZED.with(|finalized| finalized.get());
+        let mut engine = Engine::new();
+        engine.scope().set_variable(
+            "open".to_string(),
+            Expr::builtin_function("open", open, BuiltinKind::Eager, Arity::Exact(0)),
+        );
+
+        engine.eval("(def h (open))").unwrap();
+        engine.eval("(def w (weak-ref h))").unwrap();
+        assert_eq!(engine.eval_to_string("(deref-weak w)"), "<counted>");
+        assert_eq!(finalized(), 0);
+        engine.eval("(def h nil)").unwrap();
+        assert_eq!(finalized(), 1);
+        assert_eq!(engine.eval_to_string("(deref-weak w)"), "nil");
+
+        assert_eq!(
+            engine.eval_to_string("(deref-weak (weak-ref (open)))"),
+            "nil"
+        );
+        assert_eq!(finalized(), 2);
+
+        engine.eval("(defn f () 1)").unwrap();
+        assert_eq!(engine.eval_to_string("((deref-weak (weak-ref f)))"), "1");
+        assert_eq!(
+            engine.eval_to_string("(weak-ref 1)"),
+            "Error: `weak-ref` expected a handle or function as argument 1, got the integer 1"
+        );
+
+        // A closure bound in the frame of the call that made it is freed
+        // along with the frame, unless something else still refers to it
+        engine.eval("(defn mk () (def inner (fn () 1)))").unwrap();
+        assert_eq!(engine.eval_to_string("(deref-weak (weak-ref (mk)))"), "nil");
+        engine.eval("(def made (mk))").unwrap();
+        assert_eq!(engine.eval_to_string("((deref-weak (weak-ref made)))"), "1");
+        engine
+            .eval("(defn countdown () (def step (fn (n) (if (= n 0) :done (step (- n 1))))))")
+            .unwrap();
+        engine.eval("(def from-3 (countdown))").unwrap();
+        engine.eval("(deref-weak (weak-ref from-3))").unwrap();
+        assert_eq!(engine.eval_to_string("(from-3 3)"), ":done");
+
+        engine
+            .eval("(defn hold (held) (def get (fn () held)))")
+            .unwrap();
+        engine.eval("(hold (open))").unwrap();
+        assert_eq!(finalized(), 2);
+        engine.eval("nil").unwrap();
+        assert_eq!(finalized(), 3);
+
+        // Handles in the global scope are finalized with the engine, even
+        // with closures defined there keeping the scope alive
+        engine.eval("(def kept (open))").unwrap();
+        engine.eval("(defn get-kept () kept)").unwrap();
+        assert_eq!(finalized(), 3);
+        drop(engine);
+        assert_eq!(finalized(), 4);
+    }
+
+    #[test]
+    fn with_open_closes_on_errors() {
+        thread_local! {
+            static CLOSED: Rc<Cell<u32>> = Rc::new(Cell::new(0));
+        }
+        fn open(_: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+            Ok(Expr::external(Counted(CLOSED.with(Rc::clone))))
+        }
+        let mut engine = Engine::new();
+        let open = BuiltinFunction::new("open", open, BuiltinKind::Eager, Arity::Exact(0));
+
+        let result = engine.eval_with_capabilities(
+            "(with-open ((a (open)) (b (open))) (first 1))",
+            [open.clone()],
+        );
+        assert!(result.is_err());
+        assert_eq!(CLOSED.with(|closed| closed.get()), 2);
+
+        // Handles bound before a failing binding are closed too
+        let result =
+            engine.eval_with_capabilities("(with-open ((a (open)) (b (first 1))) a)", [open]);
+        assert!(result.is_err());
+        assert_eq!(CLOSED.with(|closed| closed.get()), 3);
+    }
+}
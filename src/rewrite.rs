@@ -0,0 +1,247 @@
+//! Walking nested data and rewriting it with pattern rules. Lists, vectors,
+//! maps and sets are walked into, a map's keys along with its values.
+
+use crate::builtins::expect_list;
+use crate::engine::Runtime;
+use crate::interpreter::{call, sorted_entries, sorted_members, Expr, Map, Scope, Set};
+use crate::messages::message;
+use crate::pattern::{match_pattern, Bindings};
+use crate::set::expect_member;
+
+/// Gives up on `rewrite` rules that keep changing the data.
+const MAX_REWRITE_PASSES: usize = 1000;
+
+/// Rebuilds the collection `data` of what `walk` makes of each of its
+/// elements, or for anything else gives it back as it is. Walked keys and
+/// set members must still be keywords, strings or numbers.
+fn walk_children(
+    name: &str,
+    data: &Expr,
+    mut walk: impl FnMut(&Expr) -> Result<Expr, String>,
+) -> Result<Expr, String> {
+    match data {
+        Expr::List(items) => items
+            .iter()
+            .map(walk)
+            .collect::<Result<_, _>>()
+            .map(Expr::List),
+        Expr::Vector(items) => items
+            .iter()
+            .map(walk)
+            .collect::<Result<_, _>>()
+            .map(Expr::vector),
+        Expr::Map(entries) => {
+            let mut map = Map::new();
+            for (key, value) in sorted_entries(entries) {
+                let key = expect_member(name, 2, &walk(key.expr())?)?;
+                map.insert(key, walk(value)?);
+            }
+            Ok(Expr::map(map))
+        }
+        Expr::Set(members) => {
+            let mut set = Set::new();
+            for member in sorted_members(members) {
+                set.insert(expect_member(name, 2, &walk(member.expr())?)?);
+            }
+            Ok(Expr::set(set))
+        }
+        data => Ok(data.clone()),
+    }
+}
+
+/// Rebuilds `data` bottom-up, replacing each node with `f` applied to it
+/// after its children have been replaced. Each node costs an evaluation
+/// step, so deeply nested data runs into the recursion limit rather than
+/// overflowing the stack.
+fn postwalk(
+    name: &str,
+    f: &mut dyn FnMut(Expr) -> Result<Expr, String>,
+    data: &Expr,
+    runtime: &Runtime,
+) -> Result<Expr, String> {
+    runtime.enter()?;
+    let node = walk_children(name, data, |item| postwalk(name, f, item, runtime));
+    runtime.leave();
+    f(node?)
+}
+
+/// Rebuilds `data` top-down, replacing each node with `f` applied to it and
+/// then walking the children of the replacement.
+fn prewalk(
+    name: &str,
+    f: &mut dyn FnMut(Expr) -> Result<Expr, String>,
+    data: &Expr,
+    runtime: &Runtime,
+) -> Result<Expr, String> {
+    runtime.enter()?;
+    let node = f(data.clone())
+        .and_then(|node| walk_children(name, &node, |item| prewalk(name, f, item, runtime)));
+    runtime.leave();
+    node
+}
+
+/// `(postwalk f data)`
+pub fn builtin_postwalk(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let runtime = scope.runtime().clone();
+    postwalk(
+        "postwalk",
+        &mut |node| call(&args[0], &[node], scope),
+        &args[1],
+        &runtime,
+    )
+}
+
+/// `(prewalk f data)`
+pub fn builtin_prewalk(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let runtime = scope.runtime().clone();
+    prewalk(
+        "prewalk",
+        &mut |node| call(&args[0], &[node], scope),
+        &args[1],
+        &runtime,
+    )
+}
+
+/// Fills the bindings into a template, splicing the list or vector bound to
+/// `?x` wherever a list or vector in the template has `& ?x`.
+fn instantiate(template: &Expr, bindings: &Bindings) -> Expr {
+    match template {
+        Expr::Symbol(name) => bindings
+            .iter()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| template.clone()),
+        Expr::List(items) => Expr::List(instantiate_items(items, bindings)),
+        Expr::Vector(items) => Expr::vector(instantiate_items(items, bindings)),
+        Expr::Map(entries) => Expr::map(
+            entries
+                .iter()
+                .map(|(key, value)| (key.clone(), instantiate(value, bindings)))
+                .collect(),
+        ),
+        _ => template.clone(),
+    }
+}
+
+fn instantiate_items(items: &[Expr], bindings: &Bindings) -> Vec<Expr> {
+    let mut result = Vec::new();
+    let mut items = items.iter();
+    while let Some(item) = items.next() {
+        if *item == Expr::symbol("&") {
+            if let Some(spliced) = items.next() {
+                match instantiate(spliced, bindings) {
+                    Expr::List(spliced) => result.extend(spliced),
+                    Expr::Vector(spliced) => result.extend(spliced.iter().cloned()),
+                    spliced => result.push(spliced),
+                }
+                continue;
+            }
+        }
+        result.push(instantiate(item, bindings));
+    }
+    result
+}
+
+/// `(rewrite rules data)`: rewrites `data` bottom-up with the first of the
+/// `(pattern template)` rules matching each node, until nothing changes.
+pub fn builtin_rewrite(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let rules = expect_list("rewrite", 1, &args[0])?
+        .iter()
+        .map(|rule| match rule {
+            Expr::List(pair) if pair.len() == 2 => Ok((&pair[0], &pair[1])),
+            _ => Err(message!("rewrite-rule", actual = rule.describe())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let runtime = scope.runtime().clone();
+    let mut data = args[1].clone();
+    for _ in 0..MAX_REWRITE_PASSES {
+        let rewritten = postwalk(
+            "rewrite",
+            &mut |node| {
+                for (pattern, template) in &rules {
+                    let mut bindings = Vec::new();
+                    if match_pattern(pattern, &node, &mut bindings) {
+                        return Ok(instantiate(template, &bindings));
+                    }
+                }
+                Ok(node)
+            },
+            &data,
+            &runtime,
+        )?;
+        if rewritten == data {
+            return Ok(data);
+        }
+        data = rewritten;
+    }
+
+    Err(message!("rewrite-unsettled", passes = MAX_REWRITE_PASSES))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn walks() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(def inc (fn (x) (if (number? x) (+ x 1) x)))")
+            .unwrap();
+        assert_eq!(
+            engine.eval_to_string("(postwalk inc (quote (1 (2 a))))"),
+            "(2 (3 a))"
+        );
+        // prewalk sees each node before its children are walked
+        assert_eq!(
+            engine.eval_to_string(
+                "(prewalk (fn (x) (if (list? x) (if (empty? x) x (rest x)) x)) (quote (a (b c) d)))"
+            ),
+            "((c) d)"
+        );
+
+        for (input, expected) in [
+            ("(postwalk inc (quote [1 {:a 2 3 #{4}} (5)]))", "[2 {4 #{5} :a 3} (6)]"),
+            (
+                "(prewalk (fn (x) (if (vector? x) (rest x) x)) [[1 2] [3 [4 5]]])",
+                "(((5)))",
+            ),
+            (
+                "(postwalk (fn (x) (if (eq? x :a) [1] x)) {:a 1})",
+                "Error: `postwalk` expected a keyword, string or number as argument 2, got the vector [1]",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rewrite_to_fixpoint() {
+        let mut engine = Engine::new();
+        engine
+            .eval(
+                "(def rules (quote (((+ 0 ?x) ?x) ((+ ?x 0) ?x) ((list & ?xs) (quote (& ?xs)))))) ",
+            )
+            .unwrap();
+        assert_eq!(
+            engine.eval_to_string("(rewrite rules (quote (* (+ 0 (+ y 0)) (list 1 2))))"),
+            "(* y (quote (1 2)))"
+        );
+        assert_eq!(
+            engine.eval_to_string(
+                "(rewrite (quote (({:op :neg :arg ?x} [:- ?x]))) (quote [{:op :neg :arg 1} 2]))"
+            ),
+            "[[:- 1] 2]"
+        );
+        assert_eq!(
+            engine.eval_to_string("(rewrite (quote ((a b) (b a))) (quote (a)))"),
+            "Error: `rewrite` did not settle after 1000 passes"
+        );
+        // Rules that keep growing the data run into the recursion limit
+        assert_eq!(
+            engine.eval_to_string("(rewrite (quote ((a (a)))) (quote a))"),
+            "Error: Maximum recursion depth exceeded"
+        );
+    }
+}
@@ -0,0 +1,354 @@
+//! Sequences: the lists, vectors, strings, maps and sets that `first`,
+//! `count`, `map` and the other sequence builtins walk the same way. A string
+//! is a sequence of its characters, a map one of `[key value]` vectors in key
+//! order and a set one of its members in order. nil is the empty sequence.
+//! The builtins that build a sequence give a list whatever they were given,
+//! except `conj` and `into`, which add to a collection of any kind.
+
+use std::rc::Rc;
+use std::str::Chars;
+use std::vec;
+
+use crate::builtins::type_error;
+use crate::interpreter::{
+    call, sorted_entries, sorted_members, Arity, BuiltinFn, BuiltinKind, Expr, MapKey, Scope,
+};
+use crate::messages::message;
+use crate::parser::expr_to_string;
+use crate::set::expect_member;
+
+/// The elements of a sequence, one at a time.
+pub enum Seq<'a> {
+    Items(std::slice::Iter<'a, Expr>),
+    Chars(Chars<'a>),
+    Entries(vec::IntoIter<(&'a MapKey, &'a Expr)>),
+    Members(vec::IntoIter<&'a MapKey>),
+}
+
+impl<'a> Seq<'a> {
+    /// The sequence of `expr`, or an error naming the builtin and argument.
+    pub fn new(name: &str, position: usize, expr: &'a Expr) -> Result<Self, String> {
+        match expr {
+            Expr::List(items) => Ok(Seq::Items(items.iter())),
+            Expr::Vector(items) => Ok(Seq::Items(items.iter())),
+            Expr::String(s) => Ok(Seq::Chars(s.chars())),
+            Expr::Map(map) => Ok(Seq::Entries(sorted_entries(map).into_iter())),
+            Expr::Set(set) => Ok(Seq::Members(sorted_members(set).into_iter())),
+            Expr::Nil => Ok(Seq::Items([].iter())),
+            _ => Err(type_error(name, "a sequence", position, expr)),
+        }
+    }
+}
+
+impl Iterator for Seq<'_> {
+    type Item = Expr;
+
+    fn next(&mut self) -> Option<Expr> {
+        match self {
+            Seq::Items(items) => items.next().cloned(),
+            Seq::Chars(chars) => chars.next().map(Expr::Char),
+            Seq::Entries(entries) => entries
+                .next()
+                .map(|(key, value)| Expr::vector(vec![key.expr().clone(), value.clone()])),
+            Seq::Members(members) => members.next().map(|member| member.expr().clone()),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Seq::Items(items) => items.size_hint(),
+            Seq::Chars(chars) => chars.size_hint(),
+            Seq::Entries(entries) => entries.size_hint(),
+            Seq::Members(members) => members.size_hint(),
+        }
+    }
+
+    // Lists and vectors skip to an element without cloning the ones before it
+    fn nth(&mut self, n: usize) -> Option<Expr> {
+        match self {
+            Seq::Items(items) => items.nth(n).cloned(),
+            Seq::Chars(chars) => chars.nth(n).map(Expr::Char),
+            Seq::Entries(entries) => {
+                let (key, value) = entries.nth(n)?;
+                Some(Expr::vector(vec![key.expr().clone(), value.clone()]))
+            }
+            Seq::Members(members) => members.nth(n).map(|member| member.expr().clone()),
+        }
+    }
+
+    fn count(self) -> usize {
+        match self {
+            Seq::Items(items) => items.len(),
+            Seq::Chars(chars) => chars.count(),
+            Seq::Entries(entries) => entries.len(),
+            Seq::Members(members) => members.len(),
+        }
+    }
+}
+
+/// The integer at `position` as a count of elements.
+fn expect_count(name: &str, position: usize, expr: &Expr) -> Result<usize, String> {
+    match expr {
+        Expr::Int(n) if *n >= 0 => Ok(*n as usize),
+        _ => Err(type_error(name, "a non-negative integer", position, expr)),
+    }
+}
+
+/// `(first coll)` is the first element, or nil if there is none.
+fn builtin_first(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Seq::new("first", 1, &args[0])?.next().unwrap_or(Expr::Nil))
+}
+
+fn builtin_rest(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::List(Seq::new("rest", 1, &args[0])?.skip(1).collect()))
+}
+
+fn builtin_is_empty(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(
+        Seq::new("empty?", 1, &args[0])?.next().is_none(),
+    ))
+}
+
+fn builtin_count(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Int(Seq::new("count", 1, &args[0])?.count() as i64))
+}
+
+/// `(nth coll i)` is the element at index `i`, counting from 0.
+fn builtin_nth(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut items = Seq::new("nth", 1, &args[0])?;
+    let out_of_range = || {
+        message!(
+            "index-out-of-range",
+            name = "nth",
+            index = expr_to_string(&args[1]),
+            length = Seq::new("nth", 1, &args[0]).map_or(0, Seq::count)
+        )
+    };
+    match &args[1] {
+        Expr::Int(n) => usize::try_from(*n)
+            .ok()
+            .and_then(|n| items.nth(n))
+            .ok_or_else(out_of_range),
+        Expr::BigInt(_) => Err(out_of_range()),
+        other => Err(type_error("nth", "an integer", 2, other)),
+    }
+}
+
+/// `(take n coll)` is the list of the first `n` elements, or all of them if
+/// there are fewer.
+fn builtin_take(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let n = expect_count("take", 1, &args[0])?;
+    Ok(Expr::List(Seq::new("take", 2, &args[1])?.take(n).collect()))
+}
+
+/// `(map f coll)` is the list of `f` applied to each element.
+fn builtin_map(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    Seq::new("map", 2, &args[1])?
+        .map(|item| call(&args[0], &[item], scope))
+        .collect::<Result<_, _>>()
+        .map(Expr::List)
+}
+
+/// `(filter pred coll)` is the list of the elements `pred` is true for.
+fn builtin_filter(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let mut kept = Vec::new();
+    for item in Seq::new("filter", 2, &args[1])? {
+        if call(&args[0], std::slice::from_ref(&item), scope)?.is_truthy() {
+            kept.push(item);
+        }
+    }
+    Ok(Expr::List(kept))
+}
+
+/// `(reduce f init coll)` combines the elements from the left, starting from
+/// `init`, so `(reduce + 0 (list 1 2))` is `(+ (+ 0 1) 2)`. `(reduce f coll)`
+/// starts from the first element instead.
+fn builtin_reduce(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let (init, coll) = match args {
+        [_, init, coll] => (Some(init.clone()), coll),
+        [_, coll] => (None, coll),
+        _ => unreachable!("arity is checked before the call"),
+    };
+    let mut items = Seq::new("reduce", args.len(), coll)?;
+    let mut acc = match init.or_else(|| items.next()) {
+        Some(acc) => acc,
+        None => return Err(message!("reduce-empty")),
+    };
+    for item in items {
+        acc = call(&args[0], &[acc, item], scope)?;
+    }
+    Ok(acc)
+}
+
+/// The collection with each item added the way its kind adds one: at the end
+/// of a list or vector, as a member of a set, or as an entry of a map from a
+/// `[key value]` vector. nil is the empty list. Each item comes with the
+/// argument it came from, for errors.
+fn conj_all(
+    name: &str,
+    target: &Expr,
+    items: impl Iterator<Item = (usize, Expr)>,
+) -> Result<Expr, String> {
+    match target {
+        Expr::List(list) => {
+            let mut list = list.clone();
+            list.extend(items.map(|(_, item)| item));
+            Ok(Expr::List(list))
+        }
+        Expr::Nil => Ok(Expr::List(items.map(|(_, item)| item).collect())),
+        Expr::Vector(vector) => {
+            let mut vector = vector.clone();
+            Rc::make_mut(&mut vector).extend(items.map(|(_, item)| item));
+            Ok(Expr::Vector(vector))
+        }
+        Expr::Set(set) => {
+            let mut set = set.clone();
+            let members = Rc::make_mut(&mut set);
+            for (position, item) in items {
+                members.insert(expect_member(name, position, &item)?);
+            }
+            Ok(Expr::Set(set))
+        }
+        Expr::Map(map) => {
+            let mut map = map.clone();
+            let entries = Rc::make_mut(&mut map);
+            for (position, item) in items {
+                match &item {
+                    Expr::Vector(entry) if entry.len() == 2 => {
+                        entries.insert(expect_member(name, position, &entry[0])?, entry[1].clone());
+                    }
+                    _ => return Err(type_error(name, "a [key value] vector", position, &item)),
+                }
+            }
+            Ok(Expr::Map(map))
+        }
+        _ => Err(type_error(name, "a list, vector, map or set", 1, target)),
+    }
+}
+
+/// `(conj coll x...)` is the collection with each `x` added to it.
+fn builtin_conj(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    conj_all(
+        "conj",
+        &args[0],
+        args.iter()
+            .cloned()
+            .enumerate()
+            .skip(1)
+            .map(|(i, item)| (i + 1, item)),
+    )
+}
+
+/// `(into target source)` is the target with every element of the source
+/// added to it, so `(into #{} (list 1 1 2))` is `#{1 2}` and `(into {} [[:a
+/// 1]])` is `{:a 1}`.
+fn builtin_into(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let items = Seq::new("into", 2, &args[1])?;
+    conj_all("into", &args[0], items.map(|item| (2, item)))
+}
+
+#[rustfmt::skip]
+pub(crate) const SEQ_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("first",  builtin_first,    BuiltinKind::Eager, Arity::Exact(1)),
+    ("rest",   builtin_rest,     BuiltinKind::Eager, Arity::Exact(1)),
+    ("empty?", builtin_is_empty, BuiltinKind::Eager, Arity::Exact(1)),
+    ("count",  builtin_count,    BuiltinKind::Eager, Arity::Exact(1)),
+    ("nth",    builtin_nth,      BuiltinKind::Eager, Arity::Exact(2)),
+    ("take",   builtin_take,     BuiltinKind::Eager, Arity::Exact(2)),
+    ("map",    builtin_map,      BuiltinKind::Eager, Arity::Exact(2)),
+    ("filter", builtin_filter,   BuiltinKind::Eager, Arity::Exact(2)),
+    ("reduce", builtin_reduce,   BuiltinKind::Eager, Arity::Range(2, 3)),
+    ("conj",   builtin_conj,     BuiltinKind::Eager, Arity::AtLeast(1)),
+    ("into",   builtin_into,     BuiltinKind::Eager, Arity::Exact(2)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn sequences() {
+        let mut engine = Engine::new();
+        engine.eval("(def m {:a 1 :b 2})").unwrap();
+        for (input, expected) in [
+            ("(first (list 1 2))", "1"),
+            ("(first [1 2])", "1"),
+            ("(first \"ab\")", "#\\a"),
+            ("(first m)", "[:a 1]"),
+            ("(first nil)", "nil"),
+            ("(rest [1 2 3])", "(2 3)"),
+            ("(rest \"abc\")", "(#\\b #\\c)"),
+            ("(empty? \"\")", "true"),
+            ("(empty? {})", "true"),
+            ("(empty? [0])", "false"),
+            ("(count (list 1 2 3))", "3"),
+            ("(count \"héllo\")", "5"),
+            ("(count m)", "2"),
+            ("(count nil)", "0"),
+            ("(nth \"abc\" 1)", "#\\b"),
+            ("(nth m 1)", "[:b 2]"),
+            ("(take 2 [1 2 3])", "(1 2)"),
+            ("(take 5 \"ab\")", "(#\\a #\\b)"),
+            ("(map (fn (x) (* x x)) [1 2 3])", "(1 4 9)"),
+            ("(map first m)", "(:a :b)"),
+            (
+                "(filter (fn (c) (eq? c #\\a)) \"banana\")",
+                "(#\\a #\\a #\\a)",
+            ),
+            ("(reduce + 0 [1 2 3])", "6"),
+            ("(reduce + (list 1 2 3))", "6"),
+            ("(reduce list 0 nil)", "0"),
+            (
+                "(reduce + [])",
+                "Error: `reduce` of an empty sequence needs an initial value",
+            ),
+            (
+                "(nth \"abc\" 3)",
+                "Error: `nth` index 3 is out of range for 3 elements",
+            ),
+            (
+                "(take -1 [1])",
+                "Error: `take` expected a non-negative integer as argument 1, got the integer -1",
+            ),
+            (
+                "(count 1)",
+                "Error: `count` expected a sequence as argument 1, got the integer 1",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn conj_and_into() {
+        let mut engine = Engine::new();
+        for (input, expected) in [
+            ("(conj (list 1 2) 3 4)", "(1 2 3 4)"),
+            ("(conj [1] 2)", "[1 2]"),
+            ("(conj nil 1)", "(1)"),
+            ("(conj #{1} 2 1)", "#{1 2}"),
+            ("(conj {:a 1} [:b 2] [:a 3])", "{:a 3 :b 2}"),
+            ("(into [] (list 1 2))", "[1 2]"),
+            ("(into (list 0) \"ab\")", "(0 #\\a #\\b)"),
+            ("(into #{} [1 2 1])", "#{1 2}"),
+            ("(into {} [[:a 1] [:b 2]])", "{:a 1 :b 2}"),
+            ("(into [] {:a 1})", "[[:a 1]]"),
+            ("(into nil #{2 1})", "(1 2)"),
+            (
+                "(conj {} [:a])",
+                "Error: `conj` expected a [key value] vector as argument 2, got the vector [:a]",
+            ),
+            (
+                "(into #{} (list (list 1)))",
+                "Error: `into` expected a keyword, string or number as argument 2, got the list (1)",
+            ),
+            (
+                "(into \"ab\" [1])",
+                "Error: `into` expected a list, vector, map or set as argument 1, \
+                 got the string \"ab\"",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! A persistent interpreter session over a pair of byte streams, for editors
+//! and other processes.
+//!
+//! Each message is its length in bytes as a decimal number on its own line,
+//! followed by that many bytes of UTF-8 holding one S-expression. Requests
+//! are `(op id args...)`, and each gets one response starting with its id:
+//!
+//! - `(eval id "source")` answers `(id value "printed")` or `(id error "message")`
+//! - `(complete id prefix)` answers `(id completions (name...))`
+//! - `(doc id name)` answers `(id doc "text")`, or `(id doc nil)` without docs
+//!
+//! Sources and prefixes may also be given as symbols, as they were before
+//! there were strings.
+//! - `(interrupt id)` stops the evaluation in progress and answers `(id ok)`
+//!   once it has stopped
+//!
+//! What programs write with `print` goes to standard error, so that it stays
+//! out of the responses.
+
+use std::io::{BufRead, Write};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::engine::{Engine, EngineConfig, Stderr};
+use crate::interpreter::Expr;
+use crate::parser::{expr_to_string, parse};
+
+/// Reads one message, or `None` at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut header = String::new();
+    if input.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+        return Ok(None);
+    }
+    let length: usize = header
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid message length '{}'", header.trim()))?;
+    let mut payload = vec![0; length];
+    input
+        .read_exact(&mut payload)
+        .map_err(|e| format!("Incomplete message: {}", e))?;
+    String::from_utf8(payload)
+        .map(Some)
+        .map_err(|_| "Message is not valid UTF-8".to_string())
+}
+
+pub fn write_message(output: &mut impl Write, payload: &str) -> Result<(), String> {
+    write!(output, "{}\n{}", payload.len(), payload)
+        .and_then(|_| output.flush())
+        .map_err(|e| e.to_string())
+}
+
+/// Answers a request other than `interrupt`, whose work happens when it
+/// arrives.
+fn respond(engine: &mut Engine, request: &Expr) -> Expr {
+    let Expr::List(items) = request else {
+        return error_response(Expr::Nil, "Requests must be lists");
+    };
+    let (op, id, args) = match items.as_slice() {
+        [Expr::Symbol(op), id, args @ ..] => (op.as_str(), id.clone(), args),
+        _ => return error_response(Expr::Nil, "Requests must be (op id args...)"),
+    };
+    let response =
+        |kind: &str, value: Expr| Expr::List(vec![id.clone(), Expr::symbol(kind), value]);
+
+    match (op, args) {
+        ("eval", [Expr::String(source) | Expr::Symbol(source)]) => match engine.eval(source) {
+            Ok(value) => response("value", Expr::String(engine.print(&value))),
+            Err(e) => response("error", Expr::String(e)),
+        },
+        ("complete", [Expr::String(prefix) | Expr::Symbol(prefix)]) => response(
+            "completions",
+            Expr::List(
+                engine
+                    .completions(prefix)
+                    .into_iter()
+                    .map(Expr::Symbol)
+                    .collect(),
+            ),
+        ),
+        ("doc", [Expr::Symbol(name)]) => response(
+            "doc",
+            engine.doc(name).map(Expr::String).unwrap_or(Expr::Nil),
+        ),
+        ("interrupt", []) => Expr::List(vec![id, Expr::symbol("ok")]),
+        ("eval" | "complete" | "doc" | "interrupt", _) => {
+            error_response(id, &format!("Invalid arguments to '{}'", op))
+        }
+        _ => error_response(id, &format!("Unknown operation '{}'", op)),
+    }
+}
+
+fn error_response(id: Expr, message: &str) -> Expr {
+    Expr::List(vec![id, Expr::symbol("error"), Expr::string(message)])
+}
+
+fn is_interrupt(request: &Expr) -> bool {
+    matches!(request, Expr::List(items) if items.first() == Some(&Expr::symbol("interrupt")))
+}
+
+/// Answers one request message with the response message.
+pub fn handle_message(engine: &mut Engine, message: &str) -> String {
+    let response = match parse(message) {
+        Ok(request) => respond(engine, &request),
+        Err(e) => error_response(Expr::Nil, &e),
+    };
+    expr_to_string(&response)
+}
+
+/// Serves requests from the input until it ends. Requests are read on a
+/// separate thread, so that an interrupt can reach an evaluation still
+/// running.
+pub fn serve(
+    config: EngineConfig,
+    mut input: impl BufRead + Send + 'static,
+    output: &mut impl Write,
+) -> Result<(), String> {
+    let mut engine = Engine::with_config(config);
+    engine.set_output(Stderr);
+    let interrupt = engine.interrupt_handle();
+    let (requests, received) = mpsc::channel();
+
+    let reader = thread::spawn(move || loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // Values cannot cross threads, so the message is parsed again by
+        // the thread answering it
+        if parse(&message).is_ok_and(|request| is_interrupt(&request)) {
+            interrupt.interrupt();
+        }
+        if requests.send(message).is_err() {
+            return Ok(());
+        }
+    });
+
+    for message in received {
+        write_message(output, &handle_message(&mut engine, &message))?;
+    }
+    reader
+        .join()
+        .map_err(|_| "The request reader panicked".to_string())?
+}
+
+/// Serves the same requests over WebSocket, one text message per request
+/// and response, with a session of its own for each connection. Requests on
+/// a connection are answered in order, so there an interrupt cannot stop an
+/// evaluation; fuel limits can.
+#[cfg(feature = "websocket")]
+pub fn serve_websocket(config: EngineConfig, listener: std::net::TcpListener) {
+    use tungstenite::Message;
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let config = config.clone();
+        thread::spawn(move || {
+            let Ok(mut socket) = tungstenite::accept(stream) else {
+                return;
+            };
+            let mut engine = Engine::with_config(config);
+            engine.set_output(Stderr);
+            while let Ok(message) = socket.read() {
+                let response = match message {
+                    Message::Text(request) => handle_message(&mut engine, &request),
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                if socket.send(Message::Text(response)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{InterruptHandle, Rng};
+    use std::io::Cursor;
+
+    fn frame(payload: &str) -> String {
+        format!("{}\n{}", payload.len(), payload)
+    }
+
+    #[test]
+    fn session() {
+        let input: String = [
+            r#"(eval 1 "(def x \"answer\" 2)")"#,
+            "(eval 2 |(+ x 1)|)",
+            "(eval 3 |(+ x y)|)",
+            "(doc 4 x)",
+            "(complete 5 fir)",
+            "(frobnicate 6)",
+            "(interrupt 7)",
+        ]
+        .iter()
+        .map(|payload| frame(payload))
+        .collect();
+
+        let mut output = Vec::new();
+        serve(EngineConfig::default(), Cursor::new(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            responses.push(message);
+        }
+        assert_eq!(
+            responses,
+            vec![
+                r#"(1 value "2")"#,
+                r#"(2 value "3")"#,
+                r#"(3 error "Undefined symbol 'y'")"#,
+                r#"(4 doc "answer")"#,
+                "(5 completions (first))",
+                r#"(6 error "Unknown operation 'frobnicate'")"#,
+                "(7 ok)",
+            ]
+        );
+    }
+
+    #[test]
+    fn interrupt() {
+        // Drawing from this generator interrupts the evaluation that drew,
+        // as another thread would
+        struct InterruptingRng(InterruptHandle);
+        impl Rng for InterruptingRng {
+            fn next_f64(&mut self) -> f64 {
+                self.0.interrupt();
+                0.0
+            }
+        }
+
+        let mut engine = Engine::new();
+        engine.set_rng(InterruptingRng(engine.interrupt_handle()));
+        assert_eq!(
+            engine.eval("(list (rand) (+ 1 2))"),
+            Err("Evaluation interrupted".to_string())
+        );
+        // The next evaluation starts afresh
+        assert_eq!(engine.eval("(+ 1 2)"), Ok(Expr::int(3)));
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn websocket_session() {
+        use tungstenite::Message;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve_websocket(EngineConfig::default(), listener));
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}", address)).unwrap();
+        socket
+            .send(Message::Text(r#"(eval 1 "(+ 1 2)")"#.to_string()))
+            .unwrap();
+        assert_eq!(
+            socket.read().unwrap(),
+            Message::Text(r#"(1 value "3")"#.to_string())
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Sets, written `#{1 2 3}`, holding each keyword, string or number at most
+//! once and telling whether one is there without walking them all. Like maps
+//! they are values, and nil stands for the empty set wherever a set is
+//! expected.
+
+use std::rc::Rc;
+
+use crate::builtins::type_error;
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, MapKey, Scope, Set};
+use crate::seq::Seq;
+
+fn expect_set(name: &str, position: usize, expr: &Expr) -> Result<Rc<Set>, String> {
+    match expr {
+        Expr::Set(set) => Ok(set.clone()),
+        Expr::Nil => Ok(Rc::new(Set::new())),
+        _ => Err(type_error(name, "a set", position, expr)),
+    }
+}
+
+pub(crate) fn expect_member(name: &str, position: usize, expr: &Expr) -> Result<MapKey, String> {
+    MapKey::new(expr.clone())
+        .ok_or_else(|| type_error(name, "a keyword, string or number", position, expr))
+}
+
+fn builtin_is_set(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Set(_))))
+}
+
+/// `(set coll)` is the set of the elements of a sequence, each once.
+fn builtin_set(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Seq::new("set", 1, &args[0])?
+        .map(|item| expect_member("set", 1, &item))
+        .collect::<Result<_, _>>()
+        .map(Expr::set)
+}
+
+/// `(set-add s x...)` is the set with each `x` in it.
+fn builtin_set_add(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut set = expect_set("set-add", 1, &args[0])?;
+    let members = Rc::make_mut(&mut set);
+    for (i, item) in args.iter().enumerate().skip(1) {
+        members.insert(expect_member("set-add", i + 1, item)?);
+    }
+    Ok(Expr::Set(set))
+}
+
+/// `(set-remove s x...)` is the set without any `x`.
+fn builtin_set_remove(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut set = expect_set("set-remove", 1, &args[0])?;
+    let members = Rc::make_mut(&mut set);
+    for (i, item) in args.iter().enumerate().skip(1) {
+        members.remove(&expect_member("set-remove", i + 1, item)?);
+    }
+    Ok(Expr::Set(set))
+}
+
+fn builtin_set_contains(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let set = expect_set("set-contains?", 1, &args[0])?;
+    let member = expect_member("set-contains?", 2, &args[1])?;
+    Ok(Expr::Bool(set.contains(&member)))
+}
+
+/// `(union s...)` is the set of what is in any of the sets.
+fn builtin_union(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut union = Set::new();
+    for (i, arg) in args.iter().enumerate() {
+        union.extend(expect_set("union", i + 1, arg)?.iter().cloned());
+    }
+    Ok(Expr::set(union))
+}
+
+/// `(intersection s...)` is the set of what is in all of the sets.
+fn builtin_intersection(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut intersection = expect_set("intersection", 1, &args[0])?;
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        let other = expect_set("intersection", i + 1, arg)?;
+        Rc::make_mut(&mut intersection).retain(|member| other.contains(member));
+    }
+    Ok(Expr::Set(intersection))
+}
+
+/// `(difference s t...)` is the set of what is in `s` but none of the others.
+fn builtin_difference(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut difference = expect_set("difference", 1, &args[0])?;
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        let other = expect_set("difference", i + 1, arg)?;
+        Rc::make_mut(&mut difference).retain(|member| !other.contains(member));
+    }
+    Ok(Expr::Set(difference))
+}
+
+#[rustfmt::skip]
+pub(crate) const SET_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("set?",          builtin_is_set,       BuiltinKind::Eager, Arity::Exact(1)),
+    ("set",           builtin_set,          BuiltinKind::Eager, Arity::Exact(1)),
+    ("set-add",       builtin_set_add,      BuiltinKind::Eager, Arity::AtLeast(1)),
+    ("set-remove",    builtin_set_remove,   BuiltinKind::Eager, Arity::AtLeast(1)),
+    ("set-contains?", builtin_set_contains, BuiltinKind::Eager, Arity::Exact(2)),
+    ("union",         builtin_union,        BuiltinKind::Eager, Arity::AtLeast(0)),
+    ("intersection",  builtin_intersection, BuiltinKind::Eager, Arity::AtLeast(1)),
+    ("difference",    builtin_difference,   BuiltinKind::Eager, Arity::AtLeast(1)),
+];
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    use crate::engine::Engine;
+    use crate::parser::parse;
+
+    #[test]
+    fn sets() {
+        let mut engine = Engine::new();
+        engine.eval(r#"(def s #{3 :b "a" 1})"#).unwrap();
+        for (input, expected) in [
+            ("s", r#"#{1 3 :b "a"}"#),
+            ("(quote #{:x})", "#{:x}"),
+            ("(set? s)", "true"),
+            ("(set? {})", "false"),
+            ("(type-of #{})", ":set"),
+            ("(eq? s #{1 3 \"a\" :b})", "true"),
+            ("(set (list 2 1 2 1))", "#{1 2}"),
+            ("(set [:a :a])", "#{:a}"),
+            ("(set nil)", "#{}"),
+            ("(set-add s 2 3)", r#"#{1 2 3 :b "a"}"#),
+            ("s", r#"#{1 3 :b "a"}"#),
+            ("(set-add nil :x)", "#{:x}"),
+            ("(set-remove s 1 :missing)", r#"#{3 :b "a"}"#),
+            ("(set-contains? s :b)", "true"),
+            ("(set-contains? s 1.0)", "false"),
+            ("(union #{1 2} nil #{2 3})", "#{1 2 3}"),
+            ("(union)", "#{}"),
+            ("(intersection #{1 2 3} #{2 3 4} #{3 2})", "#{2 3}"),
+            ("(difference #{1 2 3} #{2} #{3 4})", "#{1}"),
+            ("(count s)", "4"),
+            ("(first #{2 1})", "1"),
+            ("#{1.0 1/2 1 0.5}", "#{1/2 0.5 1 1.0}"),
+            (
+                "(set-contains? [1] 1)",
+                "Error: `set-contains?` expected a set as argument 1, got the vector [1]",
+            ),
+            (
+                "(set-add s [1])",
+                "Error: `set-add` expected a keyword, string or number as argument 2, \
+                 got the vector [1]",
+            ),
+            (
+                "#{x}",
+                "Error: Set members must be keywords, strings or numbers, got x in the set at line 1, column 1",
+            ),
+            (
+                "#{1 1}",
+                "Error: Duplicate member 1 in the set at line 1, column 1",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+
+        let hash = |source: &str| {
+            let mut hasher = DefaultHasher::new();
+            parse(source).unwrap().hash(&mut hasher);
+            hasher.finish()
+        };
+        for _ in 0..20 {
+            assert_eq!(hash("#{1 1.0 :a}"), hash("#{:a 1.0 1}"));
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Specs: quoted data describing the shape of values, checked with
+//! `validate`.
+//!
+//! A spec is either a predicate, such as `number?` or a function, or a map
+//! from keys to specs, `{:x number? :y number?}`, describing a map holding
+//! every key, each value conforming to its spec. A symbol naming another
+//! spec stands for that spec.
+
+use crate::builtins::{define, expect_symbol};
+use crate::interpreter::{call, sorted_entries, Expr, Function, Scope};
+use crate::messages::message;
+use crate::parser::expr_to_string;
+
+/// A place where a value fails its spec: the keys leading to it, and the
+/// failed predicate or `missing` for an absent key.
+pub type Violation = (Vec<Expr>, Expr);
+
+/// Checks `value` against `spec`, resolving symbols in `scope`.
+pub fn validate(spec: &Expr, value: &Expr, scope: &mut Scope) -> Result<Vec<Violation>, String> {
+    let mut violations = Vec::new();
+    check(spec, value, &mut Vec::new(), &mut violations, scope)?;
+    Ok(violations)
+}
+
+fn check(
+    spec: &Expr,
+    value: &Expr,
+    path: &mut Vec<Expr>,
+    violations: &mut Vec<Violation>,
+    scope: &mut Scope,
+) -> Result<(), String> {
+    let resolved = match spec {
+        Expr::Symbol(name) => scope
+            .get_variable(name)
+            .ok_or_else(|| message!("undefined-spec", name = name))?,
+        spec => spec.clone(),
+    };
+
+    match &resolved {
+        Expr::Function(_) | Expr::BuiltinFunction(_) => {
+            if !call(&resolved, std::slice::from_ref(value), scope)?.is_truthy() {
+                // Name predicates passed as values rather than printing them opaquely
+                let predicate = match spec {
+                    Expr::BuiltinFunction(builtin) => Expr::symbol(&builtin.name),
+                    Expr::Function(func) => Expr::symbol(func.name()),
+                    spec => spec.clone(),
+                };
+                violations.push((path.clone(), predicate));
+            }
+        }
+        Expr::Map(fields) => {
+            let Expr::Map(entries) = value else {
+                violations.push((path.clone(), Expr::symbol("map?")));
+                return Ok(());
+            };
+            for (key, field_spec) in sorted_entries(fields) {
+                path.push(key.expr().clone());
+                match entries.get(key) {
+                    Some(field_value) => check(field_spec, field_value, path, violations, scope)?,
+                    None => violations.push((path.clone(), Expr::symbol("missing"))),
+                }
+                path.pop();
+            }
+        }
+        _ => return Err(message!("invalid-spec", spec = expr_to_string(spec))),
+    }
+    Ok(())
+}
+
+/// Checks the shape of a spec given to `defspec`: a symbol, or a map whose
+/// values are specs. Symbols are resolved when values are validated, so
+/// specs may refer to ones defined later.
+fn check_spec(spec: &Expr) -> Result<(), String> {
+    match spec {
+        Expr::Symbol(_) => Ok(()),
+        Expr::Map(fields) => fields.values().try_for_each(check_spec),
+        _ => Err(message!("invalid-spec", spec = expr_to_string(spec))),
+    }
+}
+
+fn violations_to_expr(violations: Vec<Violation>) -> Expr {
+    Expr::List(
+        violations
+            .into_iter()
+            .map(|(path, violation)| Expr::List(vec![Expr::List(path), violation]))
+            .collect(),
+    )
+}
+
+/// Checks the arguments of a function defined with parameter specs, when
+/// instrumentation is enabled.
+pub fn check_arguments(func: &Function, args: &[Expr]) -> Result<(), String> {
+    let mut scope = func.closure.clone();
+    for (i, (spec, arg)) in func.parameter_specs.iter().zip(args).enumerate() {
+        if let Some(spec) = spec {
+            let violations = validate(spec, arg, &mut scope)?;
+            if !violations.is_empty() {
+                return Err(message!(
+                    "nonconforming-argument",
+                    position = i + 1,
+                    name = func.name(),
+                    violations = expr_to_string(&violations_to_expr(violations))
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `(defspec name spec)` binds the spec, unevaluated, to `name`.
+pub fn builtin_defspec(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let name = expect_symbol("defspec", 1, &args[0])?;
+    check_spec(&args[1])?;
+
+    define(name, None, args[1].clone(), scope)
+}
+
+/// `(validate spec value)` returns the empty list if the value conforms, or
+/// a list of `(path violation)` pairs.
+pub fn builtin_validate(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let violations = validate(&args[0], &args[1], scope)?;
+
+    // The empty list doubles as nil
+    Ok(violations_to_expr(violations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig, Features};
+
+    #[test]
+    fn validate_maps() {
+        let mut engine = Engine::new();
+        engine
+            .eval("(defspec point {:x number? :y number?})")
+            .unwrap();
+        engine
+            .eval("(defspec line {:from point :to point})")
+            .unwrap();
+
+        for (input, expected) in [
+            ("(validate point {:x 1 :y 2 :label \"a\"})", "()"),
+            ("(validate point {:x 1 :y :a})", "(((:y) number?))"),
+            (
+                "(validate line {:from {:x 1 :y 2} :to {:x 1}})",
+                "(((:to :y) missing))",
+            ),
+            ("(validate point 5)", "((() map?))"),
+            ("(validate point (quote ((x 1) (y 2))))", "((() map?))"),
+            ("(validate {:n number?} {:n \"1\"})", "(((:n) number?))"),
+            ("(validate number? (quote a))", "((() number?))"),
+            (
+                "(defspec pair ((x number?)))",
+                "Error: Invalid spec ((x number?))",
+            ),
+            ("(defspec bad {:x 1})", "Error: Invalid spec 1"),
+            ("pair", "Error: Undefined symbol 'pair'"),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn instrumented_argument_specs() {
+        let source = "(defn dist ((p point)) (get p :x))";
+
+        let mut engine = Engine::with_config(EngineConfig {
+            features: Features {
+                instrument: true,
+                ..Features::default()
+            },
+            ..EngineConfig::default()
+        });
+        engine.eval("(defspec point {:x number?})").unwrap();
+        engine.eval(source).unwrap();
+        assert_eq!(engine.eval("(dist {:x 3})"), Ok(Expr::int(3)));
+        assert_eq!(
+            engine.eval("(dist {:x :a})"),
+            Err("Argument 1 to `dist` does not conform to its spec: (((:x) number?))".to_string())
+        );
+
+        // Without instrumentation the specs are not checked
+        let mut engine = Engine::new();
+        engine.eval("(defspec point {:x number?})").unwrap();
+        engine.eval(source).unwrap();
+        assert_eq!(engine.eval("(dist {:x :a})"), Ok(Expr::keyword("a")));
+    }
+}
@@ -0,0 +1,184 @@
+//! A small key-value store that outlives the engine, so scripts can keep
+//! state between runs: `store-set!` saves a value under a key and
+//! `store-get` reads it back, `nil` if it was never set.
+//!
+//! Values are saved as EDN text, so only values EDN can write are stored.
+//! Where they end up is up to the engine's `Storage`: a JSON file natively
+//! and `localStorage` in the browser.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde_json::{Map, Value};
+
+use crate::builtins::expect_text;
+use crate::edn::{from_edn_with, to_edn};
+use crate::engine::SandboxPolicy;
+use crate::interpreter::{Expr, Scope};
+use crate::messages::message;
+
+/// Where `store-get` and `store-set!` keep their values, as EDN text.
+pub trait Storage {
+    fn get(&self, key: &str) -> Result<Option<String>, String>;
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String>;
+}
+
+/// Values kept in memory and lost with the engine, for tests.
+#[derive(Debug, Default)]
+pub struct MemoryStorage(HashMap<String, String>);
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.0.get(key).cloned())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.0.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+/// Values kept in a JSON object in a file, which is read on every `get` and
+/// rewritten on every `set` so that other processes see the changes.
+#[derive(Debug)]
+pub struct JsonFileStorage {
+    path: PathBuf,
+}
+
+/// The file `JsonFileStorage::default` keeps its values in, in the working
+/// directory.
+pub const DEFAULT_FILE: &str = "rs-lisp-store.json";
+
+impl JsonFileStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonFileStorage { path: path.into() }
+    }
+
+    fn path(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load(&self) -> Result<Map<String, Value>, String> {
+        let json = match std::fs::read_to_string(&self.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Map::new()),
+            Err(e) => return Err(message!("read-failed", path = self.path(), error = e)),
+        };
+        match serde_json::from_str(&json) {
+            Ok(Value::Object(values)) => Ok(values),
+            _ => Err(message!("store-corrupt", path = self.path())),
+        }
+    }
+}
+
+impl Default for JsonFileStorage {
+    fn default() -> Self {
+        JsonFileStorage::new(DEFAULT_FILE)
+    }
+}
+
+impl Storage for JsonFileStorage {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        match self.load()?.remove(key) {
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(message!("store-corrupt", path = self.path())),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut values = self.load()?;
+        values.insert(key.to_string(), Value::String(value.to_string()));
+        let json = serde_json::to_string_pretty(&values).expect("strings serialize");
+        std::fs::write(&self.path, json)
+            .map_err(|e| message!("write-failed", path = self.path(), error = e))
+    }
+}
+
+/// Storage reaches outside the interpreter everywhere but the browser.
+fn check_sandbox(name: &str, scope: &Scope) -> Result<(), String> {
+    if !cfg!(target_arch = "wasm32") && scope.runtime().config.sandbox == SandboxPolicy::Strict {
+        return Err(message!("sandboxed", name = name));
+    }
+    Ok(())
+}
+
+/// `(store-get key)` is the value last stored under the key, or `nil`.
+pub fn builtin_store_get(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    check_sandbox("store-get", scope)?;
+    let key = expect_text("store-get", 1, &args[0])?;
+    let runtime = scope.runtime();
+    let Some(text) = runtime.storage().get(key)? else {
+        return Ok(Expr::Nil);
+    };
+    let tags = runtime.reader().clone();
+    from_edn_with(&text, &tags)
+}
+
+/// `(store-set! key value)` stores the value under the key, returning it.
+pub fn builtin_store_set(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    check_sandbox("store-set!", scope)?;
+    let key = expect_text("store-set!", 1, &args[0])?;
+    let text = to_edn(&args[1])?;
+    scope.runtime().storage().set(key, &text)?;
+    Ok(args[1].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+
+    #[test]
+    fn store_values() {
+        let mut engine = Engine::new();
+        engine.set_storage(MemoryStorage::default());
+        assert_eq!(engine.eval_to_string("(store-get \"count\")"), "nil");
+        assert_eq!(
+            engine.eval_to_string("(store-set! \"count\" (list 1 \"two\" true))"),
+            "(1 \"two\" true)"
+        );
+        assert_eq!(
+            engine.eval_to_string("(store-get \"count\")"),
+            "(1 \"two\" true)"
+        );
+        assert_eq!(
+            engine.eval_to_string("(store-set! \"f\" (fn (x) x))"),
+            "Error: Cannot write a function as EDN"
+        );
+
+        let mut strict = Engine::with_config(EngineConfig {
+            sandbox: SandboxPolicy::Strict,
+            ..EngineConfig::default()
+        });
+        assert_eq!(
+            strict.eval_to_string("(store-get \"count\")"),
+            "Error: `store-get` is not available in the strict sandbox"
+        );
+    }
+
+    #[test]
+    fn json_file() {
+        let path = std::env::temp_dir().join(format!("rs-lisp-store-{}.json", std::process::id()));
+        let mut storage = JsonFileStorage::new(&path);
+        assert_eq!(storage.get("a"), Ok(None));
+        storage.set("a", "1").unwrap();
+        storage.set("b", "\"two\"").unwrap();
+        // A second storage on the same file sees the values
+        assert_eq!(
+            JsonFileStorage::new(&path).get("b"),
+            Ok(Some("\"two\"".to_string()))
+        );
+
+        std::fs::write(&path, "[1]").unwrap();
+        let corrupt = storage.get("a");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            corrupt,
+            Err(format!(
+                "'{}' is not a JSON object of stored values",
+                path.display()
+            ))
+        );
+    }
+}
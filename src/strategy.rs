@@ -0,0 +1,108 @@
+//! Proptest strategies for generating random expressions, available with the
+//! `proptest` feature so embedders can fuzz their own builtins.
+
+use num_rational::BigRational;
+use proptest::prelude::*;
+
+use crate::builtins::builtin_names;
+use crate::interpreter::Expr;
+use crate::parser::read_number;
+
+/// Symbols that print and parse back as themselves, so never anything the
+/// reader would take for a number such as `-1` or `inf`.
+pub fn arb_symbol() -> impl Strategy<Value = String> {
+    "[a-z+*/<>=!?-][a-z0-9+*/<>=!?-]{0,8}"
+        .prop_filter("must not read as a number", |s| read_number(s).is_none())
+}
+
+/// Finite numbers, which print and parse back to the same value.
+pub fn arb_number() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        (-1000i32..1000).prop_map(f64::from),
+        any::<f64>().prop_filter("must be finite", |n| n.is_finite()),
+    ]
+}
+
+/// Strings of any characters, quotes and backslashes included.
+pub fn arb_string() -> impl Strategy<Value = String> {
+    "\\PC{0,12}"
+}
+
+/// Plain data: nil, integers, ratios, floats, booleans, keywords, symbols, strings,
+/// characters, and nested lists of them.
+pub fn arb_expr() -> impl Strategy<Value = Expr> {
+    let leaf = prop_oneof![
+        any::<i64>().prop_map(Expr::Int),
+        any::<i128>().prop_map(|n| Expr::integer(n.into())),
+        (any::<i64>(), 1..1000i64)
+            .prop_map(|(n, d)| { Expr::rational(BigRational::new(n.into(), d.into())) }),
+        arb_number().prop_map(Expr::Number),
+        Just(Expr::Nil),
+        any::<bool>().prop_map(Expr::Bool),
+        arb_symbol().prop_map(Expr::Keyword),
+        arb_symbol().prop_map(Expr::Symbol),
+        arb_string().prop_map(Expr::String),
+        any::<char>().prop_map(Expr::Char),
+    ];
+    leaf.prop_recursive(4, 32, 6, |inner| {
+        prop::collection::vec(inner, 0..6).prop_map(Expr::List)
+    })
+}
+
+/// Expressions shaped like code: lists that usually start with a builtin
+/// name, so that evaluating them exercises the builtins.
+pub fn arb_form() -> impl Strategy<Value = Expr> {
+    let names: Vec<&'static str> = builtin_names().collect();
+    let leaf = prop_oneof![
+        (-10i64..10).prop_map(Expr::Int),
+        prop::sample::select(names.clone()).prop_map(Expr::symbol),
+        prop::sample::select(&["x", "y", "f"][..]).prop_map(Expr::symbol),
+    ];
+    leaf.prop_recursive(4, 32, 5, move |inner| {
+        (
+            prop::sample::select(names.clone()),
+            prop::collection::vec(inner, 0..4),
+        )
+            .prop_map(|(head, mut args)| {
+                args.insert(0, Expr::symbol(head));
+                Expr::List(args)
+            })
+    })
+}
+
+impl Arbitrary for Expr {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Expr>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        arb_expr().boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig, SandboxPolicy};
+    use crate::parser::{expr_to_string, parse};
+
+    proptest! {
+        #[test]
+        fn print_parse_round_trip(expr in any::<Expr>()) {
+            prop_assert_eq!(parse(&expr_to_string(&expr)), Ok(expr));
+        }
+
+        #[test]
+        fn eval_never_panics(forms in prop::collection::vec(arb_form(), 1..4)) {
+            let mut engine = Engine::with_config(EngineConfig {
+                fuel_limit: Some(10_000),
+                recursion_limit: 64,
+                // Random programs must not write files
+                sandbox: SandboxPolicy::Strict,
+                ..EngineConfig::default()
+            });
+            for form in forms {
+                let _ = engine.eval(&expr_to_string(&form));
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Taking strings apart and putting them together.
+//!
+//! Positions in strings count characters, not bytes, as `str-len` does.
+
+use crate::builtins::{expect_list, expect_number, expect_string};
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+use crate::parser::{expr_to_string, read_number};
+
+/// Checks that the argument at the 1-based `position` of builtin `name` is an
+/// integer from 0 to `max`.
+fn expect_index(name: &str, position: usize, expr: &Expr, max: usize) -> Result<usize, String> {
+    match expr {
+        Expr::Int(n) => usize::try_from(*n).ok().filter(|n| *n <= max),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        message!(
+            "wrong-type",
+            name = name,
+            expected = format!("an index from 0 to {}", max),
+            position = position,
+            actual = expr.describe()
+        )
+    })
+}
+
+/// `(substring s start)` is the string from character `start` on, and
+/// `(substring s start end)` up to but not including character `end`.
+fn builtin_substring(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("substring", 1, &args[0])?;
+    let length = s.chars().count();
+    let start = expect_index("substring", 2, &args[1], length)?;
+    let end = match args.get(2) {
+        Some(end) => expect_index("substring", 3, end, length)?,
+        None => length,
+    };
+    if end < start {
+        return Err(message!(
+            "wrong-type",
+            name = "substring",
+            expected = format!("an index from {} to {}", start, length),
+            position = 3,
+            actual = args[2].describe()
+        ));
+    }
+    Ok(Expr::String(
+        s.chars().skip(start).take(end - start).collect(),
+    ))
+}
+
+/// `(string-split s separator)` is the list of the parts of the string
+/// between separators. An empty separator splits it into its characters,
+/// each as a string.
+fn builtin_string_split(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("string-split", 1, &args[0])?;
+    let separator = expect_string("string-split", 2, &args[1])?;
+    let parts = if separator.is_empty() {
+        s.chars().map(|c| Expr::String(c.to_string())).collect()
+    } else {
+        s.split(separator).map(Expr::string).collect()
+    };
+    Ok(Expr::List(parts))
+}
+
+/// `(string-join strings)` joins a list of strings, and
+/// `(string-join strings separator)` puts the separator between them.
+fn builtin_string_join(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let strings = match &args[0] {
+        Expr::Nil => &[],
+        list => expect_list("string-join", 1, list)?,
+    };
+    let separator = match args.get(1) {
+        Some(separator) => expect_string("string-join", 2, separator)?,
+        None => "",
+    };
+    let parts = strings
+        .iter()
+        .map(|item| match item {
+            Expr::String(s) => Ok(s.as_str()),
+            _ => Err(message!(
+                "wrong-type",
+                name = "string-join",
+                expected = "a list of strings",
+                position = 1,
+                actual = args[0].describe()
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Expr::String(parts.join(separator)))
+}
+
+fn builtin_string_upcase(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::String(
+        expect_string("string-upcase", 1, &args[0])?.to_uppercase(),
+    ))
+}
+
+fn builtin_string_downcase(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::String(
+        expect_string("string-downcase", 1, &args[0])?.to_lowercase(),
+    ))
+}
+
+/// `(string-trim s)` is the string without whitespace at either end.
+fn builtin_string_trim(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::string(
+        expect_string("string-trim", 1, &args[0])?.trim(),
+    ))
+}
+
+/// `(string-contains? s part)` is whether `part` occurs in the string.
+fn builtin_string_contains(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("string-contains?", 1, &args[0])?;
+    let part = expect_string("string-contains?", 2, &args[1])?;
+    Ok(Expr::Bool(s.contains(part)))
+}
+
+/// `(string-replace s from to)` replaces every occurrence of `from` in the
+/// string with `to`. `from` must not be empty.
+fn builtin_string_replace(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("string-replace", 1, &args[0])?;
+    let from = expect_string("string-replace", 2, &args[1])?;
+    let to = expect_string("string-replace", 3, &args[2])?;
+    if from.is_empty() {
+        return Err(message!(
+            "wrong-type",
+            name = "string-replace",
+            expected = "a non-empty string",
+            position = 2,
+            actual = args[1].describe()
+        ));
+    }
+    Ok(Expr::String(s.replace(from, to)))
+}
+
+/// `(string->number s)` reads a number written as in source code, or is nil
+/// if the string is not one.
+fn builtin_string_to_number(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let s = expect_string("string->number", 1, &args[0])?;
+    Ok(match read_number(s.trim()) {
+        Some(Ok(n)) => n,
+        _ => Expr::Nil,
+    })
+}
+
+/// `(number->string n)` writes a number as it prints, so that
+/// `string->number` reads it back.
+fn builtin_number_to_string(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    expect_number("number->string", 1, &args[0])?;
+    Ok(Expr::String(expr_to_string(&args[0])))
+}
+
+#[rustfmt::skip]
+pub(crate) const STRING_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("substring",        builtin_substring,         BuiltinKind::Eager, Arity::Range(2, 3)),
+    ("string-split",     builtin_string_split,      BuiltinKind::Eager, Arity::Exact(2)),
+    ("string-join",      builtin_string_join,       BuiltinKind::Eager, Arity::Range(1, 2)),
+    ("string-upcase",    builtin_string_upcase,     BuiltinKind::Eager, Arity::Exact(1)),
+    ("string-downcase",  builtin_string_downcase,   BuiltinKind::Eager, Arity::Exact(1)),
+    ("string-trim",      builtin_string_trim,       BuiltinKind::Eager, Arity::Exact(1)),
+    ("string-contains?", builtin_string_contains,   BuiltinKind::Eager, Arity::Exact(2)),
+    ("string-replace",   builtin_string_replace,    BuiltinKind::Eager, Arity::Exact(3)),
+    ("string->number",   builtin_string_to_number,  BuiltinKind::Eager, Arity::Exact(1)),
+    ("number->string",   builtin_number_to_string,  BuiltinKind::Eager, Arity::Exact(1)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn strings() {
+        let mut engine = Engine::new();
+        for (input, expected) in [
+            (r#"(substring "héllo" 1 3)"#, r#""él""#),
+            (r#"(substring "hello" 5)"#, r#""""#),
+            (r#"(string-split "a,b,,c" ",")"#, r#"("a" "b" "" "c")"#),
+            (r#"(string-split "ab" "")"#, r#"("a" "b")"#),
+            (r#"(string-join (list "a" "b" "c") ", ")"#, r#""a, b, c""#),
+            ("(string-join nil)", r#""""#),
+            (r#"(string-upcase "straße")"#, r#""STRASSE""#),
+            (r#"(string-downcase "ÀB")"#, r#""àb""#),
+            (r#"(string-trim "  a b \n")"#, r#""a b""#),
+            (
+                r#"(list (string-contains? "hello" "ell") (string-contains? "hello" "le"))"#,
+                "(true false)",
+            ),
+            (r#"(string-replace "a-b-c" "-" "+")"#, r#""a+b+c""#),
+            (
+                r#"(list (string->number "42") (string->number " 1/2 ") (string->number "0x1f") (string->number "2.5e1"))"#,
+                "(42 1/2 31 25.0)",
+            ),
+            (r#"(string->number "forty")"#, "nil"),
+            (r#"(number->string 1.5)"#, r#""1.5""#),
+            (
+                "(string->number (number->string 123456789012345678901234567890))",
+                "123456789012345678901234567890",
+            ),
+            (
+                r#"(substring "abc" 2 1)"#,
+                "Error: `substring` expected an index from 2 to 3 as argument 3, got the integer 1",
+            ),
+            (
+                r#"(substring "abc" 4)"#,
+                "Error: `substring` expected an index from 0 to 3 as argument 2, got the integer 4",
+            ),
+            (
+                r#"(string-join (list "a" 1))"#,
+                r#"Error: `string-join` expected a list of strings as argument 1, got the list ("a" 1)"#,
+            ),
+            (
+                r#"(number->string "1")"#,
+                r#"Error: `number->string` expected a number as argument 1, got the string "1""#,
+            ),
+            (
+                r#"(string-split "abc")"#,
+                "Error: wrong number of args (1) passed to: string-split (expects 2)",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
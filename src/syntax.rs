@@ -0,0 +1,330 @@
+//! A lossless view of source text for tooling such as formatters, which keeps
+//! the comments and whitespace that `parse` throws away.
+
+use crate::interpreter::Expr;
+use crate::messages::message;
+use crate::parser::{expr_to_string, parse, Lexer, ReaderConfig, Span, DEFAULT_MAX_DEPTH};
+
+/// Text between tokens that means nothing to the reader.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    /// From the `;` up to the end of the line.
+    Comment(String),
+}
+
+/// What a list is written between: `(...)` for a list, `[...]` for a
+/// vector, `{...}` for a map and `#{...}` for a set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Brackets {
+    Round,
+    Square,
+    Curly,
+    HashCurly,
+}
+
+impl Brackets {
+    fn open(self) -> &'static str {
+        match self {
+            Brackets::Round => "(",
+            Brackets::Square => "[",
+            Brackets::Curly => "{",
+            Brackets::HashCurly => "#{",
+        }
+    }
+
+    fn close(self) -> char {
+        match self {
+            Brackets::Round => ')',
+            Brackets::Square => ']',
+            Brackets::Curly | Brackets::HashCurly => '}',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    /// A number or symbol, exactly as written.
+    Atom(String),
+    List {
+        brackets: Brackets,
+        items: Vec<Node>,
+        /// The trivia before the closing paren.
+        closing: Vec<Trivia>,
+    },
+}
+
+/// A form along with the trivia that comes before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub leading: Vec<Trivia>,
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+/// Every form in some source, printing back to exactly that source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub forms: Vec<Node>,
+    /// The trivia after the last form.
+    pub trailing: Vec<Trivia>,
+}
+
+fn write_trivia(trivia: &[Trivia], out: &mut String) {
+    for piece in trivia {
+        match piece {
+            Trivia::Whitespace(text) | Trivia::Comment(text) => out.push_str(text),
+        }
+    }
+}
+
+impl Node {
+    fn write_form(&self, out: &mut String) {
+        match &self.kind {
+            NodeKind::Atom(text) => out.push_str(text),
+            NodeKind::List {
+                brackets,
+                items,
+                closing,
+            } => {
+                out.push_str(brackets.open());
+                for item in items {
+                    write_trivia(&item.leading, out);
+                    item.write_form(out);
+                }
+                write_trivia(closing, out);
+                out.push(brackets.close());
+            }
+        }
+    }
+
+    /// The form as the reader would see it, without any trivia.
+    pub fn to_expr(&self) -> Result<Expr, String> {
+        let mut source = String::new();
+        self.write_form(&mut source);
+        parse(&source)
+    }
+}
+
+impl SyntaxTree {
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        for form in &self.forms {
+            write_trivia(&form.leading, &mut source);
+            form.write_form(&mut source);
+        }
+        write_trivia(&self.trailing, &mut source);
+        source
+    }
+}
+
+/// Reads every form in the input, keeping the trivia around them. Atoms are
+/// kept as written, so only the nesting of lists is checked.
+pub fn parse_syntax(input: &str) -> Result<SyntaxTree, String> {
+    let reader = ReaderConfig::default();
+    let mut lexer = Lexer::new(input, &reader);
+    let mut forms = Vec::new();
+    // The lists still open, each with the trivia before it
+    let mut open: Vec<(Vec<Trivia>, Span, Brackets, Vec<Node>)> = Vec::new();
+
+    loop {
+        let mut leading = Vec::new();
+        lexer.skip_trivia(Some(&mut leading));
+        let token = match lexer.next_token() {
+            Some(token) => token?,
+            None if open.is_empty() => {
+                return Ok(SyntaxTree {
+                    forms,
+                    trailing: leading,
+                })
+            }
+            None => return Err(message!("unexpected-end")),
+        };
+
+        let node = match token.text.as_ref() {
+            "(" | "[" | "{" | "#{" if !token.quoted => {
+                if open.len() >= DEFAULT_MAX_DEPTH {
+                    return Err(message!(
+                        "nested-too-deep",
+                        depth = DEFAULT_MAX_DEPTH,
+                        span = token.span
+                    ));
+                }
+                let brackets = match token.text.as_ref() {
+                    "(" => Brackets::Round,
+                    "[" => Brackets::Square,
+                    "{" => Brackets::Curly,
+                    _ => Brackets::HashCurly,
+                };
+                open.push((leading, token.span, brackets, Vec::new()));
+                continue;
+            }
+            ")" | "]" | "}" if !token.quoted => match open.pop() {
+                Some((list_leading, start, brackets, items))
+                    if token.text.starts_with(brackets.close()) =>
+                {
+                    Node {
+                        leading: list_leading,
+                        kind: NodeKind::List {
+                            brackets,
+                            items,
+                            closing: leading,
+                        },
+                        span: Span {
+                            end: token.span.end,
+                            ..start
+                        },
+                    }
+                }
+                _ => {
+                    return Err(message!(
+                        "unexpected-character",
+                        character = token.text,
+                        span = token.span
+                    ))
+                }
+            },
+            _ => Node {
+                leading,
+                kind: NodeKind::Atom(input[token.span.start..token.span.end].to_string()),
+                span: token.span,
+            },
+        };
+
+        match open.last_mut() {
+            Some((_, _, _, items)) => items.push(node),
+            None => forms.push(node),
+        }
+    }
+}
+
+/// A replacement of part of some source text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+/// Applies edits to the source, which must not overlap.
+pub fn apply_edits(source: &str, edits: &[Edit]) -> String {
+    let mut edits: Vec<&Edit> = edits.iter().collect();
+    edits.sort_by_key(|edit| edit.span.start);
+
+    let mut output = String::with_capacity(source.len());
+    let mut copied = 0;
+    for edit in edits {
+        output.push_str(&source[copied..edit.span.start]);
+        output.push_str(&edit.text);
+        copied = edit.span.end;
+    }
+    output.push_str(&source[copied..]);
+    output
+}
+
+/// Offers every form in the source to the callback, outermost first, and
+/// returns an edit printing the replacement for each one it replaces. The
+/// forms inside a replaced one are not offered.
+pub fn source_edits(
+    source: &str,
+    mut replace: impl FnMut(&Expr) -> Option<Expr>,
+) -> Result<Vec<Edit>, String> {
+    let tree = parse_syntax(source)?;
+    let mut edits = Vec::new();
+    let mut pending: Vec<&Node> = tree.forms.iter().rev().collect();
+    while let Some(node) = pending.pop() {
+        match replace(&node.to_expr()?) {
+            Some(expr) => edits.push(Edit {
+                span: node.span,
+                text: expr_to_string(&expr),
+            }),
+            None => {
+                if let NodeKind::List { items, .. } = &node.kind {
+                    pending.extend(items.iter().rev());
+                }
+            }
+        }
+    }
+    Ok(edits)
+}
+
+/// Rewrites the forms the callback replaces, leaving the rest of the source,
+/// comments and layout included, as it was.
+pub fn rewrite_source(
+    source: &str,
+    replace: impl FnMut(&Expr) -> Option<Expr>,
+) -> Result<String, String> {
+    let edits = source_edits(source, replace)?;
+    Ok(apply_edits(source, &edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let source = "; Adds one\n(def inc (fn (x) ; the argument\n  (+ x 1)))\n\n|a b| 2 ; done\n";
+        let tree = parse_syntax(source).unwrap();
+        assert_eq!(tree.to_source(), source);
+
+        assert_eq!(tree.forms.len(), 3);
+        assert_eq!(
+            tree.forms[0].leading,
+            vec![
+                Trivia::Comment("; Adds one".to_string()),
+                Trivia::Whitespace("\n".to_string())
+            ]
+        );
+        assert_eq!(tree.forms[0].to_expr(), parse("(def inc (fn (x) (+ x 1)))"));
+        assert_eq!(tree.forms[1].kind, NodeKind::Atom("|a b|".to_string()));
+        assert_eq!(
+            tree.trailing,
+            vec![
+                Trivia::Whitespace(" ".to_string()),
+                Trivia::Comment("; done".to_string()),
+                Trivia::Whitespace("\n".to_string())
+            ]
+        );
+
+        assert_eq!(
+            parse_syntax("(a ; (b\n"),
+            Err("Unexpected end of input".to_string())
+        );
+        assert_eq!(parse("(list 1;two\n 2)"), parse("(list 1 2)"));
+
+        let source = "(def v [1 ; one\n  (f [] {:a 1})])";
+        let tree = parse_syntax(source).unwrap();
+        assert_eq!(tree.to_source(), source);
+        assert_eq!(tree.forms[0].to_expr(), parse("(def v [1 (f [] {:a 1})])"));
+        assert_eq!(
+            parse_syntax("[1 2)"),
+            Err("Unexpected ')' at line 1, column 5".to_string())
+        );
+    }
+
+    #[test]
+    fn rewrite_source_in_place() {
+        let source = "; Doubles\n(defn twice (x)\n  (+ x x))  ; keep\n(twice 2)\n";
+        let rewritten = rewrite_source(source, |expr| match expr {
+            Expr::Symbol(name) if name == "twice" => Some(Expr::symbol("double")),
+            Expr::Int(n) => Some(Expr::int(n * 10)),
+            _ => None,
+        });
+        assert_eq!(
+            rewritten,
+            Ok("; Doubles\n(defn double (x)\n  (+ x x))  ; keep\n(double 20)\n".to_string())
+        );
+
+        // Replacing a form leaves the forms inside it alone
+        let mut offered = Vec::new();
+        let rewritten = rewrite_source("(a (b c) d)", |expr| {
+            offered.push(expr_to_string(expr));
+            match expr {
+                Expr::List(list) if list.len() == 2 => Some(Expr::symbol("bc")),
+                _ => None,
+            }
+        });
+        assert_eq!(rewritten, Ok("(a bc d)".to_string()));
+        assert_eq!(offered, vec!["(a (b c) d)", "a", "(b c)", "d"]);
+    }
+}
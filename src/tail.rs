@@ -0,0 +1,228 @@
+//! Finding the calls a function makes to itself, and which of them are in
+//! tail position, where the caller's frame is no longer needed.
+//!
+//! A call is in tail position when its value is the function's value as it
+//! is: the body itself, a branch of `if` or `cond`, the body of `let`, or
+//! the last form of `progn`, `and` or `or` in tail position. Functions
+//! written inside the body make calls of their own and are not looked into.
+
+use crate::builtins::type_error;
+use crate::interpreter::{sorted_entries, Expr, Function, Scope};
+use crate::messages::message;
+use crate::parser::expr_to_string;
+
+/// Each call to `name` in `body`, in the order they appear, with whether it
+/// is in tail position.
+pub fn self_calls<'a>(name: &str, parameters: &[String], body: &'a Expr) -> Vec<(&'a Expr, bool)> {
+    let mut calls = Vec::new();
+    if !parameters.iter().any(|parameter| parameter == name) {
+        walk(name, body, true, &mut calls);
+    }
+    calls
+}
+
+fn walk<'a>(name: &str, expr: &'a Expr, tail: bool, calls: &mut Vec<(&'a Expr, bool)>) {
+    let list = match expr {
+        Expr::List(list) => list,
+        Expr::Vector(items) => {
+            for item in items.iter() {
+                walk(name, item, false, calls);
+            }
+            return;
+        }
+        Expr::Map(map) => {
+            for (_, value) in sorted_entries(map) {
+                walk(name, value, false, calls);
+            }
+            return;
+        }
+        _ => return,
+    };
+    let Some(Expr::Symbol(head)) = list.first() else {
+        for item in list {
+            walk(name, item, false, calls);
+        }
+        return;
+    };
+    let args = &list[1..];
+
+    match head.as_str() {
+        "quote" | "fn" | "lambda" | "defn" => {}
+        "if" => {
+            for (i, arg) in args.iter().enumerate() {
+                walk(name, arg, tail && i > 0, calls);
+            }
+        }
+        "cond" => {
+            for clause in args {
+                match clause {
+                    Expr::List(clause) if clause.len() == 2 => {
+                        walk(name, &clause[0], false, calls);
+                        walk(name, &clause[1], tail, calls);
+                    }
+                    _ => walk(name, clause, false, calls),
+                }
+            }
+        }
+        "let" => {
+            let mut shadowed = false;
+            if let Some(Expr::List(bindings)) = args.first() {
+                for binding in bindings {
+                    if let Expr::List(binding) = binding {
+                        shadowed |= matches!(binding.first(), Some(Expr::Symbol(s)) if s == name);
+                        for value in &binding[1..] {
+                            walk(name, value, false, calls);
+                        }
+                    }
+                }
+            }
+            if !shadowed {
+                for body in args.iter().skip(1) {
+                    walk(name, body, tail, calls);
+                }
+            }
+        }
+        "progn" | "and" | "or" => {
+            for (i, arg) in args.iter().enumerate() {
+                walk(name, arg, tail && i + 1 == args.len(), calls);
+            }
+        }
+        _ => {
+            if head == name {
+                calls.push((expr, tail));
+            }
+            for arg in args {
+                walk(name, arg, false, calls);
+            }
+        }
+    }
+}
+
+/// Whether a function of this name is meant as a loop, like `loop` or
+/// `sum-iter`.
+fn is_loop_name(name: &str) -> bool {
+    name.split('-').any(|part| part == "loop" || part == "iter")
+}
+
+/// Warns about each call a function named like a loop makes to itself
+/// outside tail position, since each of those keeps a frame alive and a long
+/// enough loop runs out of stack.
+pub(crate) fn check_loop(name: &str, value: &Expr, scope: &Scope) {
+    let Expr::Function(function) = value else {
+        return;
+    };
+    if !is_loop_name(name) {
+        return;
+    }
+    for (call, tail) in self_calls(name, &function.parameters, &function.body) {
+        if !tail {
+            scope.runtime().warn(message!(
+                "non-tail-self-call",
+                name = name,
+                form = expr_to_string(call)
+            ));
+        }
+    }
+}
+
+fn is_tail_recursive(name: &str, function: &Function) -> bool {
+    let calls = self_calls(name, &function.parameters, &function.body);
+    !calls.is_empty() && calls.iter().all(|(_, tail)| *tail)
+}
+
+/// `(tail-recursive? (quote f))` is whether the function calls itself, and only
+/// ever in tail position.
+pub fn builtin_is_tail_recursive(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let value = match &args[0] {
+        Expr::Symbol(name) => scope
+            .get_variable(name)
+            .ok_or_else(|| message!("undefined-symbol", name = name))?,
+        value => value.clone(),
+    };
+    match &value {
+        Expr::Function(function) => {
+            let name = match (&args[0], &function.name) {
+                (Expr::Symbol(name), _) | (_, Some(name)) => name,
+                _ => return Ok(Expr::Bool(false)),
+            };
+            Ok(Expr::Bool(is_tail_recursive(name, function)))
+        }
+        _ => Err(type_error("tail-recursive?", "a function", 1, &value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::parser::parse;
+
+    fn non_tail(body: &str) -> Vec<String> {
+        let body = parse(body).unwrap();
+        self_calls("f", &[], &body)
+            .into_iter()
+            .filter(|(_, tail)| !tail)
+            .map(|(call, _)| expr_to_string(call))
+            .collect()
+    }
+
+    #[test]
+    fn tail_positions() {
+        for (body, expected) in [
+            ("(if (< n 1) acc (f (- n 1) (* acc n)))", vec![]),
+            (
+                "(cond ((< n 1) 0) (true (let ((m (- n 1))) (f m))))",
+                vec![],
+            ),
+            ("(if (< n 1) 1 (* n (f (- n 1))))", vec!["(f (- n 1))"]),
+            ("(if (f 1) 1 2)", vec!["(f 1)"]),
+            ("(and (f 1) (or false (f 2)))", vec!["(f 1)"]),
+            ("(f (f 1))", vec!["(f 1)"]),
+            ("(if true [(f 1)] 2)", vec!["(f 1)"]),
+            // Shadowed names and nested functions make other calls
+            ("(+ 1 (let ((f g)) (f 1)))", vec![]),
+            ("(list (fn (x) (f x)) (quote (f 1)))", vec![]),
+        ] {
+            assert_eq!(non_tail(body), expected, "{}", body);
+        }
+    }
+
+    #[test]
+    fn lint_and_predicate() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval_to_string(
+                "(defn sum-loop (xs) (if (empty? xs) 0 (+ (first xs) (sum-loop (rest xs)))))"
+            ),
+            "Warning: `sum-loop` calls itself outside tail position in (sum-loop (rest xs)), \
+             so each call keeps a frame on the stack\n<function sum-loop>"
+        );
+        assert_eq!(
+            engine.eval_to_string("(defn fact (n) (if (< n 2) 1 (* n (fact (- n 1)))))"),
+            "<function fact>"
+        );
+        assert_eq!(
+            engine.eval_to_string(
+                "(def count-iter (fn (n acc) (if (< n 1) acc (count-iter (- n 1) (+ acc 1)))))"
+            ),
+            "<function count-iter>"
+        );
+        for (input, expected) in [
+            ("(tail-recursive? (quote count-iter))", "true"),
+            ("(tail-recursive? count-iter)", "true"),
+            ("(tail-recursive? (quote fact))", "false"),
+            ("(tail-recursive? (quote sum-loop))", "false"),
+            ("(tail-recursive? (fn (x) x))", "false"),
+            (
+                "(tail-recursive? (quote first))",
+                "Error: `tail-recursive?` expected a function as argument 1, got a builtin function",
+            ),
+            (
+                "(tail-recursive? (quote nothing))",
+                "Error: Undefined symbol 'nothing'",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
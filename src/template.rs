@@ -0,0 +1,132 @@
+//! Text templates with embedded expressions: everything outside `{{ ... }}`
+//! is copied as is, and each embedded expression is replaced by its value,
+//! strings by their text and anything else as it prints.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::engine::Engine;
+use crate::interpreter::{Expr, MapKey};
+use crate::parser::expr_to_string;
+
+/// Renders a template, evaluating each embedded expression against `data`
+/// layered over the engine's global scope.
+pub fn render(
+    engine: &mut Engine,
+    template: &str,
+    data: &HashMap<String, Expr>,
+) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find("{{") {
+        output.push_str(&rest[..open]);
+        let line = line_of(template, rest, open);
+        let after_open = &rest[open + 2..];
+        let Some(close) = after_open.find("}}") else {
+            return Err(format!("Unclosed '{{{{' on line {}", line));
+        };
+
+        let value = engine
+            .eval_with_context(&after_open[..close], data.clone())
+            .map_err(|e| format!("{} in template on line {}", e, line))?;
+        match value {
+            Expr::String(text) => output.push_str(&text),
+            Expr::Char(c) => output.push(c),
+            value => output.push_str(&expr_to_string(&value)),
+        }
+        rest = &after_open[close + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// The 1-based line of `offset` into `rest`, which is a suffix of `template`.
+fn line_of(template: &str, rest: &str, offset: usize) -> usize {
+    let position = template.len() - rest.len() + offset;
+    template[..position].matches('\n').count() + 1
+}
+
+/// Reads template data from a JSON object, one binding per key.
+pub fn data_from_json(json: &str) -> Result<HashMap<String, Expr>, String> {
+    match serde_json::from_str(json).map_err(|e| format!("Invalid JSON: {}", e))? {
+        Value::Object(object) => Ok(object
+            .into_iter()
+            .map(|(key, value)| (key, expr_from_json(value)))
+            .collect()),
+        _ => Err("Template data must be a JSON object".to_string()),
+    }
+}
+
+/// Converts JSON to the nearest lisp value; objects become maps with keyword
+/// keys.
+fn expr_from_json(value: Value) -> Expr {
+    match value {
+        Value::Null => Expr::Nil,
+        Value::Bool(b) => Expr::Bool(b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => Expr::Int(n),
+            (_, Some(n)) => Expr::integer(n.into()),
+            _ => Expr::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => Expr::String(s),
+        Value::Array(items) => Expr::List(items.into_iter().map(expr_from_json).collect()),
+        Value::Object(object) => Expr::map(
+            object
+                .into_iter()
+                .map(|(key, value)| (MapKey::keyword(key), expr_from_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template() {
+        let mut engine = Engine::new();
+        let data = data_from_json(r#"{"name": "the world", "scores": [1, 2, 3]}"#).unwrap();
+
+        assert_eq!(
+            render(
+                &mut engine,
+                "Hello {{ name }}!\nTotal: {{(apply + scores)}}",
+                &data
+            ),
+            Ok("Hello the world!\nTotal: 6".to_string())
+        );
+        assert_eq!(
+            render(&mut engine, "a\n{{ (vec name) }}", &data),
+            Err(
+                "`vec` expected a list or vector as argument 1, got the string \"the world\" in template on line 2"
+                    .to_string()
+            )
+        );
+
+        let data = data_from_json(
+            r#"{"user": {"name": "Ada", "address": {"city": "London"}, "langs": [{"name": "lisp"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            render(
+                &mut engine,
+                "{{ (get user :name) }} of {{ (get (get user :address) :city) }} writes \
+                 {{ (get (first (get user :langs)) :name) }}; {{ (get user :address) }}",
+                &data
+            ),
+            Ok("Ada of London writes lisp; {:city \"London\"}".to_string())
+        );
+        assert_eq!(
+            render(&mut engine, "{{ name", &data),
+            Err("Unclosed '{{' on line 1".to_string())
+        );
+        assert_eq!(
+            data_from_json("[1]"),
+            Err("Template data must be a JSON object".to_string())
+        );
+    }
+}
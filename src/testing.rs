@@ -0,0 +1,556 @@
+//! Tests written in Lisp.
+//!
+//! `(deftest name body...)` registers a test, which passes when its body
+//! runs without an error, such as from a failed `assert`. Tests may be
+//! tagged, `(deftest name :tags (slow integration) body...)`, and selected
+//! by tag or by name when they are run, as `rs_lisp test` does.
+//!
+//! `(use-fixture :each setup teardown)` calls `setup` before each test and
+//! `teardown` after it, even when the test fails; `:once` does so around
+//! all the tests that run. The teardown may be left out.
+//!
+//! `(defbench name body...)` registers a benchmark, tagged like a test,
+//! whose body `rs_lisp bench` runs over and over to time it. Benchmarks run
+//! inside the `:once` fixtures but not the `:each` ones, which would be
+//! timed along with them.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Number, Value};
+
+use crate::builtins::{builtin_progn, expect_symbol, type_error};
+use crate::interpreter::{call, Expr, Scope};
+
+/// How many times a benchmark runs at most, however fast it is.
+const MAX_ITERATIONS: u64 = 1_000_000;
+
+/// A test registered with `deftest`, or a benchmark with `defbench`.
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub tags: Vec<String>,
+    body: Vec<Expr>,
+    /// Where the test was defined, which its body sees.
+    scope: Scope,
+}
+
+/// Functions called around tests, registered with `use-fixture`.
+#[derive(Debug, Clone)]
+struct Fixture {
+    setup: Expr,
+    teardown: Option<Expr>,
+}
+
+/// The tests and fixtures registered so far.
+#[derive(Debug, Clone, Default)]
+pub struct TestSuite {
+    tests: Vec<TestCase>,
+    benches: Vec<TestCase>,
+    each: Vec<Fixture>,
+    once: Vec<Fixture>,
+}
+
+impl TestSuite {
+    pub fn tests(&self) -> &[TestCase] {
+        &self.tests
+    }
+
+    pub fn benches(&self) -> &[TestCase] {
+        &self.benches
+    }
+}
+
+/// Which tests to run: those with any of the tags, if any are given, whose
+/// names match the pattern, if one is given. `*` in the pattern matches any
+/// run of characters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestFilter {
+    pub tags: Vec<String>,
+    pub name: Option<String>,
+}
+
+impl TestFilter {
+    pub fn matches(&self, test: &TestCase) -> bool {
+        (self.tags.is_empty() || self.tags.iter().any(|tag| test.tags.contains(tag)))
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|pattern| glob_match(pattern, &test.name))
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of
+/// characters and everything else only itself.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            name.char_indices()
+                .map(|(i, _)| i)
+                .chain([name.len()])
+                .any(|i| glob_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// How a test went: `error` is why it failed, or `None` if it passed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub error: Option<String>,
+}
+
+/// Calls the setups in order, and if they all succeed runs `body`. The
+/// teardowns of the setups that ran are called in reverse order whatever
+/// happens. The first error wins.
+fn with_fixtures(
+    fixtures: &[Fixture],
+    scope: &mut Scope,
+    body: impl FnOnce(&mut Scope) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut result = Ok(());
+    let mut set_up = 0;
+    for fixture in fixtures {
+        result = call(&fixture.setup, &[], scope).map(|_| ());
+        if result.is_err() {
+            break;
+        }
+        set_up += 1;
+    }
+    if result.is_ok() {
+        result = body(scope);
+    }
+    for fixture in fixtures[..set_up].iter().rev() {
+        if let Some(teardown) = &fixture.teardown {
+            let torn_down = call(teardown, &[], scope).map(|_| ());
+            result = result.and(torn_down);
+        }
+    }
+    result
+}
+
+/// Runs the registered tests the filter selects, in the order they were
+/// defined, each with a full tank of fuel.
+pub fn run_tests(scope: &mut Scope, filter: &TestFilter) -> Vec<TestResult> {
+    let runtime = scope.runtime().clone();
+    let (tests, each, once) = {
+        let suite = runtime.tests();
+        let tests: Vec<TestCase> = suite
+            .tests
+            .iter()
+            .filter(|test| filter.matches(test))
+            .cloned()
+            .collect();
+        (tests, suite.each.clone(), suite.once.clone())
+    };
+    if tests.is_empty() {
+        return Vec::new();
+    }
+
+    let mut results = Vec::new();
+    runtime.refuel();
+    let outcome = with_fixtures(&once, scope, |scope| {
+        for test in &tests {
+            runtime.refuel();
+            let result = with_fixtures(&each, scope, |_| {
+                builtin_progn(&test.body, &mut Scope::with_parent(&test.scope)).map(|_| ())
+            });
+            results.push(TestResult {
+                name: test.name.clone(),
+                error: result.err(),
+            });
+        }
+        Ok(())
+    });
+    // Tests cannot pass without their fixtures
+    if let Err(e) = outcome {
+        results = tests
+            .iter()
+            .enumerate()
+            .map(|(i, test)| TestResult {
+                name: test.name.clone(),
+                error: results
+                    .get(i)
+                    .and_then(|result| result.error.clone())
+                    .or_else(|| Some(e.clone())),
+            })
+            .collect();
+    }
+    results
+}
+
+/// How a benchmark went: how many times its body ran and the mean time
+/// each run took, or why it failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub name: String,
+    pub iterations: u64,
+    pub mean_ms: f64,
+    pub error: Option<String>,
+}
+
+/// Runs the body over and over until it has taken `budget_ms` in all, by
+/// the engine's clock, after a first run that is not timed.
+fn time_bench(bench: &TestCase, budget_ms: f64, scope: &Scope) -> Result<(u64, f64), String> {
+    let runtime = scope.runtime();
+    let run = || {
+        runtime.refuel();
+        builtin_progn(&bench.body, &mut Scope::with_parent(&bench.scope)).map(|_| ())
+    };
+    run()?;
+    let start = runtime.now();
+    let mut iterations = 0;
+    let mut elapsed = 0.0;
+    while iterations < MAX_ITERATIONS && (iterations == 0 || elapsed < budget_ms) {
+        run()?;
+        iterations += 1;
+        elapsed = runtime.now() - start;
+    }
+    Ok((iterations, elapsed / iterations as f64))
+}
+
+/// Runs the registered benchmarks the filter selects, in the order they
+/// were defined, giving each about `budget_ms` milliseconds.
+pub fn run_benches(scope: &mut Scope, filter: &TestFilter, budget_ms: f64) -> Vec<BenchResult> {
+    let runtime = scope.runtime().clone();
+    let (benches, once) = {
+        let suite = runtime.tests();
+        let benches: Vec<TestCase> = suite
+            .benches
+            .iter()
+            .filter(|bench| filter.matches(bench))
+            .cloned()
+            .collect();
+        (benches, suite.once.clone())
+    };
+    if benches.is_empty() {
+        return Vec::new();
+    }
+
+    let mut results = Vec::new();
+    let outcome = with_fixtures(&once, scope, |scope| {
+        for bench in &benches {
+            let (iterations, mean_ms, error) = match time_bench(bench, budget_ms, scope) {
+                Ok((iterations, mean_ms)) => (iterations, mean_ms, None),
+                Err(e) => (0, 0.0, Some(e)),
+            };
+            results.push(BenchResult {
+                name: bench.name.clone(),
+                iterations,
+                mean_ms,
+                error,
+            });
+        }
+        Ok(())
+    });
+    if let Err(e) = outcome {
+        results = benches
+            .iter()
+            .map(|bench| BenchResult {
+                name: bench.name.clone(),
+                iterations: 0,
+                mean_ms: 0.0,
+                error: Some(e.clone()),
+            })
+            .collect();
+    }
+    results
+}
+
+/// The mean times of benchmarks by name, kept to compare later runs with.
+/// As JSON it is an object of milliseconds per run,
+/// `{"fib-20": 1.25}`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Baseline(pub BTreeMap<String, f64>);
+
+impl Baseline {
+    /// The times of the benchmarks that succeeded.
+    pub fn from_results(results: &[BenchResult]) -> Self {
+        Baseline(
+            results
+                .iter()
+                .filter(|result| result.error.is_none())
+                .map(|result| (result.name.clone(), result.mean_ms))
+                .collect(),
+        )
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let value: Value =
+            serde_json::from_str(json).map_err(|e| format!("Invalid JSON: {}", e))?;
+        let Value::Object(times) = value else {
+            return Err("A baseline must be a JSON object".to_string());
+        };
+        times
+            .into_iter()
+            .map(|(name, time)| match time.as_f64() {
+                Some(time) => Ok((name, time)),
+                None => Err(format!("The baseline time of '{}' is not a number", name)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Baseline)
+    }
+
+    pub fn to_json(&self) -> String {
+        let times = self
+            .0
+            .iter()
+            .map(|(name, time)| {
+                let time = Number::from_f64(*time).map_or(Value::Null, Value::Number);
+                (name.clone(), time)
+            })
+            .collect();
+        serde_json::to_string_pretty(&Value::Object(times)).expect("numbers serialize")
+    }
+
+    /// How much slower the benchmark ran than its baseline, in percent,
+    /// negative when it got faster. `None` when it failed or has no
+    /// baseline to compare with.
+    pub fn change(&self, result: &BenchResult) -> Option<f64> {
+        let baseline = *self.0.get(&result.name)?;
+        if result.error.is_some() || baseline <= 0.0 {
+            return None;
+        }
+        Some((result.mean_ms - baseline) / baseline * 100.0)
+    }
+}
+
+/// The names in a `:tags` list, symbols or keywords.
+fn tag_names(tags: &Expr) -> Option<Vec<String>> {
+    let Expr::List(tags) = tags else {
+        return None;
+    };
+    tags.iter()
+        .map(|tag| match tag {
+            Expr::Symbol(name) | Expr::Keyword(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `(form name body...)` or `(form name :tags (tag...) body...)`, for
+/// `deftest` and `defbench`.
+fn test_case(form: &str, args: &[Expr], scope: &Scope) -> Result<TestCase, String> {
+    let name = expect_symbol(form, 1, &args[0])?;
+    let (tags, body) = match &args[1..] {
+        [Expr::Keyword(key), tags, body @ ..] if key == "tags" => {
+            let tags =
+                tag_names(tags).ok_or_else(|| type_error(form, "a list of tags", 3, tags))?;
+            (tags, body)
+        }
+        body => (Vec::new(), body),
+    };
+    Ok(TestCase {
+        name: name.to_string(),
+        tags,
+        body: body.to_vec(),
+        scope: scope.clone(),
+    })
+}
+
+/// Adds the test, replacing any of the same name.
+fn register(tests: &mut Vec<TestCase>, test: TestCase) -> Expr {
+    let name = Expr::symbol(&test.name);
+    match tests.iter_mut().find(|t| t.name == test.name) {
+        Some(existing) => *existing = test,
+        None => tests.push(test),
+    }
+    name
+}
+
+/// `(deftest name body...)` or `(deftest name :tags (tag...) body...)`
+/// registers a test, replacing any of the same name.
+pub fn builtin_deftest(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let test = test_case("deftest", args, scope)?;
+    Ok(register(&mut scope.runtime().tests().tests, test))
+}
+
+/// `(defbench name body...)` or `(defbench name :tags (tag...) body...)`
+/// registers a benchmark, replacing any of the same name.
+pub fn builtin_defbench(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let bench = test_case("defbench", args, scope)?;
+    Ok(register(&mut scope.runtime().tests().benches, bench))
+}
+
+/// `(use-fixture :each setup)` or `(use-fixture :each setup teardown)`, and
+/// the same with `:once`.
+pub fn builtin_use_fixture(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let fixture = Fixture {
+        setup: args[1].clone(),
+        teardown: args.get(2).cloned(),
+    };
+    let runtime = scope.runtime();
+    let mut suite = runtime.tests();
+    match &args[0] {
+        Expr::Keyword(when) if when == "each" => suite.each.push(fixture),
+        Expr::Keyword(when) if when == "once" => suite.once.push(fixture),
+        when => return Err(type_error("use-fixture", ":each or :once", 1, when)),
+    }
+    Ok(Expr::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    use crate::engine::{CapturedOutput, Clock, Engine};
+
+    /// A clock that moves on a millisecond each time it is read.
+    struct Ticking(Cell<f64>);
+
+    impl Clock for Ticking {
+        fn now(&self) -> f64 {
+            self.0.set(self.0.get() + 1.0);
+            self.0.get()
+        }
+    }
+
+    fn run(engine: &mut Engine, filter: TestFilter) -> Vec<(String, Option<String>)> {
+        engine
+            .run_tests(&filter)
+            .into_iter()
+            .map(|result| (result.name, result.error))
+            .collect()
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("parser*", "parser-errors"));
+        assert!(glob_match("*err*", "parser-errors"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(!glob_match("parser*", "lexer"));
+        assert!(!glob_match("parser", "parser-errors"));
+    }
+
+    #[test]
+    fn deftest_and_filters() {
+        let mut engine = Engine::new();
+        for source in [
+            "(deftest parser-reads (assert (= (first (read-string \"(1)\")) 1)))",
+            "(deftest parser-fails :tags (integration) (assert (= 1 2)))",
+            "(deftest math :tags (unit :fast) (assert (= (+ 1 1) 2)))",
+        ] {
+            engine.eval(source).unwrap();
+        }
+        assert_eq!(
+            run(&mut engine, TestFilter::default()),
+            vec![
+                ("parser-reads".to_string(), None),
+                (
+                    "parser-fails".to_string(),
+                    Some("Assertion failed in (= 1 2)".to_string())
+                ),
+                ("math".to_string(), None),
+            ]
+        );
+        let filter = TestFilter {
+            tags: vec!["integration".to_string(), "fast".to_string()],
+            name: None,
+        };
+        let names: Vec<String> = run(&mut engine, filter).into_iter().map(|r| r.0).collect();
+        assert_eq!(names, vec!["parser-fails", "math"]);
+        let filter = TestFilter {
+            tags: Vec::new(),
+            name: Some("parser*".to_string()),
+        };
+        let names: Vec<String> = run(&mut engine, filter).into_iter().map(|r| r.0).collect();
+        assert_eq!(names, vec!["parser-reads", "parser-fails"]);
+
+        // Defining a test again replaces it
+        engine.eval("(deftest parser-fails (assert true))").unwrap();
+        assert_eq!(run(&mut engine, TestFilter::default())[1].1, None);
+        assert_eq!(
+            engine.eval_to_string("(deftest bad :tags 1 true)"),
+            "Error: `deftest` expected a list of tags as argument 3, got the integer 1"
+        );
+    }
+
+    #[test]
+    fn fixtures() {
+        let mut engine = Engine::new();
+        let output = CapturedOutput::default();
+        engine.set_output(output.clone());
+        for source in [
+            r#"(use-fixture :once (fn () (print "[")) (fn () (print "]")))"#,
+            r#"(use-fixture :each (fn () (print "<")) (fn () (print ">")))"#,
+            r#"(deftest passes (print "pass"))"#,
+            r#"(deftest fails (print "fail") (assert false))"#,
+        ] {
+            engine.eval(source).unwrap();
+        }
+        let results = run(&mut engine, TestFilter::default());
+        assert_eq!(output.take(), "[<pass><fail>]");
+        assert_eq!(results[0].1, None);
+        assert!(results[1].1.is_some());
+
+        // A failing setup fails the test without running it, but still
+        // tears down what was set up
+        engine
+            .eval(r#"(use-fixture :each (fn () (+ 1 :a)))"#)
+            .unwrap();
+        let results = run(
+            &mut engine,
+            TestFilter {
+                tags: Vec::new(),
+                name: Some("passes".to_string()),
+            },
+        );
+        assert_eq!(output.take(), "[<>]");
+        assert_eq!(
+            results[0].1.as_deref(),
+            Some("`+` expected a number as argument 2, got the keyword :a")
+        );
+        assert_eq!(
+            engine.eval_to_string("(use-fixture :always first)"),
+            "Error: `use-fixture` expected :each or :once as argument 1, got the keyword :always"
+        );
+    }
+
+    #[test]
+    fn benchmarks() {
+        let mut engine = Engine::new();
+        engine.set_clock(Ticking(Cell::new(0.0)));
+        for source in [
+            "(defbench sum :tags (fast) (+ 1 2))",
+            "(defbench broken (+ 1 :a))",
+            "(deftest not-a-bench (assert true))",
+        ] {
+            engine.eval(source).unwrap();
+        }
+        let results = engine.run_benches(&TestFilter::default(), 5.0);
+        assert_eq!(
+            results,
+            vec![
+                BenchResult {
+                    name: "sum".to_string(),
+                    iterations: 5,
+                    mean_ms: 1.0,
+                    error: None,
+                },
+                BenchResult {
+                    name: "broken".to_string(),
+                    iterations: 0,
+                    mean_ms: 0.0,
+                    error: Some(
+                        "`+` expected a number as argument 2, got the keyword :a".to_string()
+                    ),
+                },
+            ]
+        );
+
+        let baseline = Baseline::from_results(&results);
+        assert_eq!(baseline.to_json(), "{\n  \"sum\": 1.0\n}");
+        assert_eq!(Baseline::from_json(&baseline.to_json()), Ok(baseline));
+        let baseline = Baseline::from_json(r#"{"sum": 0.5, "broken": 1}"#).unwrap();
+        assert_eq!(baseline.change(&results[0]), Some(100.0));
+        assert_eq!(baseline.change(&results[1]), None);
+        assert_eq!(
+            Baseline::from_json(r#"{"sum": "fast"}"#),
+            Err("The baseline time of 'sum' is not a number".to_string())
+        );
+    }
+}
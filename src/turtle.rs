@@ -0,0 +1,157 @@
+//! Turtle graphics for teaching: a turtle starts at the origin facing up and
+//! draws a line wherever it walks with its pen down. The host collects what
+//! it drew as SVG with `Engine::take_drawing`.
+
+use crate::builtins::expect_number;
+use crate::interpreter::{Expr, Scope};
+
+/// Blank space around the drawing, in the drawing's units.
+const PADDING: f64 = 10.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turtle {
+    x: f64,
+    y: f64,
+    /// Degrees clockwise from up.
+    heading: f64,
+    pen_down: bool,
+    lines: Vec<((f64, f64), (f64, f64))>,
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Turtle {
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            pen_down: true,
+            lines: Vec::new(),
+        }
+    }
+}
+
+/// A coordinate rounded to hundredths, without trailing zeros or `-0`.
+fn coordinate(n: f64) -> String {
+    let rounded = (n * 100.0).round() / 100.0;
+    (if rounded == 0.0 { 0.0 } else { rounded }).to_string()
+}
+
+impl Turtle {
+    /// Walks `distance` ahead, or behind if negative, drawing a line if the
+    /// pen is down.
+    pub fn forward(&mut self, distance: f64) {
+        let radians = self.heading.to_radians();
+        let from = (self.x, self.y);
+        // SVG's y axis points down, so up is negative y
+        self.x += distance * radians.sin();
+        self.y -= distance * radians.cos();
+        if self.pen_down {
+            self.lines.push((from, (self.x, self.y)));
+        }
+    }
+
+    /// Turns clockwise by `degrees`, or anticlockwise if negative.
+    pub fn turn(&mut self, degrees: f64) {
+        self.heading = (self.heading + degrees).rem_euclid(360.0);
+    }
+
+    pub fn set_pen_down(&mut self, down: bool) {
+        self.pen_down = down;
+    }
+
+    /// The lines drawn so far as an SVG document sized to fit them, or
+    /// `None` if nothing has been drawn.
+    pub fn to_svg(&self) -> Option<String> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        let points = self.lines.iter().flat_map(|(from, to)| [from, to]);
+        let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+        let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (x, y) in points {
+            min_x = min_x.min(*x);
+            min_y = min_y.min(*y);
+            max_x = max_x.max(*x);
+            max_y = max_y.max(*y);
+        }
+
+        // Lines that continue where the last one ended join into one stroke
+        let mut path = String::new();
+        let mut last = None;
+        for (from, to) in &self.lines {
+            if last != Some(*from) {
+                path.push_str(&format!("M{} {} ", coordinate(from.0), coordinate(from.1)));
+            }
+            path.push_str(&format!("L{} {} ", coordinate(to.0), coordinate(to.1)));
+            last = Some(*to);
+        }
+
+        let width = coordinate(max_x - min_x + 2.0 * PADDING);
+        let height = coordinate(max_y - min_y + 2.0 * PADDING);
+        Some(format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"{} {} {w} {h}\">\n  <path d=\"{}\" fill=\"none\" stroke=\"black\"/>\n</svg>\n",
+            coordinate(min_x - PADDING),
+            coordinate(min_y - PADDING),
+            path.trim_end(),
+            w = width,
+            h = height,
+        ))
+    }
+}
+
+/// `(forward distance)` walks the turtle ahead, drawing if its pen is down.
+pub fn builtin_forward(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let distance = expect_number("forward", 1, &args[0])?;
+    scope.runtime().turtle().forward(distance);
+    Ok(Expr::Nil)
+}
+
+/// `(turn degrees)` turns the turtle clockwise.
+pub fn builtin_turn(args: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    let degrees = expect_number("turn", 1, &args[0])?;
+    scope.runtime().turtle().turn(degrees);
+    Ok(Expr::Nil)
+}
+
+/// `(pen-up)` lets the turtle walk without drawing.
+pub fn builtin_pen_up(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    scope.runtime().turtle().set_pen_down(false);
+    Ok(Expr::Nil)
+}
+
+/// `(pen-down)` has the turtle draw again.
+pub fn builtin_pen_down(_: &[Expr], scope: &mut Scope) -> Result<Expr, String> {
+    scope.runtime().turtle().set_pen_down(true);
+    Ok(Expr::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn draw_lines() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.take_drawing(), None);
+        engine.eval("(forward 10)").unwrap();
+        engine.eval("(turn 90)").unwrap();
+        engine.eval("(forward 10)").unwrap();
+        engine.eval("(pen-up)").unwrap();
+        engine.eval("(forward 5)").unwrap();
+        engine.eval("(pen-down)").unwrap();
+        engine.eval("(turn -450)").unwrap();
+        engine.eval("(forward 10)").unwrap();
+        assert_eq!(
+            engine.take_drawing(),
+            Some(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"35\" height=\"40\" viewBox=\"-10 -30 35 40\">
+  <path d=\"M0 0 L0 -10 L10 -10 M15 -10 L15 -20\" fill=\"none\" stroke=\"black\"/>
+</svg>
+"
+                .to_string()
+            )
+        );
+        // Taking the drawing starts a new one
+        assert_eq!(engine.take_drawing(), None);
+    }
+}
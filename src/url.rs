@@ -0,0 +1,232 @@
+//! Taking URLs apart and percent-encoding their parts. Parsed URLs and
+//! query strings are maps with keyword keys.
+
+use std::rc::Rc;
+
+use crate::builtins::expect_text;
+use crate::interpreter::{Expr, Map, MapKey, Scope};
+use crate::messages::message;
+
+fn field(key: &str, value: Expr) -> (MapKey, Expr) {
+    (MapKey::keyword(key), value)
+}
+
+fn text_or_nil(text: Option<&str>) -> Expr {
+    match text {
+        Some(text) => Expr::string(text),
+        None => Expr::Nil,
+    }
+}
+
+/// Percent-encodes everything but the characters RFC 3986 leaves unreserved.
+pub fn url_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decodes `%XX` escapes, and with `plus_as_space` the `+` of form-encoded
+/// query strings.
+pub fn url_decode(text: &str, plus_as_space: bool) -> Result<String, String> {
+    let invalid = || message!("invalid-percent-encoding", text = text);
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.bytes();
+    while let Some(byte) = rest.next() {
+        match byte {
+            b'%' => {
+                let digits = [rest.next(), rest.next()];
+                let [Some(high), Some(low)] = digits else {
+                    return Err(invalid());
+                };
+                let hex = std::str::from_utf8(&[high, low])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(invalid)?;
+                bytes.push(hex);
+            }
+            b'+' if plus_as_space => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// The `(key value)` pairs of a query string, decoded, in order.
+pub fn parse_query(query: &str) -> Result<Vec<(String, String)>, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((url_decode(key, true)?, url_decode(value, true)?))
+        })
+        .collect()
+}
+
+/// Splits an absolute URL into a map of its scheme, user, host, port, path,
+/// query and fragment, with `nil` for the parts it does not have.
+pub fn parse_url(url: &str) -> Result<Expr, String> {
+    let invalid = || message!("invalid-url", url = url);
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    if scheme.is_empty()
+        || !scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    {
+        return Err(invalid());
+    }
+
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (rest, None),
+    };
+    let (rest, query) = match rest.split_once('?') {
+        Some((rest, query)) => (rest, Some(query)),
+        None => (rest, None),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, ""),
+    };
+    let (user, host_port) = match authority.rsplit_once('@') {
+        Some((user, host_port)) => (Some(user), host_port),
+        None => (None, authority),
+    };
+    // The colon inside a bracketed IPv6 address is not a port separator
+    let (host, port) = match host_port.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            let port: u16 = port.parse().map_err(|_| invalid())?;
+            (host, Expr::Int(port.into()))
+        }
+        _ => (host_port, Expr::Nil),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(Expr::map(Map::from([
+        field("scheme", Expr::String(scheme.to_ascii_lowercase())),
+        field("user", text_or_nil(user)),
+        field("host", Expr::string(host)),
+        field("port", port),
+        field(
+            "path",
+            Expr::string(if path.is_empty() { "/" } else { path }),
+        ),
+        field("query", text_or_nil(query)),
+        field("fragment", text_or_nil(fragment)),
+    ])))
+}
+
+/// `(url-parse url)` is the map of the URL's parts.
+pub fn builtin_url_parse(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    parse_url(expect_text("url-parse", 1, &args[0])?)
+}
+
+/// `(url-encode text)` percent-encodes the text for use in a URL.
+pub fn builtin_url_encode(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let text = expect_text("url-encode", 1, &args[0])?;
+    Ok(Expr::String(url_encode(text)))
+}
+
+/// `(url-decode text)` undoes `url-encode`.
+pub fn builtin_url_decode(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let text = expect_text("url-decode", 1, &args[0])?;
+    url_decode(text, false).map(Expr::String)
+}
+
+/// `(query-string->map query)` is the map of a query string's decoded keys,
+/// as keywords, to their decoded values. A key given more than once maps to
+/// a vector of its values, in order.
+pub fn builtin_query_string_to_map(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let query = expect_text("query-string->map", 1, &args[0])?;
+    let mut map = Map::new();
+    for (key, value) in parse_query(query)? {
+        let key = MapKey::keyword(key);
+        let value = Expr::String(value);
+        let value = match map.remove(&key) {
+            None => value,
+            Some(Expr::Vector(mut values)) => {
+                Rc::make_mut(&mut values).push(value);
+                Expr::Vector(values)
+            }
+            Some(first) => Expr::vector(vec![first, value]),
+        };
+        map.insert(key, value);
+    }
+    Ok(Expr::map(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::parser::parse;
+
+    #[test]
+    fn parse_urls() {
+        assert_eq!(
+            parse_url("HTTPS://me@example.com:8080/a/b?q=1#top"),
+            parse(
+                r#"{:scheme "https" :user "me" :host "example.com" :port 8080
+                   :path "/a/b" :query "q=1" :fragment "top"}"#
+            )
+        );
+        assert_eq!(
+            parse_url("http://[::1]"),
+            parse(
+                r#"{:scheme "http" :user nil :host "[::1]" :port nil :path "/"
+                   :query nil :fragment nil}"#
+            )
+        );
+        assert_eq!(
+            parse_url("example.com/a"),
+            Err("Invalid URL 'example.com/a'".to_string())
+        );
+        assert_eq!(
+            parse_url("http://host:99999"),
+            Err("Invalid URL 'http://host:99999'".to_string())
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.eval_to_string(r#"(url-encode "a b&c/ü")"#),
+            r#""a%20b%26c%2F%C3%BC""#
+        );
+        assert_eq!(
+            engine.eval_to_string(r#"(url-decode (url-encode "a b&c/ü"))"#),
+            r#""a b&c/ü""#
+        );
+        assert_eq!(
+            engine.eval_to_string(r#"(url-decode "50%")"#),
+            "Error: Invalid percent-encoding in '50%'"
+        );
+        for (input, expected) in [
+            (
+                r#"(query-string->map "q=a+b%21&flag&&x=")"#,
+                r#"{:flag "" :q "a b!" :x ""}"#,
+            ),
+            (
+                r#"(query-string->map "tag=a&page=2&tag=b&tag=c")"#,
+                r#"{:page "2" :tag ["a" "b" "c"]}"#,
+            ),
+            (
+                r#"(get (url-parse "http://example.com/search?q=lisp") :query)"#,
+                r#""q=lisp""#,
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Vectors, written `[1 2 3]`: sequences indexed in constant time, for data
+//! rather than code. Like lists they are values, so `vec-push` and
+//! `vec-slice` give a new vector and leave the one they were given alone.
+
+use std::rc::Rc;
+
+use crate::builtins::type_error;
+use crate::interpreter::{Arity, BuiltinFn, BuiltinKind, Expr, Scope};
+use crate::messages::message;
+use crate::parser::expr_to_string;
+
+fn expect_vector<'a>(
+    name: &str,
+    position: usize,
+    expr: &'a Expr,
+) -> Result<&'a Rc<Vec<Expr>>, String> {
+    match expr {
+        Expr::Vector(items) => Ok(items),
+        _ => Err(type_error(name, "a vector", position, expr)),
+    }
+}
+
+/// The integer at `position` as an index into something of `length`
+/// elements, from 0 up to but not including `end`.
+fn expect_index(
+    name: &str,
+    position: usize,
+    expr: &Expr,
+    end: usize,
+    length: usize,
+) -> Result<usize, String> {
+    match expr {
+        Expr::Int(n) if usize::try_from(*n).is_ok_and(|n| n < end) => Ok(*n as usize),
+        Expr::Int(_) | Expr::BigInt(_) => Err(message!(
+            "index-out-of-range",
+            name = name,
+            index = expr_to_string(expr),
+            length = length
+        )),
+        _ => Err(type_error(name, "an integer", position, expr)),
+    }
+}
+
+fn builtin_is_vector(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    Ok(Expr::Bool(matches!(&args[0], Expr::Vector(_))))
+}
+
+/// `(vec coll)` is a vector of the elements of a list, or of nil, or the
+/// vector itself.
+fn builtin_vec(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    match &args[0] {
+        Expr::Vector(_) => Ok(args[0].clone()),
+        Expr::List(items) => Ok(Expr::vector(items.clone())),
+        Expr::Nil => Ok(Expr::vector(Vec::new())),
+        other => Err(type_error("vec", "a list or vector", 1, other)),
+    }
+}
+
+fn builtin_vec_len(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let items = expect_vector("vec-len", 1, &args[0])?;
+    Ok(Expr::Int(items.len() as i64))
+}
+
+/// `(vec-push v x)` is the vector with `x` added to the end.
+fn builtin_vec_push(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let mut items = expect_vector("vec-push", 1, &args[0])?.clone();
+    Rc::make_mut(&mut items).push(args[1].clone());
+    Ok(Expr::Vector(items))
+}
+
+/// `(vec-slice v start)` is the elements from index `start` on, and
+/// `(vec-slice v start end)` those up to but not including `end`.
+fn builtin_vec_slice(args: &[Expr], _: &mut Scope) -> Result<Expr, String> {
+    let items = expect_vector("vec-slice", 1, &args[0])?;
+    let start = expect_index("vec-slice", 2, &args[1], items.len() + 1, items.len())?;
+    let end = match args.get(2) {
+        Some(end) => expect_index("vec-slice", 3, end, items.len() + 1, items.len())?,
+        None => items.len(),
+    };
+    if end < start {
+        return Err(message!(
+            "slice-backwards",
+            name = "vec-slice",
+            start = start,
+            end = end
+        ));
+    }
+    Ok(Expr::vector(items[start..end].to_vec()))
+}
+
+#[rustfmt::skip]
+pub(crate) const VECTOR_BUILTINS: &[(&str, BuiltinFn, BuiltinKind, Arity)] = &[
+    ("vector?",   builtin_is_vector, BuiltinKind::Eager, Arity::Exact(1)),
+    ("vec",       builtin_vec,       BuiltinKind::Eager, Arity::Exact(1)),
+    ("vec-len",   builtin_vec_len,   BuiltinKind::Eager, Arity::Exact(1)),
+    ("vec-push",  builtin_vec_push,  BuiltinKind::Eager, Arity::Exact(2)),
+    ("vec-slice", builtin_vec_slice, BuiltinKind::Eager, Arity::Range(2, 3)),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn vectors() {
+        let mut engine = Engine::new();
+        engine.eval("(def v [1 (+ 1 1) :three])").unwrap();
+        for (input, expected) in [
+            ("v", "[1 2 :three]"),
+            ("(quote [a (b)])", "[a (b)]"),
+            ("(vector? v)", "true"),
+            ("(vector? (list 1))", "false"),
+            ("(type-of [])", ":vector"),
+            ("(eq? v [1 2 :three])", "true"),
+            ("(eq? v (list 1 2 :three))", "false"),
+            ("(compare [1 2] [1 3])", "-1"),
+            ("(vec (list 1 2))", "[1 2]"),
+            ("(vec nil)", "[]"),
+            ("(nth v 2)", ":three"),
+            ("(nth (list 1 2) 1)", "2"),
+            ("(vec-len v)", "3"),
+            ("(vec-push v 4)", "[1 2 :three 4]"),
+            ("v", "[1 2 :three]"),
+            ("(vec-slice v 1)", "[2 :three]"),
+            ("(vec-slice v 0 2)", "[1 2]"),
+            ("(vec-slice v 3)", "[]"),
+            (
+                "(nth v 3)",
+                "Error: `nth` index 3 is out of range for 3 elements",
+            ),
+            (
+                "(nth v -1)",
+                "Error: `nth` index -1 is out of range for 3 elements",
+            ),
+            (
+                "(nth [] 0)",
+                "Error: `nth` index 0 is out of range for 0 elements",
+            ),
+            (
+                "(nth v 1.0)",
+                "Error: `nth` expected an integer as argument 2, got the number 1.0",
+            ),
+            (
+                "(vec-slice v 1 4)",
+                "Error: `vec-slice` index 4 is out of range for 3 elements",
+            ),
+            (
+                "(vec-slice v 2 1)",
+                "Error: `vec-slice` cannot slice from 2 back to 1",
+            ),
+            (
+                "(vec-len (list 1))",
+                "Error: `vec-len` expected a vector as argument 1, got the list (1)",
+            ),
+        ] {
+            assert_eq!(engine.eval_to_string(input), expected, "{}", input);
+        }
+    }
+}
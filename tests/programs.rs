@@ -0,0 +1,49 @@
+//! Golden tests: each `tests/programs/NAME.lisp` is run and its transcript
+//! compared with `NAME.out`. Run with `UPDATE_GOLDEN=1` to write the
+//! transcripts instead, then review the changes before committing them.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use rs_lisp::program::run_program_file;
+
+#[test]
+fn programs() {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "lisp")
+        })
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for program in programs {
+        let actual = run_program_file(program.to_str().unwrap()).unwrap();
+        let golden = program.with_extension("out");
+        if update {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}",
+                program.display(),
+                expected,
+                actual
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nRun with UPDATE_GOLDEN=1 to accept the new output.",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,16 @@
+; Numbers and the builtins on them
+(+ 1 2 3)
+(+)
+(+ 0x10 0b11 1_000)
+(- 10 (* 2 3) (/ 8 4))
+(- 5)
+(mod -7 3)
+(list (abs -2) (min 3 1 2) (max 3 1 2))
+(list (sqrt 2) (pow 2 0.5) (round pi))
+(/ 1 0)
+(compare 1 2)
+(list (= 2 2) (< 1 2 3) (>= 1 2))
+(if (> 2 1) (quote bigger) (quote smaller))
+(and (> 2 1) (or nil (quote yes)))
+(sort (list 3 1 2))
+(+ 1 a)
@@ -0,0 +1,10 @@
+; Definitions, closures and recursion
+(defn add (a b) (+ a b))
+(add 1 2)
+(def adder (fn (n) (fn (x) (+ x n))))
+((adder 10) 5)
+(let ((x 1) (y 2)) (add x y))
+(add 1)
+(doc add)
+(def answer "The answer." 42)
+(doc answer)
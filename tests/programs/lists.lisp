@@ -0,0 +1,11 @@
+; Building and taking apart lists
+(list 1 (list 2 3) (quote (a b)))
+(first (quote (a b c)))
+(first (quote ()))
+(rest (quote (a b c)))
+(empty? (quote ()))
+(apply + (list 1 2 3))
+(quote |odd symbol|)
+(eq? (list 1 (quote a)) (quote (1 a)))
+(list :name (quote name) (keyword? :name))
+(first 1)
@@ -0,0 +1,17 @@
+; Strings and the builtins joining them
+(def greeting "Hello, world")
+(str-len greeting)
+(str greeting "! " 42 " " (quote (a b)))
+(str-concat "tab\t" "quote\"")
+(string? greeting)
+(string? (quote greeting))
+(str-concat "a" 1)
+(string->list "hi!")
+(list->string (list #\h #\space #\i))
+(char->int (first (string->list greeting)))
+(string-split "a b c" " ")
+(string-join (list "x" "y") "-")
+(string-replace (string-upcase greeting) "WORLD" "there")
+(string->number "12")
+(println "total:" (+ 1 2) (list "a" #\b))
+(display "no newline")